@@ -0,0 +1,124 @@
+//! `#[derive(TemplateData)]`, re-exported by `mustache` with its `derive`
+//! feature. See `mustache::TypedTemplate`.
+
+extern crate proc_macro;
+
+use proc_macro2::TokenStream;
+use quote::{quote, quote_spanned};
+use syn::spanned::Spanned;
+use syn::{parse_macro_input, parse_quote, Attribute, Data, DeriveInput, Fields, LitStr};
+
+/// Implements `mustache::TemplateData` for a struct with named fields, a
+/// newtype or an enum of unit variants, following the `rename`, `skip` and
+/// `skip_serializing` serde attributes.
+#[proc_macro_derive(TemplateData, attributes(serde))]
+pub fn derive_template_data(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match shape(&input) {
+        Ok(shape) => implement(&input, shape).into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn implement(input: &DeriveInput, shape: TokenStream) -> TokenStream {
+    let name = &input.ident;
+
+    let mut generics = input.generics.clone();
+    for param in generics.type_params_mut() {
+        param.bounds.push(parse_quote!(::mustache::TemplateData));
+    }
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    quote! {
+        impl #impl_generics ::mustache::TemplateData for #name #ty_generics #where_clause {
+            fn shape() -> ::mustache::Shape {
+                #shape
+            }
+        }
+    }
+}
+
+fn shape(input: &DeriveInput) -> syn::Result<TokenStream> {
+    match input.data {
+        Data::Struct(ref data) => match data.fields {
+            Fields::Named(ref fields) => {
+                let mut entries = Vec::new();
+                for field in &fields.named {
+                    let attrs = SerdeAttrs::parse(&field.attrs)?;
+                    if attrs.skip {
+                        continue;
+                    }
+                    if attrs.flatten {
+                        return Err(syn::Error::new(field.span(), "TemplateData can't follow #[serde(flatten)]"));
+                    }
+
+                    let ident = field.ident.as_ref().expect("named fields have names");
+                    let name = attrs.rename.unwrap_or_else(|| ident.to_string().trim_start_matches("r#").to_string());
+                    let ty = &field.ty;
+                    entries.push(quote_spanned! {ty.span()=>
+                        (#name, <#ty as ::mustache::TemplateData>::shape as fn() -> ::mustache::Shape)
+                    });
+                }
+                Ok(quote!(::mustache::Shape::Struct(vec![#(#entries),*])))
+            }
+            Fields::Unnamed(ref fields) if fields.unnamed.len() == 1 => {
+                let ty = &fields.unnamed[0].ty;
+                Ok(quote!(<#ty as ::mustache::TemplateData>::shape()))
+            }
+            _ => Err(syn::Error::new(input.ident.span(), "TemplateData needs named fields or a single unnamed one")),
+        },
+        Data::Enum(ref data) => {
+            // Unit variants serialize as their names.
+            match data.variants.iter().find(|variant| !matches!(variant.fields, Fields::Unit)) {
+                None => Ok(quote!(::mustache::Shape::Scalar)),
+                Some(variant) => Err(syn::Error::new(variant.span(), "TemplateData only takes enums of unit variants")),
+            }
+        }
+        Data::Union(_) => Err(syn::Error::new(input.ident.span(), "TemplateData can't be derived for unions")),
+    }
+}
+
+#[derive(Default)]
+struct SerdeAttrs {
+    rename: Option<String>,
+    skip: bool,
+    flatten: bool,
+}
+
+impl SerdeAttrs {
+    fn parse(attrs: &[Attribute]) -> syn::Result<SerdeAttrs> {
+        let mut parsed = SerdeAttrs::default();
+
+        for attr in attrs.iter().filter(|attr| attr.path().is_ident("serde")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("rename") {
+                    if meta.input.peek(syn::Token![=]) {
+                        parsed.rename = Some(meta.value()?.parse::<LitStr>()?.value());
+                    } else {
+                        // `rename(serialize = "..", deserialize = "..")`
+                        meta.parse_nested_meta(|meta| {
+                            let value = meta.value()?.parse::<LitStr>()?.value();
+                            if meta.path.is_ident("serialize") {
+                                parsed.rename = Some(value);
+                            }
+                            Ok(())
+                        })?;
+                    }
+                } else if meta.path.is_ident("skip") || meta.path.is_ident("skip_serializing") {
+                    parsed.skip = true;
+                } else if meta.path.is_ident("flatten") {
+                    parsed.flatten = true;
+                } else if meta.input.peek(syn::Token![=]) {
+                    meta.value()?.parse::<syn::Expr>()?;
+                } else if meta.input.peek(syn::token::Paren) {
+                    let content;
+                    syn::parenthesized!(content in meta.input);
+                    content.parse::<TokenStream>()?;
+                }
+                Ok(())
+            })?;
+        }
+
+        Ok(parsed)
+    }
+}
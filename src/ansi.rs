@@ -0,0 +1,185 @@
+//! Styling terminal output with ANSI escape codes, for command line reports.
+//!
+//! [`style`] is a [`Data::Helper`](../enum.Data.html#variant.Helper) lambda
+//! whose section renders to a list of styles and then the text to style,
+//! separated by `|`: `{{#style}}bold,green|{{status}}{{/style}}`. Styles nest,
+//! each one only turns off what it turned on.
+//!
+//! Output that doesn't go to a terminal shouldn't carry codes, so the helper
+//! is made enabled or not, [`enabled`] telling which for stdout. A disabled
+//! helper writes its text with any codes in it [`strip`]ped.
+//!
+//! ```
+//! use mustache::{ansi, Data, MapBuilder};
+//!
+//! let template = mustache::compile_str("{{#style}}bold,red|{{count}} failed{{/style}}").unwrap();
+//!
+//! for &(enabled, expected) in &[(true, "\x1b[1;31m2 failed\x1b[22;39m"), (false, "2 failed")] {
+//!     let mut data = MapBuilder::new().insert_str("count", "2").build();
+//!     if let Data::Map(ref mut map) = data {
+//!         map.insert("style".to_string(), ansi::style(enabled));
+//!     }
+//!     assert_eq!(template.render_data_to_string(&data).unwrap(), expected);
+//! }
+//! ```
+
+use std::cell::RefCell;
+use std::env;
+use std::io::{self, IsTerminal};
+
+use {Data, Scope};
+
+const COLORS: &[&str] = &["black", "red", "green", "yellow", "blue", "magenta", "cyan", "white"];
+
+// The codes turning a style on and off, `None` for unknown names.
+fn codes(name: &str) -> Option<(u8, u8)> {
+    let attribute = match name {
+        "bold" => Some((1, 22)),
+        "dim" => Some((2, 22)),
+        "italic" => Some((3, 23)),
+        "underline" => Some((4, 24)),
+        "blink" => Some((5, 25)),
+        "reverse" => Some((7, 27)),
+        "hidden" => Some((8, 28)),
+        "strikethrough" => Some((9, 29)),
+        _ => None,
+    };
+    if attribute.is_some() {
+        return attribute;
+    }
+
+    let (background, name) = match name.strip_prefix("on_") {
+        Some(name) => (true, name),
+        None => (false, name),
+    };
+    let (bright, name) = match name.strip_prefix("bright_") {
+        Some(name) => (true, name),
+        None => (false, name),
+    };
+
+    let color = COLORS.iter().position(|&color| color == name)? as u8;
+    Some(match (background, bright) {
+        (false, false) => (30 + color, 39),
+        (false, true) => (90 + color, 39),
+        (true, false) => (40 + color, 49),
+        (true, true) => (100 + color, 49),
+    })
+}
+
+/// Styles the text of its section, `{{#style}}bold,green|{{text}}{{/style}}`.
+///
+/// The styles are `bold`, `dim`, `italic`, `underline`, `blink`, `reverse`,
+/// `hidden` and `strikethrough`, the eight colors from `black` to `white`,
+/// their `bright_` variants and all of those as backgrounds with `on_`, like
+/// `on_bright_blue`. Unknown styles are left out, and a section without a
+/// `|` is written as it is.
+pub fn style(enabled: bool) -> Data {
+    Data::Helper(RefCell::new(Box::new(move |content: String, _: &Scope| {
+        let mut parts = content.splitn(2, '|');
+        let (styles, text) = match (parts.next(), parts.next()) {
+            (Some(styles), Some(text)) => (styles, text),
+            _ => return content.clone(),
+        };
+
+        if !enabled {
+            return strip(text);
+        }
+
+        let (on, off): (Vec<_>, Vec<_>) = styles.split(',').filter_map(|name| codes(name.trim())).unzip();
+        if on.is_empty() {
+            return text.to_string();
+        }
+
+        let join = |codes: &[u8]| codes.iter().map(|code| code.to_string()).collect::<Vec<_>>().join(";");
+        format!("\x1b[{}m{}\x1b[{}m", join(&on), text, join(&off))
+    })))
+}
+
+/// Whether output to stdout should be styled: it is a terminal, and the
+/// `NO_COLOR` environment variable isn't set.
+pub fn enabled() -> bool {
+    env::var_os("NO_COLOR").unwrap_or_default().is_empty() && io::stdout().is_terminal()
+}
+
+/// Removes the ANSI escape sequences in `s`, such as the styles written by
+/// [`style`] or anything a value brought along.
+pub fn strip(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            out.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            // Control sequences: parameters and intermediates up to a final
+            // byte from `@` to `~`.
+            Some('[') => {
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            // Operating system commands, such as hyperlinks, end with BEL or
+            // `ESC \`.
+            Some(']') => {
+                while let Some(c) = chars.next() {
+                    if c == '\x07' {
+                        break;
+                    }
+                    if c == '\x1b' && chars.peek() == Some(&'\\') {
+                        chars.next();
+                        break;
+                    }
+                }
+            }
+            // Any other escape is two characters long.
+            _ => {}
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{strip, style};
+    use {Data, MapBuilder};
+
+    #[test]
+    fn styles() {
+        let template = ::compile_str(concat!(
+            "{{#style}}bold,green|ok {{#style}}on_bright_blue,underline|{{{name}}}{{/style}}{{/style}}",
+            " {{#style}}nope|plain{{/style}} {{#style}}no styles{{/style}}",
+        )).unwrap();
+
+        let render = |enabled| {
+            let mut data = MapBuilder::new().insert_str("name", "\x1b[31mred\x1b[0m").build();
+            if let Data::Map(ref mut map) = data {
+                map.insert("style".to_string(), style(enabled));
+            }
+            template.render_data_to_string(&data).unwrap()
+        };
+
+        assert_eq!(render(true), "\x1b[1;32mok \x1b[104;4m\x1b[31mred\x1b[0m\x1b[49;24m\x1b[22;39m plain no styles");
+        assert_eq!(render(false), "ok red plain no styles");
+    }
+
+    #[test]
+    fn stripped() {
+        let cases = &[
+            ("plain", "plain"),
+            ("\x1b[1;32mgreen\x1b[0m", "green"),
+            ("\x1b]8;;https://example.com\x1b\\link\x1b]8;;\x07!", "link!"),
+            ("a\x1bMb", "ab"),
+            ("cut \x1b[3", "cut "),
+        ];
+
+        for &(s, expected) in cases {
+            assert_eq!(strip(s), expected, "{:?}", s);
+        }
+    }
+}
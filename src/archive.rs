@@ -0,0 +1,169 @@
+//! Loading template packs from archives, enabled with the `zip` and `tar`
+//! features.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::Read;
+#[cfg(feature = "zip")]
+use std::io::{self, Seek};
+use std::path::{Component, Path, PathBuf};
+use std::str;
+use std::sync::Arc;
+
+#[cfg(feature = "tar")]
+use tar;
+#[cfg(feature = "zip")]
+use zip::ZipArchive;
+
+use context::{self, PartialLoader};
+use {Error, Result};
+
+/// Loads templates out of a zip or tar archive, read in full when the loader
+/// is made. Like `DefaultLoader`, names get the extension appended and a
+/// missing template is empty. Clones share the same contents.
+///
+/// ```ignore
+/// let pack = File::open("themes/dark.zip")?;
+/// let ctx = Context::with_loader(ArchiveLoader::from_zip(pack)?);
+/// ```
+#[derive(Clone, Debug)]
+pub struct ArchiveLoader {
+    files: Arc<HashMap<PathBuf, Vec<u8>>>,
+    extension: String,
+}
+
+impl ArchiveLoader {
+    /// Reads every file of the zip archive in `reader`.
+    #[cfg(feature = "zip")]
+    pub fn from_zip<R: Read + Seek>(reader: R) -> Result<Self> {
+        let mut archive = ZipArchive::new(reader).map_err(io::Error::from)?;
+        let mut files = HashMap::new();
+
+        for i in 0..archive.len() {
+            let mut file = archive.by_index(i).map_err(io::Error::from)?;
+            if file.is_dir() {
+                continue;
+            }
+
+            let mut contents = Vec::with_capacity(file.size() as usize);
+            file.read_to_end(&mut contents)?;
+            files.insert(normalize(Path::new(file.name())), contents);
+        }
+
+        Ok(ArchiveLoader::new(files))
+    }
+
+    /// Reads every regular file of the tar archive in `reader`.
+    #[cfg(feature = "tar")]
+    pub fn from_tar<R: Read>(reader: R) -> Result<Self> {
+        let mut archive = tar::Archive::new(reader);
+        let mut files = HashMap::new();
+
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+
+            let path = normalize(&entry.path()?);
+            let mut contents = Vec::with_capacity(entry.size() as usize);
+            entry.read_to_end(&mut contents)?;
+            files.insert(path, contents);
+        }
+
+        Ok(ArchiveLoader::new(files))
+    }
+
+    fn new(files: HashMap<PathBuf, Vec<u8>>) -> Self {
+        ArchiveLoader {
+            files: Arc::new(files),
+            extension: "mustache".to_string(),
+        }
+    }
+
+    /// Loads `{name}.{extension}` rather than `{name}.mustache`.
+    pub fn with_extension(mut self, extension: String) -> Self {
+        self.extension = extension;
+        self
+    }
+}
+
+// Drops the `.` components so `./user.mustache` and `user.mustache` match.
+fn normalize(path: &Path) -> PathBuf {
+    path.components().filter(|c| *c != Component::CurDir).collect()
+}
+
+impl PartialLoader for ArchiveLoader {
+    fn load(&self, name: impl AsRef<Path>) -> Result<String> {
+        self.load_str(name).map(Cow::into_owned)
+    }
+
+    fn load_str(&self, name: impl AsRef<Path>) -> Result<Cow<'_, str>> {
+        let path = context::with_extension(normalize(name.as_ref()), &self.extension);
+
+        match self.files.get(&path) {
+            Some(contents) => str::from_utf8(contents).map(Cow::Borrowed).map_err(|_| Error::InvalidStr),
+            None => Ok(Cow::Borrowed("")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ArchiveLoader;
+    use {Context, MapBuilder};
+
+    const FILES: &[(&str, &str)] = &[
+        ("./page.mustache", "[{{>parts/user}}]"),
+        ("parts/user.mustache", "{{name}}"),
+        ("parts/user.txt", "text"),
+    ];
+
+    fn check(loader: ArchiveLoader) {
+        let data = MapBuilder::new().insert_str("name", "n").build();
+
+        let ctx = Context::with_loader(loader.clone());
+        let template = ctx.compile("{{>page}}{{>missing}}".chars()).unwrap();
+        assert_eq!(template.render_data_to_string(&data).unwrap(), "[n]");
+
+        let ctx = Context::with_loader(loader.with_extension("txt".to_string()));
+        let template = ctx.compile("{{>parts/user}}".chars()).unwrap();
+        assert_eq!(template.render_data_to_string(&data).unwrap(), "text");
+    }
+
+    #[cfg(feature = "zip")]
+    #[test]
+    fn zip() {
+        use std::io::{Cursor, Write};
+        use zip::ZipWriter;
+        use zip::write::FileOptions;
+
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        zip.add_directory("parts/", FileOptions::default()).unwrap();
+        for &(name, contents) in FILES {
+            zip.start_file(name, FileOptions::default()).unwrap();
+            zip.write_all(contents.as_bytes()).unwrap();
+        }
+        let bytes = zip.finish().unwrap().into_inner();
+
+        check(ArchiveLoader::from_zip(Cursor::new(bytes)).unwrap());
+    }
+
+    #[cfg(feature = "tar")]
+    #[test]
+    fn tar() {
+        use tar::{Builder, Header};
+
+        let mut tar = Builder::new(Vec::new());
+        for &(name, contents) in FILES {
+            let mut header = Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            tar.append_data(&mut header, name, contents.as_bytes()).unwrap();
+        }
+        let bytes = tar.into_inner().unwrap();
+
+        check(ArchiveLoader::from_tar(&bytes[..]).unwrap());
+    }
+}
@@ -0,0 +1,216 @@
+//! Rendering into the async writers of tokio and futures, for
+//! `Template::render_to_async_write` and `Template::render_to_futures_write`.
+//!
+//! The render goes a buffer at a time: the template is rendered until
+//! `BUFFER` bytes are waiting, those are written as fast as the writer takes
+//! them, and only then is more rendered, so a slow reader holds the render
+//! back instead of the output piling up in memory.
+
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context as TaskContext, Poll};
+use std::time::Instant;
+
+use template::Resumable;
+use {Data, PartialLoader, RenderOptions, Result, Template};
+
+// How much output is rendered ahead of the writer.
+const BUFFER: usize = 8 * 1024;
+
+pub fn render<'a, P, S>(template: &'a Template<P>, sink: S, data: &'a Data, options: &'a RenderOptions) -> Render<'a, P, S>
+where P: PartialLoader,
+      S: Sink,
+{
+    Render {
+        template,
+        resumable: Resumable::new(template, data, options),
+        sink,
+        buf: Vec::with_capacity(BUFFER),
+        written: 0,
+        total: 0,
+        more: true,
+        started: template.ctx.metrics.as_ref().map(|_| Instant::now()),
+    }
+}
+
+// The options of the renders not given any, which have to be borrowed for as
+// long as the render goes on. One is made for every thread and kept.
+pub fn default_options() -> &'static RenderOptions {
+    thread_local!(static OPTIONS: &'static RenderOptions = Box::leak(Box::default()));
+    OPTIONS.with(|options| *options)
+}
+
+// The writers of either library.
+pub trait Sink {
+    fn poll_write(&mut self, cx: &mut TaskContext, buf: &[u8]) -> Poll<io::Result<usize>>;
+    fn poll_flush(&mut self, cx: &mut TaskContext) -> Poll<io::Result<()>>;
+}
+
+#[cfg(feature = "tokio")]
+pub struct Tokio<W>(pub W);
+
+#[cfg(feature = "tokio")]
+impl<W: ::tokio::io::AsyncWrite + Unpin> Sink for Tokio<W> {
+    fn poll_write(&mut self, cx: &mut TaskContext, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(&mut self, cx: &mut TaskContext) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+}
+
+#[cfg(feature = "futures")]
+pub struct Futures<W>(pub W);
+
+#[cfg(feature = "futures")]
+impl<W: ::futures_io::AsyncWrite + Unpin> Sink for Futures<W> {
+    fn poll_write(&mut self, cx: &mut TaskContext, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(&mut self, cx: &mut TaskContext) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+}
+
+pub struct Render<'a, P: PartialLoader, S> {
+    template: &'a Template<P>,
+    resumable: Resumable<'a, P>,
+    sink: S,
+    // The output rendered, of which `written` bytes went to the writer, and
+    // all it took so far.
+    buf: Vec<u8>,
+    written: usize,
+    total: usize,
+    // Whether the template has more to render.
+    more: bool,
+    // When the render started, for the context's metrics sink.
+    started: Option<Instant>,
+}
+
+impl<'a, P: PartialLoader, S> Render<'a, P, S> {
+    fn done(&mut self, rendered: Result<()>) -> Poll<Result<()>> {
+        if let Some(started) = self.started.take() {
+            self.template.measure(started, self.total, rendered.is_ok());
+        }
+        Poll::Ready(rendered)
+    }
+}
+
+impl<'a, P: PartialLoader, S: Sink + Unpin> Future for Render<'a, P, S> {
+    type Output = Result<()>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut TaskContext) -> Poll<Result<()>> {
+        let this = self.get_mut();
+
+        loop {
+            if this.written < this.buf.len() {
+                match this.sink.poll_write(cx, &this.buf[this.written..]) {
+                    Poll::Ready(Ok(0)) => return this.done(Err(io::Error::from(io::ErrorKind::WriteZero).into())),
+                    Poll::Ready(Ok(n)) => {
+                        this.written += n;
+                        this.total += n;
+                    }
+                    Poll::Ready(Err(err)) => return this.done(Err(err.into())),
+                    Poll::Pending => return Poll::Pending,
+                }
+            } else if this.more {
+                this.buf.clear();
+                this.written = 0;
+                this.more = match this.resumable.fill(&mut this.buf, BUFFER) {
+                    Ok(more) => more,
+                    Err(err) => return this.done(Err(err)),
+                };
+            } else {
+                return match this.sink.poll_flush(cx) {
+                    Poll::Ready(flushed) => this.done(flushed.map_err(Into::into)),
+                    Poll::Pending => Poll::Pending,
+                };
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::task::{Context as TaskContext, Poll};
+
+    use futures::executor::block_on;
+
+    use super::{render, Sink, BUFFER};
+    use {compile_str, MapBuilder, RenderOptions};
+
+    // Takes a few bytes a write, and makes the render wait before each one.
+    #[derive(Default)]
+    struct Slow {
+        out: Vec<u8>,
+        ready: bool,
+        written: Arc<AtomicUsize>,
+    }
+
+    impl Sink for &mut Slow {
+        fn poll_write(&mut self, cx: &mut TaskContext, buf: &[u8]) -> Poll<io::Result<usize>> {
+            self.ready = !self.ready;
+            if !self.ready {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            let n = buf.len().min(100);
+            self.out.extend_from_slice(&buf[..n]);
+            self.written.fetch_add(n, Ordering::SeqCst);
+            Poll::Ready(Ok(n))
+        }
+
+        fn poll_flush(&mut self, _: &mut TaskContext) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[test]
+    fn backpressure() {
+        let mut sink = Slow::default();
+        let written = sink.written.clone();
+
+        // Every row is 10 bytes, and sees how far ahead of the writer the
+        // render is.
+        let mut rows = 0;
+        let template = compile_str("{{#rows}}{{#ahead}}{{/ahead}}{{.}}{{/rows}}").unwrap();
+        let data = MapBuilder::new()
+            .insert_vec("rows", |mut v| {
+                for i in 0..5000 {
+                    v = v.push_str(format!("row {:06}", i));
+                }
+                v
+            })
+            .insert_fn("ahead", move |_| {
+                assert!(rows * 10 - written.load(Ordering::SeqCst) <= BUFFER);
+                rows += 1;
+                String::new()
+            })
+            .build();
+        let options = RenderOptions::default();
+
+        block_on(render(&template, &mut sink, &data, &options)).unwrap();
+        assert_eq!(sink.out.len(), 50000);
+        assert!(sink.out.starts_with(b"row 000000row 000001"));
+    }
+
+    #[test]
+    fn failed_render() {
+        let template = compile_str("x{{#a}}{{#b}}x{{/b}}{{/a}}").unwrap();
+        let data = MapBuilder::new()
+            .insert_map("a", |m| m.insert_try_fn("b", |_| Err("no")))
+            .build();
+        let options = RenderOptions::default();
+
+        let mut sink = Slow::default();
+        assert!(block_on(render(&template, &mut sink, &data, &options)).is_err());
+        assert!(sink.out.is_empty());
+    }
+
+}
@@ -4,7 +4,7 @@ use std::collections::HashMap;
 use serde::Serialize;
 
 use encoder::Error;
-use super::{Data, to_data};
+use super::{Data, DataIter, LambdaError, Scope, to_data};
 
 /// `MapBuilder` is a helper type that construct `Data` types.
 #[derive(Default)]
@@ -37,7 +37,30 @@ impl MapBuilder {
         let MapBuilder { mut data } = self;
         let value = to_data(value)?;
         data.insert(key.into(), value);
-        Ok(MapBuilder { data: data })
+        Ok(MapBuilder { data })
+    }
+
+    /// Add an `Encodable` to the `MapBuilder` if there is one, leaving the
+    /// key out for `None`.
+    ///
+    /// ```rust
+    /// use mustache::MapBuilder;
+    /// let nick: Option<String> = None;
+    /// let data = MapBuilder::new()
+    ///     .insert_opt("name", Some(&"Jane Austen")).expect("Failed to encode name")
+    ///     .insert_opt("nick", nick.as_ref()).expect("Failed to encode nick")
+    ///     .build();
+    /// ```
+    #[inline]
+    pub fn insert_opt<K, T>(self, key: K, value: Option<&T>) -> Result<MapBuilder, Error>
+    where
+        K: Into<String>,
+        T: Serialize,
+    {
+        match value {
+            Some(value) => self.insert(key, value),
+            None => Ok(self),
+        }
     }
 
     /// Add a `String` to the `MapBuilder`.
@@ -56,7 +79,7 @@ impl MapBuilder {
     {
         let MapBuilder { mut data } = self;
         data.insert(key.into(), Data::String(value.into()));
-        MapBuilder { data: data }
+        MapBuilder { data }
     }
 
     /// Add a `bool` to the `MapBuilder`.
@@ -74,7 +97,26 @@ impl MapBuilder {
     {
         let MapBuilder { mut data } = self;
         data.insert(key.into(), Data::Bool(value));
-        MapBuilder { data: data }
+        MapBuilder { data }
+    }
+
+    /// Add bytes to the `MapBuilder`.
+    ///
+    /// ```rust
+    /// use mustache::MapBuilder;
+    /// let data = MapBuilder::new()
+    ///     .insert_bytes("avatar", vec![0x89, b'P', b'N', b'G'])
+    ///     .build();
+    /// ```
+    #[inline]
+    pub fn insert_bytes<K, V>(self, key: K, value: V) -> MapBuilder
+    where
+        K: Into<String>,
+        V: Into<Vec<u8>>,
+    {
+        let MapBuilder { mut data } = self;
+        data.insert(key.into(), Data::Bytes(value.into()));
+        MapBuilder { data }
     }
 
     /// Add a `Vec` to the `MapBuilder`.
@@ -98,7 +140,7 @@ impl MapBuilder {
         let MapBuilder { mut data } = self;
         let builder = f(VecBuilder::new());
         data.insert(key.into(), builder.build());
-        MapBuilder { data: data }
+        MapBuilder { data }
     }
 
     /// Add a `Map` to the `MapBuilder`.
@@ -127,7 +169,7 @@ impl MapBuilder {
         let MapBuilder { mut data } = self;
         let builder = f(MapBuilder::new());
         data.insert(key.into(), builder.build());
-        MapBuilder { data: data }
+        MapBuilder { data }
     }
 
     /// Add a function to the `MapBuilder`.
@@ -148,7 +190,76 @@ impl MapBuilder {
     {
         let MapBuilder { mut data } = self;
         data.insert(key.to_string(), Data::Fun(RefCell::new(Box::new(f))));
-        MapBuilder { data: data }
+        MapBuilder { data }
+    }
+
+    /// Add a function that may fail to the `MapBuilder`. An error stops the
+    /// render with `Error::Lambda`.
+    ///
+    /// ```rust
+    /// use mustache::{Error, MapBuilder};
+    /// let data = MapBuilder::new()
+    ///     .insert_try_fn("price", |_| "12.5".parse::<f64>().map(|p| format!("{:.2}", p)))
+    ///     .build();
+    ///
+    /// let template = mustache::compile_str("{{price}}").unwrap();
+    /// assert_eq!(template.render_data_to_string(&data).unwrap(), "12.50");
+    /// ```
+    #[inline]
+    pub fn insert_try_fn<K: ToString, F, E>(self, key: K, mut f: F) -> MapBuilder
+    where F: FnMut(String) -> Result<String, E> + Send + 'static,
+          E: Into<LambdaError>,
+    {
+        let MapBuilder { mut data } = self;
+        let f = move |s| f(s).map_err(Into::into);
+        data.insert(key.to_string(), Data::TryFun(RefCell::new(Box::new(f))));
+        MapBuilder { data }
+    }
+
+    /// Add a helper to the `MapBuilder`, given its section's rendered content
+    /// and a `Scope` to look up the rest of the context with.
+    ///
+    /// ```rust
+    /// use mustache::{Data, MapBuilder};
+    /// let data = MapBuilder::new()
+    ///     .insert_str("unit", "kg")
+    ///     .insert_helper("weigh", |content, scope| match scope.lookup("unit") {
+    ///         Some(Data::String(unit)) => format!("{} {}", content.trim(), unit),
+    ///         _ => content,
+    ///     })
+    ///     .build();
+    ///
+    /// let template = mustache::compile_str("{{#weigh}}12{{/weigh}}").unwrap();
+    /// assert_eq!(template.render_data_to_string(&data).unwrap(), "12 kg");
+    /// ```
+    #[inline]
+    pub fn insert_helper<K: ToString, F>(self, key: K, f: F) -> MapBuilder
+    where F: FnMut(String, &Scope) -> String + Send + 'static
+    {
+        let MapBuilder { mut data } = self;
+        data.insert(key.to_string(), Data::Helper(RefCell::new(Box::new(f))));
+        MapBuilder { data }
+    }
+
+    /// Add a list whose items are made as a section goes through it, see
+    /// `Data::Iter`.
+    ///
+    /// ```rust
+    /// use mustache::{Data, MapBuilder};
+    /// let template = mustache::compile_str("{{#rows}}{{.}},{{/rows}}").unwrap();
+    /// let data = MapBuilder::new()
+    ///     .insert_iter("rows", (1..4).map(|i| Data::String(i.to_string())))
+    ///     .build();
+    /// assert_eq!(template.render_data_to_string(&data).unwrap(), "1,2,3,");
+    /// ```
+    #[inline]
+    pub fn insert_iter<K: ToString, I>(self, key: K, items: I) -> MapBuilder
+    where I: IntoIterator<Item = Data>,
+          I::IntoIter: Send + 'static,
+    {
+        let MapBuilder { mut data } = self;
+        data.insert(key.to_string(), iter(items));
+        MapBuilder { data }
     }
 
     /// Return the built `Data`.
@@ -184,7 +295,30 @@ impl VecBuilder {
         let VecBuilder { mut data } = self;
         let value = to_data(value)?;
         data.push(value);
-        Ok(VecBuilder { data: data })
+        Ok(VecBuilder { data })
+    }
+
+    /// Add every `Encodable` of `items` to the `VecBuilder`.
+    ///
+    /// ```rust
+    /// use mustache::VecBuilder;
+    /// let names = vec!["Jane Austen", "Lewis Carroll"];
+    /// let data = VecBuilder::new()
+    ///     .extend(&names).expect("Failed to encode names")
+    ///     .push_str("Mary Shelley")
+    ///     .build();
+    /// ```
+    #[inline]
+    pub fn extend<I>(self, items: I) -> Result<VecBuilder, Error>
+    where
+        I: IntoIterator,
+        I::Item: Serialize,
+    {
+        let VecBuilder { mut data } = self;
+        for item in items {
+            data.push(to_data(item)?);
+        }
+        Ok(VecBuilder { data })
     }
 
     /// Add a `String` to the `VecBuilder`.
@@ -200,7 +334,7 @@ impl VecBuilder {
     pub fn push_str<T: ToString>(self, value: T) -> VecBuilder {
         let VecBuilder { mut data } = self;
         data.push(Data::String(value.to_string()));
-        VecBuilder { data: data }
+        VecBuilder { data }
     }
 
     /// Add a `bool` to the `VecBuilder`.
@@ -216,7 +350,22 @@ impl VecBuilder {
     pub fn push_bool(self, value: bool) -> VecBuilder {
         let VecBuilder { mut data } = self;
         data.push(Data::Bool(value));
-        VecBuilder { data: data }
+        VecBuilder { data }
+    }
+
+    /// Add bytes to the `VecBuilder`.
+    ///
+    /// ```rust
+    /// use mustache::VecBuilder;
+    /// let data = VecBuilder::new()
+    ///     .push_bytes(&b"header"[..])
+    ///     .build();
+    /// ```
+    #[inline]
+    pub fn push_bytes<T: Into<Vec<u8>>>(self, value: T) -> VecBuilder {
+        let VecBuilder { mut data } = self;
+        data.push(Data::Bytes(value.into()));
+        VecBuilder { data }
     }
 
     /// Add a `Vec` to the `MapBuilder`.
@@ -238,7 +387,7 @@ impl VecBuilder {
         let VecBuilder { mut data } = self;
         let builder = f(VecBuilder::new());
         data.push(builder.build());
-        VecBuilder { data: data }
+        VecBuilder { data }
     }
 
     /// Add a `Map` to the `VecBuilder`.
@@ -265,7 +414,7 @@ impl VecBuilder {
         let VecBuilder { mut data } = self;
         let builder = f(MapBuilder::new());
         data.push(builder.build());
-        VecBuilder { data: data }
+        VecBuilder { data }
     }
 
     /// Add a function to the `VecBuilder`.
@@ -286,7 +435,42 @@ impl VecBuilder {
     {
         let VecBuilder { mut data } = self;
         data.push(Data::Fun(RefCell::new(Box::new(f))));
-        VecBuilder { data: data }
+        VecBuilder { data }
+    }
+
+    /// Add a function that may fail to the `VecBuilder`. An error stops the
+    /// render with `Error::Lambda`.
+    #[inline]
+    pub fn push_try_fn<F, E>(self, mut f: F) -> VecBuilder
+    where F: FnMut(String) -> Result<String, E> + Send + 'static,
+          E: Into<LambdaError>,
+    {
+        let VecBuilder { mut data } = self;
+        let f = move |s| f(s).map_err(Into::into);
+        data.push(Data::TryFun(RefCell::new(Box::new(f))));
+        VecBuilder { data }
+    }
+
+    /// Add a helper to the `VecBuilder`, see `MapBuilder::insert_helper`.
+    #[inline]
+    pub fn push_helper<F>(self, f: F) -> VecBuilder
+    where F: FnMut(String, &Scope) -> String + Send + 'static
+    {
+        let VecBuilder { mut data } = self;
+        data.push(Data::Helper(RefCell::new(Box::new(f))));
+        VecBuilder { data }
+    }
+
+    /// Add a list whose items are made as a section goes through it, see
+    /// `MapBuilder::insert_iter`.
+    #[inline]
+    pub fn push_iter<I>(self, items: I) -> VecBuilder
+    where I: IntoIterator<Item = Data>,
+          I::IntoIter: Send + 'static,
+    {
+        let VecBuilder { mut data } = self;
+        data.push(iter(items));
+        VecBuilder { data }
     }
 
     #[inline]
@@ -294,3 +478,11 @@ impl VecBuilder {
         Data::Vec(self.data)
     }
 }
+
+fn iter<I>(items: I) -> Data
+where I: IntoIterator<Item = Data>,
+      I::IntoIter: Send + 'static,
+{
+    let items: Box<DataIter> = Box::new(items.into_iter());
+    Data::Iter(Box::new(RefCell::new(items.peekable())))
+}
@@ -0,0 +1,536 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Arc;
+use std::time::Instant;
+
+use log::{log, warn};
+
+use compiler::{self, PartialsMap};
+use metrics::Metrics;
+use parser::{Bounds, CompareOp, Comparison, Operand, Token};
+use template::Fnv;
+use {Context, PartialLoader, Result, Template};
+
+// Starts every cache file. Bump it when the format changes.
+const MAGIC: &[u8] = b"mustache-cache-1\n";
+
+/// Keeps compiled templates in a directory, so a process compiling the same
+/// templates as the last one, like a CLI or a build script, can skip
+/// parsing them.
+///
+/// Templates are kept under a hash of their source and the context's
+/// [`extensions`](struct.Context.html#structfield.extensions). The partials
+/// they were compiled with are kept along with a hash of their sources, and
+/// a template is compiled again when one of those sources changed. Files
+/// that can't be read, or were written by another version of the crate,
+/// count as missing.
+///
+/// Failing to write a file only logs a warning, the template is still
+/// returned.
+///
+/// ```rust
+/// use std::path::PathBuf;
+/// use mustache::{CompileCache, Context};
+///
+/// let ctx = Context::new(PathBuf::from("."));
+/// let cache = CompileCache::new(std::env::temp_dir().join("mustache-doc-cache"));
+///
+/// let template = cache.compile(&ctx, "Hello {{name}}").unwrap();
+/// let again = cache.compile(&ctx, "Hello {{name}}").unwrap();
+/// assert_eq!(template.fingerprint(), again.fingerprint());
+/// ```
+#[derive(Clone, Debug)]
+pub struct CompileCache {
+    dir: PathBuf,
+}
+
+impl CompileCache {
+    /// A cache in `dir`, which is created when the first template is
+    /// written to it.
+    pub fn new<T: Into<PathBuf>>(dir: T) -> CompileCache {
+        CompileCache { dir: dir.into() }
+    }
+
+    /// The directory the templates are kept in.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Compiles `source` with `ctx`, or reads it back compiled if it was
+    /// before.
+    pub fn compile<P: PartialLoader>(&self, ctx: &Context<P>, source: &str) -> Result<Template<P>> {
+        self.compile_named(ctx, None, source)
+    }
+
+    fn compile_named<P: PartialLoader>(&self, ctx: &Context<P>, name: Option<String>, source: &str) -> Result<Template<P>> {
+        let path = self.path(ctx, source);
+
+        let started = Instant::now();
+        let cached = fs::read(&path).ok().and_then(|bytes| read(&bytes, &ctx.partial_loader));
+        if let Some(Metrics(ref sink)) = ctx.metrics {
+            sink.cache_lookup("compile", name.as_ref().map_or("", |name| &name[..]), cached.is_some());
+        }
+        if let Some((tokens, partials)) = cached {
+            return Ok(ctx.template(tokens, partials));
+        }
+
+        let (tokens, partials) = compiler::Compiler::new(ctx, source.chars()).named(name.clone()).compile()?;
+        if let Some(Metrics(ref sink)) = ctx.metrics {
+            sink.compiled(name.as_deref(), started.elapsed());
+        }
+        if let Err(err) = self.write(&path, ctx, &tokens, &partials) {
+            warn!("could not write {} to the compile cache: {}", path.display(), err);
+        }
+
+        Ok(ctx.template(tokens, partials))
+    }
+
+    /// Compiles the template the context's loader loads for `path`, like
+    /// [`Context::compile_path`](struct.Context.html#method.compile_path).
+    pub fn compile_path<P: PartialLoader>(&self, ctx: &Context<P>, path: impl AsRef<Path>) -> Result<Template<P>> {
+        let path = path.as_ref();
+        let source = ctx.partial_loader.load_str(path)?;
+        self.compile_named(ctx, Some(path.display().to_string()), &source)
+    }
+
+    /// Removes every file kept in the directory.
+    pub fn clear(&self) -> Result<()> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(_) => return Ok(()),
+        };
+
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().and_then(OsStr::to_str) == Some("bin") {
+                fs::remove_file(path)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn path<P: PartialLoader>(&self, ctx: &Context<P>, source: &str) -> PathBuf {
+        let mut hash = Fnv::new();
+        hash.bytes(MAGIC);
+        hash.str(env!("CARGO_PKG_VERSION"));
+        // Every extension and limit is in the `Debug` output, new ones
+        // included. A template compiled within looser limits, or keeping the
+        // front matter of its partials, isn't one for this context.
+        hash.str(&format!("{:?}", ctx.extensions));
+        hash.str(&format!("{:?}", ctx.limits));
+        hash.bytes(&[ctx.front_matter as u8]);
+        hash.str(source);
+
+        self.dir.join(format!("{:016x}.bin", hash.0))
+    }
+
+    fn write<P: PartialLoader>(&self, path: &Path, ctx: &Context<P>, tokens: &[Token], partials: &PartialsMap) -> Result<()> {
+        let mut defined = Vec::new();
+        compiler::defined(tokens, &mut defined);
+
+        let mut out = Vec::from(MAGIC);
+        write_str(&mut out, env!("CARGO_PKG_VERSION"));
+
+        // The sources of the partials from the loader, to tell when they
+        // change.
+        let mut names: Vec<&String> = partials.keys().collect();
+        names.sort();
+        let loaded: Vec<&&String> = names.iter().filter(|name| !defined.contains(name)).collect();
+        write_len(&mut out, loaded.len());
+        for name in loaded {
+            write_str(&mut out, name);
+            out.extend_from_slice(&source_hash(&ctx.partial_loader.load_str(name)?).to_le_bytes());
+        }
+
+        write_tokens(&mut out, tokens);
+        write_len(&mut out, names.len());
+        for name in names {
+            write_str(&mut out, name);
+            write_tokens(&mut out, &partials[name]);
+        }
+
+        // Written next to it then moved in place, so other processes never
+        // see half a file.
+        fs::create_dir_all(&self.dir)?;
+        let tmp = path.with_extension(format!("{}.tmp", process::id()));
+        fs::write(&tmp, &out)?;
+        fs::rename(&tmp, path).map_err(|err| {
+            let _ = fs::remove_file(&tmp);
+            err.into()
+        })
+    }
+}
+
+fn source_hash(source: &str) -> u64 {
+    let mut hash = Fnv::new();
+    hash.str(source);
+    hash.0
+}
+
+fn write_len(out: &mut Vec<u8>, len: usize) {
+    out.extend_from_slice(&(len as u64).to_le_bytes());
+}
+
+fn write_str(out: &mut Vec<u8>, s: &str) {
+    write_len(out, s.len());
+    out.extend_from_slice(s.as_bytes());
+}
+
+fn write_strs(out: &mut Vec<u8>, strs: &[String]) {
+    write_len(out, strs.len());
+    for s in strs {
+        write_str(out, s);
+    }
+}
+
+// The same tags as `Fnv::tokens`.
+fn write_operand(out: &mut Vec<u8>, operand: &Operand) {
+    match *operand {
+        Operand::Name(ref path) => {
+            out.push(0);
+            write_strs(out, path);
+        }
+        Operand::Text(ref text) => {
+            out.push(1);
+            write_str(out, text);
+        }
+        Operand::Number(ref number) => {
+            out.push(2);
+            write_str(out, number);
+        }
+    }
+}
+
+fn write_tokens(out: &mut Vec<u8>, tokens: &[Token]) {
+    write_len(out, tokens.len());
+
+    for token in tokens {
+        match *token {
+            Token::Text(ref text) => {
+                out.push(0);
+                write_str(out, text);
+            }
+            Token::EscapedTag(ref path, ref src) => {
+                out.push(1);
+                write_strs(out, path);
+                write_str(out, src);
+            }
+            Token::UnescapedTag(ref path, ref src) => {
+                out.push(2);
+                write_strs(out, path);
+                write_str(out, src);
+            }
+            Token::PointerTag(ref pointer, ref src) => {
+                out.push(3);
+                write_strs(out, pointer);
+                write_str(out, src);
+            }
+            Token::FallbackTag(ref paths, escaped, ref src) => {
+                out.extend_from_slice(&[12, escaped as u8]);
+                write_len(out, paths.len());
+                for path in paths {
+                    write_strs(out, path);
+                }
+                write_str(out, src);
+            }
+            Token::Section(ref path, inverted, ref children, ref otag, ref osection, ref src, ref tag, ref ctag) => {
+                out.extend_from_slice(&[4, inverted as u8]);
+                write_strs(out, path);
+                write_tokens(out, children);
+                for s in &[otag, osection, src, tag, ctag] {
+                    write_str(out, s);
+                }
+            }
+            Token::Condition(ref comparison, inverted, ref children, ref otag, ref osection, ref src, ref tag, ref ctag) => {
+                out.extend_from_slice(&[13, inverted as u8, comparison.op as u8]);
+                write_operand(out, &comparison.left);
+                write_operand(out, &comparison.right);
+                write_tokens(out, children);
+                for s in &[otag, osection, src, tag, ctag] {
+                    write_str(out, s);
+                }
+            }
+            Token::Range(ref bounds, inverted, ref children, ref otag, ref osection, ref src, ref tag, ref ctag) => {
+                out.extend_from_slice(&[14, inverted as u8]);
+                write_operand(out, &bounds.start);
+                write_operand(out, &bounds.end);
+                write_tokens(out, children);
+                for s in &[otag, osection, src, tag, ctag] {
+                    write_str(out, s);
+                }
+            }
+            Token::Partial(ref name, ref indent, ref src) => {
+                out.push(5);
+                for s in &[name, indent, src] {
+                    write_str(out, s);
+                }
+            }
+            Token::PartialWithFallback(ref name, ref indent, ref children, ref otag, ref src, ref ctag) => {
+                out.push(6);
+                write_str(out, name);
+                write_str(out, indent);
+                write_tokens(out, children);
+                for s in &[otag, src, ctag] {
+                    write_str(out, s);
+                }
+            }
+            Token::Definition(ref name, ref children, ref otag, ref src, ref ctag) => {
+                out.push(7);
+                write_str(out, name);
+                write_tokens(out, children);
+                for s in &[otag, src, ctag] {
+                    write_str(out, s);
+                }
+            }
+            Token::IncompleteSection(ref path, inverted, ref src, standalone) => {
+                out.extend_from_slice(&[8, inverted as u8, standalone as u8]);
+                write_strs(out, path);
+                write_str(out, src);
+            }
+            Token::IncompleteDefinition(ref name, ref src, standalone) => {
+                out.extend_from_slice(&[11, standalone as u8]);
+                write_str(out, name);
+                write_str(out, src);
+            }
+            Token::Raw(ref text, ref src) => {
+                out.push(9);
+                write_str(out, text);
+                write_str(out, src);
+            }
+            Token::Deprecated(ref message) => {
+                out.push(10);
+                write_str(out, message);
+            }
+        }
+    }
+}
+
+// Reads back what `CompileCache::write` wrote, if it's whole and the
+// partials' sources are the same.
+fn read<P: PartialLoader>(bytes: &[u8], loader: &P) -> Option<(Vec<Token>, PartialsMap)> {
+    let mut reader = Reader { bytes };
+    if reader.take(MAGIC.len())? != MAGIC || reader.str()? != env!("CARGO_PKG_VERSION") {
+        return None;
+    }
+
+    for _ in 0..reader.len()? {
+        let name = reader.str()?;
+        let hash = reader.u64()?;
+        match loader.load_str(&name) {
+            Ok(ref source) if source_hash(source) == hash => {}
+            _ => return None,
+        }
+    }
+
+    let tokens = reader.tokens()?;
+    let mut partials = PartialsMap::new();
+    for _ in 0..reader.len()? {
+        let name = reader.str()?;
+        partials.insert(name, Arc::new(reader.tokens()?));
+    }
+
+    if !reader.bytes.is_empty() {
+        return None;
+    }
+    Some((tokens, partials))
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if len > self.bytes.len() {
+            return None;
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Some(taken)
+    }
+
+    fn byte(&mut self) -> Option<u8> {
+        self.take(1).map(|bytes| bytes[0])
+    }
+
+    fn bool(&mut self) -> Option<bool> {
+        match self.byte()? {
+            0 => Some(false),
+            1 => Some(true),
+            _ => None,
+        }
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Some(u64::from_le_bytes(bytes))
+    }
+
+    fn len(&mut self) -> Option<usize> {
+        let len = self.u64()?;
+        // Every item takes a byte at least, more would be a corrupt file.
+        if len > self.bytes.len() as u64 {
+            return None;
+        }
+        Some(len as usize)
+    }
+
+    fn str(&mut self) -> Option<String> {
+        let len = self.len()?;
+        String::from_utf8(self.take(len)?.to_vec()).ok()
+    }
+
+    fn strs(&mut self) -> Option<Vec<String>> {
+        (0..self.len()?).map(|_| self.str()).collect()
+    }
+
+    fn operand(&mut self) -> Option<Operand> {
+        Some(match self.byte()? {
+            0 => Operand::Name(self.strs()?),
+            1 => Operand::Text(self.str()?),
+            2 => Operand::Number(self.str()?),
+            _ => return None,
+        })
+    }
+
+    fn tokens(&mut self) -> Option<Vec<Token>> {
+        (0..self.len()?).map(|_| self.token()).collect()
+    }
+
+    fn token(&mut self) -> Option<Token> {
+        Some(match self.byte()? {
+            0 => Token::Text(self.str()?),
+            1 => Token::EscapedTag(self.strs()?, self.str()?),
+            2 => Token::UnescapedTag(self.strs()?, self.str()?),
+            3 => Token::PointerTag(self.strs()?, self.str()?),
+            4 => {
+                let inverted = self.bool()?;
+                let path = self.strs()?;
+                let children = self.tokens()?;
+                Token::Section(path, inverted, children, self.str()?, self.str()?, self.str()?, self.str()?, self.str()?)
+            }
+            5 => Token::Partial(self.str()?, self.str()?, self.str()?),
+            6 => {
+                let (name, indent) = (self.str()?, self.str()?);
+                let children = self.tokens()?;
+                Token::PartialWithFallback(name, indent, children, self.str()?, self.str()?, self.str()?)
+            }
+            7 => {
+                let name = self.str()?;
+                let children = self.tokens()?;
+                Token::Definition(name, children, self.str()?, self.str()?, self.str()?)
+            }
+            8 => {
+                let (inverted, standalone) = (self.bool()?, self.bool()?);
+                Token::IncompleteSection(self.strs()?, inverted, self.str()?, standalone)
+            }
+            9 => Token::Raw(self.str()?, self.str()?),
+            10 => Token::Deprecated(self.str()?),
+            11 => {
+                let standalone = self.bool()?;
+                Token::IncompleteDefinition(self.str()?, self.str()?, standalone)
+            }
+            12 => {
+                let escaped = self.bool()?;
+                let paths = (0..self.len()?).map(|_| self.strs()).collect::<Option<_>>()?;
+                Token::FallbackTag(paths, escaped, self.str()?)
+            }
+            13 => {
+                let inverted = self.bool()?;
+                let op = match self.byte()? {
+                    0 => CompareOp::Eq,
+                    1 => CompareOp::Ne,
+                    2 => CompareOp::Lt,
+                    3 => CompareOp::Le,
+                    4 => CompareOp::Gt,
+                    5 => CompareOp::Ge,
+                    _ => return None,
+                };
+                let comparison = Comparison { left: self.operand()?, op, right: self.operand()? };
+                let children = self.tokens()?;
+                Token::Condition(comparison, inverted, children, self.str()?, self.str()?, self.str()?, self.str()?, self.str()?)
+            }
+            14 => {
+                let inverted = self.bool()?;
+                let bounds = Bounds { start: self.operand()?, end: self.operand()? };
+                let children = self.tokens()?;
+                Token::Range(bounds, inverted, children, self.str()?, self.str()?, self.str()?, self.str()?, self.str()?)
+            }
+            _ => return None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::fs;
+    use std::sync::{Arc, Mutex};
+
+    use tempdir::TempDir;
+
+    use super::CompileCache;
+    use {Context, Error, KvLoader};
+
+    #[test]
+    fn reused() {
+        let dir = TempDir::new("cache").unwrap();
+        let cache = CompileCache::new(dir.path().join("templates"));
+
+        let partials = Arc::new(Mutex::new(HashMap::new()));
+        partials.lock().unwrap().insert("user".to_string(), "<b>{{name}}</b>".to_string());
+        let loaded = partials.clone();
+        let mut ctx = Context::with_loader(KvLoader::new(move |key| Ok(loaded.lock().unwrap().get(key).cloned())));
+        ctx.extensions.inline_partials = true;
+
+        let source = "{{<*row}}[{{>user}}]{{/row}}{{#users}}{{>row}}{{/users}}";
+        let fresh = cache.compile(&ctx, source).unwrap();
+        let files = || fs::read_dir(cache.dir()).unwrap().count();
+        assert_eq!(files(), 1);
+
+        // Read back, the same as compiled.
+        let cached = cache.compile(&ctx, source).unwrap();
+        assert_eq!(cached.tokens, fresh.tokens);
+        assert_eq!(cached.partials, fresh.partials);
+        assert_eq!(files(), 1);
+
+        // Other extensions compile it again, into another file.
+        ctx.extensions.inline_partials = false;
+        cache.compile(&ctx, "{{>user}}").unwrap();
+        assert_eq!(files(), 2);
+        ctx.extensions.inline_partials = true;
+
+        // So does a partial that changed, replacing the file.
+        partials.lock().unwrap().insert("user".to_string(), "<i>{{name}}</i>".to_string());
+        let changed = cache.compile(&ctx, source).unwrap();
+        assert_ne!(changed.fingerprint(), fresh.fingerprint());
+        assert_eq!(cache.compile(&ctx, source).unwrap().fingerprint(), changed.fingerprint());
+        assert_eq!(files(), 2);
+
+        // And a file that isn't whole.
+        for entry in fs::read_dir(cache.dir()).unwrap() {
+            let path = entry.unwrap().path();
+            let bytes = fs::read(&path).unwrap();
+            fs::write(&path, &bytes[..bytes.len() - 1]).unwrap();
+        }
+        assert_eq!(cache.compile(&ctx, source).unwrap().fingerprint(), changed.fingerprint());
+
+        cache.clear().unwrap();
+        assert_eq!(files(), 0);
+    }
+
+    #[test]
+    fn limits() {
+        let dir = TempDir::new("cache").unwrap();
+        let cache = CompileCache::new(dir.path());
+        let mut ctx = Context::new(dir.path().to_path_buf());
+        cache.compile(&ctx, "{{#a}}{{#b}}{{/b}}{{/a}}").unwrap();
+
+        ctx.limits.max_depth = Some(1);
+        assert!(matches!(cache.compile(&ctx, "{{#a}}{{#b}}{{/b}}{{/a}}"), Err(Error::NestingTooDeep(1))));
+    }
+}
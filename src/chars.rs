@@ -0,0 +1,161 @@
+use std::io::{ErrorKind, Read};
+use std::str;
+
+use {Error, Result};
+
+const BUFFER_SIZE: usize = 8 * 1024;
+
+/// Decodes the UTF-8 read from `reader` one `char` at a time, so only a
+/// small buffer of the source is ever held in memory.
+///
+/// Iteration stops at the first read error or invalid UTF-8, which
+/// [`finish`](#method.finish) then reports.
+pub struct ReadChars<R> {
+    reader: R,
+    buf: Vec<u8>,
+    start: usize,
+    end: usize,
+    error: Option<Error>,
+}
+
+impl<R: Read> ReadChars<R> {
+    pub fn new(reader: R) -> ReadChars<R> {
+        ReadChars {
+            reader,
+            buf: vec![0; BUFFER_SIZE],
+            start: 0,
+            end: 0,
+            error: None,
+        }
+    }
+
+    /// Returns the error that stopped the iteration, if any.
+    pub fn finish(self) -> Result<()> {
+        match self.error {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    // Makes sure at least `needed` bytes are buffered, returning false if
+    // the reader ran out first.
+    fn fill(&mut self, needed: usize) -> bool {
+        if self.end - self.start >= needed {
+            return true;
+        }
+
+        self.buf.copy_within(self.start..self.end, 0);
+        self.end -= self.start;
+        self.start = 0;
+
+        while self.end < needed {
+            match self.reader.read(&mut self.buf[self.end..]) {
+                Ok(0) => return false,
+                Ok(n) => self.end += n,
+                Err(ref err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) => {
+                    self.error = Some(err.into());
+                    return false;
+                }
+            }
+        }
+
+        true
+    }
+}
+
+impl<R: Read> Iterator for ReadChars<R> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        if self.error.is_some() || !self.fill(1) {
+            return None;
+        }
+
+        let width = match self.buf[self.start] {
+            b if b < 0x80 => 1,
+            b if b >> 5 == 0b110 => 2,
+            b if b >> 4 == 0b1110 => 3,
+            b if b >> 3 == 0b11110 => 4,
+            _ => 0,
+        };
+
+        if width == 0 || !self.fill(width) {
+            if self.error.is_none() {
+                self.error = Some(Error::InvalidStr);
+            }
+            return None;
+        }
+
+        let ch = match str::from_utf8(&self.buf[self.start..self.start + width]) {
+            Ok(s) => s.chars().next(),
+            Err(_) => None,
+        };
+
+        match ch {
+            Some(ch) => {
+                self.start += width;
+                Some(ch)
+            }
+            None => {
+                self.error = Some(Error::InvalidStr);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read};
+
+    use super::ReadChars;
+    use Error;
+
+    // Hands out one byte per read, to split characters across reads.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl<'a> Read for Trickle<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.0.split_first() {
+                Some((&b, rest)) if !buf.is_empty() => {
+                    buf[0] = b;
+                    self.0 = rest;
+                    Ok(1)
+                }
+                _ => Ok(0),
+            }
+        }
+    }
+
+    #[test]
+    fn decodes_split_characters() {
+        let source = "héllo wörld ✓ 🦀";
+        let mut chars = ReadChars::new(Trickle(source.as_bytes()));
+
+        assert_eq!(chars.by_ref().collect::<String>(), source);
+        assert!(chars.finish().is_ok());
+    }
+
+    #[test]
+    fn longer_than_the_buffer() {
+        let source = "ünïcode ".repeat(2000);
+        let mut chars = ReadChars::new(source.as_bytes());
+
+        assert_eq!(chars.by_ref().collect::<String>(), source);
+        assert!(chars.finish().is_ok());
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        for bytes in [&b"ab\xffcd"[..], &b"ab\xc3"[..], &b"\xe2\x28\xa1"[..]].iter() {
+            let mut chars = ReadChars::new(*bytes);
+            chars.by_ref().count();
+
+            match chars.finish() {
+                Err(Error::InvalidStr) => {}
+                result => panic!("unexpected result for {:?}: {:?}", bytes, result),
+            }
+        }
+    }
+}
@@ -1,81 +1,216 @@
 use std::collections::HashMap;
-use std::io::ErrorKind::NotFound;
-use std::io::Read;
-use std::fs::File;
+use std::mem;
+use std::sync::Arc;
 
-use parser::{Parser, Token};
+use front_matter;
+use metrics::Metrics;
+use parser::{Extensions, Parser, Token};
 use super::{Context, PartialLoader};
+use template;
 
-use Result;
+use {Error, Result};
 
-pub type PartialsMap = HashMap<String, Vec<Token>>;
+pub type PartialsMap = HashMap<String, Arc<Vec<Token>>>;
+
+// The partials kept by `Context::cache_partials`, apart for each set of
+// settings they were compiled with, as clones of a context can change them.
+pub(crate) type PartialCache = HashMap<CompiledWith, PartialsMap>;
+
+// The settings of a context that change how a partial compiles, or whether
+// it does.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct CompiledWith {
+    extensions: Extensions,
+    limits: CompileLimits,
+    front_matter: bool,
+}
+
+impl CompiledWith {
+    pub(crate) fn of<P: PartialLoader>(ctx: &Context<P>) -> CompiledWith {
+        CompiledWith { extensions: ctx.extensions, limits: ctx.limits, front_matter: ctx.front_matter }
+    }
+}
+
+/// Bounds on what compiling a template may take, set on the
+/// [`Context`](struct.Context.html) compiling templates that can't be
+/// trusted. There are none by default, and sections never nest more than
+/// 512 deep.
+///
+/// ```rust
+/// use std::path::PathBuf;
+/// use mustache::{Context, Error};
+///
+/// let mut ctx = Context::new(PathBuf::from("."));
+/// ctx.limits.max_source_len = Some(64 << 10);
+/// ctx.limits.max_tokens = Some(10_000);
+/// ctx.limits.max_depth = Some(16);
+///
+/// let deep = "{{#a}}".repeat(17) + &"{{/a}}".repeat(17);
+/// match ctx.compile(deep) {
+///     Err(Error::NestingTooDeep(16)) => {}
+///     _ => panic!("expected the nesting to be refused"),
+/// }
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct CompileLimits {
+    /// The most bytes of source in the template, and in each of its
+    /// partials. Longer sources fail with
+    /// [`Error::SourceTooLong`](enum.Error.html#variant.SourceTooLong)
+    /// before the rest of them is parsed.
+    pub max_source_len: Option<usize>,
+    /// The most tokens, text and tags, in the template and its partials
+    /// together, failing with
+    /// [`Error::TooManyTokens`](enum.Error.html#variant.TooManyTokens).
+    pub max_tokens: Option<usize>,
+    /// How deep sections may nest in a source, failing with
+    /// [`Error::NestingTooDeep`](enum.Error.html#variant.NestingTooDeep).
+    pub max_depth: Option<usize>,
+}
 
 /// `Compiler` is a object that compiles a string into a `Vec<Token>`.
-pub struct Compiler<T, P: PartialLoader> {
-    ctx: Context<P>,
+pub struct Compiler<'a, T, P: PartialLoader + 'a> {
+    ctx: &'a Context<P>,
     reader: T,
     partials: PartialsMap,
     otag: String,
     ctag: String,
+    // What the template is called in errors, `None` when it has no name.
+    name: Option<String>,
+    // Whether the template is a partial, whose syntax errors say where in
+    // it they are.
+    partial: bool,
 }
 
-impl<T: Iterator<Item = char>, P: PartialLoader> Compiler<T, P> {
+impl<'a, T: Iterator<Item = char>, P: PartialLoader> Compiler<'a, T, P> {
     /// Construct a default compiler.
-    pub fn new(ctx: Context<P>, reader: T) -> Compiler<T, P> {
+    pub fn new(ctx: &'a Context<P>, reader: T) -> Compiler<'a, T, P> {
         Compiler {
-            ctx: ctx,
-            reader: reader,
+            ctx,
+            reader,
             partials: HashMap::new(),
             otag: "{{".to_string(),
             ctag: "}}".to_string(),
+            name: None,
+            partial: false,
         }
     }
 
     /// Construct a default compiler.
     pub fn new_with(
-        ctx: Context<P>,
+        ctx: &'a Context<P>,
         reader: T,
         partials: PartialsMap,
         otag: String,
         ctag: String
-    ) -> Compiler<T, P> {
+    ) -> Compiler<'a, T, P> {
         Compiler {
-            ctx: ctx,
-            reader: reader,
-            partials: partials,
-            otag: otag,
-            ctag: ctag,
+            ctx,
+            reader,
+            partials,
+            otag,
+            ctag,
+            name: None,
+            partial: false,
         }
     }
 
+    /// Names the template in the errors of the partials it includes.
+    pub fn named(mut self, name: Option<String>) -> Compiler<'a, T, P> {
+        self.name = name;
+        self
+    }
+
     /// Compiles a template into a series of tokens.
-    pub fn compile(mut self) -> Result<(Vec<Token>, PartialsMap)> {
-        let (tokens, partials) = {
-            let parser = Parser::new(&mut self.reader, &self.otag, &self.ctag);
-            parser.parse()?
+    pub fn compile(self) -> Result<(Vec<Token>, PartialsMap)> {
+        self.compile_counting(&mut 0)
+    }
+
+    // Compiles the template, adding its tokens and those of its partials to
+    // `counted` for `CompileLimits::max_tokens`.
+    fn compile_counting(mut self, counted: &mut usize) -> Result<(Vec<Token>, PartialsMap)> {
+        let limits = self.ctx.limits;
+        let (mut tokens, mut partials) = {
+            let mut reader = Limited { chars: &mut self.reader, left: limits.max_source_len, exceeded: false };
+            let parsed = Parser::new(&mut reader, &self.otag, &self.ctag, self.ctx.extensions).parse();
+
+            // The parser only saw the start of the source, what it made of
+            // it doesn't matter.
+            if reader.exceeded {
+                return Err(Error::SourceTooLong(limits.max_source_len.unwrap_or(0)));
+            }
+            match (parsed, &self.name) {
+                (Ok(parsed), _) => parsed,
+                (Err((source, line, column)), Some(name)) if self.partial => {
+                    return Err(Error::PartialSyntax {
+                        name: name.clone(),
+                        path: self.ctx.partial_loader.path(name),
+                        line,
+                        column,
+                        source,
+                    });
+                }
+                (Err((source, _, _)), _) => return Err(source.into()),
+            }
         };
 
+        if let Some(max) = limits.max_depth {
+            if depth(&tokens) > max {
+                return Err(Error::NestingTooDeep(max));
+            }
+        }
+        self.count(&tokens, counted)?;
+
+        // Partials defined in the template take precedence over the loader's.
+        // Those defined in a partial are only its own.
+        if let (true, Some(name)) = (self.partial, &self.name) {
+            let names = scope(&mut tokens, name);
+            for included in &mut partials {
+                if names.contains(&included.0) {
+                    included.0 = scoped(name, &included.0);
+                }
+            }
+        }
+        define(&tokens, &mut self.partials);
+
         // Compile the partials if we haven't done so already.
-        for name in partials.into_iter() {
-            if !self.partials.contains_key(&name) {
+        for (name, line) in partials.into_iter() {
+            if !self.partials.contains_key(&name) && !self.use_cached(&name, counted)? {
                 // Insert a placeholder so we don't recurse off to infinity.
-                self.partials.insert(name.to_string(), Vec::new());
+                self.partials.insert(name.to_string(), Arc::default());
+
+                let string = self.ctx.partial_loader.load_str(&name).map_err(|err| Error::PartialLoad {
+                    name: name.clone(),
+                    template: self.name.clone(),
+                    line,
+                    source: Box::new(err),
+                })?;
+                // The front matter of a partial is of no use to its includer.
+                let source = match front_matter::split(&string) {
+                    Some((_, rest)) if self.ctx.front_matter => rest,
+                    _ => &string,
+                };
 
-                let string = self.ctx.partial_loader.load(&name)?;
+                // Lend the partials to the inner compiler rather than copying
+                // them, it hands them back along with any it compiled. The
+                // partial starts over with the default delimiters, whatever
+                // this template switched to, so one compilation per name
+                // serves every includer.
                 let compiler = Compiler {
-                    ctx: self.ctx.clone(),
-                    reader: string.chars(),
-                    partials: self.partials.clone(),
+                    ctx: self.ctx,
+                    reader: source.chars(),
+                    partials: mem::take(&mut self.partials),
                     otag: "{{".to_string(),
                     ctag: "}}".to_string(),
+                    name: Some(name.clone()),
+                    partial: true,
                 };
 
-                let (tokens, subpartials) = compiler.compile()?;
-
-                // Include subpartials
-                self.partials.extend(subpartials.into_iter());
+                let (tokens, partials) = compiler.compile_counting(counted)?;
+                self.partials = partials;
 
                 // Set final compiled tokens for *this* partial
+                let tokens = Arc::new(tokens);
+                self.cache(&name, &tokens);
                 self.partials.insert(name, tokens);
             }
         }
@@ -84,6 +219,376 @@ impl<T: Iterator<Item = char>, P: PartialLoader> Compiler<T, P> {
 
         Ok((tokens, partials))
     }
+
+    // Keeps a partial from the loader in the context's cache, if it has one.
+    // Inline definitions are not kept, they belong to the template.
+    fn cache(&self, name: &str, tokens: &Arc<Vec<Token>>) {
+        if let Some(ref cache) = self.ctx.partial_cache {
+            if let Ok(mut cache) = cache.lock() {
+                cache.entry(CompiledWith::of(self.ctx)).or_default().entry(name.to_string()).or_insert_with(|| tokens.clone());
+            }
+        }
+    }
+
+    // Adds the tokens in `tokens` to `counted`, failing once there are more
+    // than the context allows.
+    fn count(&self, tokens: &[Token], counted: &mut usize) -> Result<()> {
+        if let Some(max) = self.ctx.limits.max_tokens {
+            *counted += count(tokens);
+            if *counted > max {
+                return Err(Error::TooManyTokens(max));
+            }
+        }
+        Ok(())
+    }
+
+    // Takes `name` and every partial it includes from the context's cache,
+    // if they are all there.
+    fn use_cached(&mut self, name: &str, counted: &mut usize) -> Result<bool> {
+        let hit = self.take_cached(name, counted)?;
+        if let (Some(_), Some(Metrics(ref sink))) = (&self.ctx.partial_cache, &self.ctx.metrics) {
+            sink.cache_lookup("partials", name, hit);
+        }
+        Ok(hit)
+    }
+
+    fn take_cached(&mut self, name: &str, counted: &mut usize) -> Result<bool> {
+        let cache = match self.ctx.partial_cache {
+            Some(ref cache) => match cache.lock() {
+                Ok(cache) => cache,
+                Err(_) => return Ok(false),
+            },
+            None => return Ok(false),
+        };
+        let cache = match cache.get(&CompiledWith::of(self.ctx)) {
+            Some(cache) => cache,
+            None => return Ok(false),
+        };
+
+        let mut found = PartialsMap::new();
+        let mut defined = PartialsMap::new();
+        let mut pending = vec![name.to_string()];
+
+        while let Some(name) = pending.pop() {
+            if found.contains_key(&name) || defined.contains_key(&name) || self.partials.contains_key(&name) {
+                continue;
+            }
+
+            match cache.get(&name) {
+                Some(tokens) => {
+                    included(tokens, &mut pending);
+                    define(tokens, &mut defined);
+                    found.insert(name, tokens.clone());
+                }
+                None => return Ok(false),
+            }
+        }
+
+        for tokens in found.values() {
+            self.count(tokens, counted)?;
+        }
+        self.partials.extend(found);
+        self.partials.extend(defined);
+        Ok(true)
+    }
+}
+
+// Reads `chars` up to `left` bytes of them, setting `exceeded` if there were
+// more.
+struct Limited<'a, T: 'a> {
+    chars: &'a mut T,
+    left: Option<usize>,
+    exceeded: bool,
+}
+
+impl<'a, T: Iterator<Item = char>> Iterator for Limited<'a, T> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        if let Some(ref mut left) = self.left {
+            if c.len_utf8() > *left {
+                self.exceeded = true;
+                return None;
+            }
+            *left -= c.len_utf8();
+        }
+        Some(c)
+    }
+}
+
+// The number of tokens in `tokens`, counting the ones in sections.
+fn count(tokens: &[Token]) -> usize {
+    tokens.iter().map(|token| match *token {
+        Token::Section(_, _, ref children, _, _, _, _, _) |
+        Token::Condition(_, _, ref children, _, _, _, _, _) |
+        Token::Range(_, _, ref children, _, _, _, _, _) |
+        Token::PartialWithFallback(_, _, ref children, _, _, _) |
+        Token::Definition(_, ref children, _, _, _) => 1 + count(children),
+        _ => 1,
+    }).sum()
+}
+
+// How deep sections, fallbacks and definitions nest in `tokens`.
+fn depth(tokens: &[Token]) -> usize {
+    tokens.iter().map(|token| match *token {
+        Token::Section(_, _, ref children, _, _, _, _, _) |
+        Token::Condition(_, _, ref children, _, _, _, _, _) |
+        Token::Range(_, _, ref children, _, _, _, _, _) |
+        Token::PartialWithFallback(_, _, ref children, _, _, _) |
+        Token::Definition(_, ref children, _, _, _) => 1 + depth(children),
+        _ => 0,
+    }).max().unwrap_or(0)
+}
+
+// Adds the partials defined inline in `tokens` to `partials`.
+fn define(tokens: &[Token], partials: &mut PartialsMap) {
+    for token in tokens {
+        match *token {
+            Token::Definition(ref name, ref children, _, _, _) => {
+                partials.insert(name.clone(), Arc::new(children.clone()));
+                define(children, partials);
+            }
+            Token::Section(_, _, ref children, _, _, _, _, _) |
+            Token::Condition(_, _, ref children, _, _, _, _, _) |
+            Token::Range(_, _, ref children, _, _, _, _, _) |
+            Token::PartialWithFallback(_, _, ref children, _, _, _) => define(children, partials),
+            _ => {}
+        }
+    }
+}
+
+// Gives the partials defined in the partial `partial` names of their own,
+// along with its includes of them, returning the names they had.
+fn scope(tokens: &mut [Token], partial: &str) -> Vec<String> {
+    let mut names = Vec::new();
+    defined(tokens, &mut names);
+    if !names.is_empty() {
+        rename(tokens, partial, &names);
+    }
+    names
+}
+
+// The name of the partial `name` defined in the partial `partial`.
+fn scoped(partial: &str, name: &str) -> String {
+    format!("{}#{}", partial, name)
+}
+
+fn rename(tokens: &mut [Token], partial: &str, names: &[String]) {
+    for token in tokens {
+        match *token {
+            Token::Partial(ref mut name, _, _) if names.contains(name) => *name = scoped(partial, name),
+            Token::PartialWithFallback(ref mut name, _, ref mut children, _, _, _) |
+            Token::Definition(ref mut name, ref mut children, _, _, _) => {
+                if names.contains(name) {
+                    *name = scoped(partial, name);
+                }
+                rename(children, partial, names);
+            }
+            Token::Section(_, _, ref mut children, _, _, _, _, _) |
+            Token::Condition(_, _, ref mut children, _, _, _, _, _) |
+            Token::Range(_, _, ref mut children, _, _, _, _, _) => rename(children, partial, names),
+            _ => {}
+        }
+    }
+}
+
+/// Collects the names of the partials defined inline in `tokens`.
+pub fn defined(tokens: &[Token], names: &mut Vec<String>) {
+    for token in tokens {
+        match *token {
+            Token::Definition(ref name, ref children, _, _, _) => {
+                names.push(name.clone());
+                defined(children, names);
+            }
+            Token::Section(_, _, ref children, _, _, _, _, _) |
+            Token::Condition(_, _, ref children, _, _, _, _, _) |
+            Token::Range(_, _, ref children, _, _, _, _, _) |
+            Token::PartialWithFallback(_, _, ref children, _, _, _) => defined(children, names),
+            _ => {}
+        }
+    }
+}
+
+/// Collects the names of the partials `tokens` include.
+pub fn included(tokens: &[Token], names: &mut Vec<String>) {
+    for token in tokens {
+        match *token {
+            Token::Partial(ref name, _, _) => names.push(name.clone()),
+            Token::PartialWithFallback(ref name, _, ref children, _, _, _) => {
+                names.push(name.clone());
+                included(children, names);
+            }
+            Token::Section(_, _, ref children, _, _, _, _, _) |
+            Token::Condition(_, _, ref children, _, _, _, _, _) |
+            Token::Range(_, _, ref children, _, _, _, _, _) |
+            Token::Definition(_, ref children, _, _, _) => included(children, names),
+            _ => {}
+        }
+    }
+}
+
+/// Collects the names of the variable tags in `tokens`, dotted or as JSON
+/// Pointers, leaving out `{{.}}`.
+pub fn variables(tokens: &[Token], names: &mut Vec<String>) {
+    for token in tokens {
+        match *token {
+            Token::EscapedTag(ref path, _) |
+            Token::UnescapedTag(ref path, _) if !path.is_empty() => names.push(path.join(".")),
+            Token::PointerTag(ref pointer, _) => names.push(template::join_pointer(pointer)),
+            Token::FallbackTag(ref paths, _, _) => {
+                names.extend(paths.iter().filter(|path| !path.is_empty()).map(|path| path.join(".")));
+            }
+            Token::Section(_, _, ref children, _, _, _, _, _) |
+            Token::Condition(_, _, ref children, _, _, _, _, _) |
+            Token::Range(_, _, ref children, _, _, _, _, _) |
+            Token::PartialWithFallback(_, _, ref children, _, _, _) |
+            Token::Definition(_, ref children, _, _, _) => variables(children, names),
+            _ => {}
+        }
+    }
+}
+
+/// Collects the names of the sections in `tokens`, inverted ones included,
+/// leaving out `{{#.}}`.
+pub fn sections(tokens: &[Token], names: &mut Vec<String>) {
+    for token in tokens {
+        match *token {
+            Token::Section(ref path, _, ref children, _, _, _, _, _) => {
+                if !path.is_empty() {
+                    names.push(path.join("."));
+                }
+                sections(children, names);
+            }
+            Token::Condition(_, _, ref children, _, _, _, _, _) |
+            Token::Range(_, _, ref children, _, _, _, _, _) |
+            Token::PartialWithFallback(_, _, ref children, _, _, _) |
+            Token::Definition(_, ref children, _, _, _) => sections(children, names),
+            _ => {}
+        }
+    }
+}
+
+/// The content of the first section over `path` in `tokens`, looking
+/// inside other sections too.
+pub fn section<'a>(tokens: &'a [Token], path: &[String]) -> Option<&'a [Token]> {
+    tokens.iter().filter_map(|token| match *token {
+        Token::Section(ref name, false, ref children, _, _, _, _, _) if name[..] == *path => Some(&children[..]),
+        Token::Section(_, _, ref children, _, _, _, _, _) |
+        Token::Condition(_, _, ref children, _, _, _, _, _) |
+        Token::Range(_, _, ref children, _, _, _, _, _) |
+        Token::PartialWithFallback(_, _, ref children, _, _, _) |
+        Token::Definition(_, ref children, _, _, _) => section(children, path),
+        _ => None,
+    }).next()
+}
+
+/// Replaces the includes in the template and its partials of partials that
+/// are only text, at most `max_len` bytes of it, with that text.
+pub fn inline_partials(tokens: &mut [Token], partials: &mut PartialsMap, max_len: usize) {
+    inline(tokens, partials, max_len);
+
+    let mut inlined = Vec::new();
+    for (name, tokens) in partials.iter() {
+        let mut tokens = (**tokens).clone();
+        if inline(&mut tokens, partials, max_len) {
+            inlined.push((name.clone(), Arc::new(tokens)));
+        }
+    }
+    partials.extend(inlined);
+}
+
+// Inlines the static partials `tokens` include, telling whether there were
+// any.
+fn inline(tokens: &mut [Token], partials: &PartialsMap, max_len: usize) -> bool {
+    let mut inlined = false;
+    // Whether the output is at the start of a line. The renderer only indents
+    // a partial's first line there, which is unknown at the start of the
+    // tokens.
+    let mut line_start = false;
+
+    for token in tokens.iter_mut() {
+        let text = match *token {
+            Token::Partial(ref name, ref indent, _) if indent.is_empty() || line_start => {
+                static_partial(name, partials, max_len, &mut Vec::new()).map(|text| indented(&text, indent))
+            }
+            Token::Section(_, _, ref mut children, _, _, _, _, _) |
+            Token::Condition(_, _, ref mut children, _, _, _, _, _) |
+            Token::Range(_, _, ref mut children, _, _, _, _, _) |
+            Token::PartialWithFallback(_, _, ref mut children, _, _, _) |
+            Token::Definition(_, ref mut children, _, _, _) => {
+                inlined |= inline(children, partials, max_len);
+                None
+            }
+            _ => None,
+        };
+
+        match text {
+            Some(ref text) if text.len() <= max_len => {
+                *token = Token::Text(text.clone());
+                inlined = true;
+            }
+            _ => {}
+        }
+
+        line_start = match *token {
+            Token::Text(ref s) | Token::Raw(ref s, _) => s.ends_with('\n'),
+            Token::Deprecated(_) => line_start,
+            _ => false,
+        };
+    }
+
+    inlined
+}
+
+// The text a partial renders to if it has nothing but text, and includes of
+// partials with nothing but text.
+fn static_partial<'a>(name: &'a str, partials: &'a PartialsMap, max_len: usize, including: &mut Vec<&'a str>) -> Option<String> {
+    let tokens = match partials.get(name) {
+        // An empty partial warns when it's rendered.
+        Some(tokens) if !tokens.is_empty() && !including.contains(&name) => tokens,
+        _ => return None,
+    };
+
+    including.push(name);
+    let mut text = String::new();
+    for token in tokens.iter() {
+        match *token {
+            Token::Text(ref s) | Token::Raw(ref s, _) => text.push_str(s),
+            Token::Deprecated(_) => {}
+            Token::Partial(ref name, ref indent, _) if indent.is_empty() || text.ends_with('\n') => {
+                text.push_str(&indented(&static_partial(name, partials, max_len, including)?, indent));
+            }
+            _ => return None,
+        }
+
+        if text.len() > max_len {
+            return None;
+        }
+    }
+    including.pop();
+
+    Some(text)
+}
+
+// Indents the lines of `text` but the blank ones, like the renderer does.
+fn indented(text: &str, indent: &str) -> String {
+    if indent.is_empty() {
+        return text.to_string();
+    }
+
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while !rest.is_empty() {
+        let end = rest.find('\n').map_or(rest.len(), |i| i + 1);
+        let (line, next) = rest.split_at(end);
+        if line != "\n" && line != "\r\n" {
+            out.push_str(indent);
+        }
+        out.push_str(line);
+        rest = next;
+    }
+    out
 }
 
 #[cfg(test)]
@@ -96,7 +601,7 @@ mod tests {
 
     fn compile_str(template: &str) -> Vec<Token> {
         let ctx = Context::new(PathBuf::from("."));
-        let (tokens, _) = Compiler::new(ctx, template.chars())
+        let (tokens, _) = Compiler::new(&ctx, template.chars())
                                    .compile()
                                    .expect("Failed to compile");
         tokens
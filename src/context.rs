@@ -1,18 +1,69 @@
-use template::{self, Template};
-use compiler;
-use {Result, Error};
+use template::{self, Escape, Template};
+use chars::ReadChars;
+use compiler::{self, CompileLimits, CompiledWith, PartialCache, PartialsMap};
+use dir;
+use email::{self, Email};
+use front_matter;
+use lint::{self, Lint};
+use loaders;
+use metrics::{Metrics, MetricsSink};
+#[cfg(feature = "mmap")]
+use mmap::MmapLoader;
+use multi;
+use parser::{Extensions, Token};
+use source::Source;
+use {Data, Error, Result};
 
+use log::{log, warn};
+use serde::Serialize;
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
 use std::fmt;
-use std::fs::File;
-use std::str;
-use std::io::Read;
-use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Instant;
 
 /// Represents the shared metadata needed to compile and render a mustache
 /// template.
 #[derive(Debug, Clone)]
 pub struct Context<P: PartialLoader> {
     pub partial_loader: P,
+    /// Non-standard syntax accepted when compiling, none by default.
+    pub extensions: Extensions,
+    /// How escaped tags escape their values, HTML by default.
+    pub escape: Escape,
+    /// Bounds on compiling, none by default.
+    pub limits: CompileLimits,
+    pub(crate) partial_cache: Option<Arc<Mutex<PartialCache>>>,
+    pub(crate) inline_limit: Option<usize>,
+    pub(crate) front_matter: bool,
+    pub(crate) metrics: Option<Metrics>,
+    pub(crate) shared: Shared<P>,
+}
+
+// The `Arc` a context was put in by `Context::shared`, for its templates to
+// keep. A clone can be changed, so it starts out without one.
+pub(crate) struct Shared<P: PartialLoader>(Weak<Context<P>>);
+
+impl<P: PartialLoader> Shared<P> {
+    fn none() -> Self {
+        Shared(Weak::new())
+    }
+}
+
+impl<P: PartialLoader> Clone for Shared<P> {
+    fn clone(&self) -> Self {
+        Shared::none()
+    }
+}
+
+impl<P: PartialLoader> fmt::Debug for Shared<P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Shared")
+    }
 }
 
 impl Context<DefaultLoader> {
@@ -22,6 +73,14 @@ impl Context<DefaultLoader> {
             // template_path: path.clone(),
             // template_extension: "mustache".to_string(),
             partial_loader: DefaultLoader::new(path, "mustache".to_string()),
+            extensions: Extensions::default(),
+            escape: Escape::default(),
+            limits: CompileLimits::default(),
+            partial_cache: None,
+            inline_limit: None,
+            front_matter: false,
+            metrics: None,
+            shared: Shared::none(),
         }
     }
 
@@ -29,31 +88,380 @@ impl Context<DefaultLoader> {
     pub fn with_extension(path: PathBuf, extension: String) -> Self {
         Context {
             partial_loader: DefaultLoader::new(path, extension),
+            extensions: Extensions::default(),
+            escape: Escape::default(),
+            limits: CompileLimits::default(),
+            partial_cache: None,
+            inline_limit: None,
+            front_matter: false,
+            metrics: None,
+            shared: Shared::none(),
         }
     }
+
+    /// Renders the `{name}.html.mustache` and `{name}.txt.mustache`
+    /// templates, with the loader's extension, with the same data as the
+    /// two bodies of an email. Partials are only compiled once for both.
+    /// Values in the text body are not escaped, see
+    /// [`Escape::Plain`](enum.Escape.html#variant.Plain).
+    pub fn render_email<T: Serialize>(&self, name: &str, data: &T) -> Result<Email> {
+        // Named with the extension, which the loader would replace the
+        // `.html` and `.txt` with otherwise.
+        let extension = &self.partial_loader.template_extension;
+        let html = format!("{}.html.{}", name, extension);
+        let text = format!("{}.txt.{}", name, extension);
+        email::render(self, &html, &text, data)
+    }
 }
 
 impl<P: PartialLoader> Context<P> {
     /// Configures a mustache context to use a custom loader
     pub fn with_loader(loader: P) -> Self {
         Self {
-            partial_loader: loader
+            partial_loader: loader,
+            extensions: Extensions::default(),
+            escape: Escape::default(),
+            limits: CompileLimits::default(),
+            partial_cache: None,
+            inline_limit: None,
+            front_matter: false,
+            metrics: None,
+            shared: Shared::none(),
         }
     }
 
-    /// Compiles a template from a string
-    pub fn compile<IT: Iterator<Item = char>>(&self, reader: IT) -> Result<Template<P>> {
-        let compiler = compiler::Compiler::new(self.clone(), reader);
+    /// Puts the context in an `Arc` that the templates compiled with it
+    /// share, instead of each keeping its own copy of it and its loader.
+    ///
+    /// ```
+    /// use std::path::PathBuf;
+    /// use mustache::Context;
+    ///
+    /// let ctx = Context::new(PathBuf::from(".")).shared();
+    /// let template = ctx.compile("Hello {{name}}").unwrap();
+    /// assert_eq!(template.render_to_string(&()).unwrap(), "Hello ");
+    /// ```
+    pub fn shared(self) -> Arc<Self> {
+        Arc::new_cyclic(|shared| Context { shared: Shared(shared.clone()), ..self })
+    }
+
+    /// Compiles a template from a string, its `chars()` or a reader, see
+    /// [`Source`](trait.Source.html).
+    ///
+    /// ```
+    /// use std::path::PathBuf;
+    /// use mustache::Context;
+    ///
+    /// let ctx = Context::new(PathBuf::from("."));
+    /// let name = "world".to_string();
+    ///
+    /// for template in vec![
+    ///     ctx.compile("Hello {{name}}").unwrap(),
+    ///     ctx.compile(format!("Hello {}", name)).unwrap(),
+    ///     ctx.compile(&b"Hello {{name}}"[..]).unwrap(),
+    /// ] {
+    ///     assert!(template.render_to_string(&()).unwrap().starts_with("Hello"));
+    /// }
+    /// ```
+    pub fn compile<S: Source>(&self, source: S) -> Result<Template<P>> {
+        source.compile_with(self)
+    }
+
+    pub(crate) fn compile_chars<IT: Iterator<Item = char>>(&self, reader: IT) -> Result<Template<P>> {
+        self.compile_named(None, reader)
+    }
+
+    // Compiles a template called `name` in errors.
+    pub(crate) fn compile_named<IT: Iterator<Item = char>>(&self, name: Option<String>, reader: IT) -> Result<Template<P>> {
+        let started = Instant::now();
+        let compiler = compiler::Compiler::new(self, reader).named(name.clone());
         let (tokens, partials) = compiler.compile()?;
+        if let Some(Metrics(ref sink)) = self.metrics {
+            sink.compiled(name.as_deref(), started.elapsed());
+        }
+
+        Ok(self.template(tokens, partials))
+    }
+
+    // Makes a template out of what the compiler made, fresh or from a
+    // `CompileCache`.
+    pub(crate) fn template(&self, mut tokens: Vec<Token>, mut partials: PartialsMap) -> Template<P> {
+        if let Some(max_len) = self.inline_limit {
+            compiler::inline_partials(&mut tokens, &mut partials, max_len);
+        }
+
+        let ctx = self.shared.0.upgrade().unwrap_or_else(|| Arc::new(self.clone()));
+        let template = template::new(ctx, tokens, partials);
+        for deprecation in template.deprecations() {
+            warn!("{}", deprecation);
+        }
+        template
+    }
+
+    /// Keeps every partial compiled from now on, so later templates compiled
+    /// with this context or its clones share one copy of it instead of
+    /// loading and compiling their own. Partials compiled with other
+    /// [`extensions`](#structfield.extensions) or
+    /// [`limits`](#structfield.limits), or with front matter parsed or not,
+    /// are kept apart.
+    ///
+    /// The clones made after this share the cache whatever their
+    /// [`partial_loader`](#structfield.partial_loader) is changed to, and
+    /// would get the partials loaded by another loader: to load partials
+    /// from somewhere else, clone a context before calling this on it.
+    ///
+    /// Changes to a cached partial's source are not picked up until
+    /// [`clear_partial_cache`](#method.clear_partial_cache) is called.
+    pub fn cache_partials(&mut self) {
+        if self.partial_cache.is_none() {
+            self.partial_cache = Some(Arc::default());
+        }
+    }
+
+    /// Replaces the includes of partials that are only text, at most
+    /// `max_len` bytes of it, with that text when compiling, so rendering
+    /// doesn't look them up. A partial that only includes such partials
+    /// counts as text too, and indentation is kept as if it was rendered.
+    ///
+    /// Inlined partials are no longer replaced by
+    /// [`RenderOptions::partials`](struct.RenderOptions.html#structfield.partials),
+    /// seen by the [`hook`](struct.RenderOptions.html#structfield.hook) or
+    /// counted towards
+    /// [`max_partials`](struct.RenderOptions.html#structfield.max_partials).
+    pub fn inline_static_partials(&mut self, max_len: usize) {
+        self.inline_limit = Some(max_len);
+    }
+
+    /// Takes the front matter off the files compiled from now on, templates
+    /// and partials: a block between two `---` lines at the very top of a
+    /// file, left out of the output. A template keeps its own, see
+    /// [`Template::front_matter`](struct.Template.html#method.front_matter);
+    /// that of partials is dropped.
+    ///
+    /// These are the files of [`compile_path`](#method.compile_path) and
+    /// [`compile_dir`](#method.compile_dir), and the partials of the loader.
+    /// The lines of syntax errors are counted from below the block.
+    pub fn parse_front_matter(&mut self) {
+        self.front_matter = true;
+    }
 
-        Ok(template::new(self.clone(), tokens, partials))
+    /// Tells `sink` of the compiles of this context and its clones from now
+    /// on, the renders of the templates they compile, and the lookups in the
+    /// partial cache. See [`MetricsSink`](trait.MetricsSink.html).
+    ///
+    /// Every kind of render is timed, an async one from when its future is
+    /// made until the writer is flushed.
+    pub fn record_metrics(&mut self, sink: Arc<dyn MetricsSink>) {
+        self.metrics = Some(Metrics(sink));
+    }
+
+    /// Forgets the partials kept by
+    /// [`cache_partials`](#method.cache_partials). Templates already compiled
+    /// keep theirs.
+    pub fn clear_partial_cache(&self) {
+        if let Some(ref cache) = self.partial_cache {
+            if let Ok(mut cache) = cache.lock() {
+                cache.clear();
+            }
+        }
+    }
+
+    /// Readies this context to render `template` and others that include
+    /// the same partials, failing with
+    /// [`Error::MissingPartials`](enum.Error.html#variant.MissingPartials)
+    /// if any it includes, directly or not, came out empty: the default
+    /// loader's missing files, which compiling doesn't fail on.
+    ///
+    /// With [`cache_partials`](#method.cache_partials) on, the partials go
+    /// into the cache too, so a template compiled elsewhere, as by a
+    /// [`CompileCache`](struct.CompileCache.html), spares the later ones
+    /// their loads.
+    ///
+    /// ```
+    /// use std::path::PathBuf;
+    /// use mustache::{Context, Error};
+    ///
+    /// let ctx = Context::new(PathBuf::from("tests/test-data"));
+    /// let template = ctx.compile("{{>username}} {{>no-such-partial}}").unwrap();
+    ///
+    /// match ctx.prewarm(&template) {
+    ///     Err(Error::MissingPartials(names)) => assert_eq!(names, ["no-such-partial"]),
+    ///     _ => panic!(),
+    /// }
+    /// ```
+    pub fn prewarm(&self, template: &Template<P>) -> Result<()> {
+        let names = template.partial_dependencies();
+
+        if let Some(ref cache) = self.partial_cache {
+            if let Ok(mut cache) = cache.lock() {
+                let cache = cache.entry(CompiledWith::of(&template.ctx)).or_default();
+                for name in &names {
+                    if let Some(tokens) = template.partials.get(name) {
+                        cache.entry(name.clone()).or_insert_with(|| tokens.clone());
+                    }
+                }
+            }
+        }
+
+        let missing: Vec<_> = names.into_iter()
+            .filter(|name| match template.partials.get(name) {
+                Some(tokens) => tokens.is_empty(),
+                None => true,
+            })
+            .collect();
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::MissingPartials(missing))
+        }
+    }
+
+    /// Compiles a template read from `reader`, decoding it as it goes so the
+    /// whole source never has to be in memory at once.
+    pub fn compile_reader<R: Read>(&self, reader: R) -> Result<Template<P>> {
+        self.compile_reader_named(None, reader)
+    }
+
+    pub(crate) fn compile_reader_named<R: Read>(&self, name: Option<String>, reader: R) -> Result<Template<P>> {
+        let mut chars = ReadChars::new(reader);
+        let template = self.compile_named(name, chars.by_ref());
+
+        // A failed read cuts the source short, which is the more useful error.
+        chars.finish()?;
+        template
     }
 
     /// Compiles a template from a path.
     pub fn compile_path(&self, path: impl AsRef<Path>) -> Result<Template<P>> {
-        let template = self.partial_loader.load(path)?;
+        let path = path.as_ref();
+        let template = self.partial_loader.load_str(path)?;
 
-        self.compile(template.chars())
+        self.compile_file(Some(path.display().to_string()), &template)
+    }
+
+    // Compiles the source of a file, taking its front matter off first if
+    // asked to.
+    pub(crate) fn compile_file(&self, name: Option<String>, source: &str) -> Result<Template<P>> {
+        let (yaml, source) = match front_matter::split(source) {
+            Some((yaml, rest)) if self.front_matter => (Some(yaml), rest),
+            _ => (None, source),
+        };
+
+        let mut template = self.compile_named(name, source.chars())?;
+        if let Some(yaml) = yaml {
+            // Fail on YAML that doesn't parse now rather than when it's used.
+            #[cfg(feature = "yaml")]
+            front_matter::parse::<::serde_yaml::Value>(yaml)?;
+            template.front_matter = Some(yaml.to_string());
+        }
+        Ok(template)
+    }
+
+    /// Compiles the templates at `path` and `layout` and renders the first
+    /// inside the second, as with
+    /// [`Template::render_in_layout`](struct.Template.html#method.render_in_layout).
+    pub fn render_with_layout<W, T>(&self,
+                                    wr: &mut W,
+                                    path: impl AsRef<Path>,
+                                    layout: impl AsRef<Path>,
+                                    data: &T) -> Result<()>
+    where W: Write,
+          T: Serialize,
+    {
+        let template = self.compile_path(path)?;
+        let layout = self.compile_path(layout)?;
+
+        template.render_in_layout(wr, &layout, data)
+    }
+
+    /// Compiles the templates of a source that holds several, each starting
+    /// at a `{{!-- template: name --}}` line, keyed by name: the subject and
+    /// bodies of an email, say. A template is the lines up to the next
+    /// marker, without the line break ahead of it, and the text before
+    /// the first marker is left out. Two templates of the same name fail with
+    /// [`Error::DuplicateTemplate`](enum.Error.html#variant.DuplicateTemplate).
+    ///
+    /// ```
+    /// use std::path::PathBuf;
+    /// use mustache::{Context, MapBuilder};
+    ///
+    /// let templates = Context::new(PathBuf::from(".")).compile_many("\
+    /// {{!-- template: subject --}}
+    /// Welcome, {{name}}
+    /// {{!-- template: text --}}
+    /// Hi {{name}}, thanks for signing up.
+    /// ").unwrap();
+    ///
+    /// let data = MapBuilder::new().insert_str("name", "Ann").build();
+    /// assert_eq!(templates["subject"].render_data_to_string(&data).unwrap(), "Welcome, Ann");
+    /// assert_eq!(templates["text"].render_data_to_string(&data).unwrap(), "Hi Ann, thanks for signing up.\n");
+    /// ```
+    pub fn compile_many(&self, source: &str) -> Result<BTreeMap<String, Template<P>>> {
+        multi::compile(self, None, source)
+    }
+
+    /// Loads `path` with the loader and compiles the templates in it, as
+    /// [`compile_many`](#method.compile_many) does. Errors call each one
+    /// `{path}#{name}`.
+    pub fn compile_many_path(&self, path: impl AsRef<Path>) -> Result<BTreeMap<String, Template<P>>> {
+        let path = path.as_ref();
+        let source = self.partial_loader.load_str(path)?;
+
+        multi::compile(self, Some(&path.display().to_string()), &source)
+    }
+
+    /// Compiles every file under `dir` whose path relative to it matches
+    /// `pattern`, keyed by that path. Patterns use `/` on every platform:
+    /// `?` is any character but `/`, `*` any run of them and `**/` any
+    /// number of directories, so `**/*.mustache` is every template.
+    ///
+    /// Only failing to read the directories is an error, each file gets its
+    /// own result. Symlinks are followed, but for those to a directory that
+    /// contains them.
+    ///
+    /// ```
+    /// use std::path::{Path, PathBuf};
+    /// use mustache::Context;
+    ///
+    /// let ctx = Context::new(PathBuf::from("tests/test-data"));
+    /// let templates = ctx.compile_dir("tests/test-data", "welcome.*").unwrap();
+    ///
+    /// assert_eq!(templates.keys().collect::<Vec<_>>(),
+    ///            vec![Path::new("welcome.html.mustache"), Path::new("welcome.txt.mustache")]);
+    /// assert!(templates.values().all(|template| template.is_ok()));
+    /// ```
+    pub fn compile_dir(&self, dir: impl AsRef<Path>, pattern: &str) -> Result<BTreeMap<PathBuf, Result<Template<P>>>> {
+        dir::compile(self, dir.as_ref(), pattern)
+    }
+
+    /// Does what [`compile_dir`](#method.compile_dir) does, compiling the
+    /// files on every core. Enabled with the `rayon` feature.
+    ///
+    /// Call [`cache_partials`](#method.cache_partials) first so partials
+    /// shared by the templates are only compiled once.
+    #[cfg(feature = "rayon")]
+    pub fn par_compile_dir(&self, dir: impl AsRef<Path>, pattern: &str) -> Result<BTreeMap<PathBuf, Result<Template<P>>>>
+    where P: Send + Sync
+    {
+        dir::par_compile(self, dir.as_ref(), pattern)
+    }
+
+    /// Looks for likely mistakes in a template, such as sections nested
+    /// inside themselves or inverted sections that can never render.
+    ///
+    /// If `sample` data is given, sections over names it never provides
+    /// are reported too. Fails if the template doesn't compile.
+    ///
+    /// ```rust
+    /// use mustache::{Context, Lint};
+    /// use std::path::PathBuf;
+    ///
+    /// let ctx = Context::new(PathBuf::from("."));
+    /// let lints = ctx.lint("{{#a}}{{^a}}never{{/a}}{{/a}}", None).unwrap();
+    /// assert_eq!(lints, vec![Lint::UnreachableInvertedSection("a".to_string())]);
+    /// ```
+    pub fn lint(&self, source: &str, sample: Option<&Data>) -> Result<Vec<Lint>> {
+        lint::lint(self, source, sample)
     }
 }
 
@@ -80,18 +488,165 @@ impl<P: PartialLoader> Context<P> {
 ///     }
 /// }
 /// ```
+///
+/// The loader is borrowed while compiling, and every compiled [`Template`]
+/// keeps one copy of it to compile the output of lambdas, unless the
+/// context is [`shared`](struct.Context.html#method.shared). A loader with
+/// state that should be shared by all of them can be wrapped in an [`Arc`].
+///
+/// Loaders that already hold their templates, from `include_str!` or a
+/// mapped file, can also implement [`PartialLoader::load_str`] to hand them
+/// to the compiler without copying them into a `String` first:
+///
+/// ```
+/// use mustache::{PartialLoader, Error};
+/// use std::borrow::Cow;
+/// use std::path::Path;
+///
+/// #[derive(Clone, Debug)]
+/// pub struct StaticLoader;
+///
+/// impl StaticLoader {
+///     fn get(&self, name: &Path) -> Result<&'static str, Error> {
+///         match name.to_str() {
+///             Some("user") => Ok("<strong>{{name}}</strong>"),
+///             _ => Err(Error::InvalidStr),
+///         }
+///     }
+/// }
+///
+/// impl PartialLoader for StaticLoader {
+///     fn load(&self, name: impl AsRef<Path>) -> Result<String, Error> {
+///         self.get(name.as_ref()).map(str::to_string)
+///     }
+///
+///     fn load_str(&self, name: impl AsRef<Path>) -> Result<Cow<'_, str>, Error> {
+///         self.get(name.as_ref()).map(Cow::Borrowed)
+///     }
+/// }
+/// ```
 pub trait PartialLoader: Clone {
     fn load(&self, name: impl AsRef<Path>) -> Result<String>;
+
+    /// Loads the template `name`, possibly borrowed from the loader. This is
+    /// what the compiler calls, and it defaults to [`PartialLoader::load`].
+    fn load_str(&self, name: impl AsRef<Path>) -> Result<Cow<'_, str>> {
+        self.load(name).map(Cow::Owned)
+    }
+
+    /// Where the template `name` is loaded from, such as its file, for the
+    /// errors found in it, see
+    /// [`Error::PartialSyntax`](enum.Error.html#variant.PartialSyntax).
+    /// Defaults to `None`, which leaves errors with the name alone.
+    fn path(&self, _name: impl AsRef<Path>) -> Option<PathBuf> {
+        None
+    }
+
+    /// Falls back to `other` for the names this loader fails on or finds
+    /// empty, as [`DefaultLoader`] does for missing files.
+    ///
+    /// ```
+    /// use std::path::PathBuf;
+    /// use mustache::{DefaultLoader, PartialLoader};
+    ///
+    /// let theme = DefaultLoader::new(PathBuf::from("themes/dark"), "mustache".to_string());
+    /// let base = DefaultLoader::new(PathBuf::from("tests/test-data"), "mustache".to_string());
+    /// let loader = theme.or(base);
+    ///
+    /// assert_eq!(loader.load("username").unwrap(), "{{name}}");
+    /// ```
+    fn or<L: PartialLoader>(self, other: L) -> loaders::Or<Self, L>
+    where Self: Sized
+    {
+        loaders::or(self, other)
+    }
+
+    /// Loads `f(name)` in place of every `name`.
+    fn map_name<F>(self, f: F) -> loaders::MapName<Self, F>
+    where Self: Sized,
+          F: Fn(&Path) -> PathBuf + Clone,
+    {
+        loaders::map_name(self, f)
+    }
+
+    /// Loads every name from under `prefix`, so `prefixed("emails")` loads
+    /// `emails/welcome` for `welcome`.
+    fn prefixed(self, prefix: impl Into<PathBuf>) -> loaders::Prefixed<Self>
+    where Self: Sized
+    {
+        loaders::prefixed(self, prefix.into())
+    }
+
+    /// Calls `f` after every load with the name, how long it took, whether
+    /// the source was found and its length, for metrics and logging.
+    ///
+    /// Observing the loader behind a cache such as
+    /// [`LruLoader`](struct.LruLoader.html) as well as the cache itself
+    /// gives its misses and its loads, and so its hit rate.
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    /// use mustache::{KvLoader, LoadEvent, LruLoader, PartialLoader};
+    ///
+    /// let (loads, misses) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+    /// let (l, m) = (loads.clone(), misses.clone());
+    ///
+    /// let source = KvLoader::new(|name| Ok(Some(format!("partial {}", name))));
+    /// let loader = LruLoader::new(source.observed(move |_: &LoadEvent| { m.fetch_add(1, Ordering::Relaxed); }))
+    ///     .observed(move |event: &LoadEvent| {
+    ///         l.fetch_add(1, Ordering::Relaxed);
+    ///         if event.duration.as_millis() > 100 {
+    ///             eprintln!("slow template {}", event.name.display());
+    ///         }
+    ///     });
+    ///
+    /// for _ in 0..4 {
+    ///     loader.load("header").unwrap();
+    /// }
+    /// assert_eq!((loads.load(Ordering::Relaxed), misses.load(Ordering::Relaxed)), (4, 1));
+    /// ```
+    fn observed<F>(self, f: F) -> loaders::Observed<Self, F>
+    where Self: Sized,
+          F: Fn(&loaders::LoadEvent) + Clone,
+    {
+        loaders::observed(self, f)
+    }
+}
+
+impl<L: PartialLoader> PartialLoader for Arc<L> {
+    fn load(&self, name: impl AsRef<Path>) -> Result<String> {
+        (**self).load(name)
+    }
+
+    fn load_str(&self, name: impl AsRef<Path>) -> Result<Cow<'_, str>> {
+        (**self).load_str(name)
+    }
+
+    fn path(&self, name: impl AsRef<Path>) -> Option<PathBuf> {
+        (**self).path(name)
+    }
 }
 
 /// Default [`PartialLoader`].
 ///
 /// For a given partial with `name`, loads `{template_path}/{name}.{template_extension}`.
 /// Uses `set_extension` to set the extension.
+///
+/// Only files under `template_path` are loaded, so a partial name that
+/// comes from user data can't read anything else: names that are absolute
+/// or have a `..` in them, and files that a symlink leads outside of the
+/// directory, fail with
+/// [`Error::InvalidPartialName`](enum.Error.html#variant.InvalidPartialName).
+/// [`allow_outside_root`](#method.allow_outside_root) turns the check off.
 #[derive(Clone, Debug, PartialEq)]
 pub struct DefaultLoader {
     pub template_path: PathBuf,
     pub template_extension: String,
+    allow_outside_root: bool,
+    // The template path with symlinks resolved, and the template path it was
+    // resolved from, in case that was changed since.
+    root: Option<(PathBuf, PathBuf)>,
 }
 
 impl DefaultLoader {
@@ -99,33 +654,96 @@ impl DefaultLoader {
         template_path: PathBuf,
         template_extension: String
     ) -> Self {
+        let root = fs::canonicalize(&template_path).ok().map(|root| (template_path.clone(), root));
         Self {
             template_path,
             template_extension,
+            allow_outside_root: false,
+            root,
+        }
+    }
+
+    /// Loads any path a partial name leads to, for setups where no name
+    /// comes from outside.
+    pub fn allow_outside_root(mut self) -> Self {
+        self.allow_outside_root = true;
+        self
+    }
+
+    /// Maps the files of at least `min_len` bytes into memory instead of
+    /// reading them, see [`MmapLoader`](struct.MmapLoader.html).
+    #[cfg(feature = "mmap")]
+    pub fn mmap(self, min_len: u64) -> MmapLoader {
+        MmapLoader::new(self, min_len)
+    }
+
+    // Opens the file of the partial `name`, `None` if there is none.
+    pub(crate) fn open(&self, name: &Path) -> Result<Option<File>> {
+        let outside = name.components().any(|part| !matches!(part, Component::Normal(_) | Component::CurDir));
+        if outside && !self.allow_outside_root {
+            return Err(Error::InvalidPartialName(name.display().to_string()));
+        }
+
+        let mut path = self.template_path.join(name);
+        path.set_extension(&self.template_extension);
+
+        match File::open(&path) {
+            Ok(file) => {
+                if !self.allow_outside_root {
+                    self.check_inside(name, &path)?;
+                }
+                Ok(Some(file))
+            }
+            Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // Fails unless `path`, the file for the partial `name`, is under the
+    // template path once symlinks are resolved.
+    fn check_inside(&self, name: &Path, path: &Path) -> Result<()> {
+        let root = match self.root {
+            Some((ref template_path, ref root)) if *template_path == self.template_path => Cow::Borrowed(root),
+            _ => Cow::Owned(fs::canonicalize(&self.template_path)?),
+        };
+
+        if fs::canonicalize(path)?.starts_with(&*root) {
+            Ok(())
+        } else {
+            Err(Error::InvalidPartialName(name.display().to_string()))
         }
     }
 }
 
 impl PartialLoader for DefaultLoader {
     fn load(&self, name: impl AsRef<Path>) -> Result<String> {
-        let mut path = self.template_path.join(name.as_ref());
-        path.set_extension(&self.template_extension);
-
         // FIXME(#6164): This should use the file decoding tools when they are
         // written. For now we'll just read the file and treat it as UTF-8file.
 
-        match File::open(path) {
-            Ok(mut file) => {
-                let mut string = String::new();
-                file.read_to_string(&mut string)?;
+        let mut string = String::new();
+        if let Some(mut file) = self.open(name.as_ref())? {
+            file.read_to_string(&mut string)?;
+        }
+        Ok(string)
+    }
 
-                Ok(string)
-            }
+    fn path(&self, name: impl AsRef<Path>) -> Option<PathBuf> {
+        let mut path = self.template_path.join(name);
+        path.set_extension(&self.template_extension);
+        Some(path)
+    }
+}
 
-            Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => {
-                Ok(String::new())
-            },
-            Err(e) => return Err(e.into()),
-        }
+// Appends `.{extension}` to `path` unless that's already its extension, for
+// the loaders of archives and embedded files.
+#[cfg(any(feature = "zip", feature = "tar", feature = "include_dir", feature = "rust-embed"))]
+pub(crate) fn with_extension(path: PathBuf, extension: &str) -> PathBuf {
+    if path.extension() == Some(::std::ffi::OsStr::new(extension)) {
+        return path;
     }
+
+    let mut file = path.into_os_string();
+    file.push(".");
+    file.push(extension);
+    PathBuf::from(file)
 }
@@ -0,0 +1,475 @@
+//! Translating Handlebars and Tera templates into mustache, enabled with the
+//! `convert` feature.
+//!
+//! Only the part of each language mustache can express is translated:
+//! variables, `if`/`unless`/`else`, loops over a list, `with`, partials and
+//! comments, plus whitespace control through the
+//! [`trim_markers`](../struct.Extensions.html#structfield.trim_markers)
+//! extension. Everything else is left out of the output and listed in
+//! [`Conversion::unsupported`] with its line, to be rewritten by hand.
+//!
+//! Mustache sections do a little more than the conditionals they come from:
+//! `if` on a non-empty list renders once per item, and `if` on a map makes
+//! its keys visible inside. Templates that only test flags and optional
+//! values behave the same.
+//!
+//! ```
+//! use mustache::convert;
+//!
+//! let conversion = convert::from_handlebars("{{#if user}}Hi {{this.user.name}}{{else}}Hi{{/if}} {{@index}}");
+//! assert_eq!(conversion.template, "{{#user}}Hi {{user.name}}{{/user}}{{^user}}Hi{{/user}} ");
+//! assert_eq!(conversion.unsupported[0].construct, "{{@index}}");
+//! ```
+
+use Extensions;
+
+/// A translated template, see the [module docs](index.html).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Conversion {
+    /// The mustache source.
+    pub template: String,
+    /// The extensions the source needs, to be set on the
+    /// [`Context`](../struct.Context.html) that compiles it.
+    pub extensions: Extensions,
+    /// What couldn't be translated, in order.
+    pub unsupported: Vec<Unsupported>,
+}
+
+/// A construct [`Conversion::template`] leaves out.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Unsupported {
+    /// The line it starts on, from 1.
+    pub line: usize,
+    /// As it was written.
+    pub construct: String,
+}
+
+// An open block and the section it became, if any.
+struct Block {
+    keyword: String,
+    section: Option<String>,
+    inverted: bool,
+    seen_else: bool,
+    // The name of the item in a Tera loop.
+    item: Option<String>,
+}
+
+struct Converter<'a> {
+    source: &'a str,
+    out: String,
+    extensions: Extensions,
+    unsupported: Vec<Unsupported>,
+    blocks: Vec<Block>,
+}
+
+// Where a tag is in the source, and whether it trims around itself.
+struct Tag<'s> {
+    start: usize,
+    end: usize,
+    content: &'s str,
+    trim_before: bool,
+    trim_after: bool,
+}
+
+impl<'a> Converter<'a> {
+    fn new(source: &'a str) -> Self {
+        Converter {
+            source,
+            out: String::with_capacity(source.len()),
+            extensions: Extensions::default(),
+            unsupported: Vec::new(),
+            blocks: Vec::new(),
+        }
+    }
+
+    fn unsupported(&mut self, tag: &Tag) {
+        self.unsupported.push(Unsupported {
+            line: self.source[..tag.start].matches('\n').count() + 1,
+            construct: self.source[tag.start..tag.end].to_string(),
+        });
+    }
+
+    // Writes `{{content}}`, with the trim markers of `tag` if it has any.
+    fn emit(&mut self, tag: &Tag, content: &str) {
+        self.out.push_str("{{");
+        if tag.trim_before {
+            self.extensions.trim_markers = true;
+            self.out.push_str("- ");
+        }
+        self.out.push_str(content);
+        if tag.trim_after {
+            self.extensions.trim_markers = true;
+            self.out.push_str(" -");
+        }
+        self.out.push_str("}}");
+    }
+
+    fn open(&mut self, tag: &Tag, keyword: &str, section: Option<String>, inverted: bool, item: Option<String>) {
+        match section {
+            Some(ref name) => {
+                let kind = if inverted { '^' } else { '#' };
+                self.emit(tag, &format!("{}{}", kind, name));
+            }
+            None => self.unsupported(tag),
+        }
+
+        self.blocks.push(Block {
+            keyword: keyword.to_string(),
+            section,
+            inverted,
+            seen_else: false,
+            item,
+        });
+    }
+
+    // An `else` closes the section and opens its opposite.
+    fn otherwise(&mut self, tag: &Tag) {
+        let section = match self.blocks.last_mut() {
+            Some(ref mut block) if block.section.is_some() && !block.seen_else => {
+                block.seen_else = true;
+                block.inverted = !block.inverted;
+                block.item = None;
+                (block.section.clone().unwrap_or_default(), block.inverted)
+            }
+            _ => return self.unsupported(tag),
+        };
+
+        let (name, inverted) = section;
+        let kind = if inverted { '^' } else { '#' };
+        let open = Tag { trim_before: false, ..*tag };
+        let close = Tag { trim_after: false, ..*tag };
+        self.emit(&close, &format!("/{}", name));
+        self.emit(&open, &format!("{}{}", kind, name));
+    }
+
+    fn close(&mut self, tag: &Tag, keyword: &str) {
+        match self.blocks.pop() {
+            Some(Block { keyword: ref open, section: Some(ref name), .. }) if open == keyword => {
+                self.emit(tag, &format!("/{}", name))
+            }
+            Some(Block { keyword: ref open, section: None, .. }) if open == keyword => {}
+            _ => self.unsupported(tag),
+        }
+    }
+
+    fn finish(self) -> Conversion {
+        Conversion {
+            template: self.out,
+            extensions: self.extensions,
+            unsupported: self.unsupported,
+        }
+    }
+}
+
+// Finds the next tag opening with one of `opens`, returning the text before
+// it, which opening it was and the tag itself.
+fn next_tag<'s>(source: &'s str, pos: usize, opens: &[(&str, &str)]) -> Option<(usize, usize, &'s str)> {
+    let rest = &source[pos..];
+    let (offset, &(open, close)) = opens.iter()
+        .filter_map(|delims| rest.find(delims.0).map(|i| (i, delims)))
+        .min_by_key(|&(i, &(open, _))| (i, usize::MAX - open.len()))?;
+
+    let start = pos + offset;
+    let inner = start + open.len();
+    let end = match source[inner..].find(close) {
+        Some(i) => inner + i + close.len(),
+        None => source.len(),
+    };
+    let content = if end == source.len() && !source.ends_with(close) {
+        &source[inner..]
+    } else {
+        &source[inner..end - close.len()]
+    };
+
+    Some((start, end, content))
+}
+
+// Splits off the `~` or `-` trim markers at either end of `content`.
+fn trims<'s>(start: usize, end: usize, content: &'s str, marker: char) -> Tag<'s> {
+    let trim_before = content.starts_with(marker);
+    let content = if trim_before { &content[1..] } else { content };
+    let trim_after = content.ends_with(marker);
+    let content = if trim_after { &content[..content.len() - 1] } else { content };
+
+    Tag { start, end, content: content.trim(), trim_before, trim_after }
+}
+
+fn is_path(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && !name.ends_with('.')
+        && !name.contains("..")
+        && name.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-' || c == '.' || c == '$')
+}
+
+/// Translates a Handlebars template.
+pub fn from_handlebars(source: &str) -> Conversion {
+    let mut converter = Converter::new(source);
+    let mut pos = 0;
+
+    while let Some((start, end, content)) = next_tag(source, pos, &[("{{", "}}"), ("{{{", "}}}"), ("{{!--", "--}}")]) {
+        // `\{{` is a literal `{{` mustache has no way to write.
+        if source[..start].ends_with('\\') {
+            converter.out.push_str(&source[pos..start - 1]);
+            converter.unsupported(&Tag { start: start - 1, end, content, trim_before: false, trim_after: false });
+            pos = end;
+            continue;
+        }
+
+        converter.out.push_str(&source[pos..start]);
+        pos = end;
+
+        let triple = source[start..].starts_with("{{{");
+        let long_comment = source[start..].starts_with("{{!--");
+        let tag = trims(start, end, content, '~');
+
+        if long_comment || tag.content.starts_with('!') {
+            let body = if long_comment { tag.content } else { &tag.content[1..] };
+            // A comment can't hold its own closing delimiter.
+            if !body.contains("}}") {
+                converter.emit(&tag, &format!("! {} ", body.trim()));
+            }
+        } else if triple || tag.content.starts_with('&') {
+            let name = if triple { tag.content } else { tag.content[1..].trim() };
+            match handlebars_path(name) {
+                Some(path) if triple && !tag.trim_before && !tag.trim_after => {
+                    converter.out.push_str(&format!("{{{{{{{}}}}}}}", path))
+                }
+                Some(path) => converter.emit(&tag, &format!("&{}", path)),
+                None => converter.unsupported(&tag),
+            }
+        } else if tag.content.starts_with('#') || tag.content.starts_with('^') {
+            let inverted = tag.content.starts_with('^');
+            let mut words = tag.content[1..].split_whitespace();
+            let (keyword, arg, more) = (words.next().unwrap_or(""), words.next(), words.next());
+
+            let (keyword, section, inverted) = match (keyword, arg, more) {
+                // `{{^name}}` is mustache's own inverted section.
+                (name, None, None) if inverted => (name, handlebars_path(name), true),
+                ("if", Some(arg), None) | ("each", Some(arg), None) | ("with", Some(arg), None) => {
+                    (keyword, handlebars_path(arg), inverted)
+                }
+                ("unless", Some(arg), None) => (keyword, handlebars_path(arg), !inverted),
+                _ => (keyword, None, inverted),
+            };
+            converter.open(&tag, keyword, section, inverted, None);
+        } else if tag.content.starts_with('/') {
+            converter.close(&tag, tag.content[1..].trim());
+        } else if tag.content == "else" || tag.content == "^" {
+            converter.otherwise(&tag);
+        } else if tag.content.starts_with('>') {
+            let name = tag.content[1..].trim().trim_matches(|c| c == '"' || c == '\'');
+            if name.is_empty() || name.contains(char::is_whitespace) {
+                converter.unsupported(&tag);
+            } else {
+                converter.emit(&tag, &format!(">{}", name));
+            }
+        } else {
+            match handlebars_path(tag.content) {
+                Some(path) => converter.emit(&tag, &path),
+                None => converter.unsupported(&tag),
+            }
+        }
+    }
+
+    converter.out.push_str(&source[pos..]);
+    unclosed(&mut converter);
+    converter.finish()
+}
+
+// `this.a`, `./a` and `a/b` are all plain paths, `../a` and `@index` have no
+// mustache equivalent.
+fn handlebars_path(name: &str) -> Option<String> {
+    if name == "this" || name == "." {
+        return Some(".".to_string());
+    }
+
+    let name = name.trim_start_matches("this.").trim_start_matches("./").replace('/', ".");
+    if is_path(&name) {
+        Some(name)
+    } else {
+        None
+    }
+}
+
+/// Translates a Tera template.
+pub fn from_tera(source: &str) -> Conversion {
+    let mut converter = Converter::new(source);
+    let mut pos = 0;
+
+    while let Some((start, end, content)) = next_tag(source, pos, &[("{{", "}}"), ("{%", "%}"), ("{#", "#}")]) {
+        converter.out.push_str(&source[pos..start]);
+        pos = end;
+
+        let tag = trims(start, end, content, '-');
+
+        match &source[start..start + 2] {
+            "{#" => {
+                if !tag.content.contains("}}") {
+                    converter.emit(&tag, &format!("! {} ", tag.content));
+                }
+            }
+            "{{" => {
+                let mut parts = tag.content.split('|').map(str::trim);
+                let path = parts.next().and_then(|name| tera_path(&converter.blocks, name));
+                let mut escaped = true;
+                let mut filters_ok = true;
+                for filter in parts {
+                    match filter {
+                        "safe" => escaped = false,
+                        "escape" | "e" => {}
+                        _ => filters_ok = false,
+                    }
+                }
+
+                match path {
+                    Some(ref path) if filters_ok && escaped => converter.emit(&tag, path),
+                    Some(ref path) if filters_ok => converter.emit(&tag, &format!("&{}", path)),
+                    _ => converter.unsupported(&tag),
+                }
+            }
+            _ => {
+                let words: Vec<_> = tag.content.split_whitespace().collect();
+                match &words[..] {
+                    ["if", name] => {
+                        let section = tera_path(&converter.blocks, name);
+                        converter.open(&tag, "if", section, false, None)
+                    }
+                    ["if", "not", name] => {
+                        let section = tera_path(&converter.blocks, name);
+                        converter.open(&tag, "if", section, true, None)
+                    }
+                    ["if", ..] => converter.open(&tag, "if", None, false, None),
+                    ["for", item, "in", list] => {
+                        let section = tera_path(&converter.blocks, list);
+                        converter.open(&tag, "for", section, false, Some(item.to_string()))
+                    }
+                    ["for", ..] => converter.open(&tag, "for", None, false, None),
+                    ["else"] => converter.otherwise(&tag),
+                    ["endif"] => converter.close(&tag, "if"),
+                    ["endfor"] => converter.close(&tag, "for"),
+                    ["include", name] => {
+                        let name = name.trim_matches(|c| c == '"' || c == '\'');
+                        converter.emit(&tag, &format!(">{}", name))
+                    }
+                    _ => converter.unsupported(&tag),
+                }
+            }
+        }
+    }
+
+    converter.out.push_str(&source[pos..]);
+    unclosed(&mut converter);
+    converter.finish()
+}
+
+// Inside `{% for item in items %}` the item is mustache's `.`, and the
+// items of outer loops can't be named at all.
+fn tera_path(blocks: &[Block], name: &str) -> Option<String> {
+    let mut items = blocks.iter().rev().filter_map(|block| block.item.as_ref());
+    let innermost = items.next();
+
+    if let Some(item) = innermost {
+        if name == item {
+            return Some(".".to_string());
+        }
+        if name.starts_with(item.as_str()) && name[item.len()..].starts_with('.') {
+            return Some(name[item.len() + 1..].to_string()).filter(|name| is_path(name));
+        }
+    }
+
+    let first = name.split('.').next().unwrap_or("");
+    if name == "loop" || name.starts_with("loop.") || items.any(|item| item == first) {
+        return None;
+    }
+
+    Some(name.to_string()).filter(|name| is_path(name))
+}
+
+// Blocks still open at the end are closed there, as the parser wants.
+fn unclosed(converter: &mut Converter) {
+    for block in converter.blocks.drain(..).rev() {
+        if let Some(name) = block.section {
+            converter.out.push_str(&format!("{{{{/{}}}}}", name));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{from_handlebars, from_tera, Unsupported};
+    use {compile_str, Context, MapBuilder};
+
+    fn unsupported(list: &[Unsupported]) -> Vec<(usize, &str)> {
+        list.iter().map(|u| (u.line, &u.construct[..])).collect()
+    }
+
+    #[test]
+    fn handlebars() {
+        let conversion = from_handlebars(concat!(
+            "{{!-- header --}}{{title}} {{{body}}}\n",
+            "{{#each items}}{{this}},{{else}}none{{/each}}\n",
+            "{{#unless done}}todo{{/unless}}{{#with user}}{{name}}{{/with}}\n",
+            "{{> footer}}{{~ tail ~}}\n",
+            "{{upper name}} {{../x}} {{#custom a b}}in{{/custom}}",
+        ));
+
+        assert_eq!(conversion.template, concat!(
+            "{{! header }}{{title}} {{{body}}}\n",
+            "{{#items}}{{.}},{{/items}}{{^items}}none{{/items}}\n",
+            "{{^done}}todo{{/done}}{{#user}}{{name}}{{/user}}\n",
+            "{{>footer}}{{- tail -}}\n",
+            "  in",
+        ));
+        assert!(conversion.extensions.trim_markers);
+        assert_eq!(unsupported(&conversion.unsupported), vec![
+            (5, "{{upper name}}"),
+            (5, "{{../x}}"),
+            (5, "{{#custom a b}}"),
+        ]);
+    }
+
+    #[test]
+    fn tera() {
+        let conversion = from_tera(concat!(
+            "{# note #}{{ title }} {{ body | safe }}\n",
+            "{% for item in items %}{{ item.name }}{{ item }}{% endfor %}\n",
+            "{% if not done %}todo{% else %}done{% endif %}\n",
+            "{%- include \"footer\" -%}\n",
+            "{{ name | upper }} {% for a in xs %}{% for b in a.ys %}{{ a.z }}{{ loop.index }}{% endfor %}{% endfor %}",
+        ));
+
+        assert_eq!(conversion.template, concat!(
+            "{{! note }}{{title}} {{&body}}\n",
+            "{{#items}}{{name}}{{.}}{{/items}}\n",
+            "{{^done}}todo{{/done}}{{#done}}done{{/done}}\n",
+            "{{- >footer -}}\n",
+            " {{#xs}}{{#ys}}{{/ys}}{{/xs}}",
+        ));
+        assert_eq!(unsupported(&conversion.unsupported), vec![
+            (5, "{{ name | upper }}"),
+            (5, "{{ a.z }}"),
+            (5, "{{ loop.index }}"),
+        ]);
+    }
+
+    #[test]
+    fn conversions_compile() {
+        let data = MapBuilder::new()
+            .insert_vec("items", |v| v.push_map(|m| m.insert_str("name", "a")))
+            .insert_str("who", "<b>")
+            .build();
+
+        let handlebars = from_handlebars("{{#each items}}{{name}}{{/each}} {{{who}}}");
+        let tera = from_tera("{% for i in items %}{{ i.name }}{% endfor %} {{ who | safe }}");
+
+        for conversion in &[handlebars, tera] {
+            let mut ctx = Context::new(".".into());
+            ctx.extensions = conversion.extensions;
+            let template = ctx.compile(&conversion.template).unwrap();
+            assert_eq!(template.render_data_to_string(&data).unwrap(), "a <b>");
+        }
+
+        assert!(compile_str(&from_handlebars("{{#if a}}{{/if}}{{#if b}}").template).is_ok());
+    }
+}
@@ -1,17 +1,308 @@
 use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::borrow::Cow;
 use std::cell::RefCell;
+use std::error::Error as StdError;
 use std::fmt;
+use std::iter::{FromIterator, Peekable};
 
 // for bug!
 use log::{log, error};
+use serde::de::{Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::ser::{self, Serialize, Serializer};
+use parser;
+use redact;
+use template::{self, Scope};
 
+/// The error a `Data::TryFun` lambda fails with.
+pub type LambdaError = Box<dyn StdError + Send + Sync>;
+
+/// The closure held by `Data::TryFun`.
+pub type TryFun = dyn FnMut(String) -> Result<String, LambdaError> + Send;
+
+/// The closure held by `Data::Helper`.
+pub type Helper = dyn FnMut(String, &Scope) -> String + Send;
+
+/// The iterator held by `Data::Iter`.
+pub type DataIter = dyn Iterator<Item = Data> + Send;
+
+/// This type is not intended to be matched exhaustively, variants may be
+/// added to it in future without a version bump.
+#[non_exhaustive]
 pub enum Data {
     Null,
     String(String),
     Bool(bool),
+    /// Binary data, written out in the encoding
+    /// [`RenderOptions::bytes`](struct.RenderOptions.html#structfield.bytes)
+    /// picks. Sections treat it like a string.
+    Bytes(Vec<u8>),
     Vec(Vec<Data>),
+    /// A list whose items are only made as a section goes through them, each
+    /// rendered and dropped before the next, for lists too long to hold.
+    /// The items are gone once a section went through them, so any later
+    /// section finds the list empty. Tags write nothing for it and inverted
+    /// sections only take out the first item to see if there is one.
+    /// Checks that don't render, like
+    /// [`Template::validate`](struct.Template.html#method.validate), leave
+    /// it alone and see no items.
+    Iter(Box<RefCell<Peekable<Box<DataIter>>>>),
     Map(HashMap<String, Data>),
     Fun(RefCell<Box<dyn FnMut(String) -> String + Send>>),
+    /// Like `Fun`, except that an error fails the whole render with
+    /// [`Error::Lambda`](enum.Error.html#variant.Lambda).
+    TryFun(RefCell<Box<TryFun>>),
+    /// Like `Fun`, except that it is given its section's content already
+    /// rendered and a [`Scope`](struct.Scope.html) to look around the render
+    /// with, and that what it returns is written out as text rather than
+    /// rendered. See the [`locale`](locale/index.html) helpers.
+    Helper(RefCell<Box<Helper>>),
+}
+
+// How many characters of a string `Data::to_pretty_string` shows.
+const PRETTY_STRING_LEN: usize = 40;
+
+/// How [`Data::merge`](enum.Data.html#method.merge) settles a key both maps
+/// have.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MergeStrategy {
+    /// The incoming value replaces the existing one.
+    Overwrite,
+    /// The existing value is left alone.
+    Keep,
+    /// Two maps are merged key by key, anything else is overwritten.
+    Recurse,
+}
+
+impl Data {
+    /// Looks up a dotted name such as `user.address.city` the way a tag in a
+    /// template rendered with this data would, `.` being the data itself.
+    ///
+    /// ```
+    /// use mustache::{Data, MapBuilder};
+    ///
+    /// let data = MapBuilder::new()
+    ///     .insert_map("user", |m| m.insert_map("address", |m| m.insert_str("city", "Lyon")))
+    ///     .build();
+    ///
+    /// assert_eq!(data.lookup("user.address.city"), Some(&Data::String("Lyon".to_string())));
+    /// assert_eq!(data.lookup("user.name"), None);
+    /// ```
+    pub fn lookup(&self, name: &str) -> Option<&Data> {
+        template::find(&parser::split_name(name), &[self])
+    }
+
+    /// Inserts `value` at a dotted name, creating the maps along the way.
+    ///
+    /// If something other than a map is in the way, nothing is changed and
+    /// `value` is handed back.
+    ///
+    /// ```
+    /// use mustache::{Data, MapBuilder};
+    ///
+    /// let mut data = MapBuilder::new().build();
+    /// data.insert_path("user.address.city", Data::String("Lyon".to_string())).unwrap();
+    ///
+    /// assert_eq!(data.lookup("user.address.city"), Some(&Data::String("Lyon".to_string())));
+    /// assert!(data.insert_path("user.address.city.zip", Data::Null).is_err());
+    /// ```
+    pub fn insert_path(&mut self, name: &str, value: Data) -> Result<(), Data> {
+        let path = parser::split_name(name);
+        let (last, parents) = match path.split_last() {
+            Some(split) => split,
+            None => {
+                *self = value;
+                return Ok(());
+            }
+        };
+
+        // Maps are only created below a missing key, so nothing can be in the
+        // way after that and a failure leaves `self` as it was.
+        let mut current = self;
+        for part in parents {
+            current = match *{ current } {
+                Data::Map(ref mut map) => map.entry(part.clone())
+                    .or_insert_with(|| Data::Map(HashMap::new())),
+                _ => return Err(value),
+            };
+        }
+
+        match *current {
+            Data::Map(ref mut map) => {
+                map.insert(last.clone(), value);
+            }
+            _ => return Err(value),
+        }
+
+        Ok(())
+    }
+
+    /// The value of the map key `key`, to change it in place. `None` if
+    /// there is no such key or this isn't a map.
+    ///
+    /// Unlike [`lookup`](#method.lookup), `key` is a key of this map and not
+    /// a dotted name.
+    ///
+    /// ```
+    /// use mustache::{Data, MapBuilder};
+    ///
+    /// let mut data = MapBuilder::new().insert_str("title", "Home").build();
+    /// if let Some(title) = data.get_mut("title") {
+    ///     *title = Data::from("Welcome");
+    /// }
+    /// assert_eq!(data.lookup("title"), Some(&Data::from("Welcome")));
+    /// ```
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut Data> {
+        match *self {
+            Data::Map(ref mut map) => map.get_mut(key),
+            _ => None,
+        }
+    }
+
+    /// Takes the map key `key` out, giving its value. `None` if there is no
+    /// such key or this isn't a map.
+    pub fn remove(&mut self, key: &str) -> Option<Data> {
+        match *self {
+            Data::Map(ref mut map) => map.remove(key),
+            _ => None,
+        }
+    }
+
+    /// The entry of the map key `key`, to insert a value unless there is
+    /// one or change it in place. `None` if this isn't a map.
+    ///
+    /// ```
+    /// use mustache::{Data, MapBuilder};
+    ///
+    /// let mut data = MapBuilder::new().insert_str("csrf", "abc").build();
+    /// data.entry("csrf").unwrap().or_insert_with(|| Data::from("new"));
+    /// data.entry("user").unwrap().or_insert_with(|| Data::from("guest"));
+    ///
+    /// assert_eq!(data, MapBuilder::new().insert_str("csrf", "abc").insert_str("user", "guest").build());
+    /// ```
+    pub fn entry(&mut self, key: impl Into<String>) -> Option<Entry<'_, String, Data>> {
+        match *self {
+            Data::Map(ref mut map) => Some(map.entry(key.into())),
+            _ => None,
+        }
+    }
+
+    /// Prints the data as an indented tree, one value per line with its
+    /// type, map keys sorted and long strings cut short. Strings go through
+    /// the redaction registered with
+    /// [`set_redaction`](fn.set_redaction.html), if any.
+    ///
+    /// ```
+    /// use mustache::MapBuilder;
+    ///
+    /// let data = MapBuilder::new()
+    ///     .insert_str("name", "Jane")
+    ///     .insert_vec("works", |v| v.push_str("Emma").push_bool(true))
+    ///     .build();
+    ///
+    /// assert_eq!(data.to_pretty_string(), "\
+    /// map (2)
+    ///   name: string \"Jane\"
+    ///   works: vec (2)
+    ///     0: string \"Emma\"
+    ///     1: bool true
+    /// ");
+    /// ```
+    pub fn to_pretty_string(&self) -> String {
+        let mut out = String::new();
+        self.pretty(&mut out, "", 0);
+        out
+    }
+
+    // `key` is what the value is under, for the redaction.
+    fn pretty(&self, out: &mut String, key: &str, indent: usize) {
+        match *self {
+            Data::Null => out.push_str("null\n"),
+            Data::Bool(v) => out.push_str(&format!("bool {}\n", v)),
+            Data::String(ref v) => {
+                let v = redact::redacted(key, v);
+                let len = v.chars().count();
+                if len > PRETTY_STRING_LEN {
+                    let start: String = v.chars().take(PRETTY_STRING_LEN).collect();
+                    out.push_str(&format!("string {:?}... ({} chars)\n", start, len));
+                } else {
+                    out.push_str(&format!("string {:?}\n", v));
+                }
+            }
+            Data::Bytes(ref v) => out.push_str(&format!("bytes ({})\n", v.len())),
+            Data::Vec(ref v) => {
+                out.push_str(&format!("vec ({})\n", v.len()));
+                for (i, item) in v.iter().enumerate() {
+                    out.push_str(&format!("{:indent$}{}: ", "", i, indent = indent + 2));
+                    item.pretty(out, key, indent + 2);
+                }
+            }
+            Data::Map(ref v) => {
+                out.push_str(&format!("map ({})\n", v.len()));
+                let mut keys: Vec<&String> = v.keys().collect();
+                keys.sort();
+                for key in keys {
+                    out.push_str(&format!("{:indent$}{}: ", "", key, indent = indent + 2));
+                    v[key].pretty(out, key, indent + 2);
+                }
+            }
+            Data::Iter(_) => out.push_str("iter\n"),
+            Data::Fun(_) | Data::TryFun(_) | Data::Helper(_) => out.push_str("lambda\n"),
+        }
+    }
+
+    /// Layers `other` over `self`, e.g. per-request overrides over defaults.
+    ///
+    /// When both are maps their keys are combined, settling the keys they
+    /// share with `strategy`. Otherwise `other` replaces `self`, unless the
+    /// strategy is `Keep`.
+    ///
+    /// ```
+    /// use mustache::{MapBuilder, MergeStrategy};
+    ///
+    /// let mut data = MapBuilder::new()
+    ///     .insert_str("title", "Home")
+    ///     .insert_map("site", |m| m.insert_str("name", "Example").insert_str("lang", "en"))
+    ///     .build();
+    ///
+    /// let overrides = MapBuilder::new()
+    ///     .insert_map("site", |m| m.insert_str("lang", "fr"))
+    ///     .build();
+    ///
+    /// data.merge(overrides, MergeStrategy::Recurse);
+    ///
+    /// let expected = MapBuilder::new()
+    ///     .insert_str("title", "Home")
+    ///     .insert_map("site", |m| m.insert_str("name", "Example").insert_str("lang", "fr"))
+    ///     .build();
+    ///
+    /// assert_eq!(data, expected);
+    /// ```
+    pub fn merge(&mut self, other: Data, strategy: MergeStrategy) {
+        match (&mut *self, other) {
+            (&mut Data::Map(ref mut map), Data::Map(other)) => {
+                for (key, value) in other {
+                    match map.entry(key) {
+                        Entry::Vacant(entry) => {
+                            entry.insert(value);
+                        }
+                        Entry::Occupied(mut entry) => match strategy {
+                            MergeStrategy::Overwrite => {
+                                entry.insert(value);
+                            }
+                            MergeStrategy::Keep => {}
+                            MergeStrategy::Recurse => entry.get_mut().merge(value, strategy),
+                        },
+                    }
+                }
+            }
+            (_, other) => {
+                if strategy != MergeStrategy::Keep {
+                    *self = other;
+                }
+            }
+        }
+    }
 }
 
 impl PartialEq for Data {
@@ -19,11 +310,14 @@ impl PartialEq for Data {
     fn eq(&self, other: &Data) -> bool {
         match (self, other) {
             (&Data::Null, &Data::Null) => true,
-            (&Data::String(ref v0), &Data::String(ref v1)) => v0 == v1,
-            (&Data::Bool(ref v0), &Data::Bool(ref v1)) => v0 == v1,
-            (&Data::Vec(ref v0), &Data::Vec(ref v1)) => v0 == v1,
-            (&Data::Map(ref v0), &Data::Map(ref v1)) => v0 == v1,
-            (&Data::Fun(_), &Data::Fun(_)) => {
+            (Data::String(v0), Data::String(v1)) => v0 == v1,
+            (Data::Bool(v0), Data::Bool(v1)) => v0 == v1,
+            (Data::Bytes(v0), Data::Bytes(v1)) => v0 == v1,
+            (Data::Vec(v0), Data::Vec(v1)) => v0 == v1,
+            (Data::Map(v0), Data::Map(v1)) => v0 == v1,
+            (&Data::Fun(_), &Data::Fun(_)) |
+            (&Data::TryFun(_), &Data::TryFun(_)) |
+            (&Data::Helper(_), &Data::Helper(_)) => {
                 bug!("Cannot compare closures");
                 false
             },
@@ -32,15 +326,225 @@ impl PartialEq for Data {
     }
 }
 
+impl From<String> for Data {
+    fn from(v: String) -> Data {
+        Data::String(v)
+    }
+}
+
+impl<'a> From<&'a str> for Data {
+    fn from(v: &'a str) -> Data {
+        Data::String(v.to_string())
+    }
+}
+
+impl<'a> From<Cow<'a, str>> for Data {
+    fn from(v: Cow<'a, str>) -> Data {
+        Data::String(v.into_owned())
+    }
+}
+
+impl From<bool> for Data {
+    fn from(v: bool) -> Data {
+        Data::Bool(v)
+    }
+}
+
+// Numbers become strings, the way `to_data` encodes them.
+macro_rules! from_numbers {
+    ($($ty:ty)*) => {
+        $(
+            impl From<$ty> for Data {
+                fn from(v: $ty) -> Data {
+                    Data::String(v.to_string())
+                }
+            }
+        )*
+    };
+}
+
+from_numbers!(i8 i16 i32 i64 isize u8 u16 u32 u64 usize f32 f64);
+
+impl From<Vec<Data>> for Data {
+    fn from(v: Vec<Data>) -> Data {
+        Data::Vec(v)
+    }
+}
+
+impl From<HashMap<String, Data>> for Data {
+    fn from(v: HashMap<String, Data>) -> Data {
+        Data::Map(v)
+    }
+}
+
+/// `None` is `Null`, as with `to_data`.
+impl<T: Into<Data>> From<Option<T>> for Data {
+    fn from(v: Option<T>) -> Data {
+        v.map_or(Data::Null, Into::into)
+    }
+}
+
+/// Collects into a `Vec`.
+///
+/// ```
+/// use mustache::Data;
+///
+/// let tags: Data = ["a", "b"].iter().map(|&tag| Data::from(tag)).collect();
+/// assert_eq!(tags, Data::Vec(vec!["a".into(), "b".into()]));
+/// ```
+impl FromIterator<Data> for Data {
+    fn from_iter<I: IntoIterator<Item = Data>>(iter: I) -> Data {
+        Data::Vec(iter.into_iter().collect())
+    }
+}
+
+/// Collects into a `Map`, later values replacing earlier ones of the same
+/// key.
+///
+/// ```
+/// use mustache::Data;
+///
+/// let user: Data = vec![("name", Data::from("Ann")), ("admin", true.into())].into_iter().collect();
+/// let template = mustache::compile_str("{{name}}{{#admin}} (admin){{/admin}}").unwrap();
+/// assert_eq!(template.render_data_to_string(&user).unwrap(), "Ann (admin)");
+/// ```
+impl<K: Into<String>, V: Into<Data>> FromIterator<(K, V)> for Data {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Data {
+        Data::Map(iter.into_iter().map(|(k, v)| (k.into(), v.into())).collect())
+    }
+}
+
+/// Strings and bytes go through the redaction registered with
+/// [`set_redaction`](fn.set_redaction.html), if any.
 impl fmt::Debug for Data {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match *self {
+        fmt::Debug::fmt(&Keyed("", self), f)
+    }
+}
+
+// Data with the key it is under, for the redaction.
+struct Keyed<'a>(&'a str, &'a Data);
+
+impl<'a> fmt::Debug for Keyed<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Keyed(key, data) = *self;
+        match *data {
             Data::Null => write!(f, "Null"),
-            Data::String(ref v) => write!(f, "StrVal({})", v),
+            Data::String(ref v) => write!(f, "StrVal({})", redact::redacted(key, v)),
             Data::Bool(v) => write!(f, "Bool({:?})", v),
-            Data::Vec(ref v) => write!(f, "VecVal({:?})", v),
-            Data::Map(ref v) => write!(f, "Map({:?})", v),
+            Data::Bytes(ref v) => match redact::redacted(key, &String::from_utf8_lossy(v)) {
+                Cow::Owned(shown) => write!(f, "Bytes({:?})", shown),
+                Cow::Borrowed(_) => write!(f, "Bytes({:?})", v),
+            },
+            Data::Vec(ref v) => {
+                write!(f, "VecVal(")?;
+                f.debug_list().entries(v.iter().map(|item| Keyed(key, item))).finish()?;
+                write!(f, ")")
+            }
+            Data::Map(ref v) => {
+                write!(f, "Map(")?;
+                f.debug_map().entries(v.iter().map(|(key, value)| (key, Keyed(key, value)))).finish()?;
+                write!(f, ")")
+            }
+            Data::Iter(_) => write!(f, "Iter(...)"),
             Data::Fun(_) => write!(f, "Fun(...)"),
+            Data::TryFun(_) => write!(f, "TryFun(...)"),
+            Data::Helper(_) => write!(f, "Helper(...)"),
+        }
+    }
+}
+
+/// Lambdas and iterators can't be serialized, and fail with an error.
+impl Serialize for Data {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match *self {
+            Data::Null => serializer.serialize_unit(),
+            Data::String(ref v) => serializer.serialize_str(v),
+            Data::Bool(v) => serializer.serialize_bool(v),
+            Data::Bytes(ref v) => serializer.serialize_bytes(v),
+            Data::Vec(ref v) => v.serialize(serializer),
+            Data::Map(ref v) => v.serialize(serializer),
+            Data::Fun(_) | Data::TryFun(_) | Data::Helper(_) => {
+                Err(ser::Error::custom("lambdas cannot be serialized"))
+            }
+            Data::Iter(_) => Err(ser::Error::custom("iterators cannot be serialized")),
+        }
+    }
+}
+
+/// Numbers become strings, the way `to_data` encodes them.
+impl<'de> Deserialize<'de> for Data {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Data, D::Error> {
+        deserializer.deserialize_any(DataVisitor)
+    }
+}
+
+struct DataVisitor;
+
+impl<'de> Visitor<'de> for DataVisitor {
+    type Value = Data;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a null, string, boolean, number, bytes, sequence or map")
+    }
+
+    fn visit_unit<E>(self) -> Result<Data, E> {
+        Ok(Data::Null)
+    }
+
+    fn visit_none<E>(self) -> Result<Data, E> {
+        Ok(Data::Null)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Data, D::Error> {
+        Deserialize::deserialize(deserializer)
+    }
+
+    fn visit_bool<E>(self, v: bool) -> Result<Data, E> {
+        Ok(Data::Bool(v))
+    }
+
+    fn visit_i64<E>(self, v: i64) -> Result<Data, E> {
+        Ok(Data::String(v.to_string()))
+    }
+
+    fn visit_u64<E>(self, v: u64) -> Result<Data, E> {
+        Ok(Data::String(v.to_string()))
+    }
+
+    fn visit_f64<E>(self, v: f64) -> Result<Data, E> {
+        Ok(Data::String(v.to_string()))
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Data, E> {
+        Ok(Data::String(v.to_string()))
+    }
+
+    fn visit_string<E>(self, v: String) -> Result<Data, E> {
+        Ok(Data::String(v))
+    }
+
+    fn visit_bytes<E>(self, v: &[u8]) -> Result<Data, E> {
+        Ok(Data::Bytes(v.to_vec()))
+    }
+
+    fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<Data, E> {
+        Ok(Data::Bytes(v))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Data, A::Error> {
+        let mut vec = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(item) = seq.next_element()? {
+            vec.push(item);
+        }
+        Ok(Data::Vec(vec))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut access: A) -> Result<Data, A::Error> {
+        let mut map = HashMap::with_capacity(access.size_hint().unwrap_or(0));
+        while let Some((key, value)) = access.next_entry()? {
+            map.insert(key, value);
         }
+        Ok(Data::Map(map))
     }
 }
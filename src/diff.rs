@@ -0,0 +1,483 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::ops::Range;
+
+use parser::Token;
+use template::{join_pointer, tag_name};
+use {PartialLoader, Template};
+
+/// What a [`Tag`] does.
+///
+/// This type is not intended to be matched exhaustively as new variants
+/// may be added in future without a version bump.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[allow(clippy::manual_non_exhaustive)]
+pub enum TagKind {
+    Variable,
+    UnescapedVariable,
+    /// A JSON Pointer, with the `json_pointers` extension.
+    Pointer,
+    Section,
+    InvertedSection,
+    Partial,
+
+    #[doc(hidden)]
+    __Nonexhaustive,
+}
+
+/// A tag as [`diff`](fn.diff.html) compares it: what it is, its name and
+/// where it sits.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Tag {
+    pub kind: TagKind,
+    pub name: String,
+    /// The sections around the tag, outermost first, written `#name`,
+    /// `^name` or, for the fallback of a partial, `>name`.
+    pub sections: Vec<String>,
+    /// The partial the tag is in, `None` for the template itself.
+    pub partial: Option<String>,
+}
+
+impl fmt::Display for Tag {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            TagKind::Variable | TagKind::Pointer => write!(f, "{{{{{}}}}}", self.name)?,
+            TagKind::UnescapedVariable => write!(f, "{{{{{{{}}}}}}}", self.name)?,
+            TagKind::Section => write!(f, "{{{{#{}}}}}", self.name)?,
+            TagKind::InvertedSection => write!(f, "{{{{^{}}}}}", self.name)?,
+            TagKind::Partial => write!(f, "{{{{>{}}}}}", self.name)?,
+            TagKind::__Nonexhaustive => unreachable!(),
+        }
+        if !self.sections.is_empty() {
+            write!(f, " in ")?;
+            for section in &self.sections {
+                write!(f, "{{{{{}}}}}", section)?;
+            }
+        }
+        if let Some(ref partial) = self.partial {
+            write!(f, " of partial {:?}", partial)?;
+        }
+        Ok(())
+    }
+}
+
+/// A difference between two templates, from [`diff`](fn.diff.html).
+///
+/// This type is not intended to be matched exhaustively as new variants
+/// may be added in future without a version bump.
+#[derive(Clone, Debug, PartialEq)]
+#[allow(clippy::manual_non_exhaustive)]
+pub enum Change {
+    Added(Tag),
+    Removed(Tag),
+    /// A tag now somewhere else, in other sections or in another order.
+    Moved { from: Tag, to: Tag },
+    /// The text between two tags that stayed, joined up. Either side may be
+    /// empty.
+    TextChanged { partial: Option<String>, before: String, after: String },
+
+    #[doc(hidden)]
+    __Nonexhaustive,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Change::Added(ref tag) => write!(f, "added {}", tag),
+            Change::Removed(ref tag) => write!(f, "removed {}", tag),
+            Change::Moved { ref from, ref to } => write!(f, "moved {} to {}", from, to),
+            Change::TextChanged { ref partial, ref before, ref after } => {
+                write!(f, "changed text {:?} to {:?}", before, after)?;
+                if let Some(ref partial) = *partial {
+                    write!(f, " in partial {:?}", partial)?;
+                }
+                Ok(())
+            }
+            Change::__Nonexhaustive => unreachable!(),
+        }
+    }
+}
+
+/// Compares the structure of two templates rather than their lines: the
+/// tags added, removed and moved, and the text changed between tags that
+/// stayed. The partials the templates were compiled with are compared by
+/// name.
+///
+/// Comments, whitespace stripped around standalone tags and delimiter
+/// changes make no difference.
+///
+/// ```rust
+/// use mustache::{Change, Tag, TagKind};
+///
+/// let old = mustache::compile_str("{{#user}}Hi {{name}}{{/user}}").unwrap();
+/// let new = mustache::compile_str("{{#user}}Hello {{name}}, {{{bio}}}{{/user}}").unwrap();
+///
+/// let changes = mustache::diff(&old, &new);
+/// assert_eq!(changes[1], Change::Added(Tag {
+///     kind: TagKind::UnescapedVariable,
+///     name: "bio".to_string(),
+///     sections: vec!["#user".to_string()],
+///     partial: None,
+/// }));
+/// assert_eq!(changes[1].to_string(), "added {{{bio}}} in {{#user}}");
+/// ```
+pub fn diff<P: PartialLoader, Q: PartialLoader>(old: &Template<P>, new: &Template<Q>) -> Vec<Change> {
+    let mut changes = Vec::new();
+    diff_tokens(&old.tokens, &new.tokens, None, &mut changes);
+
+    let names: BTreeSet<&String> = old.partials.keys().chain(new.partials.keys()).collect();
+    for name in names {
+        let empty = Vec::new();
+        let old_tokens = old.partials.get(name).map_or(&empty, |tokens| &**tokens);
+        let new_tokens = new.partials.get(name).map_or(&empty, |tokens| &**tokens);
+        diff_tokens(old_tokens, new_tokens, Some(name), &mut changes);
+    }
+
+    changes
+}
+
+/// A part of an earlier output to replace, from
+/// [`diff_output`](fn.diff_output.html).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Splice {
+    /// Where it is in the earlier output, in bytes.
+    pub range: Range<usize>,
+    /// What it was replaced with.
+    pub text: String,
+}
+
+// The most pairs of lines compared, beyond which the changed part of the
+// output makes a single splice.
+const MAX_COMPARED: usize = 1 << 20;
+
+/// The parts of `before` to replace for it to read `after`, in order and
+/// not overlapping, so a live preview can send those instead of the whole
+/// page. Applied from last to first, their ranges stay valid.
+///
+/// The outputs are compared line by line, and changed lines narrowed down to
+/// the text that changed in them.
+///
+/// ```rust
+/// use mustache::Splice;
+///
+/// let splices = mustache::diff_output("<h1>Hi</h1>\n<p>a</p>\n<p>b</p>\n", "<h1>Hello</h1>\n<p>a</p>\n");
+/// assert_eq!(splices, vec![
+///     Splice { range: 5..6, text: "ello".to_string() },
+///     Splice { range: 21..30, text: "".to_string() },
+/// ]);
+/// ```
+pub fn diff_output(before: &str, after: &str) -> Vec<Splice> {
+    // The lines the outputs start and end with are left out.
+    let prefix = common_prefix(before, after);
+    let prefix = before[..prefix].rfind('\n').map_or(0, |i| i + 1);
+    let suffix = common_suffix(&before[prefix..], &after[prefix..]);
+    let suffix = before[before.len() - suffix..].find('\n').map_or(0, |i| suffix - i - 1);
+    let old = &before[prefix..before.len() - suffix];
+    let new = &after[prefix..after.len() - suffix];
+
+    if old.is_empty() && new.is_empty() {
+        return Vec::new();
+    }
+    let old_lines: Vec<&str> = old.split_inclusive('\n').collect();
+    let new_lines: Vec<&str> = new.split_inclusive('\n').collect();
+    let (n, m) = (old_lines.len(), new_lines.len());
+    if n.saturating_mul(m) > MAX_COMPARED {
+        return vec![Splice { range: prefix..prefix + old.len(), text: new.to_string() }];
+    }
+
+    // As in `diff_tokens`, `lengths[i][j]` is the length of the longest
+    // common subsequence of the lines from `i` and `j` on.
+    let mut lengths = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lengths[i][j] = if old_lines[i] == new_lines[j] {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let mut splices = Vec::new();
+    let mut at = prefix;
+    let mut splice: Option<Splice> = None;
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && old_lines[i] == new_lines[j] {
+            splices.extend(splice.take().map(|splice| narrow(before, splice)));
+            at += old_lines[i].len();
+            i += 1;
+            j += 1;
+            continue;
+        }
+
+        let splice = splice.get_or_insert_with(|| Splice { range: at..at, text: String::new() });
+        if j == m || (i < n && lengths[i + 1][j] >= lengths[i][j + 1]) {
+            at += old_lines[i].len();
+            splice.range.end = at;
+            i += 1;
+        } else {
+            splice.text.push_str(new_lines[j]);
+            j += 1;
+        }
+    }
+    splices.extend(splice.map(|splice| narrow(before, splice)));
+    splices
+}
+
+// Leaves out of `splice` what its text starts and ends like the part of
+// `before` it replaces.
+fn narrow(before: &str, splice: Splice) -> Splice {
+    let old = &before[splice.range.clone()];
+    let prefix = common_prefix(old, &splice.text);
+    let suffix = common_suffix(&old[prefix..], &splice.text[prefix..]);
+    Splice {
+        range: splice.range.start + prefix..splice.range.end - suffix,
+        text: splice.text[prefix..splice.text.len() - suffix].to_string(),
+    }
+}
+
+// The length of the text `a` and `b` start with, ending on a character.
+fn common_prefix(a: &str, b: &str) -> usize {
+    let mut len = a.bytes().zip(b.bytes()).take_while(|&(x, y)| x == y).count();
+    while !a.is_char_boundary(len) {
+        len -= 1;
+    }
+    len
+}
+
+// The length of the text `a` and `b` end with, starting on a character.
+fn common_suffix(a: &str, b: &str) -> usize {
+    let mut len = a.bytes().rev().zip(b.bytes().rev()).take_while(|&(x, y)| x == y).count();
+    while !a.is_char_boundary(a.len() - len) {
+        len -= 1;
+    }
+    len
+}
+
+#[derive(PartialEq)]
+enum Item {
+    Text(String),
+    Tag(Tag),
+}
+
+fn diff_tokens(old: &[Token], new: &[Token], partial: Option<&String>, changes: &mut Vec<Change>) {
+    let (mut old_items, mut new_items) = (Vec::new(), Vec::new());
+    flatten(old, partial, &mut Vec::new(), &mut old_items);
+    flatten(new, partial, &mut Vec::new(), &mut new_items);
+
+    // The longest common subsequence, `lengths[i][j]` being its length for
+    // the items from `i` and `j` on.
+    let (n, m) = (old_items.len(), new_items.len());
+    let mut lengths = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lengths[i][j] = if old_items[i] == new_items[j] {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let start = changes.len();
+    let (mut before, mut after) = (String::new(), String::new());
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && old_items[i] == new_items[j] {
+            text_changed(partial, &mut before, &mut after, changes);
+            i += 1;
+            j += 1;
+        } else if j == m || (i < n && lengths[i + 1][j] >= lengths[i][j + 1]) {
+            match old_items[i] {
+                Item::Text(ref text) => before.push_str(text),
+                Item::Tag(ref tag) => changes.push(Change::Removed(tag.clone())),
+            }
+            i += 1;
+        } else {
+            match new_items[j] {
+                Item::Text(ref text) => after.push_str(text),
+                Item::Tag(ref tag) => changes.push(Change::Added(tag.clone())),
+            }
+            j += 1;
+        }
+    }
+    text_changed(partial, &mut before, &mut after, changes);
+
+    pair_moves(&mut changes[start..]);
+    changes.retain(|change| *change != Change::__Nonexhaustive);
+}
+
+fn text_changed(partial: Option<&String>, before: &mut String, after: &mut String, changes: &mut Vec<Change>) {
+    if !before.is_empty() || !after.is_empty() {
+        changes.push(Change::TextChanged {
+            partial: partial.cloned(),
+            before: before.split_off(0),
+            after: after.split_off(0),
+        });
+    }
+}
+
+// Turns a removed tag and an added one of the same kind and name into a
+// move, leaving `__Nonexhaustive` where the addition was for the caller to
+// drop.
+fn pair_moves(changes: &mut [Change]) {
+    for i in 0..changes.len() {
+        let removed = match changes[i] {
+            Change::Removed(ref tag) => tag.clone(),
+            _ => continue,
+        };
+
+        let added = changes.iter().position(|change| match *change {
+            Change::Added(ref tag) => tag.kind == removed.kind && tag.name == removed.name,
+            _ => false,
+        });
+        if let Some(added) = added {
+            if let Change::Added(to) = ::std::mem::replace(&mut changes[added], Change::__Nonexhaustive) {
+                changes[i] = Change::Moved { from: removed, to };
+            }
+        }
+    }
+}
+
+// Lists the tags and text of `tokens` in order, sections before what they
+// hold. Inline definitions are left to the partials.
+fn flatten(tokens: &[Token], partial: Option<&String>, sections: &mut Vec<String>, items: &mut Vec<Item>) {
+    let tag = |kind, name: String, sections: &[String]| {
+        Item::Tag(Tag { kind, name, sections: sections.to_vec(), partial: partial.cloned() })
+    };
+
+    for token in tokens {
+        match *token {
+            Token::Text(ref text) | Token::Raw(ref text, _) => match items.last_mut() {
+                Some(&mut Item::Text(ref mut last)) => last.push_str(text),
+                _ => items.push(Item::Text(text.clone())),
+            },
+            Token::EscapedTag(ref path, _) => items.push(tag(TagKind::Variable, tag_name(path), sections)),
+            Token::UnescapedTag(ref path, _) => items.push(tag(TagKind::UnescapedVariable, tag_name(path), sections)),
+            Token::PointerTag(ref pointer, _) => items.push(tag(TagKind::Pointer, join_pointer(pointer), sections)),
+            Token::FallbackTag(ref paths, escaped, _) => {
+                let kind = if escaped { TagKind::Variable } else { TagKind::UnescapedVariable };
+                let names: Vec<_> = paths.iter().map(|path| tag_name(path)).collect();
+                items.push(tag(kind, names.join(" || "), sections));
+            }
+            Token::Section(ref path, inverted, ref children, _, _, _, _, _) => {
+                let (kind, mark) = if inverted { (TagKind::InvertedSection, '^') } else { (TagKind::Section, '#') };
+                let name = tag_name(path);
+                items.push(tag(kind, name.clone(), sections));
+
+                sections.push(format!("{}{}", mark, name));
+                flatten(children, partial, sections, items);
+                sections.pop();
+            }
+            Token::Condition(ref comparison, inverted, ref children, _, _, _, _, _) => {
+                let (kind, mark) = if inverted { (TagKind::InvertedSection, '^') } else { (TagKind::Section, '#') };
+                let name = comparison.to_string();
+                items.push(tag(kind, name.clone(), sections));
+
+                sections.push(format!("{}{}", mark, name));
+                flatten(children, partial, sections, items);
+                sections.pop();
+            }
+            Token::Range(ref bounds, inverted, ref children, _, _, _, _, _) => {
+                let (kind, mark) = if inverted { (TagKind::InvertedSection, '^') } else { (TagKind::Section, '#') };
+                let name = bounds.to_string();
+                items.push(tag(kind, name.clone(), sections));
+
+                sections.push(format!("{}{}", mark, name));
+                flatten(children, partial, sections, items);
+                sections.pop();
+            }
+            Token::Partial(ref name, _, _) => items.push(tag(TagKind::Partial, name.clone(), sections)),
+            Token::PartialWithFallback(ref name, _, ref children, _, _, _) => {
+                items.push(tag(TagKind::Partial, name.clone(), sections));
+
+                sections.push(format!(">{}", name));
+                flatten(children, partial, sections, items);
+                sections.pop();
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{diff, diff_output, Change, Splice, Tag, TagKind};
+    use Context;
+
+    fn tag(kind: TagKind, name: &str, sections: &[&str], partial: Option<&str>) -> Tag {
+        Tag {
+            kind,
+            name: name.to_string(),
+            sections: sections.iter().map(|s| s.to_string()).collect(),
+            partial: partial.map(|p| p.to_string()),
+        }
+    }
+
+    #[test]
+    fn changes() {
+        let mut ctx = Context::new(PathBuf::from("."));
+        ctx.extensions.inline_partials = true;
+
+        let old = ctx.compile(concat!(
+            "{{<*card}}<b>{{title}}</b>{{/card}}",
+            "{{! gone }}Hi {{name}}\n{{#items}}{{>card}}{{/items}}{{^items}}none{{/items}}{{a}}{{b}}",
+        )).unwrap();
+        let new = ctx.compile(concat!(
+            "{{<*card}}<b>{{{title}}}</b>{{/card}}",
+            "Hi {{name}}!\n{{#items}}{{>card}}{{name}}{{/items}}{{b}}{{a}}",
+        )).unwrap();
+
+        assert_eq!(diff(&old, &new), vec![
+            Change::TextChanged { partial: None, before: "\n".to_string(), after: "!\n".to_string() },
+            Change::Removed(tag(TagKind::InvertedSection, "items", &[], None)),
+            Change::Moved {
+                from: tag(TagKind::Variable, "a", &[], None),
+                to: tag(TagKind::Variable, "a", &[], None),
+            },
+            Change::Added(tag(TagKind::Variable, "name", &["#items"], None)),
+            Change::TextChanged { partial: None, before: "none".to_string(), after: "".to_string() },
+            Change::Removed(tag(TagKind::Variable, "title", &[], Some("card"))),
+            Change::Added(tag(TagKind::UnescapedVariable, "title", &[], Some("card"))),
+        ]);
+
+        assert!(diff(&old, &old).is_empty());
+    }
+
+    // Applies `splices` to `before`, last first.
+    fn apply(before: &str, splices: &[Splice]) -> String {
+        let mut text = before.to_string();
+        for splice in splices.iter().rev() {
+            text.replace_range(splice.range.clone(), &splice.text);
+        }
+        text
+    }
+
+    #[test]
+    fn output() {
+        let cases = [
+            ("", ""),
+            ("same", "same"),
+            ("", "new\n"),
+            ("old\n", ""),
+            ("a\nb\nc\nd\n", "a\nB\nc\nD\ne\n"),
+            ("naïve", "naive"),
+            ("é", "è"),
+            ("x\ny\nx\n", "y\nx\ny\n"),
+        ];
+        for &(before, after) in &cases {
+            let splices = diff_output(before, after);
+            assert_eq!(apply(before, &splices), after, "{:?} to {:?}: {:?}", before, after, splices);
+            assert!(splices.windows(2).all(|pair| pair[0].range.end < pair[1].range.start));
+        }
+
+        assert!(diff_output("same", "same").is_empty());
+        assert_eq!(diff_output("a\nb\nc\nd\n", "a\nB\nc\nD\ne\n"), vec![
+            Splice { range: 2..3, text: "B".to_string() },
+            Splice { range: 6..7, text: "D\ne".to_string() },
+        ]);
+    }
+}
@@ -0,0 +1,162 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+use context::{Context, PartialLoader};
+use {Result, Template};
+
+pub fn compile<P: PartialLoader>(ctx: &Context<P>, dir: &Path, pattern: &str)
+                                 -> Result<BTreeMap<PathBuf, Result<Template<P>>>> {
+    Ok(find(dir, pattern)?
+        .into_iter()
+        .map(|path| {
+            let template = compile_file(ctx, dir, &path);
+            (path, template)
+        })
+        .collect())
+}
+
+#[cfg(feature = "rayon")]
+pub fn par_compile<P>(ctx: &Context<P>, dir: &Path, pattern: &str)
+                      -> Result<BTreeMap<PathBuf, Result<Template<P>>>>
+where P: PartialLoader + Send + Sync,
+{
+    use rayon::prelude::*;
+
+    let files = find(dir, pattern)?;
+    let templates: Vec<_> = files.par_iter().map(|path| compile_file(ctx, dir, path)).collect();
+
+    Ok(files.into_iter().zip(templates).collect())
+}
+
+// The files under `dir` matching `pattern`, relative to `dir`.
+fn find(dir: &Path, pattern: &str) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    walk(dir, Path::new(""), &mut vec![fs::canonicalize(dir)?], &mut files)?;
+
+    files.retain(|path| matches(pattern.as_bytes(), slashed(path).as_bytes()));
+    Ok(files)
+}
+
+fn compile_file<P: PartialLoader>(ctx: &Context<P>, dir: &Path, path: &Path) -> Result<Template<P>> {
+    let name = Some(path.display().to_string());
+
+    // Front matter has to be split off the whole source.
+    if ctx.front_matter {
+        return ctx.compile_file(name, &fs::read_to_string(dir.join(path))?);
+    }
+    ctx.compile_reader_named(name, File::open(dir.join(path))?)
+}
+
+// Collects the files under `dir`, relative to where the walk started.
+// Symlinks are followed, but for those to a directory the walk is already
+// in, `walking` with symlinks resolved, which would never end.
+fn walk(dir: &Path, relative: &Path, walking: &mut Vec<PathBuf>, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = relative.join(entry.file_name());
+
+        // A broken symlink is kept as a file, to fail on its own.
+        let is_dir = match fs::metadata(entry.path()) {
+            Ok(metadata) => metadata.is_dir(),
+            Err(_) => false,
+        };
+        if !is_dir {
+            files.push(path);
+            continue;
+        }
+
+        let resolved = fs::canonicalize(entry.path())?;
+        if walking.contains(&resolved) {
+            continue;
+        }
+        walking.push(resolved);
+        walk(&entry.path(), &path, walking, files)?;
+        walking.pop();
+    }
+
+    Ok(())
+}
+
+// Patterns always use `/`, whatever the platform.
+fn slashed(path: &Path) -> String {
+    let parts: Vec<_> = path.iter().map(|part| part.to_string_lossy()).collect();
+    parts.join("/")
+}
+
+// Matches a glob: `?` is any character but `/`, `*` any run of them and
+// `**/` any number of whole directories.
+fn matches(pattern: &[u8], path: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&b'*', rest)) if rest.starts_with(b"*/") => {
+            let rest = &rest[2..];
+            // Either no directories at all, or skip one and try again.
+            matches(rest, path) || match path.iter().position(|&b| b == b'/') {
+                Some(i) => matches(pattern, &path[i + 1..]),
+                None => false,
+            }
+        }
+        Some((&b'*', rest)) if rest == b"*" => true,
+        Some((&b'*', rest)) => {
+            (0..=path.len())
+                .take_while(|&i| i == 0 || path[i - 1] != b'/')
+                .any(|i| matches(rest, &path[i..]))
+        }
+        Some((&b'?', rest)) => match path.split_first() {
+            Some((&b, path)) if b != b'/' => matches(rest, path),
+            _ => false,
+        },
+        Some((&c, rest)) => match path.split_first() {
+            Some((&b, path)) if b == c => matches(rest, path),
+            _ => false,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::matches;
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn par_compile() {
+        use std::path::PathBuf;
+        use {Context, MapBuilder};
+
+        let mut ctx = Context::new(PathBuf::from("tests/test-data"));
+        ctx.cache_partials();
+        let data = MapBuilder::new().insert_str("name", "n").build();
+
+        let serial = ctx.compile_dir("tests/test-data", "*.mustache").unwrap();
+        let parallel = ctx.par_compile_dir("tests/test-data", "*.mustache").unwrap();
+
+        assert_eq!(serial.keys().collect::<Vec<_>>(), parallel.keys().collect::<Vec<_>>());
+        for (path, template) in parallel {
+            let render = |template: &::Result<::Template<_>>| template.as_ref().unwrap().render_data_to_string(&data).unwrap();
+            assert_eq!(render(&template), render(&serial[&path]), "{:?}", path);
+        }
+    }
+
+    #[test]
+    fn globs() {
+        let cases: &[(&str, &str, bool)] = &[
+            ("*.mustache", "user.mustache", true),
+            ("*.mustache", "emails/user.mustache", false),
+            ("**/*.mustache", "user.mustache", true),
+            ("**/*.mustache", "emails/en/user.mustache", true),
+            ("emails/**/*.html", "emails/a/b.html", true),
+            ("emails/**/*.html", "pages/a/b.html", false),
+            ("emails/*", "emails/a/b.html", false),
+            ("emails/**", "emails/a/b.html", true),
+            ("use?.mustache", "user.mustache", true),
+            ("use?.mustache", "use/.mustache", false),
+            ("*", "", true),
+            ("**/a", "xa", false),
+        ];
+
+        for &(pattern, path, expected) in cases {
+            assert_eq!(matches(pattern.as_bytes(), path.as_bytes()), expected, "{} against {}", pattern, path);
+        }
+    }
+}
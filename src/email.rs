@@ -0,0 +1,37 @@
+use serde::Serialize;
+
+use context::{Context, PartialLoader};
+use {to_data, Escape, Result};
+
+/// The bodies of an email, rendered by
+/// [`Context::render_email`](struct.Context.html#method.render_email).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Email {
+    pub html: String,
+    pub text: String,
+}
+
+pub fn render<P, T>(ctx: &Context<P>, html: &str, text: &str, data: &T) -> Result<Email>
+where P: PartialLoader,
+      T: Serialize,
+{
+    // Both templates usually include the same partials, a shared cache keeps
+    // the second from compiling them again. A context that already caches
+    // keeps its own.
+    let mut ctx = ctx.clone();
+    ctx.cache_partials();
+
+    let html = ctx.compile_path(html)?;
+
+    // The text body is not HTML, its values go in as they are.
+    let mut plain = ctx.clone();
+    plain.escape = Escape::Plain;
+    let text = plain.compile_path(text)?;
+
+    let data = to_data(data)?;
+
+    Ok(Email {
+        html: html.render_data_to_string(&data)?,
+        text: text.render_data_to_string(&data)?,
+    })
+}
@@ -0,0 +1,168 @@
+//! Loaders over templates built into the binary, enabled with the
+//! `include_dir` and `rust-embed` features. Like `DefaultLoader`, they
+//! append the extension to names and treat a missing template as empty.
+
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "include_dir")]
+use include_dir::Dir;
+#[cfg(feature = "rust-embed")]
+use std::marker::PhantomData;
+#[cfg(feature = "rust-embed")]
+use std::str;
+#[cfg(feature = "rust-embed")]
+use rust_embed::RustEmbed;
+
+use context::{self, PartialLoader};
+use {Error, Result};
+
+fn path(name: &Path, extension: &str) -> PathBuf {
+    context::with_extension(name.to_path_buf(), extension)
+}
+
+/// Loads templates from an [`include_dir::Dir`](https://docs.rs/include_dir),
+/// borrowing them straight out of the binary.
+///
+/// ```ignore
+/// static TEMPLATES: Dir = include_dir!("$CARGO_MANIFEST_DIR/templates");
+///
+/// let ctx = Context::with_loader(DirLoader::new(&TEMPLATES));
+/// ```
+#[cfg(feature = "include_dir")]
+#[derive(Clone, Debug)]
+pub struct DirLoader {
+    dir: &'static Dir<'static>,
+    extension: String,
+}
+
+#[cfg(feature = "include_dir")]
+impl DirLoader {
+    /// Loads `{name}.mustache` from `dir`.
+    pub fn new(dir: &'static Dir<'static>) -> Self {
+        DirLoader::with_extension(dir, "mustache".to_string())
+    }
+
+    /// Loads `{name}.{extension}` from `dir`.
+    pub fn with_extension(dir: &'static Dir<'static>, extension: String) -> Self {
+        DirLoader { dir, extension }
+    }
+}
+
+#[cfg(feature = "include_dir")]
+impl PartialLoader for DirLoader {
+    fn load(&self, name: impl AsRef<Path>) -> Result<String> {
+        self.load_str(name).map(Cow::into_owned)
+    }
+
+    fn load_str(&self, name: impl AsRef<Path>) -> Result<Cow<'_, str>> {
+        match self.dir.get_file(path(name.as_ref(), &self.extension)) {
+            Some(file) => file.contents_utf8().map(Cow::Borrowed).ok_or(Error::InvalidStr),
+            None => Ok(Cow::Borrowed("")),
+        }
+    }
+}
+
+/// Loads templates from a type deriving
+/// [`rust_embed::RustEmbed`](https://docs.rs/rust-embed). Debug builds of
+/// rust-embed read the folder from disk unless its `debug-embed` feature is
+/// on.
+///
+/// ```ignore
+/// #[derive(RustEmbed)]
+/// #[folder = "templates/"]
+/// struct Templates;
+///
+/// let ctx = Context::with_loader(EmbedLoader::<Templates>::new());
+/// ```
+#[cfg(feature = "rust-embed")]
+#[derive(Debug)]
+pub struct EmbedLoader<E> {
+    extension: String,
+    embed: PhantomData<fn() -> E>,
+}
+
+#[cfg(feature = "rust-embed")]
+impl<E: RustEmbed> EmbedLoader<E> {
+    /// Loads `{name}.mustache` from `E`.
+    pub fn new() -> Self {
+        EmbedLoader::with_extension("mustache".to_string())
+    }
+
+    /// Loads `{name}.{extension}` from `E`.
+    pub fn with_extension(extension: String) -> Self {
+        EmbedLoader { extension, embed: PhantomData }
+    }
+}
+
+#[cfg(feature = "rust-embed")]
+impl<E: RustEmbed> Default for EmbedLoader<E> {
+    fn default() -> Self {
+        EmbedLoader::new()
+    }
+}
+
+// `E` is only a marker, it needn't be `Clone` itself.
+#[cfg(feature = "rust-embed")]
+impl<E> Clone for EmbedLoader<E> {
+    fn clone(&self) -> Self {
+        EmbedLoader { extension: self.extension.clone(), embed: PhantomData }
+    }
+}
+
+#[cfg(feature = "rust-embed")]
+impl<E: RustEmbed> PartialLoader for EmbedLoader<E> {
+    fn load(&self, name: impl AsRef<Path>) -> Result<String> {
+        self.load_str(name).map(Cow::into_owned)
+    }
+
+    fn load_str(&self, name: impl AsRef<Path>) -> Result<Cow<'_, str>> {
+        let path = path(name.as_ref(), &self.extension);
+        // Embedded paths always use forward slashes.
+        let path = path.to_str().ok_or(Error::InvalidStr)?.replace('\\', "/");
+
+        match E::get(&path).map(|file| file.data) {
+            Some(Cow::Borrowed(bytes)) => str::from_utf8(bytes).map(Cow::Borrowed).map_err(|_| Error::InvalidStr),
+            Some(Cow::Owned(bytes)) => String::from_utf8(bytes).map(Cow::Owned).map_err(|_| Error::InvalidStr),
+            None => Ok(Cow::Borrowed("")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {Context, MapBuilder};
+
+    #[cfg(feature = "include_dir")]
+    #[test]
+    fn dir_loader() {
+        use include_dir::Dir;
+        use super::DirLoader;
+
+        static TEMPLATES: Dir = include_dir!("$CARGO_MANIFEST_DIR/tests/test-data");
+
+        let ctx = Context::with_loader(DirLoader::new(&TEMPLATES));
+        let template = ctx.compile("{{>user}}{{>missing}}".chars()).unwrap();
+        let data = MapBuilder::new().insert_str("name", "n").build();
+
+        assert_eq!(template.render_data_to_string(&data).unwrap(), "<strong>n</strong>\n");
+        assert_eq!(ctx.compile_path("user.mustache").unwrap().render_data_to_string(&data).unwrap(),
+                   "<strong>n</strong>\n");
+    }
+
+    #[cfg(feature = "rust-embed")]
+    #[test]
+    fn embed_loader() {
+        use super::EmbedLoader;
+
+        #[derive(RustEmbed)]
+        #[folder = "tests/test-data/"]
+        struct Templates;
+
+        let ctx = Context::with_loader(EmbedLoader::<Templates>::new());
+        let template = ctx.compile("{{>user}}{{>missing}}".chars()).unwrap();
+        let data = MapBuilder::new().insert_str("name", "n").build();
+
+        assert_eq!(template.render_data_to_string(&data).unwrap(), "<strong>n</strong>\n");
+    }
+}
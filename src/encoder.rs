@@ -2,6 +2,7 @@ use std::collections::HashMap;
 use std::error::Error as StdError;
 use std::fmt::{self, Display};
 use std::result::Result as StdResult;
+use std::time::Duration;
 
 use serde::{self, Serialize, ser};
 
@@ -12,6 +13,7 @@ use super::{Data, to_data};
 /// This type is not intended to be matched exhaustively as new variants
 /// may be added in future without a version bump.
 #[derive(Debug)]
+#[allow(clippy::manual_non_exhaustive)]
 pub enum Error {
     NestedOptions,
     UnsupportedType,
@@ -54,7 +56,7 @@ pub struct Encoder;
 
 impl Encoder {
     pub fn new() -> Encoder {
-        Encoder::default()
+        Encoder
     }
 }
 
@@ -110,6 +112,14 @@ impl serde::Serializer for Encoder {
         Ok(Data::String(v.to_string()))
     }
 
+    fn serialize_i128(self, v: i128) -> Result<Data> {
+        Ok(Data::String(v.to_string()))
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<Data> {
+        Ok(Data::String(v.to_string()))
+    }
+
     fn serialize_f32(self, v: f32) -> Result<Data> {
         Ok(Data::String(v.to_string()))
     }
@@ -123,8 +133,8 @@ impl serde::Serializer for Encoder {
     }
 
     fn serialize_unit_struct(self, _name: &'static str) -> Result<Data> {
-        // FIXME: Perhaps this could be relaxed to just 'do nothing'
-        Err(Error::UnsupportedType)
+        // Like `()`, there's nothing to render.
+        Ok(Data::Null)
     }
 
     fn serialize_unit_variant(
@@ -146,19 +156,21 @@ impl serde::Serializer for Encoder {
         Ok(Data::Null)
     }
 
-    fn serialize_some<T: ? Sized>(self, value: &T) -> Result<Data>
+    fn serialize_some<T>(self, value: &T) -> Result<Data>
     where
-        T: Serialize
+        T: ?Sized + Serialize
     {
         value.serialize(self)
     }
 
     fn serialize_struct(
         self,
-        _name: &'static str,
+        name: &'static str,
         len: usize,
     ) -> Result<Self::SerializeStruct> {
-        self.serialize_map(Some(len))
+        let mut map = self.serialize_map(Some(len))?;
+        map.duration = name == "Duration" && len == 2;
+        Ok(map)
     }
 
     fn serialize_struct_variant(
@@ -174,19 +186,19 @@ impl serde::Serializer for Encoder {
         })
     }
 
-    fn serialize_newtype_struct<T: ?Sized>(
+    fn serialize_newtype_struct<T>(
         self,
         _name: &'static str,
         value: &T,
     ) -> Result<Data>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
         // Ignore newtype name
         value.serialize(self)
     }
 
-    fn serialize_newtype_variant<T: ?Sized>(
+    fn serialize_newtype_variant<T>(
         self,
         _name: &'static str,
         _variant_index: u32,
@@ -194,18 +206,14 @@ impl serde::Serializer for Encoder {
         value: &T,
     ) -> Result<Data>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
         // Ignore newtype name
         value.serialize(self)
     }
 
     fn serialize_bytes(self, value: &[u8]) -> Result<Data> {
-        let vec = value.iter()
-            .map(|&b| Data::String(b.to_string()))
-            .collect();
-
-        Ok(Data::Vec(vec))
+        Ok(Data::Bytes(value.to_vec()))
     }
 
     fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq> {
@@ -243,6 +251,7 @@ impl serde::Serializer for Encoder {
         Ok(SerializeMap {
             map: HashMap::with_capacity(len.unwrap_or(0)),
             next_key: None,
+            duration: false,
         })
     }
 }
@@ -262,6 +271,8 @@ pub struct SerializeTupleVariant {
 pub struct SerializeMap {
     map: HashMap<String, Data>,
     next_key: Option<String>,
+    // Set for a struct that may be a `std::time::Duration`.
+    duration: bool,
 }
 
 #[doc(hidden)]
@@ -274,10 +285,10 @@ impl ser::SerializeSeq for SerializeVec {
     type Ok = Data;
     type Error = Error;
 
-    fn serialize_element<T: ?Sized>(&mut self, value: &T) -> Result<()>
-        where T: Serialize
+    fn serialize_element<T>(&mut self, value: &T) -> Result<()>
+        where T: ?Sized + Serialize
     {
-        self.vec.push(to_data(&value)?);
+        self.vec.push(to_data(value)?);
         Ok(())
     }
 
@@ -290,8 +301,8 @@ impl ser::SerializeTuple for SerializeVec {
     type Ok = Data;
     type Error = Error;
 
-    fn serialize_element<T: ?Sized>(&mut self, value: &T) -> Result<()>
-        where T: Serialize
+    fn serialize_element<T>(&mut self, value: &T) -> Result<()>
+        where T: ?Sized + Serialize
     {
         ser::SerializeSeq::serialize_element(self, value)
     }
@@ -305,8 +316,8 @@ impl ser::SerializeTupleStruct for SerializeVec {
     type Ok = Data;
     type Error = Error;
 
-    fn serialize_field<T: ?Sized>(&mut self, value: &T) -> Result<()>
-        where T: Serialize
+    fn serialize_field<T>(&mut self, value: &T) -> Result<()>
+        where T: ?Sized + Serialize
     {
         ser::SerializeSeq::serialize_element(self, value)
     }
@@ -320,10 +331,10 @@ impl ser::SerializeTupleVariant for SerializeTupleVariant {
     type Ok = Data;
     type Error = Error;
 
-    fn serialize_field<T: ?Sized>(&mut self, value: &T) -> Result<()>
-        where T: Serialize
+    fn serialize_field<T>(&mut self, value: &T) -> Result<()>
+        where T: ?Sized + Serialize
     {
-        self.vec.push(to_data(&value)?);
+        self.vec.push(to_data(value)?);
         Ok(())
     }
 
@@ -340,27 +351,33 @@ impl ser::SerializeMap for SerializeMap {
     type Ok = Data;
     type Error = Error;
 
-    fn serialize_key<T: ?Sized>(&mut self, key: &T) -> Result<()>
+    fn serialize_key<T>(&mut self, key: &T) -> Result<()>
     where
-        T: Serialize
+        T: ?Sized + Serialize
     {
+        // Numbers and chars already encode as strings, booleans are spelled out
+        // the same way.
         match to_data(key)? {
             Data::String(s) => {
                 self.next_key = Some(s);
                 Ok(())
             }
+            Data::Bool(b) => {
+                self.next_key = Some(b.to_string());
+                Ok(())
+            }
             _ => Err(Error::KeyIsNotString),
         }
     }
 
-    fn serialize_value<T: ?Sized>(&mut self, value: &T) -> Result<()>
+    fn serialize_value<T>(&mut self, value: &T) -> Result<()>
     where
-        T: Serialize
+        T: ?Sized + Serialize
     {
         // Taking the key should only fail if this gets called before
         // serialize_key, which is a bug in the library.
         let key = self.next_key.take().ok_or(Error::MissingElements)?;
-        self.map.insert(key, to_data(&value)?);
+        self.map.insert(key, to_data(value)?);
         Ok(())
     }
 
@@ -373,28 +390,44 @@ impl ser::SerializeStruct for SerializeMap {
     type Ok = Data;
     type Error = Error;
 
-    fn serialize_field<T: ?Sized>(&mut self, key: &'static str, value: &T) -> Result<()>
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<()>
     where
-        T: Serialize
+        T: ?Sized + Serialize
     {
         ser::SerializeMap::serialize_key(self, key)?;
         ser::SerializeMap::serialize_value(self, value)
     }
 
     fn end(self) -> Result<Data> {
+        if self.duration {
+            if let Some(duration) = duration(&self.map) {
+                return Ok(Data::String(format!("{:?}", duration)));
+            }
+        }
         ser::SerializeMap::end(self)
     }
 }
 
+// Serde writes a `Duration` as a struct of its `secs` and `nanos`.
+fn duration(map: &HashMap<String, Data>) -> Option<Duration> {
+    match (map.get("secs"), map.get("nanos")) {
+        (Some(Data::String(secs)), Some(Data::String(nanos))) => {
+            let nanos = nanos.parse().ok().filter(|&nanos| nanos < 1_000_000_000)?;
+            Some(Duration::new(secs.parse().ok()?, nanos))
+        }
+        _ => None,
+    }
+}
+
 impl ser::SerializeStructVariant for SerializeStructVariant {
     type Ok = Data;
     type Error = Error;
 
-    fn serialize_field<T: ?Sized>(&mut self, key: &'static str, value: &T) -> Result<()>
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<()>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
-        self.map.insert(String::from(key), to_data(&value)?);
+        self.map.insert(String::from(key), to_data(value)?);
         Ok(())
     }
 
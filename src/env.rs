@@ -0,0 +1,111 @@
+use std::collections::HashMap;
+use std::env;
+
+use Data;
+
+/// Environment variables made available to templates under the `env` key,
+/// `{{env.HOME}}`, for generating configuration files.
+///
+/// Only the variables allowed by name or prefix are read; anything else in
+/// the environment stays out of the data.
+///
+/// ```rust
+/// use mustache::{Env, MapBuilder};
+///
+/// let mut data = MapBuilder::new().insert_str("service", "api").build();
+/// Env::new().allow("HOME").allow_prefix("APP_").insert_into(&mut data);
+///
+/// let template = mustache::compile_str("{{service}} runs in {{env.HOME}}").unwrap();
+/// template.render_data_to_string(&data).unwrap();
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Env {
+    names: Vec<String>,
+    prefixes: Vec<String>,
+}
+
+impl Env {
+    /// The key the variables are inserted under.
+    pub const KEY: &'static str = "env";
+
+    /// An `Env` that allows nothing yet.
+    pub fn new() -> Env {
+        Env::default()
+    }
+
+    /// Allows the variable `name`.
+    pub fn allow<T: Into<String>>(mut self, name: T) -> Env {
+        self.names.push(name.into());
+        self
+    }
+
+    /// Allows every variable whose name starts with `prefix`.
+    pub fn allow_prefix<T: Into<String>>(mut self, prefix: T) -> Env {
+        self.prefixes.push(prefix.into());
+        self
+    }
+
+    /// The allowed variables that are set, as a map from their names to
+    /// their values. Variables that aren't valid unicode are left out.
+    pub fn to_data(&self) -> Data {
+        let mut vars = HashMap::new();
+
+        for name in &self.names {
+            if let Ok(value) = env::var(name) {
+                vars.insert(name.clone(), Data::String(value));
+            }
+        }
+
+        if !self.prefixes.is_empty() {
+            for (name, value) in env::vars_os() {
+                if let (Some(name), Ok(value)) = (name.to_str(), value.into_string()) {
+                    if self.prefixes.iter().any(|prefix| name.starts_with(&prefix[..])) {
+                        vars.insert(name.to_string(), Data::String(value));
+                    }
+                }
+            }
+        }
+
+        Data::Map(vars)
+    }
+
+    /// Inserts the variables into `data` under [`Env::KEY`], replacing
+    /// whatever was there. Does nothing unless `data` is a map.
+    pub fn insert_into(&self, data: &mut Data) {
+        if let Data::Map(ref mut map) = *data {
+            map.insert(Env::KEY.to_string(), self.to_data());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::Env;
+    use {Data, MapBuilder};
+
+    #[test]
+    fn allowed() {
+        env::set_var("MUSTACHE_ENV_TEST_A", "a");
+        env::set_var("MUSTACHE_ENV_TEST_PREFIXED_B", "b");
+        env::set_var("MUSTACHE_ENV_TEST_HIDDEN", "hidden");
+
+        let mut data = MapBuilder::new().insert_str("env", "replaced").insert_str("x", "x").build();
+        Env::new()
+            .allow("MUSTACHE_ENV_TEST_A")
+            .allow("MUSTACHE_ENV_TEST_UNSET")
+            .allow_prefix("MUSTACHE_ENV_TEST_PREFIXED_")
+            .insert_into(&mut data);
+
+        let template = ::compile_str(concat!(
+            "{{x}} {{env.MUSTACHE_ENV_TEST_A}} {{env.MUSTACHE_ENV_TEST_PREFIXED_B}}",
+            "[{{env.MUSTACHE_ENV_TEST_HIDDEN}}]{{^env.MUSTACHE_ENV_TEST_UNSET}}unset{{/env.MUSTACHE_ENV_TEST_UNSET}}",
+        )).unwrap();
+        assert_eq!(template.render_data_to_string(&data).unwrap(), "x a b[]unset");
+
+        let mut list = Data::Vec(Vec::new());
+        Env::new().allow("MUSTACHE_ENV_TEST_A").insert_into(&mut list);
+        assert_eq!(list, Data::Vec(Vec::new()));
+    }
+}
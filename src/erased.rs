@@ -0,0 +1,83 @@
+//! Rendering through trait objects, enabled with the `erased` feature.
+
+use std::io::Write;
+
+use erased_serde::Serialize;
+
+use {to_data, Data, PartialLoader, Result, Template};
+
+/// A [`Template`](struct.Template.html) with its loader type hidden, so
+/// templates from different loaders can sit behind one `Box<dyn DynTemplate>`.
+///
+/// ```
+/// use std::collections::HashMap;
+/// use mustache::DynTemplate;
+///
+/// let mut templates: HashMap<&str, Box<dyn DynTemplate>> = HashMap::new();
+/// templates.insert("hello", Box::new(mustache::compile_str("Hello {{name}}").unwrap()));
+///
+/// let mut data = HashMap::new();
+/// data.insert("name", "world");
+///
+/// let mut out = Vec::new();
+/// templates["hello"].render_erased(&mut out, &data).unwrap();
+/// assert_eq!(out, b"Hello world");
+/// ```
+pub trait DynTemplate {
+    /// Renders the template with any `Serialize` data.
+    fn render_erased(&self, wr: &mut dyn Write, data: &dyn Serialize) -> Result<()>;
+
+    /// Renders the template with the `Data`.
+    fn render_data_erased(&self, wr: &mut dyn Write, data: &Data) -> Result<()>;
+}
+
+impl<P: PartialLoader> DynTemplate for Template<P> {
+    fn render_erased(&self, wr: &mut dyn Write, data: &dyn Serialize) -> Result<()> {
+        Template::render_erased(self, wr, data)
+    }
+
+    fn render_data_erased(&self, mut wr: &mut dyn Write, data: &Data) -> Result<()> {
+        self.render_data(&mut wr, data)
+    }
+}
+
+impl<P: PartialLoader> Template<P> {
+    /// Renders the template with data and a writer behind trait objects,
+    /// for callers that can't be generic over them.
+    pub fn render_erased(&self, mut wr: &mut dyn Write, data: &dyn Serialize) -> Result<()> {
+        let data = to_data(data)?;
+        self.render_data(&mut wr, &data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::DynTemplate;
+    use {Context, MapBuilder, PartialLoader, Result};
+
+    #[derive(Clone, Debug)]
+    struct Loader;
+
+    impl PartialLoader for Loader {
+        fn load(&self, _: impl AsRef<Path>) -> Result<String> {
+            Ok("<{{name}}>".to_string())
+        }
+    }
+
+    #[test]
+    fn mixed_loaders() {
+        let templates: Vec<Box<dyn DynTemplate>> = vec![
+            Box::new(Context::new(PathBuf::from("tests/test-data")).compile("{{>user}}".chars()).unwrap()),
+            Box::new(Context::with_loader(Loader).compile("{{>any}}".chars()).unwrap()),
+        ];
+        let data = MapBuilder::new().insert_str("name", "n").build();
+
+        let mut out = Vec::new();
+        for template in &templates {
+            template.render_data_erased(&mut out, &data).unwrap();
+        }
+        assert_eq!(String::from_utf8(out).unwrap(), "<strong>n</strong>\n<n>");
+    }
+}
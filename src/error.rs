@@ -1,7 +1,9 @@
 use std::fmt;
 use std::io::Error as StdIoError;
+use std::path::PathBuf;
 use std::result::Result as StdResult;
 
+use data::LambdaError;
 use parser;
 use encoder;
 
@@ -10,6 +12,7 @@ use encoder;
 /// This type is not intended to be matched exhaustively as new variants
 /// may be added in future without a version bump.
 #[derive(Debug)]
+#[allow(clippy::manual_non_exhaustive)]
 pub enum Error {
     InvalidStr,
     NoFilename,
@@ -17,6 +20,72 @@ pub enum Error {
     Io(StdIoError),
     Parser(parser::Error),
     Encoder(encoder::Error),
+    /// The render was cancelled through
+    /// [`RenderOptions::cancel`](struct.RenderOptions.html#structfield.cancel).
+    Cancelled,
+    /// The lambda at `name` failed.
+    Lambda { name: String, source: LambdaError },
+    /// The lambda at `name` panicked, caught because of
+    /// [`RenderOptions::catch_lambda_panics`](struct.RenderOptions.html#structfield.catch_lambda_panics).
+    LambdaPanicked { name: String },
+    /// A value escaped with [`Escape::Xml`](enum.Escape.html#variant.Xml)
+    /// held a character XML 1.0 does not allow.
+    InvalidXmlChar(char),
+    /// A value held a Unicode bidirectional control, with
+    /// [`RenderOptions::bidi_controls`](struct.RenderOptions.html#structfield.bidi_controls)
+    /// set to reject them.
+    BidiControl(char),
+    /// The render included more partials than
+    /// [`RenderOptions::max_partials`](struct.RenderOptions.html#structfield.max_partials)
+    /// allows.
+    TooManyPartials(usize),
+    /// The render went through section contents more often than
+    /// [`RenderOptions::max_iterations`](struct.RenderOptions.html#structfield.max_iterations)
+    /// allows.
+    TooManyIterations(usize),
+    /// A partial name a loader refused, such as one that climbs out of its
+    /// templates with `..`.
+    InvalidPartialName(String),
+    /// Names a [`TypedTemplate`](struct.TypedTemplate.html) uses that its
+    /// data type doesn't have.
+    UnknownNames(Vec<String>),
+    /// [`Template::render_section`](struct.Template.html#method.render_section)
+    /// was asked for a section the template doesn't have.
+    NoSuchSection(String),
+    /// A template or partial source was longer than
+    /// [`CompileLimits::max_source_len`](struct.CompileLimits.html#structfield.max_source_len)
+    /// allows.
+    SourceTooLong(usize),
+    /// The template and its partials had more tokens than
+    /// [`CompileLimits::max_tokens`](struct.CompileLimits.html#structfield.max_tokens)
+    /// allows.
+    TooManyTokens(usize),
+    /// Sections were nested deeper than
+    /// [`CompileLimits::max_depth`](struct.CompileLimits.html#structfield.max_depth)
+    /// allows.
+    NestingTooDeep(usize),
+    /// The loader failed on the partial `name`, included on `line` of
+    /// `template`. `template` is the name that was compiled, `None` for a
+    /// template compiled from a string.
+    PartialLoad { name: String, template: Option<String>, line: usize, source: Box<Error> },
+    /// A syntax error in the partial `name`, found at `line` and `column` of
+    /// the partial's own source. `path` is where its loader found it, see
+    /// [`PartialLoader::path`](trait.PartialLoader.html#method.path).
+    PartialSyntax { name: String, path: Option<PathBuf>, line: usize, column: usize, source: parser::Error },
+    /// A file of several templates, see
+    /// [`Context::compile_many`](struct.Context.html#method.compile_many),
+    /// had two named `name`.
+    DuplicateTemplate(String),
+    /// The front matter of a template wasn't valid YAML, or didn't
+    /// deserialize to the type asked for, see
+    /// [`Template::front_matter`](struct.Template.html#method.front_matter).
+    FrontMatter(String),
+    /// Partials a template includes that are empty or missing, found by
+    /// [`Context::prewarm`](struct.Context.html#method.prewarm).
+    MissingPartials(Vec<String>),
+    /// The render failed while inside `partials`, outermost first. Running
+    /// out of a render limit or being cancelled isn't wrapped this way.
+    InPartials { partials: Vec<String>, source: Box<Error> },
 
     #[doc(hidden)]
     __Nonexhaustive,
@@ -33,6 +102,41 @@ impl fmt::Display for Error {
             Error::Io(ref err) => err.to_string(),
             Error::Parser(ref err) => err.to_string(),
             Error::Encoder(ref err) => err.to_string(),
+            Error::Cancelled => "the render was cancelled".to_string(),
+            Error::Lambda { ref name, ref source } => format!("lambda {:?} failed: {}", name, source),
+            Error::LambdaPanicked { ref name } => format!("lambda {:?} panicked", name),
+            Error::InvalidXmlChar(c) => format!("{:?} is not allowed in XML", c),
+            Error::BidiControl(c) => format!("a value held the bidirectional control {:?}", c),
+            Error::TooManyPartials(max) => format!("the render included more than {} partials", max),
+            Error::TooManyIterations(max) => format!("the render went through sections more than {} times", max),
+            Error::InvalidPartialName(ref name) => format!("invalid partial name {:?}", name),
+            Error::UnknownNames(ref names) => format!("the data type has no {}", names.join(", ")),
+            Error::NoSuchSection(ref name) => format!("the template has no section {:?}", name),
+            Error::SourceTooLong(max) => format!("a template source was longer than {} bytes", max),
+            Error::TooManyTokens(max) => format!("the template and its partials had more than {} tokens", max),
+            Error::NestingTooDeep(max) => format!("sections were nested more than {} deep", max),
+            Error::PartialLoad { ref name, template: Some(ref template), line, ref source } => {
+                format!("partial {:?} referenced from {}:{}: {}", name, template, line, source)
+            }
+            Error::PartialLoad { ref name, template: None, line, ref source } => {
+                format!("partial {:?} referenced from line {}: {}", name, line, source)
+            }
+            Error::PartialSyntax { ref name, path: Some(ref path), line, column, ref source } => {
+                format!("partial {:?} at {}:{}:{}: {}", name, path.display(), line, column, source)
+            }
+            Error::PartialSyntax { ref name, path: None, line, column, ref source } => {
+                format!("partial {:?} at line {}, column {}: {}", name, line, column, source)
+            }
+            Error::DuplicateTemplate(ref name) => format!("there are two templates named {:?}", name),
+            Error::FrontMatter(ref err) => format!("invalid front matter: {}", err),
+            Error::MissingPartials(ref names) => {
+                let names: Vec<_> = names.iter().map(|name| format!("{:?}", name)).collect();
+                format!("missing partials: {}", names.join(", "))
+            }
+            Error::InPartials { ref partials, ref source } => {
+                let partials: Vec<_> = partials.iter().map(|name| format!("{:?}", name)).collect();
+                format!("in partial {}: {}", partials.join(" > "), source)
+            }
             Error::__Nonexhaustive => unreachable!(),
         })
     }
@@ -55,3 +159,33 @@ impl From<encoder::Error> for Error {
         Error::Encoder(err)
     }
 }
+
+/// A non-fatal problem noticed while rendering.
+///
+/// Warnings never stop a render; they are only collected when asked for,
+/// see [`Template::render_data_with_warnings`](struct.Template.html#method.render_data_with_warnings).
+///
+/// This type is not intended to be matched exhaustively as new variants
+/// may be added in future without a version bump.
+#[derive(Clone, Debug, PartialEq)]
+#[allow(clippy::manual_non_exhaustive)]
+pub enum Warning {
+    /// A variable or section name could not be found in the data.
+    MissingKey(String),
+    /// A partial was included but has no content, usually because it could
+    /// not be found by the loader.
+    EmptyPartial(String),
+
+    #[doc(hidden)]
+    __Nonexhaustive,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Warning::MissingKey(ref name) => write!(f, "no value found for {:?}", name),
+            Warning::EmptyPartial(ref name) => write!(f, "partial {:?} is empty or missing", name),
+            Warning::__Nonexhaustive => unreachable!(),
+        }
+    }
+}
@@ -0,0 +1,203 @@
+//! A C API, enabled with the `ffi` feature, for embedding the library in
+//! other languages. Build it as a shared library with
+//! `cargo rustc --release --features ffi --crate-type cdylib`.
+//!
+//! ```c
+//! typedef struct mustache_template mustache_template;
+//!
+//! int mustache_compile(const char *source, mustache_template **out);
+//! int mustache_render_json(const mustache_template *template, const char *json, char **out);
+//! void mustache_free(char *string);
+//! void mustache_template_free(mustache_template *template);
+//! ```
+//!
+//! Every function returning an `int` returns one of the `MUSTACHE_*` codes
+//! below, and only writes to `out` on `MUSTACHE_OK`. The codes are stable,
+//! new ones may be added.
+
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::panic::{self, AssertUnwindSafe};
+
+use serde_json::{self, Value};
+
+use {compile_str, to_data, DefaultLoader, Template};
+
+/// Success.
+pub const MUSTACHE_OK: c_int = 0;
+/// A required pointer was null.
+pub const MUSTACHE_NULL_POINTER: c_int = 1;
+/// A string was not valid UTF-8, or the output contained a nul byte.
+pub const MUSTACHE_INVALID_STRING: c_int = 2;
+/// The template failed to compile.
+pub const MUSTACHE_COMPILE_ERROR: c_int = 3;
+/// The data was not valid JSON.
+pub const MUSTACHE_JSON_ERROR: c_int = 4;
+/// The template failed to render.
+pub const MUSTACHE_RENDER_ERROR: c_int = 5;
+/// A bug in the library, which panicked.
+pub const MUSTACHE_PANIC: c_int = 6;
+
+/// A compiled template, opaque to C.
+pub struct MustacheTemplate(Template<DefaultLoader>);
+
+// Runs `f`, making sure a panic doesn't unwind into the caller.
+fn guard<F: FnOnce() -> c_int>(f: F) -> c_int {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(MUSTACHE_PANIC)
+}
+
+unsafe fn to_str<'a>(s: *const c_char) -> Result<&'a str, c_int> {
+    if s.is_null() {
+        return Err(MUSTACHE_NULL_POINTER);
+    }
+    CStr::from_ptr(s).to_str().map_err(|_| MUSTACHE_INVALID_STRING)
+}
+
+/// Compiles the nul-terminated `source` into a template, stored in `out`.
+/// Partials are loaded relative to the working directory.
+///
+/// # Safety
+///
+/// `source` must be a nul-terminated string and `out` must be valid for
+/// writes. The template must be released with `mustache_template_free`.
+#[no_mangle]
+pub unsafe extern "C" fn mustache_compile(source: *const c_char, out: *mut *mut MustacheTemplate) -> c_int {
+    guard(|| {
+        if out.is_null() {
+            return MUSTACHE_NULL_POINTER;
+        }
+
+        let source = match to_str(source) {
+            Ok(source) => source,
+            Err(code) => return code,
+        };
+
+        match compile_str(source) {
+            Ok(template) => {
+                *out = Box::into_raw(Box::new(MustacheTemplate(template)));
+                MUSTACHE_OK
+            }
+            Err(_) => MUSTACHE_COMPILE_ERROR,
+        }
+    })
+}
+
+/// Renders `template` with the nul-terminated JSON `json`, storing the
+/// output in `out`.
+///
+/// # Safety
+///
+/// `template` must come from `mustache_compile`, `json` must be a
+/// nul-terminated string and `out` must be valid for writes. The output
+/// must be released with `mustache_free`.
+#[no_mangle]
+pub unsafe extern "C" fn mustache_render_json(template: *const MustacheTemplate,
+                                              json: *const c_char,
+                                              out: *mut *mut c_char) -> c_int {
+    guard(|| {
+        if template.is_null() || out.is_null() {
+            return MUSTACHE_NULL_POINTER;
+        }
+
+        let json = match to_str(json) {
+            Ok(json) => json,
+            Err(code) => return code,
+        };
+
+        let data = match serde_json::from_str::<Value>(json).map(to_data) {
+            Ok(Ok(data)) => data,
+            _ => return MUSTACHE_JSON_ERROR,
+        };
+
+        let output = match (*template).0.render_data_to_string(&data) {
+            Ok(output) => output,
+            Err(_) => return MUSTACHE_RENDER_ERROR,
+        };
+
+        match CString::new(output) {
+            Ok(output) => {
+                *out = output.into_raw();
+                MUSTACHE_OK
+            }
+            Err(_) => MUSTACHE_INVALID_STRING,
+        }
+    })
+}
+
+/// Releases a string returned by the library. Null is ignored.
+///
+/// # Safety
+///
+/// `string` must come from `mustache_render_json` and not be freed twice.
+#[no_mangle]
+pub unsafe extern "C" fn mustache_free(string: *mut c_char) {
+    if !string.is_null() {
+        drop(CString::from_raw(string));
+    }
+}
+
+/// Releases a template. Null is ignored.
+///
+/// # Safety
+///
+/// `template` must come from `mustache_compile` and not be freed twice.
+#[no_mangle]
+pub unsafe extern "C" fn mustache_template_free(template: *mut MustacheTemplate) {
+    if !template.is_null() {
+        drop(Box::from_raw(template));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::{CStr, CString};
+    use std::ptr;
+
+    use super::*;
+
+    fn compile(source: &str) -> (c_int, *mut MustacheTemplate) {
+        let source = CString::new(source).unwrap();
+        let mut template = ptr::null_mut();
+        let code = unsafe { mustache_compile(source.as_ptr(), &mut template) };
+        (code, template)
+    }
+
+    fn render(template: *const MustacheTemplate, json: &str) -> Result<String, c_int> {
+        let json = CString::new(json).unwrap();
+        let mut out = ptr::null_mut();
+        unsafe {
+            match mustache_render_json(template, json.as_ptr(), &mut out) {
+                MUSTACHE_OK => {
+                    let output = CStr::from_ptr(out).to_str().unwrap().to_string();
+                    mustache_free(out);
+                    Ok(output)
+                }
+                code => Err(code),
+            }
+        }
+    }
+
+    #[test]
+    fn render_json() {
+        let (code, template) = compile("{{#users}}<{{name}}>{{/users}}");
+        assert_eq!(code, MUSTACHE_OK);
+
+        assert_eq!(render(template, r#"{"users": [{"name": "a"}, {"name": "b&c"}]}"#),
+                   Ok("<a><b&amp;c>".to_string()));
+        assert_eq!(render(template, "{"), Err(MUSTACHE_JSON_ERROR));
+
+        unsafe { mustache_template_free(template) };
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(compile("{{#a}}").0, MUSTACHE_COMPILE_ERROR);
+        assert_eq!(unsafe { mustache_compile(ptr::null(), &mut ptr::null_mut()) }, MUSTACHE_NULL_POINTER);
+        assert_eq!(render(ptr::null(), "{}"), Err(MUSTACHE_NULL_POINTER));
+
+        unsafe {
+            mustache_free(ptr::null_mut());
+            mustache_template_free(ptr::null_mut());
+        }
+    }
+}
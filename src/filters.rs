@@ -0,0 +1,318 @@
+//! Helpers for the usual small transformations of values, so templates
+//! don't need a lambda of their own for each.
+//!
+//! Each one is a [`Data::Helper`](../enum.Data.html#variant.Helper) lambda,
+//! given by [`all`] under its name. The text filters, [`upper`], [`lower`],
+//! [`trim`], [`truncate`] and [`default`], work on what their section renders
+//! to, `{{#upper}}{{name}}{{/upper}}`, and leave the character references
+//! escaping wrote alone. [`join`], [`length`] and [`json`] are given the name
+//! of a value instead, `{{#length}}items{{/length}}`. Filters taking an
+//! argument read it ahead of a `|`, `{{#truncate}}20|{{title}}{{/truncate}}`.
+//!
+//! ```
+//! use mustache::{filters, Data, MapBuilder};
+//!
+//! let template = mustache::compile_str(
+//!     "{{#upper}}{{name}}{{/upper}} ({{#join}}, |tags{{/join}}): {{#default}}none|{{bio}}{{/default}}",
+//! ).unwrap();
+//!
+//! let mut data = MapBuilder::new()
+//!     .insert_str("name", "Tom & Jerry")
+//!     .insert_vec("tags", |v| v.push_str("cat").push_str("<mouse>"))
+//!     .build();
+//! if let Data::Map(ref mut map) = data {
+//!     map.extend(filters::all());
+//! }
+//! assert_eq!(
+//!     template.render_data_to_string(&data).unwrap(),
+//!     "TOM &amp; JERRY (cat, &lt;mouse&gt;): none",
+//! );
+//! ```
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use {Data, Scope};
+
+// What `truncate` ends cut text with.
+const ELLIPSIS: &str = "…";
+
+fn helper<F>(f: F) -> Data
+where F: Fn(String, &Scope) -> String + Send + 'static
+{
+    Data::Helper(RefCell::new(Box::new(move |content: String, scope: &Scope| f(content, scope))))
+}
+
+/// Every filter of this module, under its name.
+pub fn all() -> HashMap<String, Data> {
+    let filters = [
+        ("upper", upper as fn() -> Data),
+        ("lower", lower),
+        ("trim", trim),
+        ("truncate", truncate),
+        ("join", join),
+        ("default", default),
+        ("length", length),
+        ("json", json),
+    ];
+    filters.iter().map(|&(name, filter)| (name.to_string(), filter())).collect()
+}
+
+/// Writes its section in upper case, `{{#upper}}{{name}}{{/upper}}`.
+pub fn upper() -> Data {
+    helper(|content, _| map_text(&content, str::to_uppercase))
+}
+
+/// Writes its section in lower case, `{{#lower}}{{name}}{{/lower}}`.
+pub fn lower() -> Data {
+    helper(|content, _| map_text(&content, str::to_lowercase))
+}
+
+/// Writes its section without leading and trailing whitespace,
+/// `{{#trim}}{{name}}{{/trim}}`.
+pub fn trim() -> Data {
+    helper(|content, _| content.trim().to_string())
+}
+
+/// Writes at most the given number of characters of its section,
+/// `{{#truncate}}20|{{title}}{{/truncate}}`, ending text it cuts with `…`
+/// in place of the last one. A character reference counts as one character.
+/// A section whose count isn't a number is written as it is.
+pub fn truncate() -> Data {
+    helper(|content, _| {
+        let (count, text) = match split_first(&content) {
+            Some(parts) => parts,
+            None => return content,
+        };
+        match count.trim().parse() {
+            Ok(count) => cut(text, count),
+            Err(_) => content,
+        }
+    })
+}
+
+/// Writes the strings of a list with the separator ahead of the last `|`
+/// between them, `{{#join}}, |tags{{/join}}`. The strings are escaped the
+/// way tags are; items that aren't strings are left out.
+pub fn join() -> Data {
+    helper(|content, scope| {
+        let (separator, name) = match content.rfind('|') {
+            Some(i) => (&content[..i], &content[i + 1..]),
+            None => return String::new(),
+        };
+        let items = match scope.lookup(name.trim()) {
+            Some(Data::Vec(items)) => items,
+            _ => return String::new(),
+        };
+
+        let strings: Vec<_> = items.iter().filter_map(|item| match *item {
+            Data::String(ref s) => Some(scope.escape(s)),
+            _ => None,
+        }).collect();
+        strings.join(separator)
+    })
+}
+
+/// Writes its section, or the text ahead of the first `|` when the rest is
+/// blank, `{{#default}}anonymous|{{nickname}}{{/default}}`.
+pub fn default() -> Data {
+    helper(|content, _| match split_first(&content) {
+        Some((fallback, text)) if text.trim().is_empty() => fallback.to_string(),
+        Some((_, text)) => text.to_string(),
+        None => content,
+    })
+}
+
+/// Writes how many items a list or map has or how many characters a string
+/// has, `{{#length}}items{{/length}}`, `0` for a missing or null value.
+pub fn length() -> Data {
+    helper(|content, scope| {
+        let length = match scope.lookup(content.trim()) {
+            None | Some(Data::Null) => 0,
+            Some(Data::String(s)) => s.chars().count(),
+            Some(Data::Bytes(bytes)) => bytes.len(),
+            Some(Data::Vec(items)) => items.len(),
+            Some(Data::Map(map)) => map.len(),
+            Some(_) => return String::new(),
+        };
+        length.to_string()
+    })
+}
+
+/// Writes a value as JSON, `{{#json}}user{{/json}}`, to hand data to the
+/// scripts of a page: `<`, `>` and `&` are written as `\u` escapes so it can
+/// sit in a `<script>` element. Numbers are strings in `Data`, and stay
+/// strings; missing values and lambdas are `null`.
+pub fn json() -> Data {
+    helper(|content, scope| {
+        let mut out = String::new();
+        write_json(&mut out, scope.lookup(content.trim()));
+        out
+    })
+}
+
+// `text` split at its first `|`.
+fn split_first(text: &str) -> Option<(&str, &str)> {
+    text.find('|').map(|i| (&text[..i], &text[i + 1..]))
+}
+
+// How long the character or character reference `text` starts with is.
+fn unit_len(text: &str) -> usize {
+    if let Some(rest) = text.strip_prefix('&') {
+        if let Some(end) = rest.find(';') {
+            let name = &rest[..end];
+            if !name.is_empty() && name.len() <= 32 && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '#') {
+                return end + 2;
+            }
+        }
+    }
+    text.chars().next().map_or(0, char::len_utf8)
+}
+
+// Applies `f` to `text` around its character references.
+fn map_text<F: Fn(&str) -> String>(text: &str, f: F) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while !rest.is_empty() {
+        let plain = rest.find('&').unwrap_or(rest.len());
+        out.push_str(&f(&rest[..plain]));
+        rest = &rest[plain..];
+
+        let len = unit_len(rest);
+        if len > 1 {
+            out.push_str(&rest[..len]);
+        } else {
+            out.push_str(&f(&rest[..len]));
+        }
+        rest = &rest[len..];
+    }
+    out
+}
+
+// The first `count` characters of `text`, the last an ellipsis if any were
+// left out.
+fn cut(text: &str, count: usize) -> String {
+    // Where each character ends, until there is one too many.
+    let mut ends = Vec::new();
+    let mut end = 0;
+    while end < text.len() && ends.len() <= count {
+        end += unit_len(&text[end..]);
+        ends.push(end);
+    }
+
+    match ends.len() {
+        len if len <= count => text.to_string(),
+        _ if count == 0 => String::new(),
+        _ if count == 1 => ELLIPSIS.to_string(),
+        _ => format!("{}{}", &text[..ends[count - 2]], ELLIPSIS),
+    }
+}
+
+fn write_json(out: &mut String, value: Option<&Data>) {
+    match value {
+        Some(Data::String(s)) => write_json_str(out, s),
+        Some(Data::Bool(b)) => out.push_str(if *b { "true" } else { "false" }),
+        Some(Data::Bytes(bytes)) => {
+            out.push('[');
+            for (i, byte) in bytes.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                let _ = write!(out, "{}", byte);
+            }
+            out.push(']');
+        }
+        Some(Data::Vec(items)) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_json(out, Some(item));
+            }
+            out.push(']');
+        }
+        Some(Data::Map(map)) => {
+            // Sorted, so the same data gives the same JSON.
+            let mut keys: Vec<_> = map.keys().collect();
+            keys.sort();
+
+            out.push('{');
+            for (i, key) in keys.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_json_str(out, key);
+                out.push(':');
+                write_json(out, map.get(key));
+            }
+            out.push('}');
+        }
+        _ => out.push_str("null"),
+    }
+}
+
+fn write_json_str(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            // Those end a `<script>`, start a reference, or end a line of
+            // older JavaScript.
+            '<' | '>' | '&' | '\u{2028}' | '\u{2029}' => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c if c < ' ' => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{cut, map_text, write_json};
+    use MapBuilder;
+
+    #[test]
+    fn text() {
+        assert_eq!(map_text("tom &amp; &#39;jerry&#39; & co;", str::to_uppercase), "TOM &amp; &#39;JERRY&#39; & CO;");
+
+        let cases = &[
+            ("hello world", 20, "hello world"),
+            ("hello world", 11, "hello world"),
+            ("hello world", 6, "hello…"),
+            ("a &amp; b", 4, "a &amp;…"),
+            ("a &amp; b", 3, "a …"),
+            ("héllo", 2, "h…"),
+            ("hello", 1, "…"),
+            ("hello", 0, ""),
+            ("", 0, ""),
+        ];
+        for &(text, count, expected) in cases {
+            assert_eq!(cut(text, count), expected, "{:?} {}", text, count);
+        }
+    }
+
+    #[test]
+    fn json() {
+        let data = MapBuilder::new()
+            .insert_str("name", "</script>\"\n")
+            .insert_bool("admin", false)
+            .insert_vec("ids", |v| v.push_str("1").push_fn(|s| s))
+            .insert_bytes("key", vec![0, 255])
+            .insert_fn("f", |s| s)
+            .build();
+
+        let mut out = String::new();
+        write_json(&mut out, Some(&data));
+        assert_eq!(out, r#"{"admin":false,"f":null,"ids":["1",null],"key":[0,255],"name":"\u003c/script\u003e\"\n"}"#);
+    }
+}
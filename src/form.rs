@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+
+use Data;
+
+/// Parses an `application/x-www-form-urlencoded` body or query string into
+/// `Data`, enabled with the `form` feature.
+///
+/// Brackets in a name nest it: `c[d]=z` makes `c` a map and `b[0]=x` or
+/// `b[]=x` makes `b` a vector, ordered by index. Every value is a string, a
+/// name given twice keeps the last value, pairs without a name are skipped
+/// and malformed escapes are kept as written.
+///
+/// ```
+/// use mustache::{from_form_str, MapBuilder};
+///
+/// let data = from_form_str("a=1&b[0]=x&b[1]=y+z&c[d]=%C3%A9");
+///
+/// assert_eq!(data, MapBuilder::new()
+///     .insert_str("a", "1")
+///     .insert_vec("b", |v| v.push_str("x").push_str("y z"))
+///     .insert_map("c", |m| m.insert_str("d", "é"))
+///     .build());
+/// ```
+pub fn from_form_str(input: &str) -> Data {
+    let mut root = HashMap::new();
+
+    for pair in input.split('&').filter(|pair| !pair.is_empty()) {
+        let (name, value) = match pair.find('=') {
+            Some(i) => (&pair[..i], &pair[i + 1..]),
+            None => (pair, ""),
+        };
+
+        let name = decode(name);
+        if !name.is_empty() {
+            insert(&mut root, &split_name(&name), Data::String(decode(value)));
+        }
+    }
+
+    // The root stays a map whatever its names.
+    for value in root.values_mut() {
+        into_vecs(value);
+    }
+
+    Data::Map(root)
+}
+
+// Splits `b[0][c]` into `b`, `0` and `c`, leaving names with unbalanced
+// brackets alone.
+fn split_name(name: &str) -> Vec<String> {
+    let (base, mut rest) = match name.find('[') {
+        Some(i) if i > 0 => (&name[..i], &name[i..]),
+        _ => return vec![name.to_string()],
+    };
+
+    let mut path = vec![base.to_string()];
+    while !rest.is_empty() {
+        match (rest.starts_with('['), rest.find(']')) {
+            (true, Some(end)) => {
+                path.push(rest[1..end].to_string());
+                rest = &rest[end + 1..];
+            }
+            _ => return vec![name.to_string()],
+        }
+    }
+
+    path
+}
+
+fn insert(map: &mut HashMap<String, Data>, path: &[String], value: Data) {
+    let (first, rest) = match path.split_first() {
+        Some(split) => split,
+        None => return,
+    };
+
+    // `b[]` appends, which comes down to the next free index.
+    let key = if first.is_empty() { map.len().to_string() } else { first.clone() };
+
+    if rest.is_empty() {
+        map.insert(key, value);
+        return;
+    }
+
+    let entry = map.entry(key).or_insert_with(|| Data::Map(HashMap::new()));
+    if let Data::Map(ref mut inner) = *entry {
+        return insert(inner, rest, value);
+    }
+
+    let mut inner = HashMap::new();
+    insert(&mut inner, rest, value);
+    *entry = Data::Map(inner);
+}
+
+// Turns the maps keyed only by indices into vectors.
+fn into_vecs(data: &mut Data) {
+    if let Data::Map(ref mut map) = *data {
+        for value in map.values_mut() {
+            into_vecs(value);
+        }
+
+        if map.is_empty() || !map.keys().all(|key| is_index(key)) {
+            return;
+        }
+
+        let mut items: Vec<(usize, Data)> = map.drain()
+            .map(|(key, value)| (key.parse().unwrap_or(0), value))
+            .collect();
+        items.sort_by_key(|item| item.0);
+        *data = Data::Vec(items.into_iter().map(|(_, value)| value).collect());
+    }
+}
+
+fn is_index(key: &str) -> bool {
+    key.bytes().all(|b| b.is_ascii_digit()) && key.parse::<usize>().is_ok()
+}
+
+// Undoes the `+` and `%XX` escapes of a form value.
+fn decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' => match (bytes.get(i + 1).and_then(|&b| hex(b)), bytes.get(i + 2).and_then(|&b| hex(b))) {
+                (Some(high), Some(low)) => {
+                    out.push(high << 4 | low);
+                    i += 2;
+                }
+                _ => out.push(b'%'),
+            },
+            b => out.push(b),
+        }
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn hex(b: u8) -> Option<u8> {
+    match b {
+        b'0'..=b'9' => Some(b - b'0'),
+        b'a'..=b'f' => Some(b - b'a' + 10),
+        b'A'..=b'F' => Some(b - b'A' + 10),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::from_form_str;
+    use MapBuilder;
+
+    #[test]
+    fn nesting() {
+        assert_eq!(from_form_str("b[]=x&b[]=y&u[0][name]=a&u[1][name]=b"), MapBuilder::new()
+            .insert_vec("b", |v| v.push_str("x").push_str("y"))
+            .insert_vec("u", |v| {
+                v.push_map(|m| m.insert_str("name", "a"))
+                    .push_map(|m| m.insert_str("name", "b"))
+            })
+            .build());
+
+        // Indices only order the items.
+        assert_eq!(from_form_str("b[10]=y&b[2]=x"), MapBuilder::new()
+            .insert_vec("b", |v| v.push_str("x").push_str("y"))
+            .build());
+    }
+
+    #[test]
+    fn edge_cases() {
+        assert_eq!(from_form_str(""), MapBuilder::new().build());
+        assert_eq!(from_form_str("a&a=1&a=2&=3&x[=y&p=%zz%4"), MapBuilder::new()
+            .insert_str("a", "2")
+            .insert_str("x[", "y")
+            .insert_str("p", "%zz%4")
+            .build());
+
+        // A later nested name replaces a plain value.
+        assert_eq!(from_form_str("a=1&a[b]=2"), MapBuilder::new()
+            .insert_map("a", |m| m.insert_str("b", "2"))
+            .build());
+
+        assert_eq!(from_form_str("0=a&1[0]=b"), MapBuilder::new()
+            .insert_str("0", "a")
+            .insert_vec("1", |v| v.push_str("b"))
+            .build());
+    }
+}
@@ -0,0 +1,70 @@
+//! Front matter, a block of YAML at the top of a template file between two
+//! `---` lines, for the settings of a template that aren't output: its
+//! title, layout or email subject. See
+//! [`Context::parse_front_matter`](../struct.Context.html#method.parse_front_matter).
+
+#[cfg(feature = "yaml")]
+use serde::de::DeserializeOwned;
+
+#[cfg(feature = "yaml")]
+use {Error, Result};
+
+/// Splits the front matter off `source`, giving it and the rest of the
+/// source. A source that doesn't start with a `---` line, or where no `---`
+/// line closes the block, has none.
+pub fn split(source: &str) -> Option<(&str, &str)> {
+    let yaml_start = source.find('\n').map_or(source.len(), |i| i + 1);
+    if !is_line(&source[..yaml_start], "---") {
+        return None;
+    }
+
+    let mut start = yaml_start;
+    while start < source.len() {
+        let end = source[start..].find('\n').map_or(source.len(), |i| start + i + 1);
+        if is_line(&source[start..end], "---") {
+            return Some((&source[yaml_start..start], &source[end..]));
+        }
+        start = end;
+    }
+    None
+}
+
+// Whether `text` is `line` and its line break, trailing spaces allowed.
+fn is_line(text: &str, line: &str) -> bool {
+    text.trim_end() == line
+}
+
+/// Parses the YAML of a front matter block.
+#[cfg(feature = "yaml")]
+pub fn parse<T: DeserializeOwned>(yaml: &str) -> Result<T> {
+    ::serde_yaml::from_str(yaml).map_err(|err| Error::FrontMatter(err.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::split;
+    #[cfg(feature = "yaml")]
+    use {compile_str, Data, Error};
+
+    #[test]
+    fn blocks() {
+        assert_eq!(split("---\ntitle: Hi\n---\n<h1>{{title}}</h1>\n"), Some(("title: Hi\n", "<h1>{{title}}</h1>\n")));
+        assert_eq!(split("---\r\na: 1\r\nb: 2\r\n---\r\nbody"), Some(("a: 1\r\nb: 2\r\n", "body")));
+        assert_eq!(split("---\n---"), Some(("", "")));
+
+        assert_eq!(split("title: Hi\n---\nbody"), None);
+        assert_eq!(split("---\ntitle: Hi\nbody"), None);
+        assert_eq!(split(" ---\na: 1\n---\n"), None);
+        assert_eq!(split("----\na: 1\n---\n"), None);
+    }
+
+    #[cfg(feature = "yaml")]
+    #[test]
+    fn yaml() {
+        let data: Data = super::parse("title: Hi\ntags: [a, b]\n").unwrap();
+        let template = compile_str("{{title}}:{{#tags}} {{.}}{{/tags}}").unwrap();
+        assert_eq!(template.render_data_to_string(&data).unwrap(), "Hi: a b");
+
+        assert!(matches!(super::parse::<Data>("title: ["), Err(Error::FrontMatter(_))));
+    }
+}
@@ -0,0 +1,148 @@
+//! [`Arbitrary`](https://docs.rs/arbitrary) implementations, enabled with
+//! the `arbitrary` feature, for property testing and fuzzing.
+//!
+//! Map keys and template names are drawn from the same few names, so
+//! generated data and templates actually meet when rendered together.
+
+use std::collections::HashMap;
+
+use arbitrary::{Arbitrary, Result, Unstructured};
+
+use Data;
+
+const NAMES: &[&str] = &["a", "b", "c", "items", "name"];
+
+// How deep generated data and sections nest.
+const MAX_DEPTH: usize = 4;
+
+impl<'a> Arbitrary<'a> for Data {
+    /// Generates any `Data` but lambdas, which can't be compared.
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Data> {
+        data(u, MAX_DEPTH)
+    }
+}
+
+fn data(u: &mut Unstructured, depth: usize) -> Result<Data> {
+    let kinds = if depth == 0 { 3 } else { 5 };
+
+    Ok(match u.choose_index(kinds)? {
+        0 => Data::Null,
+        1 => Data::Bool(u.arbitrary()?),
+        2 => Data::String(u.arbitrary()?),
+        3 => {
+            let mut items = Vec::new();
+            for _ in 0..u.int_in_range(0..=3)? {
+                items.push(data(u, depth - 1)?);
+            }
+            Data::Vec(items)
+        }
+        _ => {
+            let mut map = HashMap::new();
+            for _ in 0..u.int_in_range(0..=NAMES.len())? {
+                map.insert(name(u)?, data(u, depth - 1)?);
+            }
+            Data::Map(map)
+        }
+    })
+}
+
+fn name(u: &mut Unstructured) -> Result<String> {
+    Ok(u.choose(NAMES)?.to_string())
+}
+
+/// The source of a template that always compiles: text, variables,
+/// sections opened and closed in order, comments and partials.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ArbitraryTemplate(pub String);
+
+impl<'a> Arbitrary<'a> for ArbitraryTemplate {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<ArbitraryTemplate> {
+        let mut source = String::new();
+        template(u, MAX_DEPTH, &mut source)?;
+        Ok(ArbitraryTemplate(source))
+    }
+}
+
+fn template(u: &mut Unstructured, depth: usize, source: &mut String) -> Result<()> {
+    for _ in 0..u.int_in_range(0..=4)? {
+        let kinds = if depth == 0 { 6 } else { 8 };
+
+        match u.choose_index(kinds)? {
+            0 => {
+                // Braces could add tags of their own.
+                let text: String = u.arbitrary()?;
+                source.extend(text.chars().filter(|&c| c != '{' && c != '}'));
+            }
+            1 => source.push_str(&format!("{{{{{}}}}}", path(u)?)),
+            2 => source.push_str(&format!("{{{{{{{}}}}}}}", path(u)?)),
+            3 => source.push_str(&format!("{{{{&{}}}}}", path(u)?)),
+            4 => source.push_str("{{! comment }}"),
+            5 => source.push_str(&format!("{{{{>{}}}}}", name(u)?)),
+            _ => {
+                let name = name(u)?;
+                let kind = if u.arbitrary()? { '^' } else { '#' };
+                source.push_str(&format!("{{{{{}{}}}}}", kind, name));
+                template(u, depth - 1, source)?;
+                source.push_str(&format!("{{{{/{}}}}}", name));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// A name, a dotted name or the implicit iterator.
+fn path(u: &mut Unstructured) -> Result<String> {
+    Ok(match u.choose_index(3)? {
+        0 => ".".to_string(),
+        1 => format!("{}.{}", name(u)?, name(u)?),
+        _ => name(u)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use arbitrary::{Arbitrary, Unstructured};
+
+    use super::ArbitraryTemplate;
+    use {Context, Data, PartialLoader, Result};
+
+    // Deterministic noise to generate from.
+    fn bytes(seed: u32, len: usize) -> Vec<u8> {
+        let mut state = seed;
+        (0..len).map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            (state >> 16) as u8
+        }).collect()
+    }
+
+    // Every partial shows the value of its own name.
+    #[derive(Clone, Debug)]
+    struct Loader;
+
+    impl PartialLoader for Loader {
+        fn load(&self, name: impl AsRef<Path>) -> Result<String> {
+            Ok(format!("[{{{{{}}}}}]", name.as_ref().display()))
+        }
+    }
+
+    #[test]
+    fn templates_compile_and_render() {
+        let ctx = Context::with_loader(Loader);
+
+        for seed in 0..200 {
+            let bytes = bytes(seed, 512);
+            let mut u = Unstructured::new(&bytes);
+
+            let ArbitraryTemplate(source) = ArbitraryTemplate::arbitrary(&mut u).unwrap();
+            let data = Data::arbitrary(&mut u).unwrap();
+
+            let template = ctx.compile(source.chars())
+                .unwrap_or_else(|err| panic!("{:?} failed to compile: {}", source, err));
+            template.render_data_to_string(&data)
+                .unwrap_or_else(|err| panic!("{:?} failed to render: {}", source, err));
+        }
+    }
+}
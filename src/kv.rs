@@ -0,0 +1,174 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Component, Path};
+use std::sync::{Arc, Mutex};
+
+use context::PartialLoader;
+use {Error, Result};
+
+/// A [`PartialLoader`] over any key-value store, from one closure that
+/// fetches a template by key and returns `None` when there is none, which
+/// renders as empty.
+///
+/// Keys are partial names with `/` between their parts. Names that are
+/// absolute or step out with `..` are refused with
+/// [`Error::InvalidPartialName`](enum.Error.html#variant.InvalidPartialName)
+/// before the store is asked.
+///
+/// ```
+/// use std::collections::HashMap;
+/// use mustache::{Context, KvLoader, MapBuilder};
+///
+/// let mut store = HashMap::new();
+/// store.insert("emails/user", "<b>{{name}}</b>");
+///
+/// let loader = KvLoader::new(move |key| Ok(store.get(key).map(|s| s.to_string()))).cached();
+/// let template = Context::with_loader(loader).compile("{{>emails/user}}".chars()).unwrap();
+///
+/// let data = MapBuilder::new().insert_str("name", "n").build();
+/// assert_eq!(template.render_data_to_string(&data).unwrap(), "<b>n</b>");
+/// ```
+pub struct KvLoader<F> {
+    fetch: Arc<F>,
+    cache: Option<Arc<Mutex<HashMap<String, String>>>>,
+}
+
+impl<F> KvLoader<F>
+where F: Fn(&str) -> Result<Option<String>>
+{
+    /// Fetches every partial with `fetch`.
+    pub fn new(fetch: F) -> Self {
+        KvLoader {
+            fetch: Arc::new(fetch),
+            cache: None,
+        }
+    }
+
+    /// Keeps what was fetched, found or not, for this loader and its clones
+    /// until [`clear_cache`](#method.clear_cache). Failed fetches are tried
+    /// again.
+    pub fn cached(mut self) -> Self {
+        if self.cache.is_none() {
+            self.cache = Some(Arc::default());
+        }
+        self
+    }
+
+    /// Forgets what [`cached`](#method.cached) kept.
+    pub fn clear_cache(&self) {
+        if let Some(ref cache) = self.cache {
+            if let Ok(mut cache) = cache.lock() {
+                cache.clear();
+            }
+        }
+    }
+}
+
+// Turns `name` into a key, refusing the names that could reach outside the
+// templates.
+fn key(name: &Path) -> Result<String> {
+    let invalid = || Error::InvalidPartialName(name.display().to_string());
+    let mut parts = Vec::new();
+
+    for component in name.components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_str().ok_or_else(invalid)?),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return Err(invalid()),
+        }
+    }
+
+    if parts.is_empty() {
+        return Err(invalid());
+    }
+
+    Ok(parts.join("/"))
+}
+
+impl<F> PartialLoader for KvLoader<F>
+where F: Fn(&str) -> Result<Option<String>>
+{
+    fn load(&self, name: impl AsRef<Path>) -> Result<String> {
+        let key = key(name.as_ref())?;
+
+        if let Some(ref cache) = self.cache {
+            if let Some(source) = cache.lock().ok().and_then(|cache| cache.get(&key).cloned()) {
+                return Ok(source);
+            }
+        }
+
+        let source = (self.fetch)(&key)?.unwrap_or_default();
+
+        if let Some(ref cache) = self.cache {
+            if let Ok(mut cache) = cache.lock() {
+                cache.insert(key, source.clone());
+            }
+        }
+
+        Ok(source)
+    }
+}
+
+// The closure needn't be `Clone` or `Debug` itself.
+impl<F> Clone for KvLoader<F> {
+    fn clone(&self) -> Self {
+        KvLoader {
+            fetch: self.fetch.clone(),
+            cache: self.cache.clone(),
+        }
+    }
+}
+
+impl<F> fmt::Debug for KvLoader<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("KvLoader")
+            .field("cached", &self.cache.is_some())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::KvLoader;
+    use {Error, PartialLoader};
+
+    #[test]
+    fn keys() {
+        let loader = KvLoader::new(|key: &str| Ok(Some(key.to_string())));
+
+        assert_eq!(loader.load("a").unwrap(), "a");
+        assert_eq!(loader.load("./a/b").unwrap(), "a/b");
+
+        for name in &["../a", "a/../../b", "/etc/passwd", "", "."] {
+            match loader.load(name) {
+                Err(Error::InvalidPartialName(ref n)) if n == name => {}
+                other => panic!("{:?} gave {:?}", name, other),
+            }
+        }
+    }
+
+    #[test]
+    fn cached() {
+        let fetches = Arc::new(Mutex::new(0));
+        let counter = fetches.clone();
+        let loader = KvLoader::new(move |key: &str| {
+            *counter.lock().unwrap() += 1;
+            match key {
+                "fail" => Err(Error::InvalidStr),
+                "missing" => Ok(None),
+                _ => Ok(Some(key.to_string())),
+            }
+        }).cached();
+
+        for name in &["a", "a", "missing", "missing", "fail", "fail"] {
+            let _ = loader.clone().load(name);
+        }
+        assert_eq!(*fetches.lock().unwrap(), 4);
+
+        loader.clear_cache();
+        assert_eq!(loader.load("a").unwrap(), "a");
+        assert_eq!(*fetches.lock().unwrap(), 5);
+    }
+}
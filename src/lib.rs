@@ -3,7 +3,48 @@
 
 extern crate log;
 extern crate serde;
+#[cfg(any(feature = "ffi", feature = "schema", feature = "spec"))]
+extern crate serde_json;
+#[cfg(feature = "arbitrary")]
+extern crate arbitrary;
+#[cfg(feature = "erased")]
+extern crate erased_serde;
+#[cfg(feature = "include_dir")]
+#[cfg_attr(test, macro_use)]
+extern crate include_dir;
+#[cfg(feature = "rust-embed")]
+#[cfg_attr(test, macro_use)]
+extern crate rust_embed;
+#[cfg(feature = "rayon")]
+extern crate rayon;
+#[cfg(feature = "tar")]
+extern crate tar;
+#[cfg(feature = "zip")]
+extern crate zip;
+#[cfg(feature = "icu4x")]
+extern crate fixed_decimal;
+#[cfg(feature = "icu4x")]
+extern crate icu;
+#[cfg(feature = "derive")]
+extern crate mustache_derive;
+#[cfg(feature = "markdown")]
+extern crate pulldown_cmark;
+#[cfg(feature = "ammonia")]
+pub extern crate ammonia;
+#[cfg(feature = "tokio")]
+extern crate tokio;
+#[cfg(feature = "futures")]
+extern crate futures_io;
+#[cfg(feature = "yaml")]
+extern crate serde_yaml;
+#[cfg(feature = "mmap")]
+extern crate memmap2;
+#[cfg(all(test, any(feature = "tokio", feature = "futures")))]
+extern crate futures;
+#[cfg(test)]
+extern crate tempdir;
 
+use std::io::Read;
 use std::str;
 use std::path::{PathBuf, Path};
 use std::result;
@@ -11,25 +52,156 @@ use std::result;
 #[macro_use]
 mod macros;
 
+#[cfg(feature = "ansi")]
+pub mod ansi;
+#[cfg(any(feature = "zip", feature = "tar"))]
+mod archive;
+#[cfg(any(feature = "tokio", feature = "futures"))]
+mod async_write;
 mod builder;
+mod cache;
+mod chars;
 mod compiler;
+#[cfg(feature = "convert")]
+pub mod convert;
 mod context;
 mod data;
+mod diff;
+mod dir;
+mod email;
+mod env;
+#[cfg(any(feature = "include_dir", feature = "rust-embed"))]
+mod embed;
 mod encoder;
+#[cfg(feature = "erased")]
+mod erased;
 mod error;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod filters;
+#[cfg(feature = "form")]
+mod form;
+mod front_matter;
+#[cfg(feature = "arbitrary")]
+mod fuzz;
+mod kv;
+mod lint;
+pub mod locale;
+mod loaders;
+mod lru;
+mod metrics;
+#[cfg(feature = "mmap")]
+mod mmap;
+#[cfg(feature = "markdown")]
+pub mod markdown;
+mod multi;
 mod parser;
+mod pool;
+mod print;
+mod redact;
+mod renderer;
+#[cfg(feature = "ammonia")]
+mod sanitize;
+mod schema;
+mod sink;
+mod source;
+#[cfg(feature = "spec")]
+pub mod spec;
+mod stream;
+mod syntax;
 mod template;
+pub mod testing;
+mod typed;
+mod validate;
 
+#[cfg(any(feature = "zip", feature = "tar"))]
+pub use archive::ArchiveLoader;
 pub use builder::{MapBuilder, VecBuilder};
+pub use cache::CompileCache;
+pub use compiler::CompileLimits;
 pub use context::{Context, PartialLoader, DefaultLoader};
-pub use data::Data;
+pub use data::{Data, DataIter, Helper, LambdaError, MergeStrategy, TryFun};
+pub use diff::{diff, diff_output, Change, Splice, Tag, TagKind};
+pub use email::Email;
+pub use env::Env;
+#[cfg(feature = "include_dir")]
+pub use embed::DirLoader;
+#[cfg(feature = "rust-embed")]
+pub use embed::EmbedLoader;
 pub use encoder::Encoder;
 pub use encoder::Error as EncoderError;
 pub use encoder::{SerializeVec, SerializeTupleVariant, SerializeMap, SerializeStructVariant};
-pub use error::{Error, Result};
+#[cfg(feature = "erased")]
+pub use erased::DynTemplate;
+pub use error::{Error, Result, Warning};
+#[cfg(feature = "form")]
+pub use form::from_form_str;
+#[cfg(feature = "arbitrary")]
+pub use fuzz::ArbitraryTemplate;
+pub use kv::KvLoader;
+pub use lint::Lint;
+pub use loaders::{LoadEvent, LoadOutcome, MapName, MultiLoader, Observed, Or, Prefixed};
+pub use lru::LruLoader;
+pub use metrics::MetricsSink;
+#[cfg(feature = "prometheus")]
+pub use metrics::PrometheusMetrics;
+#[cfg(feature = "mmap")]
+pub use mmap::MmapLoader;
 pub use parser::Error as ParserError;
-pub use template::Template;
+pub use parser::Extensions;
+pub use pool::{BufferPool, PooledOutput};
+pub use redact::{redacted, set_redaction, Redaction};
+pub use renderer::Renderer;
+#[cfg(feature = "ammonia")]
+pub use sanitize::Sanitizer;
+pub use sink::OutputSink;
+pub use source::Source;
+pub use syntax::{classify, comments, parse_tree, parse_tree_lenient, Class, Comment, Diagnostic, Node, Span};
+pub use template::{BidiControls, BytesEncoding, Deprecation, Escape, PartialOverrides, Progress, ProgressCallback, Region, RenderHook, RenderOptions, Scope, Template};
+pub use typed::{Field, Shape, TemplateData, TypedTemplate};
+#[cfg(feature = "derive")]
+pub use mustache_derive::TemplateData;
+pub use validate::{Missing, Usage};
 
+/// Encodes any `Serialize` value into `Data`.
+///
+/// `None` becomes `Data::Null` and `Some(v)` encodes as `v` itself, so an
+/// `Option` field is always present in its map. A `Null` value renders
+/// nothing in a tag, skips a section and shows an inverted section, and
+/// because the key exists it hides any value of the same name further up
+/// the context stack. Nested options collapse, `Some(None)` is `Null` too.
+///
+/// Maps get the keys the value has when serialized to JSON: serde's
+/// `rename`, `rename_all`, `skip`, `skip_serializing_if`, `flatten` and
+/// internally tagged enums all apply. An option skipped when `None` leaves
+/// no key rather than a `Null` one, so a value of the same name further up
+/// the stack shows through.
+///
+/// A `std::time::Duration` becomes a string the way it debug prints, such
+/// as `1.5s` or `20ms`.
+///
+/// ```
+/// #[macro_use] extern crate serde_derive;
+/// extern crate mustache;
+///
+/// #[derive(Serialize)]
+/// struct User {
+///     name: String,
+///     email: Option<String>,
+/// }
+///
+/// # fn main() {
+/// let template = mustache::compile_str(
+///     "{{name}}: {{#email}}{{.}}{{/email}}{{^email}}no email{{/email}}"
+/// ).unwrap();
+///
+/// let user = User { name: "jo".to_string(), email: None };
+/// assert_eq!(template.render_to_string(&user).unwrap(), "jo: no email");
+///
+/// let user = User { name: "jo".to_string(), email: Some("jo@example.com".to_string()) };
+/// assert_eq!(template.render_to_string(&user).unwrap(), "jo: jo@example.com");
+/// # }
+/// ```
 pub fn to_data<T>(value: T) -> result::Result<Data, encoder::Error>
 where
     T: serde::Serialize,
@@ -37,9 +209,30 @@ where
     value.serialize(Encoder)
 }
 
+/// Compiles a template from a string, its `chars()` or a reader, see
+/// [`Source`](trait.Source.html). Partials are loaded from the current
+/// directory.
+///
+/// ```
+/// let template = mustache::compile("Hello {{name}}").unwrap();
+/// let mut data = std::collections::HashMap::new();
+/// data.insert("name", "world");
+///
+/// assert_eq!(template.render_to_string(&data).unwrap(), "Hello world");
+/// ```
+pub fn compile<S: Source>(source: S) -> Result<Template<DefaultLoader>> {
+    Context::new(PathBuf::from(".")).compile(source)
+}
+
 /// Compiles a template from an `Iterator<char>`.
 pub fn compile_iter<T: Iterator<Item = char>>(iter: T) -> Result<Template<DefaultLoader>> {
-    Context::new(PathBuf::from(".")).compile(iter)
+    Context::new(PathBuf::from(".")).compile_chars(iter)
+}
+
+/// Compiles a template read from `reader`, without holding all of its
+/// source in memory.
+pub fn compile_reader<R: Read>(reader: R) -> Result<Template<DefaultLoader>> {
+    Context::new(PathBuf::from(".")).compile_reader(reader)
 }
 
 /// Compiles a template from a path.
@@ -0,0 +1,175 @@
+use std::fmt;
+
+use compiler::PartialsMap;
+use parser::Token;
+use syntax::{self, Node};
+use template;
+use super::{Context, Data, PartialLoader, Result};
+
+/// A likely mistake in a template, as reported by
+/// [`Context::lint`](struct.Context.html#method.lint).
+///
+/// This type is not intended to be matched exhaustively as new variants
+/// may be added in future without a version bump.
+#[derive(Clone, Debug, PartialEq)]
+#[allow(clippy::manual_non_exhaustive)]
+pub enum Lint {
+    /// A section iterates over a name the sample data never provides.
+    UnknownSection(String),
+    /// A section is nested directly inside a section of the same name.
+    NestedIdenticalSection(String),
+    /// A set delimiter tag picked delimiters which are identical to each
+    /// other or contain alphanumeric characters, so ordinary text will
+    /// likely be mistaken for tags.
+    SuspiciousDelimiters(String, String),
+    /// An inverted section is nested inside a section of the same name, so
+    /// it can never render.
+    UnreachableInvertedSection(String),
+
+    #[doc(hidden)]
+    __Nonexhaustive,
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Lint::UnknownSection(ref name) => {
+                write!(f, "section {:?} is never present in the sample data", name)
+            }
+            Lint::NestedIdenticalSection(ref name) => {
+                write!(f, "section {:?} is nested directly inside itself", name)
+            }
+            Lint::SuspiciousDelimiters(ref otag, ref ctag) => {
+                write!(f, "suspicious delimiters {:?} and {:?}", otag, ctag)
+            }
+            Lint::UnreachableInvertedSection(ref name) => {
+                write!(f, "inverted section {:?} can never render inside section {:?}", name, name)
+            }
+            Lint::__Nonexhaustive => unreachable!(),
+        }
+    }
+}
+
+/// Lints the template `source`, compiling it and its partials with `ctx`.
+///
+/// When `sample` is given, sections are checked against it as if it was
+/// rendered.
+pub fn lint<P: PartialLoader>(ctx: &Context<P>, source: &str, sample: Option<&Data>) -> Result<Vec<Lint>> {
+    let template = ctx.compile(source.chars())?;
+
+    let mut linter = Linter {
+        partials: &template.partials,
+        including: Vec::new(),
+        lints: Vec::new(),
+    };
+
+    lint_delimiters(&syntax::parse_tree(source, ctx.extensions)?, &mut linter.lints);
+
+    let stack = sample.map(|sample| vec![sample]);
+    linter.walk(&template.tokens, &mut Vec::new(), stack);
+
+    Ok(linter.lints)
+}
+
+struct Linter<'a> {
+    partials: &'a PartialsMap,
+    // Partials currently being walked, so recursive partials terminate.
+    including: Vec<&'a str>,
+    lints: Vec<Lint>,
+}
+
+impl<'a> Linter<'a> {
+    // `sections` holds the enclosing sections, innermost last. `stack` is the
+    // sample data context, or `None` if it can't be followed from here.
+    fn walk(&mut self,
+            tokens: &'a [Token],
+            sections: &mut Vec<(&'a [String], bool)>,
+            stack: Option<Vec<&'a Data>>) {
+        for token in tokens {
+            match *token {
+                Token::Section(ref path, inverted, ref children, _, _, _, _, _) => {
+                    let name = path.join(".");
+
+                    if sections.last() == Some(&(&path[..], inverted)) {
+                        self.lints.push(Lint::NestedIdenticalSection(name.clone()));
+                    }
+
+                    if inverted && !path.is_empty() && sections.contains(&(&path[..], false)) {
+                        self.lints.push(Lint::UnreachableInvertedSection(name.clone()));
+                    }
+
+                    let inner = match stack {
+                        Some(ref stack) if !inverted => {
+                            match template::find(path, stack) {
+                                None => {
+                                    self.lints.push(Lint::UnknownSection(name));
+                                    None
+                                }
+                                Some(value) => enter(stack, value),
+                            }
+                        }
+                        ref stack => stack.clone(),
+                    };
+
+                    sections.push((path, inverted));
+                    self.walk(children, sections, inner);
+                    sections.pop();
+                }
+                Token::Condition(_, _, ref children, _, _, _, _, _) |
+                Token::Range(_, _, ref children, _, _, _, _, _) => self.walk(children, sections, stack.clone()),
+                Token::PartialWithFallback(ref name, _, ref children, _, _, _) => {
+                    self.walk(children, sections, stack.clone());
+                    self.walk_partial(name, sections, &stack);
+                }
+                Token::Partial(ref name, _, _) => self.walk_partial(name, sections, &stack),
+                _ => {}
+            }
+        }
+    }
+
+    fn walk_partial(&mut self,
+                    name: &'a str,
+                    sections: &mut Vec<(&'a [String], bool)>,
+                    stack: &Option<Vec<&'a Data>>) {
+        if self.including.contains(&name) {
+            return;
+        }
+
+        if let Some(tokens) = self.partials.get(name) {
+            self.including.push(name);
+            self.walk(tokens, sections, stack.clone());
+            self.including.pop();
+        }
+    }
+}
+
+fn lint_delimiters(nodes: &[Node], lints: &mut Vec<Lint>) {
+    for node in nodes {
+        match *node {
+            Node::Delimiters { ref open, ref close, .. }
+                if open == close || open.chars().chain(close.chars()).any(char::is_alphanumeric) => {
+                lints.push(Lint::SuspiciousDelimiters(open.clone(), close.clone()));
+            }
+            Node::Section { ref children, .. } |
+            Node::PartialWithFallback { ref children, .. } |
+            Node::Definition { ref children, .. } => lint_delimiters(children, lints),
+            _ => {}
+        }
+    }
+}
+
+// The sample context inside a section over `value`, mirroring what the
+// renderer pushes for it.
+fn enter<'a>(stack: &[&'a Data], value: &'a Data) -> Option<Vec<&'a Data>> {
+    let mut stack = stack.to_vec();
+
+    match *value {
+        Data::Vec(ref items) => stack.push(items.first()?),
+        Data::String(_) | Data::Bytes(_) | Data::Map(_) => stack.push(value),
+        // Taking an item out would leave the render without it.
+        Data::Iter(_) | Data::Fun(_) | Data::TryFun(_) | Data::Helper(_) => return None,
+        Data::Null | Data::Bool(_) => {}
+    }
+
+    Some(stack)
+}
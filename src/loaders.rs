@@ -0,0 +1,364 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use context::PartialLoader;
+use Result;
+
+/// Tries one loader, then another when the first fails or finds nothing.
+/// See [`PartialLoader::or`].
+#[derive(Clone, Debug)]
+pub struct Or<A, B> {
+    first: A,
+    second: B,
+}
+
+pub fn or<A, B>(first: A, second: B) -> Or<A, B> {
+    Or { first, second }
+}
+
+impl<A: PartialLoader, B: PartialLoader> PartialLoader for Or<A, B> {
+    fn load(&self, name: impl AsRef<Path>) -> Result<String> {
+        match self.first.load(name.as_ref()) {
+            Ok(ref source) if source.is_empty() => self.second.load(name),
+            Err(_) => self.second.load(name),
+            source => source,
+        }
+    }
+
+    fn load_str(&self, name: impl AsRef<Path>) -> Result<Cow<'_, str>> {
+        match self.first.load_str(name.as_ref()) {
+            Ok(ref source) if source.is_empty() => self.second.load_str(name),
+            Err(_) => self.second.load_str(name),
+            source => source,
+        }
+    }
+
+    // The path of the loader `load` takes the source from, which only
+    // loading again tells.
+    fn path(&self, name: impl AsRef<Path>) -> Option<PathBuf> {
+        match self.first.load_str(name.as_ref()) {
+            Ok(ref source) if !source.is_empty() => self.first.path(name),
+            _ => self.second.path(name),
+        }
+    }
+}
+
+/// Rewrites the names given to a loader. See [`PartialLoader::map_name`].
+#[derive(Clone, Debug)]
+pub struct MapName<L, F> {
+    loader: L,
+    f: F,
+}
+
+pub fn map_name<L, F>(loader: L, f: F) -> MapName<L, F> {
+    MapName { loader, f }
+}
+
+impl<L, F> PartialLoader for MapName<L, F>
+where L: PartialLoader,
+      F: Fn(&Path) -> PathBuf + Clone,
+{
+    fn load(&self, name: impl AsRef<Path>) -> Result<String> {
+        self.loader.load((self.f)(name.as_ref()))
+    }
+
+    fn load_str(&self, name: impl AsRef<Path>) -> Result<Cow<'_, str>> {
+        self.loader.load_str((self.f)(name.as_ref()))
+    }
+
+    fn path(&self, name: impl AsRef<Path>) -> Option<PathBuf> {
+        self.loader.path((self.f)(name.as_ref()))
+    }
+}
+
+/// Looks every name up under a prefix. See [`PartialLoader::prefixed`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Prefixed<L> {
+    loader: L,
+    prefix: PathBuf,
+}
+
+pub fn prefixed<L>(loader: L, prefix: PathBuf) -> Prefixed<L> {
+    Prefixed { loader, prefix }
+}
+
+impl<L: PartialLoader> PartialLoader for Prefixed<L> {
+    fn load(&self, name: impl AsRef<Path>) -> Result<String> {
+        self.loader.load(self.prefix.join(name))
+    }
+
+    fn load_str(&self, name: impl AsRef<Path>) -> Result<Cow<'_, str>> {
+        self.loader.load_str(self.prefix.join(name))
+    }
+
+    fn path(&self, name: impl AsRef<Path>) -> Option<PathBuf> {
+        self.loader.path(self.prefix.join(name))
+    }
+}
+
+/// Calls a function after every load, see [`PartialLoader::observed`].
+#[derive(Clone, Debug)]
+pub struct Observed<L, F> {
+    loader: L,
+    f: F,
+}
+
+pub fn observed<L, F>(loader: L, f: F) -> Observed<L, F> {
+    Observed { loader, f }
+}
+
+/// A load through an [`Observed`] loader.
+#[derive(Clone, Debug)]
+pub struct LoadEvent<'a> {
+    /// The name as it was asked for.
+    pub name: &'a Path,
+    /// How long the inner loader took.
+    pub duration: Duration,
+    pub outcome: LoadOutcome,
+    /// The length of the source, 0 unless it was found.
+    pub bytes: usize,
+}
+
+/// How a load went.
+///
+/// This type is not intended to be matched exhaustively as new variants
+/// may be added in future without a version bump.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[allow(clippy::manual_non_exhaustive)]
+pub enum LoadOutcome {
+    Found,
+    /// The loader found nothing, which is how [`DefaultLoader`](struct.DefaultLoader.html)
+    /// answers for missing files.
+    Empty,
+    Failed,
+
+    #[doc(hidden)]
+    __Nonexhaustive,
+}
+
+impl<L, F> Observed<L, F>
+where L: PartialLoader,
+      F: Fn(&LoadEvent) + Clone,
+{
+    fn observe<T, G>(&self, name: &Path, load: G) -> Result<T>
+    where T: AsRef<str>,
+          G: FnOnce() -> Result<T>,
+    {
+        let start = Instant::now();
+        let source = load();
+        let duration = start.elapsed();
+
+        let (outcome, bytes) = match source {
+            Ok(ref source) if source.as_ref().is_empty() => (LoadOutcome::Empty, 0),
+            Ok(ref source) => (LoadOutcome::Found, source.as_ref().len()),
+            Err(_) => (LoadOutcome::Failed, 0),
+        };
+        (self.f)(&LoadEvent { name, duration, outcome, bytes });
+        source
+    }
+}
+
+impl<L, F> PartialLoader for Observed<L, F>
+where L: PartialLoader,
+      F: Fn(&LoadEvent) + Clone,
+{
+    fn load(&self, name: impl AsRef<Path>) -> Result<String> {
+        let name = name.as_ref();
+        self.observe(name, || self.loader.load(name))
+    }
+
+    fn load_str(&self, name: impl AsRef<Path>) -> Result<Cow<'_, str>> {
+        let name = name.as_ref();
+        self.observe(name, || self.loader.load_str(name))
+    }
+
+    fn path(&self, name: impl AsRef<Path>) -> Option<PathBuf> {
+        self.loader.path(name)
+    }
+}
+
+/// Sends every name to the loader routed for the longest prefix it starts
+/// with, taking the prefix off, and the others to a fallback. Prefixes
+/// match whole path components, so `emails` takes `emails/welcome` but not
+/// `emailsx`.
+///
+/// ```
+/// use std::path::PathBuf;
+/// use mustache::{Context, DefaultLoader, KvLoader, MultiLoader};
+///
+/// let emails = KvLoader::new(|name| Ok(Some(format!("email {}", name))));
+/// let loader = MultiLoader::new(KvLoader::new(|name| Ok(Some(format!("built in {}", name)))))
+///     .route("emails", emails)
+///     .route("web", DefaultLoader::new(PathBuf::from("templates/web"), "html".to_string()));
+///
+/// let ctx = Context::with_loader(loader);
+/// let template = ctx.compile("{{> emails/welcome}}, {{> footer}}").unwrap();
+/// assert_eq!(template.render_to_string(&()).unwrap(), "email welcome, built in footer");
+/// ```
+#[derive(Clone)]
+pub struct MultiLoader<D> {
+    // Longest prefixes first.
+    routes: Vec<(PathBuf, Arc<dyn DynLoader>)>,
+    fallback: D,
+}
+
+// `PartialLoader` for loaders of any type side by side.
+trait DynLoader: Send + Sync {
+    fn load_dyn(&self, name: &Path) -> Result<Cow<'_, str>>;
+    fn path_dyn(&self, name: &Path) -> Option<PathBuf>;
+}
+
+impl<L: PartialLoader + Send + Sync> DynLoader for L {
+    fn load_dyn(&self, name: &Path) -> Result<Cow<'_, str>> {
+        self.load_str(name)
+    }
+
+    fn path_dyn(&self, name: &Path) -> Option<PathBuf> {
+        self.path(name)
+    }
+}
+
+impl<D: PartialLoader> MultiLoader<D> {
+    /// Sends every name to `fallback` until routes are added.
+    pub fn new(fallback: D) -> Self {
+        MultiLoader { routes: Vec::new(), fallback }
+    }
+
+    /// Sends the names under `prefix` to `loader`, without the prefix. A
+    /// route for the same prefix replaces this one.
+    pub fn route<L>(mut self, prefix: impl Into<PathBuf>, loader: L) -> Self
+    where L: PartialLoader + Send + Sync + 'static
+    {
+        let prefix = prefix.into();
+        self.routes.retain(|(routed, _)| *routed != prefix);
+
+        let len = prefix.components().count();
+        let at = self.routes.iter().position(|(routed, _)| routed.components().count() < len);
+        self.routes.insert(at.unwrap_or(self.routes.len()), (prefix, Arc::new(loader)));
+        self
+    }
+}
+
+impl<D: PartialLoader> PartialLoader for MultiLoader<D> {
+    fn load(&self, name: impl AsRef<Path>) -> Result<String> {
+        self.load_str(name).map(Cow::into_owned)
+    }
+
+    fn load_str(&self, name: impl AsRef<Path>) -> Result<Cow<'_, str>> {
+        let name = name.as_ref();
+        for (prefix, loader) in &self.routes {
+            if let Ok(rest) = name.strip_prefix(prefix) {
+                return loader.load_dyn(rest);
+            }
+        }
+        self.fallback.load_str(name)
+    }
+
+    fn path(&self, name: impl AsRef<Path>) -> Option<PathBuf> {
+        let name = name.as_ref();
+        for (prefix, loader) in &self.routes {
+            if let Ok(rest) = name.strip_prefix(prefix) {
+                return loader.path_dyn(rest);
+            }
+        }
+        self.fallback.path(name)
+    }
+}
+
+impl<D: fmt::Debug> fmt::Debug for MultiLoader<D> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MultiLoader")
+            .field("routes", &self.routes.iter().map(|(prefix, _)| prefix).collect::<Vec<_>>())
+            .field("fallback", &self.fallback)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use std::sync::{Arc, Mutex};
+
+    use super::{LoadEvent, LoadOutcome, MultiLoader};
+    use {Error, PartialLoader, Result};
+
+    // Knows a few names, and which loader it is.
+    #[derive(Clone, Debug)]
+    struct Loader(&'static str, &'static [&'static str]);
+
+    impl PartialLoader for Loader {
+        fn load(&self, name: impl AsRef<Path>) -> Result<String> {
+            let name = name.as_ref().to_str().unwrap();
+            match self.1.iter().find(|&&known| known == name) {
+                Some(_) => Ok(format!("{}:{}", self.0, name)),
+                None if name == "empty" => Ok(String::new()),
+                None => Err(Error::InvalidStr),
+            }
+        }
+    }
+
+    #[test]
+    fn or() {
+        let loader = Loader("a", &["x"]).or(Loader("b", &["x", "y", "empty"]));
+
+        assert_eq!(loader.load("x").unwrap(), "a:x");
+        assert_eq!(loader.load("y").unwrap(), "b:y");
+        assert_eq!(loader.load("empty").unwrap(), "b:empty");
+        assert!(loader.load("z").is_err());
+        assert_eq!(loader.load_str("y").unwrap(), "b:y");
+    }
+
+    #[test]
+    fn observed() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let seen = events.clone();
+        let loader = Loader("a", &["x"]).observed(move |event: &LoadEvent| {
+            seen.lock().unwrap().push((event.name.to_path_buf(), event.outcome, event.bytes));
+        });
+
+        assert_eq!(loader.load("x").unwrap(), "a:x");
+        assert_eq!(loader.load_str("empty").unwrap(), "");
+        assert!(loader.clone().load("y").is_err());
+
+        assert_eq!(*events.lock().unwrap(), vec![
+            (PathBuf::from("x"), LoadOutcome::Found, 3),
+            (PathBuf::from("empty"), LoadOutcome::Empty, 0),
+            (PathBuf::from("y"), LoadOutcome::Failed, 0),
+        ]);
+    }
+
+    #[test]
+    fn routes() {
+        let loader = MultiLoader::new(Loader("fallback", &["x", "emailsx", "emails"]))
+            .route("emails", Loader("emails", &["x", "y"]))
+            .route("emails/legacy", Loader("legacy", &["x"]))
+            .route("web", Loader("old web", &["x"]))
+            .route("web", Loader("web", &["x"]));
+
+        assert_eq!(loader.load("emails/x").unwrap(), "emails:x");
+        assert_eq!(loader.load_str("emails/legacy/x").unwrap(), "legacy:x");
+        assert_eq!(loader.load("web/x").unwrap(), "web:x");
+        assert_eq!(loader.load("x").unwrap(), "fallback:x");
+        assert_eq!(loader.load("emailsx").unwrap(), "fallback:emailsx");
+        assert!(loader.load("emails/z").is_err());
+        assert_eq!(format!("{:?}", loader), concat!(
+            r#"MultiLoader { routes: ["emails/legacy", "emails", "web"], "#,
+            r#"fallback: Loader("fallback", ["x", "emailsx", "emails"]) }"#,
+        ));
+    }
+
+    #[test]
+    fn names() {
+        let loader = Loader("a", &["emails/x", "x.txt"]);
+
+        assert_eq!(loader.clone().prefixed("emails/").load("x").unwrap(), "a:emails/x");
+        assert_eq!(loader.clone().prefixed("emails").load_str("x").unwrap(), "a:emails/x");
+
+        let loader = loader.map_name(|name: &Path| name.with_extension("txt"));
+        assert_eq!(loader.load("x").unwrap(), "a:x.txt");
+        assert_eq!(loader.load_str(PathBuf::from("x.html")).unwrap(), "a:x.txt");
+    }
+}
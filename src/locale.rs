@@ -0,0 +1,476 @@
+//! Formatting numbers, dates and plurals for the locale of a render, set in
+//! [`RenderOptions::locale`](../struct.RenderOptions.html#structfield.locale).
+//!
+//! The helpers here are [`Data::Helper`](../enum.Data.html#variant.Helper)
+//! lambdas, which are given their section's content rendered, take the
+//! locale from their [`Scope`](../struct.Scope.html) and write out text.
+//! How a locale formats comes from its [`LocaleProvider`]: [`Basic`] knows a
+//! few common languages, the `icu4x` feature adds [`Icu`] for the rest.
+//!
+//! ```
+//! use mustache::{locale, Data, MapBuilder, RenderOptions};
+//! use mustache::locale::Locale;
+//!
+//! let template = mustache::compile_str(
+//!     "{{#number}}{{total}}{{/number}}, {{#plural}}{{count}}|un article|{{count}} articles{{/plural}}",
+//! ).unwrap();
+//!
+//! let mut data = MapBuilder::new()
+//!     .insert_str("total", "1234.5")
+//!     .insert_str("count", "0")
+//!     .build();
+//! if let Data::Map(ref mut map) = data {
+//!     map.insert("number".to_string(), locale::number());
+//!     map.insert("plural".to_string(), locale::plural());
+//! }
+//!
+//! let options = RenderOptions { locale: Some(Locale::new("fr-FR")), ..RenderOptions::default() };
+//! let mut out = Vec::new();
+//! template.render_data_with(&mut out, &data, &options).unwrap();
+//! assert_eq!(String::from_utf8(out).unwrap(), "1\u{202f}234,5, un article");
+//! ```
+
+use std::cell::RefCell;
+use std::fmt;
+use std::sync::Arc;
+
+#[cfg(feature = "icu4x")]
+use fixed_decimal::FixedDecimal;
+#[cfg(feature = "icu4x")]
+use icu;
+
+use {Data, Scope};
+
+/// A language tag such as `en-US`, and the provider formatting for it.
+/// Defaults to `en` with [`Basic`].
+#[derive(Clone)]
+pub struct Locale {
+    tag: String,
+    provider: Arc<dyn LocaleProvider>,
+}
+
+impl Locale {
+    /// The locale `tag`, formatted by [`Basic`].
+    pub fn new<T: Into<String>>(tag: T) -> Self {
+        Locale::with_provider(tag, Basic)
+    }
+
+    /// The locale `tag`, formatted by `provider`.
+    pub fn with_provider<T: Into<String>, P: LocaleProvider + 'static>(tag: T, provider: P) -> Self {
+        Locale {
+            tag: tag.into(),
+            provider: Arc::new(provider),
+        }
+    }
+
+    pub fn tag(&self) -> &str {
+        &self.tag
+    }
+
+    /// The decimal `number` as this locale writes it, `None` if it isn't
+    /// one.
+    pub fn format_number(&self, number: &str) -> Option<String> {
+        self.provider.format_number(&self.tag, number)
+    }
+
+    /// The plural category of the decimal `number`.
+    pub fn plural(&self, number: &str) -> Plural {
+        self.provider.plural(&self.tag, number)
+    }
+
+    /// The plural categories this locale uses, in order.
+    pub fn categories(&self) -> Vec<Plural> {
+        self.provider.categories(&self.tag)
+    }
+
+    /// A date as this locale writes it, `None` if it doesn't exist.
+    pub fn format_date(&self, year: i32, month: u8, day: u8) -> Option<String> {
+        self.provider.format_date(&self.tag, year, month, day)
+    }
+}
+
+impl Default for Locale {
+    fn default() -> Self {
+        Locale::new("en")
+    }
+}
+
+impl fmt::Debug for Locale {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Locale").field(&self.tag).finish()
+    }
+}
+
+/// The CLDR plural categories, in their usual order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Plural {
+    Zero,
+    One,
+    Two,
+    Few,
+    Many,
+    Other,
+}
+
+/// How the locales of a [`Locale`] format. Numbers are given as written in
+/// the data, such as `-1234.50`, and locales by their tag.
+pub trait LocaleProvider: Send + Sync {
+    /// `None` if `number` isn't a decimal number.
+    fn format_number(&self, locale: &str, number: &str) -> Option<String>;
+
+    fn plural(&self, locale: &str, number: &str) -> Plural;
+
+    /// Every category `plural` can return for `locale`, in order. English's
+    /// `One` and `Other` by default.
+    fn categories(&self, locale: &str) -> Vec<Plural> {
+        let _ = locale;
+        vec![Plural::One, Plural::Other]
+    }
+
+    /// `None` if the date doesn't exist.
+    fn format_date(&self, locale: &str, year: i32, month: u8, day: u8) -> Option<String>;
+}
+
+/// Formats the common European and East Asian languages, falling back on
+/// English. Dates are always ISO 8601.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Basic;
+
+// The language of a tag, lowercase.
+fn language(locale: &str) -> String {
+    locale.split(&['-', '_'][..]).next().unwrap_or("").to_ascii_lowercase()
+}
+
+// Splits a decimal number into its sign, integer and fraction digits.
+fn decimal(number: &str) -> Option<(bool, &str, &str)> {
+    let (negative, number) = match number.strip_prefix('-') {
+        Some(number) => (true, number),
+        None => (false, number),
+    };
+    let (integer, fraction) = match number.find('.') {
+        Some(i) => (&number[..i], &number[i + 1..]),
+        None => (number, ""),
+    };
+
+    let digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+    if integer.is_empty() || !digits(integer) || !digits(fraction) || number.ends_with('.') {
+        return None;
+    }
+    Some((negative, integer, fraction))
+}
+
+impl LocaleProvider for Basic {
+    fn format_number(&self, locale: &str, number: &str) -> Option<String> {
+        let (negative, integer, fraction) = decimal(number)?;
+        let (group, point) = match &language(locale)[..] {
+            "de" | "es" | "it" | "nl" | "pt" | "da" | "id" | "tr" => (".", ","),
+            "fr" => ("\u{202f}", ","),
+            "ru" | "uk" | "pl" | "cs" | "sk" | "sv" | "nb" | "no" | "fi" => ("\u{a0}", ","),
+            _ => (",", "."),
+        };
+
+        let mut out = String::with_capacity(number.len() * 2);
+        if negative {
+            out.push('-');
+        }
+        for (i, digit) in integer.chars().enumerate() {
+            if i > 0 && (integer.len() - i) % 3 == 0 {
+                out.push_str(group);
+            }
+            out.push(digit);
+        }
+        if !fraction.is_empty() {
+            out.push_str(point);
+            out.push_str(fraction);
+        }
+        Some(out)
+    }
+
+    fn plural(&self, locale: &str, number: &str) -> Plural {
+        let (integer, fraction) = match decimal(number) {
+            Some((_, integer, fraction)) => (integer, fraction),
+            None => return Plural::Other,
+        };
+        // The rules only look at the last digits, and a visible fraction
+        // is never `one` but in French.
+        let last = |digits: usize| integer[integer.len().saturating_sub(digits)..].parse::<u32>().unwrap_or(0);
+        let (n10, n100) = (last(1), last(2));
+        let is = |value: &str| integer.trim_start_matches('0') == value;
+        let whole = fraction.is_empty();
+
+        match &language(locale)[..] {
+            "ja" | "zh" | "ko" | "th" | "vi" | "id" => Plural::Other,
+            "fr" if is("") || is("1") => Plural::One,
+            "fr" => Plural::Other,
+            "ru" | "uk" if !whole => Plural::Other,
+            "ru" | "uk" if n10 == 1 && n100 != 11 => Plural::One,
+            "ru" | "uk" | "pl" if (2..=4).contains(&n10) && !(12..=14).contains(&n100) && whole => Plural::Few,
+            "ru" | "uk" => Plural::Many,
+            "pl" if !whole => Plural::Other,
+            "pl" if is("1") => Plural::One,
+            "pl" => Plural::Many,
+            "ar" if !whole => Plural::Other,
+            "ar" if is("") => Plural::Zero,
+            "ar" if is("1") => Plural::One,
+            "ar" if is("2") => Plural::Two,
+            "ar" if (3..=10).contains(&n100) => Plural::Few,
+            "ar" if n100 >= 11 => Plural::Many,
+            "ar" => Plural::Other,
+            _ if whole && is("1") => Plural::One,
+            _ => Plural::Other,
+        }
+    }
+
+    fn categories(&self, locale: &str) -> Vec<Plural> {
+        use self::Plural::*;
+
+        match &language(locale)[..] {
+            "ja" | "zh" | "ko" | "th" | "vi" | "id" => vec![Other],
+            "ru" | "uk" | "pl" => vec![One, Few, Many, Other],
+            "ar" => vec![Zero, One, Two, Few, Many, Other],
+            _ => vec![One, Other],
+        }
+    }
+
+    fn format_date(&self, _locale: &str, year: i32, month: u8, day: u8) -> Option<String> {
+        let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+        let days = match month {
+            1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+            4 | 6 | 9 | 11 => 30,
+            2 if leap => 29,
+            2 => 28,
+            _ => return None,
+        };
+        if day == 0 || day > days {
+            return None;
+        }
+        Some(format!("{:04}-{:02}-{:02}", year, month, day))
+    }
+}
+
+/// Formats with the CLDR data built into ICU4X, enabled with the `icu4x`
+/// feature. Dates use the medium length, such as `9 mars 2024`. Locales it
+/// can't parse fall back on [`Basic`].
+#[cfg(feature = "icu4x")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Icu;
+
+#[cfg(feature = "icu4x")]
+impl Icu {
+    fn locale(locale: &str) -> Option<icu::locid::Locale> {
+        locale.replace('_', "-").parse().ok()
+    }
+
+    fn plural_rules(locale: &str) -> Option<icu::plurals::PluralRules> {
+        Icu::locale(locale).and_then(|locale| icu::plurals::PluralRules::try_new_cardinal(&(&locale).into()).ok())
+    }
+
+    fn category(category: icu::plurals::PluralCategory) -> Plural {
+        use icu::plurals::PluralCategory;
+
+        match category {
+            PluralCategory::Zero => Plural::Zero,
+            PluralCategory::One => Plural::One,
+            PluralCategory::Two => Plural::Two,
+            PluralCategory::Few => Plural::Few,
+            PluralCategory::Many => Plural::Many,
+            PluralCategory::Other => Plural::Other,
+        }
+    }
+}
+
+#[cfg(feature = "icu4x")]
+impl LocaleProvider for Icu {
+    fn format_number(&self, locale: &str, number: &str) -> Option<String> {
+        use icu::decimal::FixedDecimalFormatter;
+
+        decimal(number)?;
+        let formatter = Icu::locale(locale)
+            .and_then(|locale| FixedDecimalFormatter::try_new(&(&locale).into(), Default::default()).ok());
+        match (formatter, number.parse::<FixedDecimal>()) {
+            (Some(formatter), Ok(number)) => Some(formatter.format_to_string(&number)),
+            _ => Basic.format_number(locale, number),
+        }
+    }
+
+    fn plural(&self, locale: &str, number: &str) -> Plural {
+        match (Icu::plural_rules(locale), number.parse::<FixedDecimal>()) {
+            (Some(rules), Ok(number)) => Icu::category(rules.category_for(&number)),
+            _ => Basic.plural(locale, number),
+        }
+    }
+
+    fn categories(&self, locale: &str) -> Vec<Plural> {
+        match Icu::plural_rules(locale) {
+            Some(rules) => {
+                let mut categories: Vec<_> = rules.categories().map(Icu::category).collect();
+                categories.sort();
+                categories
+            }
+            None => Basic.categories(locale),
+        }
+    }
+
+    fn format_date(&self, locale: &str, year: i32, month: u8, day: u8) -> Option<String> {
+        use icu::calendar::Date;
+        use icu::datetime::{options::length, DateFormatter};
+
+        let date = Date::try_new_iso_date(year, month, day).ok()?.to_any();
+        let formatter = Icu::locale(locale)
+            .and_then(|locale| DateFormatter::try_new_with_length(&(&locale).into(), length::Date::Medium).ok());
+        match formatter.and_then(|formatter| formatter.format_to_string(&date).ok()) {
+            Some(date) => Some(date),
+            None => Basic.format_date(locale, year, month, day),
+        }
+    }
+}
+
+fn locale_fun<F>(mut f: F) -> Data
+where F: FnMut(String, &Locale) -> String + Send + 'static
+{
+    Data::Helper(RefCell::new(Box::new(move |content, scope: &Scope| f(content, scope.locale()))))
+}
+
+/// Writes the number its section renders to, `{{#number}}{{total}}{{/number}}`.
+/// Anything else is left as it is.
+pub fn number() -> Data {
+    locale_fun(|content, locale| locale.format_number(content.trim()).unwrap_or(content))
+}
+
+/// Writes the `YYYY-MM-DD` date its section renders to,
+/// `{{#date}}{{created}}{{/date}}`. Anything else is left as it is.
+pub fn date() -> Data {
+    locale_fun(|content, locale| {
+        let parts: Vec<_> = content.trim().splitn(3, '-').collect();
+        let date = match parts[..] {
+            [year, month, day] if month.len() == 2 && day.len() == 2 => match (year.parse(), month.parse(), day.parse()) {
+                (Ok(year), Ok(month), Ok(day)) => locale.format_date(year, month, day),
+                _ => None,
+            },
+            _ => None,
+        };
+        date.unwrap_or(content)
+    })
+}
+
+/// Picks the plural form for a count, `{{#plural}}{{count}}|one apple|{{count}} apples{{/plural}}`.
+///
+/// The section renders to the count and then the forms, all separated by
+/// `|`. The forms go with the locale's
+/// [`categories`](struct.Locale.html#method.categories) in order, and the
+/// last one is used for any category left without its own, so an English
+/// template needs two forms and a Russian one four, for `One`, `Few`, `Many`
+/// and `Other`.
+pub fn plural() -> Data {
+    locale_fun(|content, locale| {
+        let mut parts = content.split('|');
+        let count = parts.next().unwrap_or("").trim();
+        let forms: Vec<_> = parts.collect();
+
+        let category = locale.plural(count);
+        let form = locale.categories()
+            .iter()
+            .position(|&c| c == category)
+            .and_then(|i| forms.get(i))
+            .or_else(|| forms.last());
+        form.map(|form| form.to_string()).unwrap_or_default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Basic, LocaleProvider, Plural};
+
+    #[test]
+    fn numbers() {
+        let cases = &[
+            ("en", "1234567.25", Some("1,234,567.25")),
+            ("en", "-12", Some("-12")),
+            ("de-DE", "1234.5", Some("1.234,5")),
+            ("fr", "1000", Some("1\u{202f}000")),
+            ("ru_RU", "100000", Some("100\u{a0}000")),
+            ("xx", "123", Some("123")),
+            ("en", "1.", None),
+            ("en", "abc", None),
+            ("en", "", None),
+        ];
+
+        for &(locale, number, expected) in cases {
+            assert_eq!(Basic.format_number(locale, number).as_ref().map(|s| &s[..]), expected, "{} {}", locale, number);
+        }
+    }
+
+    #[test]
+    fn plurals() {
+        let cases = &[
+            ("en", "1", Plural::One),
+            ("en", "1.0", Plural::Other),
+            ("en", "0", Plural::Other),
+            ("en", "101", Plural::Other),
+            ("fr", "0", Plural::One),
+            ("fr", "1.5", Plural::One),
+            ("fr", "2", Plural::Other),
+            ("ru", "21", Plural::One),
+            ("ru", "11", Plural::Many),
+            ("ru", "23", Plural::Few),
+            ("ru", "1.5", Plural::Other),
+            ("pl", "1", Plural::One),
+            ("pl", "22", Plural::Few),
+            ("pl", "25", Plural::Many),
+            ("ar", "2", Plural::Two),
+            ("ar", "105", Plural::Few),
+            ("ar", "111", Plural::Many),
+            ("ar", "100", Plural::Other),
+            ("ja", "1", Plural::Other),
+        ];
+
+        for &(locale, number, expected) in cases {
+            assert_eq!(Basic.plural(locale, number), expected, "{} {}", locale, number);
+        }
+    }
+
+    #[test]
+    fn plural_forms() {
+        use super::{plural, Locale};
+        use {compile_str, Data, MapBuilder, RenderOptions};
+
+        let template = compile_str("{{#plural}}{{n}}|{{n}} <one>|{{n}} few|{{n}} other{{/plural}}").unwrap();
+        let render = |locale: &str, n: &str| {
+            let mut data = MapBuilder::new().insert_str("n", n).build();
+            if let Data::Map(ref mut map) = data {
+                map.insert("plural".to_string(), plural());
+            }
+            let options = RenderOptions { locale: Some(Locale::new(locale)), ..RenderOptions::default() };
+            let mut out = Vec::new();
+            template.render_data_with(&mut out, &data, &options).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        assert_eq!(render("en", "1"), "1 <one>");
+        assert_eq!(render("en", "3"), "3 few");
+        assert_eq!(render("ru", "3"), "3 few");
+        assert_eq!(render("ru", "5"), "5 other");
+        assert_eq!(render("ja", "1"), "1 <one>");
+        // Tags in the data stay text.
+        assert_eq!(render("en", "{{n}}"), "{{n}} few");
+    }
+
+    #[test]
+    fn dates() {
+        assert_eq!(Basic.format_date("en", 2024, 2, 29), Some("2024-02-29".to_string()));
+        assert_eq!(Basic.format_date("en", 2023, 2, 29), None);
+        assert_eq!(Basic.format_date("en", 2023, 13, 1), None);
+    }
+
+    #[cfg(feature = "icu4x")]
+    #[test]
+    fn icu() {
+        use super::Icu;
+
+        assert_eq!(Icu.format_number("de", "1234.5").unwrap(), "1.234,5");
+        assert_eq!(Icu.plural("ru", "23"), Plural::Few);
+        assert_eq!(Icu.categories("ru"), vec![Plural::One, Plural::Few, Plural::Many, Plural::Other]);
+        assert_eq!(Icu.format_date("fr", 2024, 3, 9).unwrap(), "9 mars 2024");
+        assert_eq!(Icu.format_date("fr", 2024, 2, 30), None);
+    }
+}
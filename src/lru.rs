@@ -0,0 +1,228 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use context::PartialLoader;
+use metrics::{Metrics, MetricsSink};
+use Result;
+
+/// A [`PartialLoader`] that keeps the sources loaded by another one, up to a
+/// number of entries and a total size, forgetting the least recently used
+/// ones first. Clones share the same cache.
+///
+/// Unlike [`Context::cache_partials`](struct.Context.html#method.cache_partials),
+/// which keeps every compiled partial for good, this bounds the memory held
+/// when there are many more partials than are in use at once.
+///
+/// ```
+/// use std::path::PathBuf;
+/// use mustache::{Context, DefaultLoader, LruLoader};
+///
+/// let loader = LruLoader::new(DefaultLoader::new(PathBuf::from("."), "mustache".to_string()))
+///     .max_entries(1000)
+///     .max_bytes(16 << 20);
+/// let ctx = Context::with_loader(loader);
+/// ```
+#[derive(Clone, Debug)]
+pub struct LruLoader<P: PartialLoader> {
+    loader: P,
+    max_entries: Option<usize>,
+    max_bytes: Option<usize>,
+    cache: Arc<Mutex<Lru>>,
+    metrics: Option<Metrics>,
+}
+
+#[derive(Debug, Default)]
+struct Lru {
+    entries: HashMap<PathBuf, (String, u64)>,
+    // Names by when they were last used, oldest first.
+    order: BTreeMap<u64, PathBuf>,
+    bytes: usize,
+    clock: u64,
+}
+
+impl<P: PartialLoader> LruLoader<P> {
+    /// Wraps `loader`, with no limits until some are set.
+    pub fn new(loader: P) -> Self {
+        LruLoader {
+            loader,
+            max_entries: None,
+            max_bytes: None,
+            cache: Arc::default(),
+            metrics: None,
+        }
+    }
+
+    /// Keeps at most `max` sources.
+    pub fn max_entries(mut self, max: usize) -> Self {
+        self.max_entries = Some(max);
+        self
+    }
+
+    /// Keeps at most `max` bytes of sources. A source larger than that on its
+    /// own is never kept.
+    pub fn max_bytes(mut self, max: usize) -> Self {
+        self.max_bytes = Some(max);
+        self
+    }
+
+    /// Tells `sink` whether each source loaded was kept, as a lookup in the
+    /// `"lru"` cache. See [`MetricsSink`](trait.MetricsSink.html).
+    pub fn record_metrics(mut self, sink: Arc<dyn MetricsSink>) -> Self {
+        self.metrics = Some(Metrics(sink));
+        self
+    }
+
+    /// The number of sources kept right now.
+    pub fn len(&self) -> usize {
+        self.cache.lock().map(|lru| lru.entries.len()).unwrap_or(0)
+    }
+
+    /// Whether no sources are kept right now.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Forgets every source kept so far.
+    pub fn clear(&self) {
+        if let Ok(mut lru) = self.cache.lock() {
+            *lru = Lru::default();
+        }
+    }
+
+    fn looked_up(&self, name: &Path, hit: bool) {
+        if let Some(Metrics(ref sink)) = self.metrics {
+            sink.cache_lookup("lru", &name.to_string_lossy(), hit);
+        }
+    }
+
+    fn fits(&self, entries: usize, bytes: usize) -> bool {
+        !matches!(self.max_entries, Some(max) if entries > max)
+            && !matches!(self.max_bytes, Some(max) if bytes > max)
+    }
+}
+
+impl Lru {
+    fn get(&mut self, name: &Path) -> Option<String> {
+        let clock = self.clock + 1;
+        let &mut (ref source, ref mut used) = self.entries.get_mut(name)?;
+
+        let name = self.order.remove(used)?;
+        *used = clock;
+        self.order.insert(clock, name);
+        self.clock = clock;
+
+        Some(source.clone())
+    }
+
+    fn remove_oldest(&mut self) {
+        let oldest = match self.order.keys().next() {
+            Some(&used) => used,
+            None => return,
+        };
+
+        if let Some(name) = self.order.remove(&oldest) {
+            if let Some((source, _)) = self.entries.remove(&name) {
+                self.bytes -= source.len();
+            }
+        }
+    }
+}
+
+impl<P: PartialLoader> PartialLoader for LruLoader<P> {
+    fn load(&self, name: impl AsRef<Path>) -> Result<String> {
+        let name = name.as_ref();
+
+        if let Ok(mut lru) = self.cache.lock() {
+            if let Some(source) = lru.get(name) {
+                drop(lru);
+                self.looked_up(name, true);
+                return Ok(source);
+            }
+        }
+        self.looked_up(name, false);
+
+        // Load without holding the lock, a slow loader shouldn't hold up the
+        // templates already cached.
+        let source = self.loader.load(name)?;
+
+        if !self.fits(1, source.len()) {
+            return Ok(source);
+        }
+
+        if let Ok(mut lru) = self.cache.lock() {
+            // Someone else may have loaded it in the meantime.
+            if lru.entries.contains_key(name) {
+                return Ok(source);
+            }
+
+            while !self.fits(lru.entries.len() + 1, lru.bytes + source.len()) {
+                lru.remove_oldest();
+            }
+
+            lru.clock += 1;
+            let clock = lru.clock;
+            lru.order.insert(clock, name.to_path_buf());
+            lru.entries.insert(name.to_path_buf(), (source.clone(), clock));
+            lru.bytes += source.len();
+        }
+
+        Ok(source)
+    }
+
+    fn path(&self, name: impl AsRef<Path>) -> Option<PathBuf> {
+        self.loader.path(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::sync::{Arc, Mutex};
+
+    use super::LruLoader;
+    use {PartialLoader, Result};
+
+    // Names its partials after themselves and counts the loads.
+    #[derive(Clone, Debug, Default)]
+    struct Loader(Arc<Mutex<Vec<String>>>);
+
+    impl PartialLoader for Loader {
+        fn load(&self, name: impl AsRef<Path>) -> Result<String> {
+            let name = name.as_ref().to_str().unwrap().to_string();
+            self.0.lock().unwrap().push(name.clone());
+            Ok(name)
+        }
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let inner = Loader::default();
+        let loader = LruLoader::new(inner.clone()).max_entries(2);
+
+        for name in &["a", "b", "a", "c", "a", "b"] {
+            assert_eq!(loader.load(name).unwrap(), *name);
+        }
+
+        // `b` went to make room for `c`, `a` was used since.
+        assert_eq!(*inner.0.lock().unwrap(), vec!["a", "b", "c", "b"]);
+        assert_eq!(loader.len(), 2);
+    }
+
+    #[test]
+    fn limits_bytes() {
+        let inner = Loader::default();
+        let loader = LruLoader::new(inner.clone()).max_bytes(4);
+
+        for name in &["aa", "bb", "toolong", "aa", "toolong", "ccc", "bb"] {
+            assert_eq!(loader.load(name).unwrap(), *name);
+        }
+
+        // `toolong` never fits, `ccc` pushes both others out.
+        assert_eq!(*inner.0.lock().unwrap(), vec!["aa", "bb", "toolong", "toolong", "ccc", "bb"]);
+        assert_eq!(loader.len(), 1);
+
+        loader.clear();
+        assert!(loader.is_empty());
+    }
+}
@@ -0,0 +1,110 @@
+//! Rendering markdown values to HTML, for bios, comments and anything else
+//! stored as markdown.
+//!
+//! [`markdown`] is a [`Data::Helper`](../enum.Data.html#variant.Helper)
+//! lambda turning the markdown its section renders to into HTML,
+//! `{{#markdown}}{{{bio}}}{{/markdown}}`. Values inside it want the triple
+//! mustache: escaping them first would turn `>` quotes and `&` into text.
+//!
+//! The HTML is safe to write into a page whatever the markdown was. Raw HTML
+//! in it is written out as text, and links and images only keep `http`,
+//! `https`, `mailto` and relative URLs.
+//!
+//! ```
+//! use mustache::{markdown, Data, MapBuilder};
+//!
+//! let template = mustache::compile_str("<div>{{#markdown}}{{{bio}}}{{/markdown}}</div>").unwrap();
+//!
+//! let mut data = MapBuilder::new().insert_str("bio", "Likes *cats* <script>").build();
+//! if let Data::Map(ref mut map) = data {
+//!     map.insert("markdown".to_string(), markdown::markdown());
+//! }
+//! assert_eq!(
+//!     template.render_data_to_string(&data).unwrap(),
+//!     "<div><p>Likes <em>cats</em> &lt;script&gt;</p>\n</div>",
+//! );
+//! ```
+
+use std::cell::RefCell;
+
+use pulldown_cmark::{html, CowStr, Event, Options, Parser, Tag, TagEnd};
+
+use {Data, Scope};
+
+/// Renders the markdown its section renders to as HTML, with tables,
+/// strikethrough and task lists on top of CommonMark.
+pub fn markdown() -> Data {
+    Data::Helper(RefCell::new(Box::new(|content: String, _: &Scope| to_html(&content))))
+}
+
+/// Renders `markdown` as HTML the way [`markdown`] does, for values rendered
+/// ahead of time.
+pub fn to_html(markdown: &str) -> String {
+    let options = Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS;
+    let events = Parser::new_ext(markdown, options).map(|event| match event {
+        // HTML blocks become paragraphs of their text.
+        Event::Start(Tag::HtmlBlock) => Event::Start(Tag::Paragraph),
+        Event::End(TagEnd::HtmlBlock) => Event::End(TagEnd::Paragraph),
+        Event::Html(html) | Event::InlineHtml(html) => Event::Text(html),
+        Event::Start(Tag::Link { link_type, dest_url, title, id }) => Event::Start(Tag::Link {
+            link_type,
+            dest_url: safe_url(dest_url),
+            title,
+            id,
+        }),
+        Event::Start(Tag::Image { link_type, dest_url, title, id }) => Event::Start(Tag::Image {
+            link_type,
+            dest_url: safe_url(dest_url),
+            title,
+            id,
+        }),
+        event => event,
+    });
+
+    let mut out = String::with_capacity(markdown.len() * 3 / 2);
+    html::push_html(&mut out, events);
+    out
+}
+
+// Keeps `url` if it is relative or has an allowed scheme, such that a
+// `javascript:` link goes nowhere.
+fn safe_url(url: CowStr) -> CowStr {
+    let scheme_end = match url.find(&[':', '/', '?', '#'][..]) {
+        Some(i) if url[i..].starts_with(':') => i,
+        _ => return url,
+    };
+
+    // Browsers ignore whitespace and control characters in schemes.
+    let scheme: String = url[..scheme_end]
+        .chars()
+        .filter(|c| !c.is_whitespace() && !c.is_control())
+        .collect();
+    match &scheme.to_ascii_lowercase()[..] {
+        "http" | "https" | "mailto" => url,
+        _ => CowStr::Borrowed(""),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::to_html;
+
+    #[test]
+    fn sanitized() {
+        let cases = &[
+            ("**bold** ~~gone~~", "<p><strong>bold</strong> <del>gone</del></p>\n"),
+            ("<b onclick=\"x()\">hi</b>", "<p>&lt;b onclick=\"x()\"&gt;hi&lt;/b&gt;</p>\n"),
+            ("<div>\nblock\n</div>", "<p>&lt;div&gt;\nblock\n&lt;/div&gt;</p>\n"),
+            ("[site](https://example.com)", "<p><a href=\"https://example.com\">site</a></p>\n"),
+            ("[page](/about?x=1:2)", "<p><a href=\"/about?x=1:2\">page</a></p>\n"),
+            ("[mail](MAILTO:a@example.com)", "<p><a href=\"MAILTO:a@example.com\">mail</a></p>\n"),
+            ("[x](javascript:alert(1))", "<p><a href=\"\">x</a></p>\n"),
+            ("[x](<java script:alert(1)>)", "<p><a href=\"\">x</a></p>\n"),
+            ("![img](data:image/png;base64,AA)", "<p><img src=\"\" alt=\"img\" /></p>\n"),
+        ];
+
+        for &(markdown, expected) in cases {
+            assert_eq!(to_html(markdown), expected, "{:?}", markdown);
+        }
+    }
+}
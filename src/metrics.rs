@@ -0,0 +1,241 @@
+//! Counting and timing compiles, renders and cache lookups, see
+//! [`MetricsSink`](trait.MetricsSink.html).
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+#[cfg(feature = "prometheus")]
+use std::collections::BTreeMap;
+#[cfg(feature = "prometheus")]
+use std::fmt::Write;
+#[cfg(feature = "prometheus")]
+use std::sync::Mutex;
+#[cfg(feature = "prometheus")]
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Told of the compiles, renders and cache lookups of a
+/// [`Context`](struct.Context.html) given it with
+/// [`Context::record_metrics`](struct.Context.html#method.record_metrics),
+/// and of the lookups of an [`LruLoader`](struct.LruLoader.html) given it
+/// with [`LruLoader::record_metrics`](struct.LruLoader.html#method.record_metrics).
+///
+/// Every method does nothing by default, so a sink only implements what it
+/// records. They are called on the thread doing the work, in the middle of
+/// it, and should be quick.
+///
+/// ```
+/// use std::path::PathBuf;
+/// use std::sync::Arc;
+/// use std::sync::atomic::{AtomicUsize, Ordering};
+/// use std::time::Duration;
+/// use mustache::{Context, MetricsSink};
+///
+/// #[derive(Default)]
+/// struct Bytes(AtomicUsize);
+///
+/// impl MetricsSink for Bytes {
+///     fn rendered(&self, _: Duration, bytes: usize) {
+///         self.0.fetch_add(bytes, Ordering::Relaxed);
+///     }
+/// }
+///
+/// let bytes = Arc::new(Bytes::default());
+/// let mut ctx = Context::new(PathBuf::from("."));
+/// ctx.record_metrics(bytes.clone());
+///
+/// let template = ctx.compile("Hello {{name}}".chars()).unwrap();
+/// template.render_data_to_string(&mustache::Data::Null).unwrap();
+/// assert_eq!(bytes.0.load(Ordering::Relaxed), 6);
+/// ```
+pub trait MetricsSink: Send + Sync {
+    /// A template was compiled in `elapsed`, its partials included. `name`
+    /// is its path, for templates compiled from one.
+    fn compiled(&self, _name: Option<&str>, _elapsed: Duration) {}
+
+    /// A render wrote `bytes` in `elapsed`. Renders that fail aren't
+    /// counted here but in [`render_failed`](#method.render_failed).
+    fn rendered(&self, _elapsed: Duration, _bytes: usize) {}
+
+    /// A render failed after `elapsed`.
+    fn render_failed(&self, _elapsed: Duration) {}
+
+    /// `name` was looked up in `cache`, and found there if `hit`. `cache` is
+    /// `"partials"` for the context's
+    /// [partial cache](struct.Context.html#method.cache_partials),
+    /// `"compile"` for a [`CompileCache`](struct.CompileCache.html) and
+    /// `"lru"` for an `LruLoader`.
+    fn cache_lookup(&self, _cache: &str, _name: &str, _hit: bool) {}
+}
+
+// A sink as kept by a context or loader, which have to be `Debug`.
+#[derive(Clone)]
+pub(crate) struct Metrics(pub Arc<dyn MetricsSink>);
+
+impl fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Metrics")
+    }
+}
+
+// The upper bounds of the histogram buckets, in seconds.
+#[cfg(feature = "prometheus")]
+const BUCKETS: [f64; 10] = [0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.1, 1.0];
+
+/// A [`MetricsSink`] keeping counters and histograms, written out in the
+/// Prometheus text format by [`encode`](#method.encode) for a `/metrics`
+/// endpoint to serve.
+///
+/// ```
+/// use std::path::PathBuf;
+/// use std::sync::Arc;
+/// use mustache::{Context, PrometheusMetrics};
+///
+/// let metrics = Arc::new(PrometheusMetrics::new());
+/// let mut ctx = Context::new(PathBuf::from("."));
+/// ctx.record_metrics(metrics.clone());
+///
+/// ctx.compile("Hello".chars()).unwrap().render_data_to_string(&mustache::Data::Null).unwrap();
+/// let text = metrics.encode();
+/// assert!(text.contains("mustache_compile_seconds_count 1\n"));
+/// assert!(text.contains("mustache_output_bytes_total 5\n"));
+/// ```
+#[cfg(feature = "prometheus")]
+#[derive(Debug, Default)]
+pub struct PrometheusMetrics {
+    compile: Histogram,
+    render: Histogram,
+    render_failures: AtomicU64,
+    output_bytes: AtomicU64,
+    // Hits and misses by cache.
+    caches: Mutex<BTreeMap<String, (u64, u64)>>,
+}
+
+#[cfg(feature = "prometheus")]
+#[derive(Debug, Default)]
+struct Histogram {
+    buckets: [AtomicU64; 10],
+    count: AtomicU64,
+    nanos: AtomicU64,
+}
+
+#[cfg(feature = "prometheus")]
+impl Histogram {
+    fn observe(&self, elapsed: Duration) {
+        let seconds = elapsed.as_secs_f64();
+        if let Some(i) = BUCKETS.iter().position(|&bound| seconds <= bound) {
+            self.buckets[i].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.nanos.fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    fn encode(&self, out: &mut String, name: &str, help: &str) {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+
+        // Buckets count everything up to their bound.
+        let mut below = 0;
+        for (bound, bucket) in BUCKETS.iter().zip(&self.buckets) {
+            below += bucket.load(Ordering::Relaxed);
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, below);
+        }
+        let count = self.count.load(Ordering::Relaxed);
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, count);
+        let _ = writeln!(out, "{}_sum {}", name, self.nanos.load(Ordering::Relaxed) as f64 / 1e9);
+        let _ = writeln!(out, "{}_count {}", name, count);
+    }
+}
+
+#[cfg(feature = "prometheus")]
+impl PrometheusMetrics {
+    /// Metrics with nothing recorded yet.
+    pub fn new() -> PrometheusMetrics {
+        PrometheusMetrics::default()
+    }
+
+    /// Everything recorded so far, in the Prometheus text format.
+    pub fn encode(&self) -> String {
+        let mut out = String::new();
+        self.compile.encode(&mut out, "mustache_compile_seconds", "Time spent compiling templates.");
+        self.render.encode(&mut out, "mustache_render_seconds", "Time spent rendering templates.");
+
+        let counters = [
+            ("mustache_render_failures_total", "Renders that failed.", &self.render_failures),
+            ("mustache_output_bytes_total", "Bytes written by renders.", &self.output_bytes),
+        ];
+        for &(name, help, counter) in &counters {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} counter", name);
+            let _ = writeln!(out, "{} {}", name, counter.load(Ordering::Relaxed));
+        }
+
+        let _ = writeln!(out, "# HELP mustache_cache_lookups_total Cache lookups, by cache and result.");
+        let _ = writeln!(out, "# TYPE mustache_cache_lookups_total counter");
+        if let Ok(caches) = self.caches.lock() {
+            for (cache, &(hits, misses)) in caches.iter() {
+                let _ = writeln!(out, "mustache_cache_lookups_total{{cache=\"{}\",result=\"hit\"}} {}", cache, hits);
+                let _ = writeln!(out, "mustache_cache_lookups_total{{cache=\"{}\",result=\"miss\"}} {}", cache, misses);
+            }
+        }
+        out
+    }
+}
+
+#[cfg(feature = "prometheus")]
+impl MetricsSink for PrometheusMetrics {
+    fn compiled(&self, _: Option<&str>, elapsed: Duration) {
+        self.compile.observe(elapsed);
+    }
+
+    fn rendered(&self, elapsed: Duration, bytes: usize) {
+        self.render.observe(elapsed);
+        self.output_bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    fn render_failed(&self, elapsed: Duration) {
+        self.render.observe(elapsed);
+        self.render_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn cache_lookup(&self, cache: &str, _: &str, hit: bool) {
+        if let Ok(mut caches) = self.caches.lock() {
+            let counts = caches.entry(cache.to_string()).or_insert((0, 0));
+            if hit {
+                counts.0 += 1;
+            } else {
+                counts.1 += 1;
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "prometheus"))]
+mod tests {
+    use std::time::Duration;
+
+    use super::{MetricsSink, PrometheusMetrics};
+
+    #[test]
+    fn encode() {
+        let metrics = PrometheusMetrics::new();
+        metrics.rendered(Duration::from_micros(300), 10);
+        metrics.rendered(Duration::from_secs(2), 5);
+        metrics.render_failed(Duration::from_millis(2));
+        metrics.cache_lookup("lru", "a", true);
+        metrics.cache_lookup("lru", "b", false);
+        metrics.cache_lookup("lru", "a", true);
+
+        let text = metrics.encode();
+        assert!(text.contains("mustache_render_seconds_bucket{le=\"0.00025\"} 0\n"));
+        assert!(text.contains("mustache_render_seconds_bucket{le=\"0.0005\"} 1\n"));
+        assert!(text.contains("mustache_render_seconds_bucket{le=\"0.0025\"} 2\n"));
+        assert!(text.contains("mustache_render_seconds_bucket{le=\"1\"} 2\n"));
+        assert!(text.contains("mustache_render_seconds_bucket{le=\"+Inf\"} 3\n"));
+        assert!(text.contains("mustache_render_seconds_sum 2.0023\n"));
+        assert!(text.contains("mustache_output_bytes_total 15\n"));
+        assert!(text.contains("mustache_render_failures_total 1\n"));
+        assert!(text.contains("mustache_cache_lookups_total{cache=\"lru\",result=\"hit\"} 2\n"));
+        assert!(text.contains("mustache_cache_lookups_total{cache=\"lru\",result=\"miss\"} 1\n"));
+        assert!(text.contains("mustache_compile_seconds_count 0\n"));
+    }
+}
@@ -0,0 +1,128 @@
+//! Loading large templates from memory-mapped files, see
+//! [`DefaultLoader::mmap`](struct.DefaultLoader.html#method.mmap).
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str;
+use std::sync::{Arc, Mutex, PoisonError};
+
+use memmap2::Mmap;
+
+use context::{DefaultLoader, PartialLoader};
+use {Error, Result};
+
+/// A [`DefaultLoader`] that maps the files of at least `min_len` bytes into
+/// memory rather than reading them, so the compiler goes through their
+/// source without a copy of it on the heap. Smaller files are read as
+/// usual.
+///
+/// A file is mapped the first time it is loaded and stays mapped for as
+/// long as the loader or a clone of it lives, later loads going through the
+/// same mapping. The files mustn't be truncated while they are: that makes
+/// reading them crash the process.
+///
+/// ```
+/// use std::path::PathBuf;
+/// use mustache::{Context, DefaultLoader};
+///
+/// let loader = DefaultLoader::new(PathBuf::from("tests/test-data"), "mustache".to_string()).mmap(1 << 20);
+/// let template = Context::with_loader(loader).compile_path("username").unwrap();
+/// ```
+#[derive(Clone)]
+pub struct MmapLoader {
+    loader: DefaultLoader,
+    min_len: u64,
+    maps: Arc<Mutex<HashMap<PathBuf, Arc<Mmap>>>>,
+}
+
+impl MmapLoader {
+    pub(crate) fn new(loader: DefaultLoader, min_len: u64) -> MmapLoader {
+        MmapLoader { loader, min_len, maps: Arc::default() }
+    }
+
+    // The mapping of `name`'s file, if it has one and is large enough.
+    fn map(&self, name: &Path) -> Result<Option<Arc<Mmap>>> {
+        // A map only ever gets mappings added, so one a panic left behind is
+        // as good as any.
+        let mut maps = self.maps.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(map) = maps.get(name) {
+            return Ok(Some(map.clone()));
+        }
+
+        let file = match self.loader.open(name)? {
+            Some(file) => file,
+            None => return Ok(None),
+        };
+        let len = file.metadata()?.len();
+        if len == 0 || len < self.min_len {
+            return Ok(None);
+        }
+
+        // The mapping is only read, and is as valid as the file it maps,
+        // which the docs ask not to be truncated while mapped.
+        let map = Arc::new(unsafe { Mmap::map(&file)? });
+        maps.insert(name.to_path_buf(), map.clone());
+        Ok(Some(map))
+    }
+}
+
+impl PartialLoader for MmapLoader {
+    fn load(&self, name: impl AsRef<Path>) -> Result<String> {
+        self.load_str(name).map(Cow::into_owned)
+    }
+
+    fn load_str(&self, name: impl AsRef<Path>) -> Result<Cow<'_, str>> {
+        let name = name.as_ref();
+        let map = match self.map(name)? {
+            Some(map) => map,
+            None => return self.loader.load(name).map(Cow::Owned),
+        };
+
+        // Mappings are never taken out of `maps`, so the bytes live as long
+        // as `self` does.
+        let bytes: &[u8] = unsafe { &*(&map[..] as *const [u8]) };
+        str::from_utf8(bytes).map(Cow::Borrowed).map_err(|_| Error::InvalidStr)
+    }
+
+    fn path(&self, name: impl AsRef<Path>) -> Option<PathBuf> {
+        self.loader.path(name)
+    }
+}
+
+impl fmt::Debug for MmapLoader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MmapLoader").field("loader", &self.loader).field("min_len", &self.min_len).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+    use std::fs::File;
+    use std::io::Write;
+
+    use tempdir::TempDir;
+
+    use {Context, DefaultLoader, MapBuilder, PartialLoader};
+
+    #[test]
+    fn mapped_files() {
+        let tmpdir = TempDir::new("").unwrap();
+        File::create(tmpdir.path().join("big.mustache")).unwrap().write_all(b"Hello {{name}}{{>small}}").unwrap();
+        File::create(tmpdir.path().join("small.mustache")).unwrap().write_all(b"!").unwrap();
+        File::create(tmpdir.path().join("bad.mustache")).unwrap().write_all(b"\xff\xff").unwrap();
+
+        let loader = DefaultLoader::new(tmpdir.path().to_path_buf(), "mustache".to_string()).mmap(2);
+        assert!(matches!(loader.load_str("big").unwrap(), Cow::Borrowed("Hello {{name}}{{>small}}")));
+        assert!(matches!(loader.load_str("small").unwrap(), Cow::Owned(ref s) if s == "!"));
+        assert_eq!(loader.load_str("missing").unwrap(), "");
+        assert!(loader.load_str("bad").is_err());
+        assert!(loader.load_str("../big").is_err());
+
+        let template = Context::with_loader(loader).compile_path("big").unwrap();
+        let data = MapBuilder::new().insert_str("name", "Ann").build();
+        assert_eq!(template.render_data_to_string(&data).unwrap(), "Hello Ann!");
+    }
+}
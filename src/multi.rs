@@ -0,0 +1,95 @@
+use std::collections::BTreeMap;
+
+use context::{Context, PartialLoader};
+use {Error, Result, Template};
+
+pub fn compile<P: PartialLoader>(ctx: &Context<P>, file: Option<&str>, source: &str)
+                                 -> Result<BTreeMap<String, Template<P>>> {
+    let mut templates = BTreeMap::new();
+
+    for (name, source) in split(source) {
+        if templates.contains_key(name) {
+            return Err(Error::DuplicateTemplate(name.to_string()));
+        }
+
+        let called = match file {
+            Some(file) => format!("{}#{}", file, name),
+            None => name.to_string(),
+        };
+        let template = ctx.compile_named(Some(called), source.chars())?;
+        templates.insert(name.to_string(), template);
+    }
+
+    Ok(templates)
+}
+
+// The named templates of `source`, each the lines from its marker to the
+// next one. The line break ahead of a marker goes with it, so a template
+// doesn't end with one it only has for the marker to be on a line of its
+// own. Whatever comes before the first marker is left out.
+fn split(source: &str) -> Vec<(&str, &str)> {
+    let mut templates = Vec::new();
+    let mut current: Option<(&str, usize)> = None;
+
+    let mut start = 0;
+    while start < source.len() {
+        let end = source[start..].find('\n').map_or(source.len(), |i| start + i + 1);
+
+        if let Some(name) = marker(&source[start..end]) {
+            if let Some((name, from)) = current.take() {
+                templates.push((name, without_line_break(&source[from..start])));
+            }
+            current = Some((name, end));
+        }
+
+        start = end;
+    }
+
+    if let Some((name, from)) = current {
+        templates.push((name, &source[from..]));
+    }
+    templates
+}
+
+// The name in a `{{!-- template: name --}}` line.
+fn marker(line: &str) -> Option<&str> {
+    let comment = line.trim().strip_prefix("{{!--")?.strip_suffix("--}}")?;
+    let name = comment.trim().strip_prefix("template:")?.trim();
+
+    if name.is_empty() {
+        None
+    } else {
+        Some(name)
+    }
+}
+
+fn without_line_break(text: &str) -> &str {
+    let text = text.strip_suffix('\n').unwrap_or(text);
+    text.strip_suffix('\r').unwrap_or(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::split;
+
+    #[test]
+    fn templates() {
+        let source = "A welcome email.\n\
+                      {{!-- template: subject --}}\n\
+                      Welcome, {{name}}\n\
+                      {{!-- template: text --}}\r\n\
+                      Hi {{name}},\r\n\
+                      \r\n\
+                      {{!-- template:html--}}\n\
+                      <p>Hi {{name}}</p>\n";
+
+        assert_eq!(split(source), vec![
+            ("subject", "Welcome, {{name}}"),
+            ("text", "Hi {{name}},\r\n"),
+            ("html", "<p>Hi {{name}}</p>\n"),
+        ]);
+
+        assert_eq!(split("{{!-- template: a --}}"), vec![("a", "")]);
+        assert_eq!(split("{{! template: a }}\n{{!-- template: --}}"), vec![]);
+    }
+}
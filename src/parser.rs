@@ -2,6 +2,8 @@ use std::error::Error as StdError;
 use std::mem;
 use std::fmt;
 
+use syntax::{Lexeme, Span};
+
 // for bug!
 use log::{log, error};
 
@@ -11,9 +13,105 @@ pub enum Token {
     Text(String),
     EscapedTag(Vec<String>, String),
     UnescapedTag(Vec<String>, String),
+    PointerTag(Vec<String>, String),
+    FallbackTag(Vec<Vec<String>>, bool, String),
+    Condition(Comparison, bool, Vec<Token>, String, String, String, String, String),
+    Range(Bounds, bool, Vec<Token>, String, String, String, String, String),
+    Raw(String, String),
+    Deprecated(String),
     Section(Vec<String>, bool, Vec<Token>, String, String, String, String, String),
     IncompleteSection(Vec<String>, bool, String, bool),
     Partial(String, String, String),
+    PartialWithFallback(String, String, Vec<Token>, String, String, String),
+    Definition(String, Vec<Token>, String, String, String),
+    IncompleteDefinition(String, String, bool),
+}
+
+/// What a section tag compares with `Extensions::comparisons`, such as
+/// `count > 0`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Comparison {
+    pub left: Operand,
+    pub op: CompareOp,
+    pub right: Operand,
+}
+
+/// A side of a `Comparison`: a name looked up in the context, or a string or
+/// number written in the template.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Operand {
+    Name(Vec<String>),
+    Text(String),
+    Number(String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// The numbers a section goes through with `Extensions::ranges`, such as
+/// `1..count`, both included.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Bounds {
+    pub start: Operand,
+    pub end: Operand,
+}
+
+impl Bounds {
+    /// The names the range looks up, in order.
+    pub fn names(&self) -> Vec<&[String]> {
+        [&self.start, &self.end].iter().filter_map(|operand| match **operand {
+            Operand::Name(ref path) => Some(&path[..]),
+            _ => None,
+        }).collect()
+    }
+}
+
+impl Comparison {
+    /// The names the comparison looks up, in order.
+    pub fn names(&self) -> Vec<&[String]> {
+        [&self.left, &self.right].iter().filter_map(|operand| match **operand {
+            Operand::Name(ref path) => Some(&path[..]),
+            _ => None,
+        }).collect()
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let op = match self.op {
+            CompareOp::Eq => "==",
+            CompareOp::Ne => "!=",
+            CompareOp::Lt => "<",
+            CompareOp::Le => "<=",
+            CompareOp::Gt => ">",
+            CompareOp::Ge => ">=",
+        };
+        write!(f, "{} {} {}", self.left, op, self.right)
+    }
+}
+
+impl fmt::Display for Bounds {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}..{}", self.start, self.end)
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Operand::Name(ref path) if path.is_empty() => f.write_str("."),
+            Operand::Name(ref path) => f.write_str(&path.join(".")),
+            Operand::Text(ref text) => write!(f, "\"{}\"", text),
+            Operand::Number(ref number) => f.write_str(number),
+        }
+    }
 }
 
 /// Error type to represent parsing failure.
@@ -21,6 +119,7 @@ pub enum Token {
 /// This type is not intended to be matched exhaustively as new variants
 /// may be added in future without a version bump.
 #[derive(Debug, PartialEq)]
+#[allow(clippy::manual_non_exhaustive)]
 pub enum Error {
     BadClosingTag(char, char),
     UnclosedTag,
@@ -30,6 +129,17 @@ pub enum Error {
     EarlySectionClose(String),
     MissingSetDelimeterClosingTag,
     InvalidSetDelimeterSyntax,
+    NestingTooDeep,
+    /// A section tag that looks like a comparison but isn't one, with
+    /// [`Extensions::comparisons`](struct.Extensions.html#structfield.comparisons).
+    InvalidComparison(String),
+    /// A section tag that looks like a range but isn't one, with
+    /// [`Extensions::ranges`](struct.Extensions.html#structfield.ranges).
+    InvalidRange(String),
+    /// A tag whose content still starts or ends with `-` once its
+    /// [trim markers](struct.Extensions.html#structfield.trim_markers) are
+    /// taken off, such as `{{--name}}`.
+    AmbiguousTrimMarker(String),
 
     #[doc(hidden)]
     __Nonexhaustive,
@@ -49,11 +159,90 @@ impl fmt::Display for Error {
             Error::EmptyTag => write!(f, "found an empty tag",),
             Error::MissingSetDelimeterClosingTag => write!(f, "missing the new closing tag in set delimeter tag"),
             Error::InvalidSetDelimeterSyntax => write!(f, "invalid set delimeter tag syntax"),
+            Error::NestingTooDeep => write!(f, "sections are nested more than {} deep", MAX_NESTING),
+            Error::InvalidComparison(ref expr) => write!(f, "invalid comparison: {:?}", expr),
+            Error::InvalidRange(ref expr) => write!(f, "invalid range: {:?}", expr),
+            Error::AmbiguousTrimMarker(ref content) => write!(f, "the `-` at the edge of {:?} can't be told from a trim marker", content),
             Error::__Nonexhaustive => unreachable!(),
         }
     }
 }
 
+/// Optional syntax extensions understood by the parser.
+///
+/// None of these are part of the mustache spec, so they are all disabled by
+/// default and must be enabled on the [`Context`](struct.Context.html).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Extensions {
+    /// Treat a `-` right after the opening delimiter or right before the
+    /// closing delimiter (`{{- name -}}`) as a request to strip all whitespace,
+    /// newlines included, before or after the tag.
+    ///
+    /// Names starting or ending with `-` can't be used along with it, as
+    /// the `-` of `{{-name}}` is taken for a marker. `{{--name}}` and
+    /// `{{name--}}` fail with
+    /// [`Error::AmbiguousTrimMarker`](enum.Error.html#variant.AmbiguousTrimMarker).
+    pub trim_markers: bool,
+    /// Let a partial tag be closed like a section (`{{>name}}...{{/name}}`),
+    /// rendering what it encloses instead when the partial is missing or
+    /// empty. A closing tag then belongs to the innermost unclosed partial or
+    /// section, so `{{#name}}{{>name}}{{/name}}` no longer parses.
+    pub partial_fallbacks: bool,
+    /// Let a template define its own partials with `{{<*name}}...{{/name}}`.
+    /// The definition renders nothing, and `{{>name}}` in the template or
+    /// the partials it includes uses it instead of asking the loader.
+    ///
+    /// One made in a partial is only seen by that partial, in which it is
+    /// known as `partial#name`: that is the name given to a
+    /// [`RenderHook`](trait.RenderHook.html) and in errors.
+    pub inline_partials: bool,
+    /// Let an escaped tag be an RFC 6901 JSON Pointer (`{{/users/0/name}}`),
+    /// resolved from the root of the data rather than the context stack,
+    /// for keys that contain dots. A tag that closes the innermost open
+    /// section is still read as its closing tag.
+    pub json_pointers: bool,
+    /// Let a block be written out as it is, tags and all, with the opening
+    /// delimiter doubled around a name (`{{{{raw}}}}...{{{{/raw}}}}`), for
+    /// templates that generate templates. The block ends at the first
+    /// closing tag with the same name.
+    pub raw_blocks: bool,
+    /// Let a variable tag list several names separated by `||`
+    /// (`{{display_name || username || email}}`), writing out the first
+    /// that has something to write: not missing, null, empty, a boolean, a
+    /// list or a map. Works for escaped and unescaped tags alike.
+    pub fallback_chains: bool,
+    /// Let a section or inverted section tag compare a name with another or
+    /// with a string or number (`{{#count > 0}}`, `{{^status == "active"}}`)
+    /// using `==`, `!=`, `<`, `<=`, `>` or `>=`, and render its content once
+    /// when the comparison holds, or doesn't for an inverted section. The
+    /// closing tag repeats the comparison as written.
+    ///
+    /// Values that both read as numbers compare as numbers, other values as
+    /// strings, a list as its number of items and a boolean as `true` or
+    /// `false`. A missing value, or a map, only equals another one.
+    pub comparisons: bool,
+    /// Let a comment be written `{{!-- ... --}}`, which only ends at a
+    /// `--` right before the closing delimiter, so it can comment out a
+    /// part of a template, tags and all.
+    pub long_comments: bool,
+    /// Let a section go through a range of whole numbers, both ends
+    /// included, each either written in the template or the name of a
+    /// number in the data (`{{#1..5}}`, `{{#1..count}}`), rendering its
+    /// content once for every number with the number as `{{.}}`. An
+    /// inverted section renders when the range is empty, such as when an
+    /// end is missing or not a number or the start is past the end.
+    pub ranges: bool,
+}
+
+/// How deep sections may be nested. Templates are usually nowhere near it,
+/// it only stops hostile ones from building trees too deep to walk.
+const MAX_NESTING: usize = 512;
+
+// The tags and stripped whitespace of a template, see `Parser::parse_lexemes`.
+type Lexemes = Vec<(Span, Lexeme)>;
+// Partial names with the line they are included on.
+type Included = Vec<(String, usize)>;
+
 enum TokenClass {
     Normal,
     StandAlone,
@@ -65,8 +254,14 @@ pub struct Parser<'a, T: 'a> {
     reader: &'a mut T,
     ch: Option<char>,
     lookahead: Option<char>,
+    // The line and column of `ch`, counted in characters from 1.
     line: usize,
     col: usize,
+    // Byte offset of `ch` in the source, and of the tag being parsed.
+    pos: usize,
+    tag_start: usize,
+    // The line the tag being parsed starts on.
+    tag_line: usize,
     content: String,
     state: ParserState,
     opening_tag: String,
@@ -75,7 +270,15 @@ pub struct Parser<'a, T: 'a> {
     closing_tag_chars: Vec<char>,
     tag_position: usize,
     tokens: Vec<Token>,
-    partials: Vec<String>,
+    // Number of sections and definitions currently open.
+    depth: usize,
+    partials: Included,
+    lexemes: Option<Lexemes>,
+    extensions: Extensions,
+    // The message of the first `{{! @deprecated ...}}` comment.
+    deprecated: Option<String>,
+    // Where errors go when parsing carries on past them.
+    errors: Option<Vec<(Span, Error)>>,
 }
 
 enum ParserState {
@@ -86,13 +289,21 @@ enum ParserState {
 }
 
 impl<'a, T: Iterator<Item = char>> Parser<'a, T> {
-    pub fn new(reader: &'a mut T, opening_tag: &str, closing_tag: &str) -> Parser<'a, T> {
+    pub fn new(
+        reader: &'a mut T,
+        opening_tag: &str,
+        closing_tag: &str,
+        extensions: Extensions
+    ) -> Parser<'a, T> {
         let mut parser = Parser {
-            reader: reader,
+            reader,
             ch: None,
             lookahead: None,
             line: 1,
             col: 1,
+            pos: 0,
+            tag_start: 0,
+            tag_line: 1,
             content: String::new(),
             state: ParserState::Text,
             opening_tag: opening_tag.to_string(),
@@ -101,7 +312,12 @@ impl<'a, T: Iterator<Item = char>> Parser<'a, T> {
             closing_tag_chars: closing_tag.chars().collect(),
             tag_position: 0,
             tokens: Vec::new(),
+            depth: 0,
             partials: Vec::new(),
+            lexemes: None,
+            extensions,
+            deprecated: None,
+            errors: None,
         };
 
         parser.bump();
@@ -109,16 +325,8 @@ impl<'a, T: Iterator<Item = char>> Parser<'a, T> {
     }
 
     fn bump(&mut self) {
-        match self.lookahead.take() {
-            None => {
-                self.ch = self.reader.next();
-            }
-            Some(ch) => {
-                self.ch = Some(ch);
-            }
-        }
-
         if let Some(ch) = self.ch {
+            self.pos += ch.len_utf8();
             if ch == '\n' {
                 self.line += 1;
                 self.col = 1;
@@ -126,6 +334,15 @@ impl<'a, T: Iterator<Item = char>> Parser<'a, T> {
                 self.col += 1;
             }
         }
+
+        match self.lookahead.take() {
+            None => {
+                self.ch = self.reader.next();
+            }
+            Some(ch) => {
+                self.ch = Some(ch);
+            }
+        }
     }
 
     fn peek(&mut self) -> Option<char> {
@@ -145,14 +362,74 @@ impl<'a, T: Iterator<Item = char>> Parser<'a, T> {
         }
     }
 
-    /// Parse the template into tokens and a list of partial files.
-    pub fn parse(mut self) -> Result<(Vec<Token>, Vec<String>), Error> {
+    /// Parse the template into tokens and a list of partial files, with the
+    /// line each one is included on. A deprecated template starts with a
+    /// `Token::Deprecated`. An error comes with the line and column, counted
+    /// from 1, it was found at.
+    pub fn parse(mut self) -> Result<(Vec<Token>, Included), (Error, usize, usize)> {
+        if let Err(err) = self.run() {
+            return Err((err, self.line, self.col));
+        }
+
+        let Parser { mut tokens, partials, deprecated, .. } = self;
+        if let Some(message) = deprecated {
+            tokens.insert(0, Token::Deprecated(message));
+        }
+
+        Ok((tokens, partials))
+    }
+
+    /// Parse the template, only keeping the byte span of every tag and of
+    /// the whitespace the tags strip, in the order they were seen.
+    pub fn parse_lexemes(mut self) -> Result<Lexemes, Error> {
+        self.lexemes = Some(Vec::new());
+        self.run()?;
+
+        Ok(self.lexemes.unwrap_or_default())
+    }
+
+    /// Parse the template like `parse_lexemes`, carrying on past errors. A
+    /// tag in error is left out, to be taken as text, and sections that are
+    /// never closed are left open.
+    pub fn parse_lexemes_lenient(mut self) -> (Lexemes, Vec<(Span, Error)>) {
+        self.lexemes = Some(Vec::new());
+        self.errors = Some(Vec::new());
+        if let Err(err) = self.run() {
+            bug!("lenient parsing failed: {}", err);
+        }
+
+        (self.lexemes.unwrap_or_default(), self.errors.unwrap_or_default())
+    }
+
+    // Notes `err` for the tag at `start..end` if parsing carries on past
+    // errors, or fails with it.
+    fn recover(&mut self, start: usize, end: usize, err: Error) -> Result<(), Error> {
+        match self.errors {
+            Some(ref mut errors) => {
+                errors.push((Span { start, end }, err));
+                Ok(())
+            }
+            None => Err(err),
+        }
+    }
+
+    fn record(&mut self, start: usize, end: usize, lexeme: Lexeme) {
+        if let Some(ref mut lexemes) = self.lexemes {
+            if start < end || lexeme != Lexeme::Whitespace {
+                lexemes.push((Span { start, end }, lexeme));
+            }
+        }
+    }
+
+    fn run(&mut self) -> Result<(), Error> {
         let mut curly_brace_tag = false;
 
         while let Some(ch) = self.ch {
             match self.state {
                 ParserState::Text => {
                     if ch == self.opening_tag_chars[0] {
+                        self.tag_start = self.pos;
+                        self.tag_line = self.line;
                         if self.opening_tag_chars.len() > 1 {
                             self.tag_position = 1;
                             self.state = ParserState::OpeningTag;
@@ -172,7 +449,7 @@ impl<'a, T: Iterator<Item = char>> Parser<'a, T> {
                             curly_brace_tag = false;
                             self.state = ParserState::Tag;
                         } else {
-                            self.tag_position = self.tag_position + 1;
+                            self.tag_position += 1;
                         }
                     } else {
                         // We don't have a tag, so add all the tag parts we've seen
@@ -184,7 +461,7 @@ impl<'a, T: Iterator<Item = char>> Parser<'a, T> {
                     self.bump();
                 }
                 ParserState::Tag => {
-                    if self.content.is_empty() && ch == '{' {
+                    if self.at_tag_start() && ch == '{' {
                         curly_brace_tag = true;
                         self.content.push(ch);
                         self.bump();
@@ -192,7 +469,7 @@ impl<'a, T: Iterator<Item = char>> Parser<'a, T> {
                         curly_brace_tag = false;
                         self.content.push(ch);
                         self.bump();
-                    } else if ch == self.closing_tag_chars[0] {
+                    } else if ch == self.closing_tag_chars[0] && self.long_comment(&self.content) != Some(false) {
                         if self.closing_tag_chars.len() > 1 {
                             self.tag_position = 1;
                             self.state = ParserState::ClosingTag;
@@ -217,9 +494,20 @@ impl<'a, T: Iterator<Item = char>> Parser<'a, T> {
                             self.content.push(ch);
                             self.bump();
                         }
+                    } else if self.long_comment(&self.content).is_some() {
+                        // Only the delimiter ends a long comment, the rest
+                        // is comment.
+                        self.not_ctag();
+                        self.state = ParserState::Tag;
                     } else {
                         let expected = self.closing_tag_chars[self.tag_position];
-                        return Err(Error::BadClosingTag(ch, expected));
+                        self.recover(self.tag_start, self.pos, Error::BadClosingTag(ch, expected))?;
+
+                        // What looked like a tag is text, up to `ch`.
+                        self.not_ctag();
+                        let opening_tag = self.opening_tag.clone();
+                        self.content.insert_str(0, &opening_tag);
+                        self.state = ParserState::Text;
                     }
                 }
             }
@@ -237,27 +525,66 @@ impl<'a, T: Iterator<Item = char>> Parser<'a, T> {
                 self.not_ctag();
                 self.add_text();
             }
-            ParserState::Tag => return Err(Error::UnclosedTag),
+            ParserState::Tag => {
+                self.recover(self.tag_start, self.pos, Error::UnclosedTag)?;
+                let opening_tag = self.opening_tag.clone();
+                self.content.insert_str(0, &opening_tag);
+                self.add_text();
+            }
         }
 
-        // Check that we don't have any incomplete sections.
+        // Check that we don't have any incomplete sections, unless they are
+        // to be left open.
+        if self.errors.is_some() {
+            return Ok(());
+        }
         for token in self.tokens.iter().rev() {
-            if let Token::IncompleteSection(ref path, _, _, _) = *token {
-                return Err(Error::UnclosedSection(path.join(".")))
+            match *token {
+                Token::IncompleteSection(ref path, _, _, _) => {
+                    return Err(Error::UnclosedSection(path.join(".")))
+                }
+                Token::IncompleteDefinition(ref name, _, _) => {
+                    return Err(Error::UnclosedSection(name.clone()))
+                }
+                _ => {}
             }
         }
 
-        let Parser { tokens, partials, .. } = self;
+        Ok(())
+    }
 
-        Ok((tokens, partials))
+    fn open(&mut self) -> Result<(), Error> {
+        if self.depth == MAX_NESTING {
+            return Err(Error::NestingTooDeep);
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    // Whether the tag being parsed is a long comment, `{{!-- ... --}}`, and
+    // if so whether its content reached the closing `--`.
+    fn long_comment(&self, content: &str) -> Option<bool> {
+        if !self.extensions.long_comments {
+            return None;
+        }
+
+        let mut content = content;
+        if self.extensions.trim_markers && content.starts_with('-') {
+            content = content[1..].trim_start();
+        }
+        content.strip_prefix("!--").map(|rest| rest.ends_with("--"))
+    }
+
+    // Whether the tag content seen so far is empty, ignoring a leading trim
+    // marker.
+    fn at_tag_start(&self) -> bool {
+        self.content.is_empty() || (self.extensions.trim_markers && self.content.trim_end() == "-")
     }
 
     fn add_text(&mut self) {
         if !self.content.is_empty() {
-            let mut content = String::new();
-            mem::swap(&mut content, &mut self.content);
-
-            self.tokens.push(Token::Text(content));
+            // The buffer becomes the token's string rather than being copied.
+            self.tokens.push(Token::Text(mem::take(&mut self.content)));
         }
     }
 
@@ -279,9 +606,10 @@ impl<'a, T: Iterator<Item = char>> Parser<'a, T> {
             // token), then this token is standalone.
             None => TokenClass::StandAlone,
 
-            Some(&Token::IncompleteSection(_, _, _, true)) => TokenClass::StandAlone,
+            Some(&Token::IncompleteSection(_, _, _, true)) |
+            Some(&Token::IncompleteDefinition(_, _, true)) => TokenClass::StandAlone,
 
-            Some(&Token::Text(ref s)) if !s.is_empty() => {
+            Some(Token::Text(s)) if !s.is_empty() => {
                 // Look for the last newline character that may have whitespace
                 // following it.
                 match s.rfind(|c: char| c == '\n' || !c.is_whitespace()) {
@@ -317,19 +645,15 @@ impl<'a, T: Iterator<Item = char>> Parser<'a, T> {
         match self.classify_token() {
             TokenClass::Normal => false,
             TokenClass::StandAlone => {
-                if self.ch_is('\r') {
-                    self.bump();
-                }
-                self.bump();
+                self.eat_newline();
                 true
             }
             TokenClass::WhiteSpace(s, pos) => {
-                if self.ch_is('\r') {
-                    self.bump();
-                }
-                self.bump();
+                self.eat_newline();
 
                 // Trim the whitespace from the last token.
+                let start = self.tag_start - (s.len() - pos);
+                self.record(start, self.tag_start, Lexeme::Whitespace);
                 self.tokens.pop();
                 self.tokens.push(Token::Text(s[0..pos].to_string()));
 
@@ -338,53 +662,149 @@ impl<'a, T: Iterator<Item = char>> Parser<'a, T> {
         }
     }
 
+    fn eat_newline(&mut self) {
+        let start = self.pos;
+        if self.ch_is('\r') {
+            self.bump();
+        }
+        self.bump();
+        self.record(start, self.pos, Lexeme::Whitespace);
+    }
+
     fn add_tag(&mut self) -> Result<(), Error> {
         self.bump();
+        let (start, end) = (self.tag_start, self.pos);
+
+        let mut tag = String::with_capacity(self.opening_tag.len() + self.content.len() + self.closing_tag.len());
+        tag.push_str(&self.opening_tag);
+        tag.push_str(&self.content);
+        tag.push_str(&self.closing_tag);
+
+        // Take the content out while the tag is handled, then hand the buffer
+        // back so it isn't reallocated for every tag.
+        let mut content = mem::take(&mut self.content);
+        let result = self.add_tag_content(&content, tag, start, end);
+        content.clear();
+        self.content = content;
+
+        match result {
+            Err(err) => self.recover(start, end, err),
+            ok => ok,
+        }
+    }
+
+    // The token for a variable tag naming `name`, a fallback chain if that
+    // is enabled and it lists several names.
+    fn variable(&self, name: &str, escaped: bool, tag: String) -> Result<Token, Error> {
+        if self.extensions.fallback_chains && name.contains("||") {
+            let paths = name.split("||").map(get_name_or_implicit).collect::<Result<_, _>>()?;
+            return Ok(Token::FallbackTag(paths, escaped, tag));
+        }
+
+        // If the name is "." then we want the top element, which we represent with
+        // an empty name.
+        let name = get_name_or_implicit(name)?;
+        Ok(if escaped { Token::EscapedTag(name, tag) } else { Token::UnescapedTag(name, tag) })
+    }
+
+    fn add_tag_content(&mut self, content: &str, tag: String, start: usize, end: usize) -> Result<(), Error> {
+        // Whitespace between a trim marker and the rest of the tag is
+        // insignificant, so `{{- #section -}}` works as expected.
+        let mut content = content;
+        let mut trim_after = false;
+        if self.extensions.trim_markers {
+            if content.starts_with('-') {
+                content = content[1..].trim_start();
+                self.trim_last_text();
+            }
+            // The `--` closing a long comment isn't a trim marker.
+            if content.ends_with('-') && self.long_comment(content).is_none() {
+                content = content[..content.len() - 1].trim_end();
+                trim_after = true;
+            }
 
-        let tag = self.opening_tag.clone() + &self.content + &self.closing_tag;
+            // Comments can hold anything, a name can't start or end with
+            // another marker.
+            let dashed = content.starts_with('-') || content.ends_with('-');
+            if dashed && !content.starts_with('!') {
+                return Err(Error::AmbiguousTrimMarker(content.to_string()));
+            }
+        }
 
-        // Move the content to avoid a copy.
-        let mut content = String::new();
-        mem::swap(&mut content, &mut self.content);
         let len = content.len();
-        deny_blank(&content)?;
-        let content = content;
+        deny_blank(content)?;
+
+        if self.extensions.raw_blocks {
+            if let Some((name, rest)) = self.raw_block(content) {
+                return self.add_raw(name, rest, tag, start, end);
+            }
+        }
 
         match content.as_bytes()[0] as char {
             '!' => {
-                // ignore comments
+                // ignore comments, except for noting a deprecation
+                let mut comment = content[1..].trim();
+                if self.extensions.long_comments {
+                    if let Some(long) = content[1..].strip_prefix("--").and_then(|c| c.strip_suffix("--")) {
+                        comment = long.trim();
+                    }
+                }
+                if self.deprecated.is_none() {
+                    self.deprecated = deprecation(comment);
+                }
+                self.record(start, end, Lexeme::Comment(comment.to_string()));
                 self.eat_whitespace();
             }
             '&' => {
-                let name = &content[1..len];
-                let name = get_name_or_implicit(name)?;
-                self.tokens.push(Token::UnescapedTag(name, tag));
+                let token = self.variable(&content[1..len], false, tag)?;
+                self.record(start, end, Lexeme::Variable(content[1..len].trim().to_string(), false));
+                self.tokens.push(token);
             }
             '{' => {
                 if content.ends_with('}') {
-                    let name = &content[1..len - 1];
-                    let name = get_name_or_implicit(name)?;
-                    self.tokens.push(Token::UnescapedTag(name, tag));
+                    let token = self.variable(&content[1..len - 1], false, tag)?;
+                    self.record(start, end, Lexeme::Variable(content[1..len - 1].trim().to_string(), false));
+                    self.tokens.push(token);
                 } else {
                     return Err(Error::UnbalancedUnescapeTag)
                 }
             }
             '#' => {
-                let newlined = self.eat_whitespace();
-
                 let name = get_name_or_implicit(&content[1..len])?;
+                self.check_comparison(&content[1..len])?;
+                self.open()?;
+
+                self.record(start, end, Lexeme::Open(content[1..len].trim().to_string(), false));
+                let newlined = self.eat_whitespace();
                 self.tokens.push(Token::IncompleteSection(name, false, tag, newlined));
             }
             '^' => {
-                let newlined = self.eat_whitespace();
-
                 let name = get_name_or_implicit(&content[1..len])?;
+                self.check_comparison(&content[1..len])?;
+                self.open()?;
+
+                self.record(start, end, Lexeme::Open(content[1..len].trim().to_string(), true));
+                let newlined = self.eat_whitespace();
                 self.tokens.push(Token::IncompleteSection(name, true, tag, newlined));
             }
+            '<' if self.extensions.inline_partials && content[1..].starts_with('*') => {
+                let name = deny_blank(&content[2..len])?.to_string();
+                self.open()?;
+
+                self.record(start, end, Lexeme::Define(name.clone()));
+                let newlined = self.eat_whitespace();
+                self.tokens.push(Token::IncompleteDefinition(name, tag, newlined));
+            }
+            '/' if self.extensions.json_pointers && !self.closes(&content[1..len]) => {
+                let pointer = content.trim();
+                self.record(start, end, Lexeme::Variable(pointer.to_string(), true));
+                self.tokens.push(Token::PointerTag(split_pointer(pointer), tag));
+            }
             '/' => {
+                let name = get_name_or_implicit(&content[1..len])?;
+                self.check_close(&name, content[1..len].trim())?;
                 self.eat_whitespace();
 
-                let name = get_name_or_implicit(&content[1..len])?;
                 let mut children: Vec<Token> = Vec::new();
 
                 loop {
@@ -396,57 +816,79 @@ impl<'a, T: Iterator<Item = char>> Parser<'a, T> {
 
                     match last {
                         Some(Token::IncompleteSection(section_name, inverted, osection, _)) => {
+                            if section_name != name {
+                                return Err(Error::UnclosedSection(section_name.join(".")))
+                            }
+
                             children.reverse();
 
-                            // Collect all the children's sources.
-                            let mut srcs = Vec::new();
-                            for child in children.iter() {
-                                match *child {
-                                    Token::Text(ref s) |
-                                    Token::EscapedTag(_, ref s) |
-                                    Token::UnescapedTag(_, ref s) |
-                                    Token::Partial(_, _, ref s) => srcs.push(s.clone()),
-                                    Token::Section(_, _, _, _, ref osection, ref src, ref csection, _) => {
-                                        srcs.push(osection.clone());
-                                        srcs.push(src.clone());
-                                        srcs.push(csection.clone());
-                                    }
-                                    _ => bug!("Incomplete sections should not be nested"),
-                                }
+                            // Cache the combination of all the sources in the
+                            // section. It's unfortunate, but we need to do this in
+                            // case the user uses a function to instantiate the
+                            // tag.
+                            let src = sources(&children);
+                            let (otag, ctag) = (self.opening_tag.clone(), self.closing_tag.clone());
+
+                            if let Some(bounds) = self.range(&content[1..len]) {
+                                self.tokens.push(Token::Range(bounds?, inverted, children, otag, osection, src, tag, ctag));
+                                self.record(start, end, Lexeme::Close);
+                                self.depth -= 1;
+                                break;
                             }
 
-                            if section_name == name {
-                                // Cache the combination of all the sources in the
-                                // section. It's unfortunate, but we need to do this in
-                                // case the user uses a function to instantiate the
-                                // tag.
-                                let mut src = String::new();
-                                for s in srcs.iter() {
-                                    src.push_str(s);
+                            match self.comparison(&content[1..len]) {
+                                Some(comparison) => {
+                                    let comparison = comparison?;
+                                    self.tokens.push(Token::Condition(comparison, inverted, children, otag, osection, src, tag, ctag));
                                 }
-
-                                self.tokens.push(Token::Section(name,
-                                                         inverted,
-                                                         children,
-                                                         self.opening_tag.clone(),
-                                                         osection,
-                                                         src,
-                                                         tag,
-                                                         self.closing_tag.clone()));
-                                break;
-                            } else {
-                                return Err(Error::UnclosedSection(section_name.join(".")))
+                                None => {
+                                    self.tokens.push(Token::Section(name, inverted, children, otag, osection, src, tag, ctag));
+                                }
+                            }
+                            self.record(start, end, Lexeme::Close);
+                            self.depth -= 1;
+                            break;
+                        }
+                        Some(Token::IncompleteDefinition(definition, odefinition, _)) => {
+                            if definition != content[1..len].trim() {
+                                return Err(Error::UnclosedSection(definition))
                             }
+
+                            children.reverse();
+                            let src = sources(&children);
+
+                            self.record(start, end, Lexeme::Close);
+                            self.tokens.push(Token::Definition(definition, children, odefinition, src, tag));
+                            self.depth -= 1;
+                            break;
+                        }
+                        // With fallbacks enabled, a closing tag for a partial
+                        // turns everything since it into the partial's default.
+                        Some(Token::Partial(partial, indent, opartial))
+                            if self.extensions.partial_fallbacks && partial == content[1..len].trim() => {
+                            children.reverse();
+                            let src = sources(&children);
+
+                            self.record(start, end, Lexeme::ClosePartial(partial.clone()));
+                            self.tokens.push(Token::PartialWithFallback(partial,
+                                                                        indent,
+                                                                        children,
+                                                                        opartial,
+                                                                        src,
+                                                                        tag));
+                            break;
                         }
                         Some(last_token) => children.push(last_token),
                         None => (),
                     }
                 }
             }
-            '>' => self.add_partial(&content, tag)?,
+            '>' => {
+                let name = deny_blank(&content[1..])?;
+                self.record(start, end, Lexeme::Partial(name.to_string()));
+                self.add_partial(content, tag)?
+            }
             '=' => {
-                self.eat_whitespace();
-
                 if len > 2usize && content.ends_with('=') {
                     let s = deny_blank(&content[1..len - 1])?;
 
@@ -456,50 +898,248 @@ impl<'a, T: Iterator<Item = char>> Parser<'a, T> {
                         Some(pos) => pos,
                     };
 
-                    self.opening_tag = s[0..pos].to_string();
-                    self.opening_tag_chars = self.opening_tag.chars().collect();
-
                     let s2 = &s[pos..];
-                    let pos = s2.find(|c: char| !c.is_whitespace());
-                    let pos = match pos {
+                    let pos2 = s2.find(|c: char| !c.is_whitespace());
+                    let pos2 = match pos2 {
                         None => return Err(Error::MissingSetDelimeterClosingTag),
-                        Some(pos) => pos,
+                        Some(pos2) => pos2,
                     };
 
-                    self.closing_tag = s2[pos..].to_string();
+                    self.eat_whitespace();
+                    self.opening_tag = s[0..pos].to_string();
+                    self.opening_tag_chars = self.opening_tag.chars().collect();
+
+                    self.closing_tag = s2[pos2..].to_string();
                     self.closing_tag_chars = self.closing_tag.chars().collect();
+
+                    let delimiters = Lexeme::Delimiters(self.opening_tag.clone(), self.closing_tag.clone());
+                    self.record(start, end, delimiters);
                 } else {
                     return Err(Error::InvalidSetDelimeterSyntax)
                 }
             }
             _ => {
-                // If the name is "." then we want the top element, which we represent with
-                // an empty name.
-                let name = get_name_or_implicit(&content)?;
-                self.tokens.push(Token::EscapedTag(name, tag));
+                let token = self.variable(content, true, tag)?;
+                self.record(start, end, Lexeme::Variable(content.trim().to_string(), true));
+                self.tokens.push(token);
             }
         };
 
+        if trim_after {
+            let start = self.pos;
+            while let Some(ch) = self.ch {
+                if !ch.is_whitespace() {
+                    break;
+                }
+                self.bump();
+            }
+            self.record(start, self.pos, Lexeme::Whitespace);
+        }
+
         Ok(())
     }
 
+    // The name of the raw block `content` opens, and the part of the closing
+    // delimiter the tag already took. The opening delimiter is doubled, so
+    // with `{{` a `{{{{raw}}}}` tag takes `{{raw}` as its content.
+    fn raw_block<'c>(&self, content: &'c str) -> Option<(&'c str, &'c str)> {
+        let inner = content.strip_prefix(&self.opening_tag[..])?;
+        let end = inner.find(self.closing_tag_chars[0]).unwrap_or(inner.len());
+        let (name, rest) = inner.split_at(end);
+
+        let name = name.trim();
+        if name.is_empty() || name.starts_with('/') || !self.closing_tag.starts_with(rest) {
+            return None;
+        }
+        Some((name, rest))
+    }
+
+    fn add_raw(&mut self, name: &str, rest: &str, mut tag: String, start: usize, end: usize) -> Result<(), Error> {
+        // The rest of the doubled closing delimiter.
+        let mut end = end;
+        let remaining: Vec<char> = self.closing_tag[rest.len()..].chars().collect();
+        for expected in remaining {
+            match self.ch {
+                Some(ch) if ch == expected => {
+                    tag.push(ch);
+                    self.bump();
+                    end = self.pos;
+                }
+                Some(ch) => return Err(Error::BadClosingTag(ch, expected)),
+                None => return Err(Error::UnclosedTag),
+            }
+        }
+        self.eat_whitespace();
+
+        let close = format!("{0}{0}/{1}{2}{2}", self.opening_tag, name, self.closing_tag);
+        let mut text = String::new();
+        loop {
+            match self.ch {
+                Some(ch) => {
+                    text.push(ch);
+                    self.bump();
+                    if text.ends_with(&close[..]) {
+                        text.truncate(text.len() - close.len());
+                        break;
+                    }
+                }
+                None => return Err(Error::UnclosedSection(name.to_string())),
+            }
+        }
+
+        // The closing tag is standalone the same way a section's is.
+        self.tag_start = self.pos - close.len();
+        let close_start = self.tag_start;
+        let close_end = self.pos;
+        self.tokens.push(Token::Text(text));
+        self.eat_whitespace();
+        // Recorded once closed, so an unclosed block is left out.
+        self.record(start, end, Lexeme::OpenRaw(name.to_string()));
+        self.record(close_start, close_end, Lexeme::Close);
+
+        let text = match self.tokens.pop() {
+            Some(Token::Text(text)) => text,
+            _ => String::new(),
+        };
+        let src = format!("{}{}{}", tag, text, close);
+        self.tokens.push(Token::Raw(text, src));
+        Ok(())
+    }
+
+    // The comparison a section tag makes, if comparisons are enabled and it
+    // has an operator outside quotes.
+    fn comparison(&self, expr: &str) -> Option<Result<Comparison, Error>> {
+        if !self.extensions.comparisons {
+            return None;
+        }
+
+        let expr = expr.trim();
+        let mut quoted = false;
+        let (at, op) = expr.char_indices().find_map(|(i, c)| {
+            if c == '"' {
+                quoted = !quoted;
+            }
+            if quoted {
+                return None;
+            }
+
+            let rest = &expr[i..];
+            [("==", CompareOp::Eq), ("!=", CompareOp::Ne), ("<=", CompareOp::Le), (">=", CompareOp::Ge),
+             ("<", CompareOp::Lt), (">", CompareOp::Gt), ("=", CompareOp::Eq), ("!", CompareOp::Ne)]
+                .iter()
+                .find(|&&(op, _)| rest.starts_with(op))
+                .map(|&(op, kind)| (i, (op, kind)))
+        })?;
+
+        let invalid = || Error::InvalidComparison(expr.to_string());
+        // A lone `=` or `!` is a typo rather than an operator.
+        if op.0 == "=" || op.0 == "!" {
+            return Some(Err(invalid()));
+        }
+        let left = operand(&expr[..at]).ok_or_else(invalid);
+        let right = operand(&expr[at + op.0.len()..]).ok_or_else(invalid);
+        Some(left.and_then(|left| Ok(Comparison { left, op: op.1, right: right? })))
+    }
+
+    // The numbers a section tag goes through, if ranges are enabled and it
+    // has a `..`.
+    fn range(&self, expr: &str) -> Option<Result<Bounds, Error>> {
+        if !self.extensions.ranges {
+            return None;
+        }
+
+        let expr = expr.trim();
+        let at = expr.find("..")?;
+        let bound = |bound: &str| match operand(bound) {
+            Some(Operand::Number(ref number)) if number.parse::<i64>().is_err() => None,
+            Some(Operand::Text(_)) => None,
+            Some(Operand::Name(ref path)) if path.is_empty() || path.iter().any(String::is_empty) => None,
+            operand => operand,
+        };
+        match (bound(&expr[..at]), bound(&expr[at + 2..])) {
+            (Some(start), Some(end)) => Some(Ok(Bounds { start, end })),
+            _ => Some(Err(Error::InvalidRange(expr.to_string()))),
+        }
+    }
+
+    // Fails for a section tag that is neither a valid range nor a valid
+    // comparison, when it looks like one.
+    fn check_comparison(&self, expr: &str) -> Result<(), Error> {
+        if let Some(Err(err)) = self.range(expr) {
+            return Err(err);
+        }
+        match self.comparison(expr) {
+            Some(Err(err)) => Err(err),
+            _ => Ok(()),
+        }
+    }
+
+    // Fails unless a closing tag for `name`, written `raw`, closes the
+    // innermost open section, definition or, with fallbacks, partial.
+    fn check_close(&self, name: &[String], raw: &str) -> Result<(), Error> {
+        for token in self.tokens.iter().rev() {
+            match *token {
+                Token::IncompleteSection(ref section, _, _, _) if section == name => return Ok(()),
+                Token::IncompleteSection(ref section, _, _, _) => return Err(Error::UnclosedSection(section.join("."))),
+                Token::IncompleteDefinition(ref definition, _, _) if definition == raw => return Ok(()),
+                Token::IncompleteDefinition(ref definition, _, _) => return Err(Error::UnclosedSection(definition.clone())),
+                Token::Partial(ref partial, _, _) if self.extensions.partial_fallbacks && partial == raw => return Ok(()),
+                _ => {}
+            }
+        }
+
+        Err(Error::EarlySectionClose(name.join(".")))
+    }
+
+    // Whether a closing tag for `name` would close the innermost open section,
+    // definition or, with fallbacks, partial.
+    fn closes(&self, name: &str) -> bool {
+        let name = name.trim();
+
+        for token in self.tokens.iter().rev() {
+            match *token {
+                Token::IncompleteSection(ref section, _, _, _) => return *section == split_name(name),
+                Token::IncompleteDefinition(ref definition, _, _) => return definition == name,
+                Token::Partial(ref partial, _, _) if self.extensions.partial_fallbacks && partial == name => {
+                    return true;
+                }
+                _ => {}
+            }
+        }
+
+        false
+    }
+
+    // Strip the trailing whitespace of the preceding text, dropping the token
+    // entirely if nothing is left.
+    fn trim_last_text(&mut self) {
+        let mut trimmed = 0;
+        if let Some(&mut Token::Text(ref mut s)) = self.tokens.last_mut() {
+            let len = s.trim_end().len();
+            trimmed = s.len() - len;
+            s.truncate(len);
+        }
+        self.record(self.tag_start - trimmed, self.tag_start, Lexeme::Whitespace);
+
+        if let Some(Token::Text(s)) = self.tokens.last() {
+            if s.is_empty() {
+                self.tokens.pop();
+            }
+        }
+    }
+
     fn add_partial(&mut self, content: &str, tag: String) -> Result<(), Error> {
         let indent = match self.classify_token() {
             TokenClass::Normal => "".to_string(),
             TokenClass::StandAlone => {
-                if self.ch_is('\r') {
-                    self.bump();
-                }
-                self.bump();
+                self.eat_newline();
                 "".to_string()
             }
             TokenClass::WhiteSpace(s, pos) => {
-                if self.ch_is('\r') {
-                    self.bump();
-                }
-                self.bump();
+                self.eat_newline();
 
                 let ws = &s[pos..];
+                self.record(self.tag_start - ws.len(), self.tag_start, Lexeme::Whitespace);
 
                 // Trim the whitespace from the last token.
                 self.tokens.pop();
@@ -516,14 +1156,14 @@ impl<'a, T: Iterator<Item = char>> Parser<'a, T> {
         let name = deny_blank(name)?;
 
         self.tokens.push(Token::Partial(name.into(), indent, tag));
-        self.partials.push(name.into());
+        self.partials.push((name.into(), self.tag_line));
 
         Ok(())
     }
 
     fn not_otag(&mut self) {
         for (i, ch) in self.opening_tag_chars.iter().enumerate() {
-            if !(i < self.tag_position) {
+            if i >= self.tag_position {
                 break;
             }
             self.content.push(*ch);
@@ -532,7 +1172,7 @@ impl<'a, T: Iterator<Item = char>> Parser<'a, T> {
 
     fn not_ctag(&mut self) {
         for (i, ch) in self.closing_tag_chars.iter().enumerate() {
-            if !(i < self.tag_position) {
+            if i >= self.tag_position {
                 break;
             }
             self.content.push(*ch);
@@ -540,17 +1180,86 @@ impl<'a, T: Iterator<Item = char>> Parser<'a, T> {
     }
 }
 
+// The combined source of `tokens`, as written in the template.
+fn sources(tokens: &[Token]) -> String {
+    let mut src = String::new();
+    for token in tokens {
+        match *token {
+            Token::Text(ref s) |
+            Token::EscapedTag(_, ref s) |
+            Token::UnescapedTag(_, ref s) |
+            Token::PointerTag(_, ref s) |
+            Token::FallbackTag(_, _, ref s) |
+            Token::Raw(_, ref s) |
+            Token::Partial(_, _, ref s) => src.push_str(s),
+            Token::Section(_, _, _, _, ref osection, ref inner, ref csection, _) |
+            Token::Condition(_, _, _, _, ref osection, ref inner, ref csection, _) |
+            Token::Range(_, _, _, _, ref osection, ref inner, ref csection, _) |
+            Token::PartialWithFallback(_, _, _, ref osection, ref inner, ref csection) |
+            Token::Definition(_, _, ref osection, ref inner, ref csection) => {
+                src.push_str(osection);
+                src.push_str(inner);
+                src.push_str(csection);
+            }
+            Token::Deprecated(_) => {}
+            Token::IncompleteSection(..) |
+            Token::IncompleteDefinition(..) => bug!("Incomplete sections should not be nested"),
+        }
+    }
+    src
+}
+
+// The message of a `@deprecated` comment, empty if it gives none.
+fn deprecation(comment: &str) -> Option<String> {
+    let message = comment.strip_prefix("@deprecated")?;
+    if message.is_empty() || message.starts_with(char::is_whitespace) {
+        Some(message.trim().to_string())
+    } else {
+        None
+    }
+}
+
 fn get_name_or_implicit(name: &str) -> Result<Vec<String>, Error> {
     // If the name is "." then we want the top element, which we represent with
     // an empty name.
-    let name = deny_blank(&name)?;
-    Ok(if name == "." {
+    let name = deny_blank(name)?;
+    Ok(split_name(name))
+}
+
+/// Splits a dotted name into the path the renderer looks up.
+pub fn split_name(name: &str) -> Vec<String> {
+    if name == "." {
         Vec::new()
     } else {
         name.split_terminator('.')
             .map(|x| x.to_string())
             .collect()
-    })
+    }
+}
+
+// Splits a JSON Pointer into its unescaped reference tokens.
+fn split_pointer(pointer: &str) -> Vec<String> {
+    pointer.split('/')
+        .skip(1)
+        .map(|part| part.replace("~1", "/").replace("~0", "~"))
+        .collect()
+}
+
+// A side of a comparison: a quoted string, a number or a name.
+fn operand(operand: &str) -> Option<Operand> {
+    let operand = operand.trim();
+    if operand.len() >= 2 && operand.starts_with('"') && operand.ends_with('"') {
+        let text = &operand[1..operand.len() - 1];
+        return if text.contains('"') { None } else { Some(Operand::Text(text.to_string())) };
+    }
+
+    if operand.is_empty() || operand.contains(|c: char| c.is_whitespace() || c == '"') {
+        None
+    } else if operand.parse::<f64>().is_ok() {
+        Some(Operand::Number(operand.to_string()))
+    } else {
+        Some(Operand::Name(split_name(operand)))
+    }
 }
 
 fn deny_blank(content: &str) -> Result<&str, Error> {
@@ -570,13 +1279,18 @@ mod tests {
     use super::*;
 
     pub fn parse(input: &str) -> Result<(Vec<Token>, Vec<String>), Error> {
+        parse_with(input, Extensions::default())
+    }
+
+    pub fn parse_with(input: &str, extensions: Extensions) -> Result<(Vec<Token>, Vec<String>), Error> {
         let input = &mut input.chars();
-        let parser = Parser::new(input, "{{", "}}");
-        parser.parse()
+        let parser = Parser::new(input, "{{", "}}", extensions);
+        let (tokens, partials) = parser.parse().map_err(|(err, _, _)| err)?;
+        Ok((tokens, partials.into_iter().map(|(name, _)| name).collect()))
     }
 
     pub fn assert_parse(input: &str) -> (Vec<Token>, Vec<String>) {
-        parse(input).expect(&format!("Failed to parse: {}", input))
+        parse(input).unwrap_or_else(|_| panic!("Failed to parse: {}", input))
     }
 
     #[test]
@@ -721,4 +1435,413 @@ mod tests {
         let input = "{{=<% %>=}} <%{ %>";
         assert_eq!(parse(input), Err(Error::UnbalancedUnescapeTag))
     }
+
+    mod trim_markers {
+        use super::*;
+
+        fn tokens(input: &str) -> Vec<Token> {
+            let (tokens, _) = parse_with(input, Extensions { trim_markers: true, ..Extensions::default() })
+                .unwrap_or_else(|_| panic!("Failed to parse: {}", input));
+            tokens
+        }
+
+        #[test]
+        fn disabled_by_default() {
+            let (tokens, _) = assert_parse("a {{- x -}} b");
+            assert_eq!(tokens, vec![
+                Token::Text("a ".into()),
+                Token::EscapedTag(vec!["- x -".into()], "{{- x -}}".into()),
+                Token::Text(" b".into()),
+            ]);
+        }
+
+        #[test]
+        fn trim_both_sides() {
+            assert_eq!(tokens("a \n {{- x -}} \n\tb"), vec![
+                Token::Text("a".into()),
+                Token::EscapedTag(vec!["x".into()], "{{- x -}}".into()),
+                Token::Text("b".into()),
+            ]);
+        }
+
+        #[test]
+        fn trim_one_side() {
+            assert_eq!(tokens("a {{-x}} b {{y-}} c"), vec![
+                Token::Text("a".into()),
+                Token::EscapedTag(vec!["x".into()], "{{-x}}".into()),
+                Token::Text(" b ".into()),
+                Token::EscapedTag(vec!["y".into()], "{{y-}}".into()),
+                Token::Text("c".into()),
+            ]);
+        }
+
+        #[test]
+        fn spaced_sigils() {
+            assert_eq!(tokens("{{- {x} -}} {{- > y -}} {{- ! z -}}"), vec![
+                Token::UnescapedTag(vec!["x".into()], "{{- {x} -}}".into()),
+                Token::Partial("y".into(), "".into(), "{{- > y -}}".into()),
+            ]);
+        }
+
+        #[test]
+        fn dashed_names() {
+            let parse = |input: &str| parse_with(input, Extensions { trim_markers: true, ..Extensions::default() });
+            assert_eq!(parse("{{--x}}"), Err(Error::AmbiguousTrimMarker("-x".into())));
+            assert_eq!(parse("{{#x--}}{{/x}}"), Err(Error::AmbiguousTrimMarker("#x-".into())));
+            assert_eq!(parse("{{- - x}}"), Err(Error::AmbiguousTrimMarker("- x".into())));
+
+            // A `-` inside a name, or after a sigil, is still part of it.
+            assert_eq!(tokens("{{-x-y-}}{{#-z}}{{/-z}}{{! a - }}"), vec![
+                Token::EscapedTag(vec!["x-y".into()], "{{-x-y-}}".into()),
+                Token::Section(vec!["-z".into()], false, vec![], "{{".into(), "{{#-z}}".into(), "".into(), "{{/-z}}".into(), "}}".into()),
+            ]);
+        }
+
+        #[test]
+        fn drops_whitespace_only_text() {
+            assert_eq!(tokens("{{x}}  \n  {{-y}}"), vec![
+                Token::EscapedTag(vec!["x".into()], "{{x}}".into()),
+                Token::EscapedTag(vec!["y".into()], "{{-y}}".into()),
+            ]);
+        }
+
+        #[test]
+        fn unescaped_and_sections() {
+            assert_eq!(tokens("a {{-{x}-}} b {{-&y-}} c"), vec![
+                Token::Text("a".into()),
+                Token::UnescapedTag(vec!["x".into()], "{{-{x}-}}".into()),
+                Token::Text("b".into()),
+                Token::UnescapedTag(vec!["y".into()], "{{-&y-}}".into()),
+                Token::Text("c".into()),
+            ]);
+
+            let tokens = tokens("x\n{{-#a-}}\n y \n{{-/a-}}\nz");
+            assert_eq!(tokens.len(), 3);
+            assert_let_section(&tokens[1]);
+        }
+
+        fn assert_let_section(token: &Token) {
+            match *token {
+                Token::Section(ref name, false, ref children, _, _, _, _, _) => {
+                    assert_eq!(name, &vec!["a".to_string()]);
+                    assert_eq!(children, &vec![Token::Text("y".into())]);
+                }
+                ref token => panic!("expected a section, found {:?}", token),
+            }
+        }
+    }
+
+    mod partial_fallbacks {
+        use super::*;
+
+        fn parse_fallbacks(input: &str) -> Result<(Vec<Token>, Vec<String>), Error> {
+            parse_with(input, Extensions { partial_fallbacks: true, ..Extensions::default() })
+        }
+
+        #[test]
+        fn disabled_by_default() {
+            assert_eq!(parse("{{>a}}x{{/a}}"), Err(Error::EarlySectionClose("a".into())));
+        }
+
+        #[test]
+        fn fallback() {
+            let (tokens, partials) = parse_fallbacks("{{>a}}x{{b}}{{/a}}{{>c}}").unwrap();
+            assert_eq!(tokens, vec![
+                Token::PartialWithFallback("a".into(),
+                                           "".into(),
+                                           vec![
+                                               Token::Text("x".into()),
+                                               Token::EscapedTag(vec!["b".into()], "{{b}}".into()),
+                                           ],
+                                           "{{>a}}".into(),
+                                           "x{{b}}".into(),
+                                           "{{/a}}".into()),
+                Token::Partial("c".into(), "".into(), "{{>c}}".into()),
+            ]);
+            assert_eq!(partials, vec!["a".to_string(), "c".to_string()]);
+        }
+
+        #[test]
+        fn nested_in_sections() {
+            let (tokens, _) = parse_fallbacks("{{#a}}{{>b}}{{#c}}{{>b}}{{/c}}{{/b}}{{/a}}").unwrap();
+            match tokens[..] {
+                [Token::Section(_, _, ref children, _, _, ref src, _, _)] => {
+                    assert_eq!(src, "{{>b}}{{#c}}{{>b}}{{/c}}{{/b}}");
+                    match children[..] {
+                        [Token::PartialWithFallback(_, _, ref fallback, _, _, _)] => assert_eq!(fallback.len(), 1),
+                        _ => panic!("Unexpected children: {:?}", children),
+                    }
+                }
+                _ => panic!("Unexpected tokens: {:?}", tokens),
+            }
+
+            assert_eq!(parse_fallbacks("{{#a}}{{>a}}{{/a}}"), Err(Error::UnclosedSection("a".into())));
+        }
+    }
+
+    mod inline_partials {
+        use super::*;
+
+        fn parse_inline(input: &str) -> Result<(Vec<Token>, Vec<String>), Error> {
+            parse_with(input, Extensions { inline_partials: true, ..Extensions::default() })
+        }
+
+        #[test]
+        fn definition() {
+            let (tokens, partials) = parse_inline("a\n{{<*item}}\n<li>{{.}}</li>\n{{/item}}\n{{>item}}").unwrap();
+            assert_eq!(tokens, vec![
+                Token::Text("a\n".into()),
+                Token::Definition("item".into(),
+                                  vec![Token::Text("<li>".into()),
+                                       Token::EscapedTag(vec![], "{{.}}".into()),
+                                       Token::Text("</li>\n".into())],
+                                  "{{<*item}}".into(),
+                                  "<li>{{.}}</li>\n".into(),
+                                  "{{/item}}".into()),
+                Token::Partial("item".into(), "".into(), "{{>item}}".into()),
+            ]);
+            assert_eq!(partials, vec!["item".to_string()]);
+        }
+
+        #[test]
+        fn unclosed() {
+            assert_eq!(parse_inline("{{<*item}}"), Err(Error::UnclosedSection("item".into())));
+            assert_eq!(parse_inline("{{<*item}}{{/other}}"), Err(Error::UnclosedSection("item".into())));
+        }
+    }
+
+    mod fallback_chains {
+        use super::*;
+
+        fn parse_chains(input: &str) -> Result<(Vec<Token>, Vec<String>), Error> {
+            parse_with(input, Extensions { fallback_chains: true, ..Extensions::default() })
+        }
+
+        #[test]
+        fn chain() {
+            let (tokens, _) = parse_chains("{{ a || b.c }}{{{a||.}}}{{&a || b}}{{a}}").unwrap();
+            let paths = || vec![vec!["a".to_string()], vec!["b".to_string(), "c".to_string()]];
+            assert_eq!(tokens, vec![
+                Token::FallbackTag(paths(), true, "{{ a || b.c }}".into()),
+                Token::FallbackTag(vec![vec!["a".into()], vec![]], false, "{{{a||.}}}".into()),
+                Token::FallbackTag(vec![vec!["a".into()], vec!["b".into()]], false, "{{&a || b}}".into()),
+                Token::EscapedTag(vec!["a".into()], "{{a}}".into()),
+            ]);
+            assert_eq!(parse_chains("{{a || }}"), Err(Error::EmptyTag));
+        }
+
+        #[test]
+        fn disabled() {
+            let (tokens, _) = parse_with("{{a || b}}", Extensions::default()).unwrap();
+            assert_eq!(tokens, vec![Token::EscapedTag(vec!["a || b".into()], "{{a || b}}".into())]);
+        }
+    }
+
+    mod comparisons {
+        use super::*;
+
+        fn parse_comparisons(input: &str) -> Result<(Vec<Token>, Vec<String>), Error> {
+            parse_with(input, Extensions { comparisons: true, ..Extensions::default() })
+        }
+
+        #[test]
+        fn comparison() {
+            let (tokens, _) = parse_comparisons("{{#a.b >= 2}}x{{/a.b >= 2}}{{^s == \"it's\"}}y{{/s == \"it's\"}}").unwrap();
+            let comparisons: Vec<_> = tokens.iter().map(|token| match *token {
+                Token::Condition(ref comparison, inverted, ref children, _, _, _, _, _) => {
+                    assert_eq!(children, &vec![Token::Text(if inverted { "y" } else { "x" }.into())]);
+                    (comparison.clone(), inverted)
+                }
+                ref token => panic!("unexpected token {:?}", token),
+            }).collect();
+            assert_eq!(comparisons, vec![
+                (Comparison {
+                    left: Operand::Name(vec!["a".into(), "b".into()]),
+                    op: CompareOp::Ge,
+                    right: Operand::Number("2".into()),
+                }, false),
+                (Comparison {
+                    left: Operand::Name(vec!["s".into()]),
+                    op: CompareOp::Eq,
+                    right: Operand::Text("it's".into()),
+                }, true),
+            ]);
+            assert_eq!(comparisons[1].0.to_string(), "s == \"it's\"");
+            assert_eq!(parse_comparisons("{{#a = 1}}{{/a = 1}}"), Err(Error::InvalidComparison("a = 1".into())));
+        }
+
+        #[test]
+        fn disabled() {
+            let (tokens, _) = parse_with("{{#a > 1}}{{/a > 1}}", Extensions::default()).unwrap();
+            match tokens[..] {
+                [Token::Section(ref path, false, _, _, _, _, _, _)] => assert_eq!(path, &vec!["a > 1".to_string()]),
+                ref tokens => panic!("unexpected tokens {:?}", tokens),
+            }
+        }
+    }
+
+    mod ranges {
+        use super::*;
+
+        fn parse_ranges(input: &str) -> Result<Vec<Token>, Error> {
+            parse_with(input, Extensions { ranges: true, ..Extensions::default() }).map(|(tokens, _)| tokens)
+        }
+
+        #[test]
+        fn range() {
+            let tokens = parse_ranges("{{#1..5}}x{{/1..5}}{{^-2..page.count}}y{{/-2..page.count}}").unwrap();
+            let ranges: Vec<_> = tokens.iter().map(|token| match *token {
+                Token::Range(ref bounds, inverted, ref children, _, _, _, _, _) => {
+                    assert_eq!(children, &vec![Token::Text(if inverted { "y" } else { "x" }.into())]);
+                    (bounds.to_string(), inverted)
+                }
+                ref token => panic!("unexpected token {:?}", token),
+            }).collect();
+            assert_eq!(ranges, vec![("1..5".to_string(), false), ("-2..page.count".to_string(), true)]);
+
+            for range in &["1..", "..n", "1.5..2", "\"a\"..2", "1...5", "1..n m"] {
+                let template = format!("{{{{#{}}}}}{{{{/{}}}}}", range, range);
+                assert_eq!(parse_ranges(&template), Err(Error::InvalidRange(range.to_string())));
+            }
+        }
+
+        #[test]
+        fn disabled() {
+            let (tokens, _) = parse_with("{{#1..5}}{{/1..5}}", Extensions::default()).unwrap();
+            match tokens[..] {
+                [Token::Section(ref path, false, _, _, _, _, _, _)] => assert_eq!(path, &vec!["1".to_string(), "".to_string(), "5".to_string()]),
+                ref tokens => panic!("unexpected tokens {:?}", tokens),
+            }
+        }
+    }
+
+    mod long_comments {
+        use super::*;
+
+        fn parse_long(input: &str, trim_markers: bool) -> Vec<Token> {
+            let extensions = Extensions { long_comments: true, trim_markers, ..Extensions::default() };
+            parse_with(input, extensions).unwrap().0
+        }
+
+        // Parses as `plain` does, a template with the comment left out.
+        fn same(input: &str, plain: &str, trim_markers: bool) {
+            let extensions = Extensions { trim_markers, ..Extensions::default() };
+            assert_eq!(parse_long(input, trim_markers), parse_with(plain, extensions).unwrap().0);
+        }
+
+        #[test]
+        fn comment() {
+            same("a{{!-- {{#b}}}}{{/b}} -}x --}}c", "a{{!}}c", false);
+            same("a {{!-- b --}}\nc", "a {{!}}\nc", false);
+            same("a\n{{!-- b\n}}\n--}}\nc", "a\n{{!}}\nc", false);
+            same("a {{- !-- b }} --}} c", "a {{- !}} c", true);
+            same("{{! a }}b --}}", "{{!}}b --}}", false);
+        }
+
+        #[test]
+        fn disabled() {
+            let (tokens, _) = parse_with("{{!-- a }}b --}}", Extensions::default()).unwrap();
+            assert_eq!(tokens, vec![Token::Text("b --}}".into())]);
+        }
+
+        #[test]
+        fn unclosed() {
+            let extensions = Extensions { long_comments: true, ..Extensions::default() };
+            assert_eq!(parse_with("{{!-- a }}", extensions), Err(Error::UnclosedTag));
+        }
+    }
+
+    mod json_pointers {
+        use super::*;
+
+        fn parse_pointers(input: &str) -> Result<(Vec<Token>, Vec<String>), Error> {
+            parse_with(input, Extensions { json_pointers: true, ..Extensions::default() })
+        }
+
+        #[test]
+        fn pointer() {
+            let (tokens, _) = parse_pointers("{{/users/0/a~1b~0c}} {{/a.b}}").unwrap();
+            assert_eq!(tokens, vec![
+                Token::PointerTag(vec!["users".into(), "0".into(), "a/b~c".into()], "{{/users/0/a~1b~0c}}".into()),
+                Token::Text(" ".into()),
+                Token::PointerTag(vec!["a.b".into()], "{{/a.b}}".into()),
+            ]);
+        }
+
+        #[test]
+        fn closing_tags() {
+            let (tokens, _) = parse_pointers("{{#a}}{{/b}}{{/a}}").unwrap();
+            assert_eq!(tokens, vec![
+                Token::Section(vec!["a".into()],
+                               false,
+                               vec![Token::PointerTag(vec!["b".into()], "{{/b}}".into())],
+                               "{{".into(),
+                               "{{#a}}".into(),
+                               "{{/b}}".into(),
+                               "{{/a}}".into(),
+                               "}}".into()),
+            ]);
+
+            assert_eq!(parse_pointers("{{#a}}{{/b}}"), Err(Error::UnclosedSection("a".into())));
+        }
+    }
+
+    mod raw_blocks {
+        use super::*;
+
+        fn parse_raw(input: &str) -> Result<(Vec<Token>, Vec<String>), Error> {
+            parse_with(input, Extensions { raw_blocks: true, ..Extensions::default() })
+        }
+
+        #[test]
+        fn raw() {
+            let (tokens, _) = parse_raw("a {{{{raw}}}}{{#b}}{{{c}}}{{/b}}{{{{/raw}}}} {{{d}}}").unwrap();
+            assert_eq!(tokens, vec![
+                Token::Text("a ".into()),
+                Token::Raw("{{#b}}{{{c}}}{{/b}}".into(), "{{{{raw}}}}{{#b}}{{{c}}}{{/b}}{{{{/raw}}}}".into()),
+                Token::Text(" ".into()),
+                Token::UnescapedTag(vec!["d".into()], "{{{d}}}".into()),
+            ]);
+        }
+
+        #[test]
+        fn standalone() {
+            let (tokens, _) = parse_raw("a\n  {{{{raw}}}}\n{{x}}\n  {{{{/raw}}}}\nb").unwrap();
+            assert_eq!(tokens, vec![
+                Token::Text("a\n".into()),
+                Token::Raw("{{x}}\n".into(), "{{{{raw}}}}{{x}}\n{{{{/raw}}}}".into()),
+                Token::Text("b".into()),
+            ]);
+        }
+
+        #[test]
+        fn names_and_delimiters() {
+            let (tokens, _) = parse_raw("{{=[[ ]]=}}[[[[outer]]]][[[[raw]]]][[[[/raw]]]][[[[/outer]]]]").unwrap();
+            assert_eq!(tokens, vec![
+                Token::Raw("[[[[raw]]]][[[[/raw]]]]".into(), "[[[[outer]]]][[[[raw]]]][[[[/raw]]]][[[[/outer]]]]".into()),
+            ]);
+        }
+
+        #[test]
+        fn unclosed() {
+            assert_eq!(parse_raw("{{{{raw}}}}{{x}}{{{{/other}}}}"), Err(Error::UnclosedSection("raw".into())));
+            assert_eq!(parse_raw("{{{{raw}}}"), Err(Error::UnclosedTag));
+        }
+    }
+
+    #[test]
+    fn deprecated() {
+        let (tokens, _) = assert_parse("{{! @deprecated use b }}\n{{! @deprecated again }}\na{{!@deprecatedly}}");
+        assert_eq!(tokens, vec![Token::Deprecated("use b".into()), Token::Text("a".into())]);
+
+        let (tokens, _) = assert_parse("a{{!@deprecated}}");
+        assert_eq!(tokens, vec![Token::Deprecated("".into()), Token::Text("a".into())]);
+    }
+
+    #[test]
+    fn nesting_too_deep() {
+        let nested = |depth| "{{#a}}".repeat(depth) + &"{{/a}}".repeat(depth);
+        assert_parse(&nested(MAX_NESTING));
+        assert_eq!(parse(&nested(MAX_NESTING + 1)), Err(Error::NestingTooDeep));
+    }
 }
@@ -0,0 +1,234 @@
+use std::fmt;
+use std::mem;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+
+use serde::Serialize;
+
+use template::RenderOptions;
+use {to_data, Data, Error, PartialLoader, Result, Template};
+
+/// Output buffers shared by renders on any number of threads. A render
+/// takes a buffer from the pool and the [`PooledOutput`] it returns puts it
+/// back once dropped, so a server rendering large pages doesn't allocate
+/// and free them for every request. Clones share the same buffers.
+///
+/// ```rust
+/// use mustache::{BufferPool, MapBuilder};
+///
+/// let pool = BufferPool::new().max_buffers(64).max_capacity(4 << 20);
+/// let template = mustache::compile_str("Hello {{name}}").unwrap();
+/// let data = MapBuilder::new().insert_str("name", "world").build();
+///
+/// {
+///     let output = pool.render_data(&template, &data).unwrap();
+///     assert_eq!(&*output, "Hello world");
+/// }
+/// assert_eq!(pool.len(), 1);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct BufferPool {
+    buffers: Arc<Mutex<Vec<Vec<u8>>>>,
+    max_buffers: Option<usize>,
+    max_capacity: Option<usize>,
+}
+
+impl BufferPool {
+    /// An empty pool keeping every buffer handed back to it.
+    pub fn new() -> BufferPool {
+        BufferPool::default()
+    }
+
+    /// Keeps at most `max` buffers, dropping the ones handed back past that.
+    pub fn max_buffers(mut self, max: usize) -> BufferPool {
+        self.max_buffers = Some(max);
+        self
+    }
+
+    /// Drops the buffers handed back that grew larger than `max` bytes, so
+    /// one huge render doesn't stay allocated.
+    pub fn max_capacity(mut self, max: usize) -> BufferPool {
+        self.max_capacity = Some(max);
+        self
+    }
+
+    /// The number of buffers waiting in the pool.
+    pub fn len(&self) -> usize {
+        self.buffers.lock().map(|buffers| buffers.len()).unwrap_or(0)
+    }
+
+    /// Whether no buffers are waiting in the pool.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Drops every buffer waiting in the pool.
+    pub fn clear(&self) {
+        if let Ok(mut buffers) = self.buffers.lock() {
+            buffers.clear();
+        }
+    }
+
+    /// Renders `template` with the `Encodable` data into a pooled buffer.
+    pub fn render<P: PartialLoader, T: Serialize>(&self, template: &Template<P>, data: &T) -> Result<PooledOutput> {
+        let data = to_data(data)?;
+        self.render_data(template, &data)
+    }
+
+    /// Renders `template` with the `Data` into a pooled buffer.
+    pub fn render_data<P: PartialLoader>(&self, template: &Template<P>, data: &Data) -> Result<PooledOutput> {
+        self.render_data_with(template, data, &RenderOptions::default())
+    }
+
+    /// Renders `template` with the `Data` and per-render `options` into a
+    /// pooled buffer. The buffer goes back to the pool if the render fails.
+    pub fn render_data_with<P: PartialLoader>(&self,
+                                              template: &Template<P>,
+                                              data: &Data,
+                                              options: &RenderOptions) -> Result<PooledOutput> {
+        let mut buffer = self.take();
+        buffer.reserve(template.reserved());
+
+        if let Err(err) = template.render_data_with(&mut buffer, data, options) {
+            self.give_back(buffer);
+            return Err(err);
+        }
+        match String::from_utf8(buffer) {
+            Ok(output) => Ok(PooledOutput { output, pool: Some(self.clone()) }),
+            Err(err) => {
+                self.give_back(err.into_bytes());
+                Err(Error::InvalidStr)
+            }
+        }
+    }
+
+    fn take(&self) -> Vec<u8> {
+        self.buffers.lock().ok().and_then(|mut buffers| buffers.pop()).unwrap_or_default()
+    }
+
+    fn give_back(&self, mut buffer: Vec<u8>) {
+        if let Some(max) = self.max_capacity {
+            if buffer.capacity() > max {
+                return;
+            }
+        }
+
+        buffer.clear();
+        if let Ok(mut buffers) = self.buffers.lock() {
+            match self.max_buffers {
+                Some(max) if buffers.len() >= max => {}
+                _ => buffers.push(buffer),
+            }
+        }
+    }
+}
+
+/// The output of a render from a [`BufferPool`], which takes its buffer
+/// back when this is dropped. Derefs to the output.
+pub struct PooledOutput {
+    output: String,
+    // `None` once the buffer left the pool.
+    pool: Option<BufferPool>,
+}
+
+impl PooledOutput {
+    /// The output as a `String` of its own, keeping the buffer out of the
+    /// pool.
+    pub fn into_string(mut self) -> String {
+        self.pool = None;
+        mem::take(&mut self.output)
+    }
+}
+
+impl Deref for PooledOutput {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.output
+    }
+}
+
+impl AsRef<str> for PooledOutput {
+    fn as_ref(&self) -> &str {
+        self
+    }
+}
+
+impl fmt::Debug for PooledOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl fmt::Display for PooledOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self)
+    }
+}
+
+impl Drop for PooledOutput {
+    fn drop(&mut self) {
+        if let Some(pool) = self.pool.take() {
+            pool.give_back(mem::take(&mut self.output).into_bytes());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::BufferPool;
+    use {Error, MapBuilder, RenderOptions};
+
+    #[test]
+    fn reused() {
+        let pool = BufferPool::new().max_buffers(2).max_capacity(1 << 10);
+        let template = ::compile_str("{{#items}}{{.}}{{/items}}").unwrap();
+        let data = |n| MapBuilder::new().insert_vec("items", |mut v| {
+            for _ in 0..n {
+                v = v.push_str("x");
+            }
+            v
+        }).build();
+
+        let (a, b, c) = (
+            pool.render_data(&template, &data(1)).unwrap(),
+            pool.render_data(&template, &data(2)).unwrap(),
+            pool.render_data(&template, &data(3)).unwrap(),
+        );
+        assert_eq!((&*a, &*b, &*c), ("x", "xx", "xxx"));
+        drop((a, b, c));
+        assert_eq!(pool.len(), 2);
+
+        // The buffers come back empty, and too large ones stay out.
+        assert_eq!(&*pool.render_data(&template, &data(4)).unwrap(), "xxxx");
+        drop(pool.render_data(&template, &data(2000)).unwrap());
+        assert_eq!(pool.len(), 1);
+        assert_eq!(pool.render_data(&template, &data(1)).unwrap().into_string(), "x");
+        assert_eq!(pool.len(), 0);
+
+        // Failed renders give their buffer back.
+        let options = RenderOptions { max_iterations: Some(1), ..RenderOptions::default() };
+        match pool.render_data_with(&template, &data(2), &options) {
+            Err(Error::TooManyIterations(1)) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(pool.len(), 1);
+
+        let threads: Vec<_> = (0..4).map(|_| {
+            let pool = pool.clone();
+            thread::spawn(move || {
+                let template = ::compile_str("{{n}}").unwrap();
+                for n in 0..100 {
+                    let data = MapBuilder::new().insert_str("n", n.to_string()).build();
+                    assert_eq!(&*pool.render_data(&template, &data).unwrap(), n.to_string());
+                }
+            })
+        }).collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert!(pool.len() <= 2);
+    }
+}
@@ -0,0 +1,255 @@
+//! Writing compiled templates back out as source, for
+//! `Template::to_source`.
+//!
+//! The tokens don't keep comments, delimiter changes or the whitespace
+//! around standalone tags, so what comes out is the template in a canonical
+//! form rather than as it was written: default delimiters, no spaces inside
+//! tags, raw blocks as text, and text with tags in it written between
+//! changed delimiters.
+//! Compiled again with the same extensions, it renders the same.
+
+use parser::Token;
+use template::{join_pointer, tag_name};
+
+// Delimiters to write text in, the first ones the text doesn't contain.
+const DELIMITERS: [(&str, &str); 6] = [("<%", "%>"), ("[[", "]]"), ("<<", ">>"), ("{%", "%}"), ("(%", "%)"), ("<?", "?>")];
+
+// A comment keeping the tag before it from being standalone. Only the text
+// the parser keeps a token for counts towards a tag being alone on its line,
+// and the tags written before line breaks all leave one.
+const BREAK: &str = "{{!}}";
+
+pub fn to_source(tokens: &[Token]) -> String {
+    let mut printer = Printer { out: String::new(), line: String::new(), text: String::new(), standalone: None };
+    printer.tokens(tokens);
+    printer.flush();
+
+    // Whitespace before a standalone tag at the very end is taken off.
+    if let Some(true) = printer.standalone {
+        printer.hidden(BREAK);
+    }
+    printer.out
+}
+
+struct Printer {
+    out: String,
+    // The current line as the parser sees it, without the tags written that
+    // it keeps no token for.
+    line: String,
+    // Text not written yet, so that neighbouring text goes out in one piece.
+    text: String,
+    // Set after a tag that would be standalone if a line break came next,
+    // to whether there is whitespace before it on its line.
+    standalone: Option<bool>,
+}
+
+impl Printer {
+    fn tokens(&mut self, tokens: &[Token]) {
+        for token in tokens {
+            match *token {
+                Token::Text(ref text) | Token::Raw(ref text, _) => self.text.push_str(text),
+                Token::EscapedTag(ref path, _) => self.variable(format!("{{{{{}}}}}", tag_name(path))),
+                Token::UnescapedTag(ref path, _) => self.variable(format!("{{{{{{{}}}}}}}", tag_name(path))),
+                Token::PointerTag(ref pointer, _) => self.variable(format!("{{{{{}}}}}", join_pointer(pointer))),
+                Token::FallbackTag(ref paths, escaped, _) => {
+                    let names: Vec<_> = paths.iter().map(|path| tag_name(path)).collect();
+                    let names = names.join(" || ");
+                    self.variable(if escaped { format!("{{{{{}}}}}", names) } else { format!("{{{{{{{}}}}}}}", names) });
+                }
+                Token::Section(ref path, inverted, ref children, _, _, _, _, _) => {
+                    self.section(inverted, &tag_name(path), children);
+                }
+                Token::Condition(ref comparison, inverted, ref children, _, _, _, _, _) => {
+                    self.section(inverted, &comparison.to_string(), children);
+                }
+                Token::Range(ref bounds, inverted, ref children, _, _, _, _, _) => {
+                    self.section(inverted, &bounds.to_string(), children);
+                }
+                Token::Partial(ref name, ref indent, _) => self.partial(name, indent),
+                Token::PartialWithFallback(ref name, ref indent, ref children, _, _, _) => {
+                    self.partial(name, indent);
+                    self.tokens(children);
+                    self.tag(format!("{{{{/{}}}}}", name));
+                }
+                Token::Definition(ref name, ref children, _, _, _) => {
+                    self.tag(format!("{{{{<*{}}}}}", name));
+                    self.tokens(children);
+                    self.tag(format!("{{{{/{}}}}}", name));
+                }
+                Token::Deprecated(_) | Token::IncompleteSection(..) | Token::IncompleteDefinition(..) => {}
+            }
+        }
+    }
+
+    fn section(&mut self, inverted: bool, name: &str, children: &[Token]) {
+        self.tag(format!("{{{{{}{}}}}}", if inverted { '^' } else { '#' }, name));
+        self.tokens(children);
+        self.tag(format!("{{{{/{}}}}}", name));
+    }
+
+    // A partial keeps its indentation by being standalone, on a line of its
+    // own after it.
+    fn partial(&mut self, name: &str, indent: &str) {
+        self.flush();
+        if indent.is_empty() || !(self.out.is_empty() || self.out.ends_with('\n')) {
+            self.tag(format!("{{{{>{}}}}}", name));
+            return;
+        }
+
+        self.write(indent);
+        self.push(&format!("{{{{>{}}}}}\n", name));
+    }
+
+    // Variables are never standalone.
+    fn variable(&mut self, tag: String) {
+        self.flush();
+        self.standalone = None;
+        self.push(&tag);
+    }
+
+    // Writes a tag that would take the line break after it, and the
+    // whitespace before it, if it was alone on its line.
+    fn tag(&mut self, tag: String) {
+        self.flush();
+        self.standalone = None;
+
+        if self.line.chars().all(char::is_whitespace) {
+            self.standalone = Some(!self.line.is_empty());
+        }
+        self.push(&tag);
+    }
+
+    fn flush(&mut self) {
+        if self.text.is_empty() {
+            return;
+        }
+        let text = ::std::mem::take(&mut self.text);
+
+        // Only the lines from the first opening delimiter to the last one
+        // are written between others, to the end if the text ends with a `{`
+        // that would make one with the next tag. That way neither delimiter
+        // change is alone on a line.
+        let (first, last) = match (text.find("{{"), text.rfind("{{")) {
+            (Some(first), Some(last)) if !text.ends_with('{') => (first, last + 2),
+            (first, _) if text.ends_with('{') => (first.unwrap_or(text.len() - 1), text.len()),
+            _ => {
+                self.write(&text);
+                return;
+            }
+        };
+        let start = text[..first].rfind('\n').map_or(0, |i| i + 1);
+        let end = text[last..].find(['\r', '\n']).map_or(text.len(), |i| last + i);
+        let (before, delimited, after) = (&text[..start], &text[start..end], &text[end..]);
+
+        let (open, close) = DELIMITERS.iter()
+            .find(|&&(open, close)| !delimited.contains(open) && !delimited.contains(close))
+            .cloned()
+            .unwrap_or(DELIMITERS[0]);
+        self.write(before);
+        self.delimiters(&format!("{{{{={} {}=}}}}", open, close));
+        self.write(delimited);
+        self.delimiters(&format!("{}={{{{ }}}}={}", open, close));
+        self.write(after);
+    }
+
+    // Writes a delimiter change, which the parser keeps no token for.
+    fn delimiters(&mut self, tag: &str) {
+        self.standalone = None;
+        self.hidden(tag);
+    }
+
+    // Writes text, after a `BREAK` if a standalone tag would take its line
+    // break.
+    fn write(&mut self, text: &str) {
+        if self.standalone.take().is_some() && (text.starts_with('\n') || text.starts_with("\r\n")) {
+            self.hidden(BREAK);
+        }
+        self.push(text);
+    }
+
+    fn push(&mut self, text: &str) {
+        self.out.push_str(text);
+        match text.rfind('\n') {
+            Some(i) => self.line = text[i + 1..].to_string(),
+            None => self.line.push_str(text),
+        }
+    }
+
+    // Writes a tag the parser keeps no token for.
+    fn hidden(&mut self, tag: &str) {
+        self.out.push_str(tag);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use {Context, Extensions};
+
+    fn context() -> Context<::DefaultLoader> {
+        let mut ctx = Context::new(PathBuf::from("."));
+        ctx.extensions = Extensions {
+            partial_fallbacks: true,
+            inline_partials: true,
+            json_pointers: true,
+            raw_blocks: true,
+            fallback_chains: true,
+            comparisons: true,
+            ranges: true,
+            ..Extensions::default()
+        };
+        ctx
+    }
+
+    #[test]
+    fn canonical() {
+        let ctx = context();
+        let source = |template: &str| ctx.compile(template.chars()).unwrap().to_source();
+
+        assert_eq!(source("Hi {{ name }}{{! hi }}, {{& html}}{{{html}}}"), "Hi {{name}}, {{{html}}}{{{html}}}");
+        assert_eq!(source("{{=<% %>=}}<%#a%>[<%.%>]<%/a%>"), "{{#a}}[{{.}}]{{/a}}");
+        assert_eq!(source("{{#a}}\n  x\n{{/a}}\n{{^b}}y{{/b}}"), "{{#a}}  x\n{{/a}}{{^b}}y{{/b}}");
+        assert_eq!(source("{{# n > 1 }}{{/n > 1}}{{#1..n}}{{/1..n}}{{a || b}}{{/p/0}}"),
+                   "{{#n > 1}}{{/n > 1}}{{#1..n}}{{/1..n}}{{a || b}}{{/p/0}}");
+        assert_eq!(source("{{<*card}}c{{/card}}{{>card}}\n  {{>item}}\n{{>x}}fallback{{/x}}"),
+                   "{{<*card}}c{{/card}}{{>card}}\n  {{>item}}\n{{>x}}fallback{{/x}}");
+        assert_eq!(source("{{{{raw}}}}{{a}}{{{{/raw}}}} a{"), "{{=<% %>=}}{{a}} a{<%={{ }}=%>");
+        assert_eq!(source("{{=<< >>=}}<<a>>\n{{<%{{ %>"), "{{a}}\n{{=[[ ]]=}}{{<%{{ %>[[={{ }}=]]");
+    }
+
+    #[test]
+    fn standalone() {
+        let ctx = context();
+        let source = |template: &str| ctx.compile(template.chars()).unwrap().to_source();
+
+        // The line breaks kept after standalone tags stay after them.
+        assert_eq!(source("{{#a}}\n\nx\n{{/a}}\n\n"), "{{#a}}{{!}}\nx\n{{/a}}{{!}}\n");
+        assert_eq!(source("a\n  {{#b}}\n\n  {{/b}}\n  "), "a\n{{#b}}{{!}}\n{{/b}}  ");
+        assert_eq!(source("{{#a}}\n{{=<% %>=}}\n\n{{b}}\n<%={{ }}=%>\n\n{{/a}}"), "{{#a}}{{!}}\n{{=<% %>=}}{{b}}<%={{ }}=%>\n\n{{/a}}");
+    }
+
+    #[test]
+    fn round_trip() {
+        let ctx = context();
+        let templates = [
+            "{{#items}}\n  <li>{{name}}</li>\n{{/items}}\n{{^items}}\n  none\n{{/items}}\n",
+            "<ul>\n  {{>item}}\n  {{#a}}\n{{#b}}{{.}}{{/b}}\n  {{/a}}\n</ul>",
+            "{{<*item}}\n\n  {{title}}\n{{/item}}\n\n{{#list}}\n  {{>item}}\n\n{{/list}}",
+            "{{#1..count}}{{.}}{{/1..count}} {{{{raw}}}}\n{{x}}\n{{{{/raw}}}}\n{{!c}}\n",
+            "{{=<% %>=}}\n  <%#a%>\n{{literal}}\n  <%/a%>\n<%={{ }}=%>\n  \n",
+            "{{#n >= 2}}\n\n{{/n >= 2}}{{a || b}}\n  {{>none}}\n  fallback\n  {{/none}}\n",
+        ];
+
+        for template in &templates {
+            let compiled = ctx.compile(template.chars()).unwrap();
+            let source = compiled.to_source();
+            let again = ctx.compile(source.chars()).unwrap_or_else(|err| panic!("{:?} gave {:?}: {}", template, source, err));
+            assert_eq!(again.to_source(), source, "{:?}", template);
+
+            let data = compiled.sample_data();
+            assert_eq!(again.render_data_to_string(&data).unwrap(), compiled.render_data_to_string(&data).unwrap(),
+                       "{:?} gave {:?}", template, source);
+        }
+    }
+}
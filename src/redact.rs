@@ -0,0 +1,66 @@
+use std::borrow::Cow;
+use std::sync::{Arc, RwLock};
+
+/// A redaction registered with [`set_redaction`](fn.set_redaction.html).
+pub type Redaction = dyn Fn(&str, &str) -> Option<String> + Send + Sync;
+
+// A value printed on another thread while the next redaction is registered
+// keeps the one it started with alive until it is done.
+static REDACTION: RwLock<Option<Arc<Redaction>>> = RwLock::new(None);
+
+/// Registers `f` to decide what the strings of a [`Data`](enum.Data.html)
+/// show as wherever this crate prints them rather than renders them: its
+/// `Debug` output, which the crate's own logs use, and
+/// [`Data::to_pretty_string`](enum.Data.html#method.to_pretty_string).
+///
+/// `f` is given the key the value is under, that of the list for the items
+/// of a list and empty at the root, and the value, and returns what to show
+/// instead, or `None` to show the value. It applies to the whole process,
+/// replacing any redaction registered before; set it once at startup.
+///
+/// ```rust
+/// use mustache::MapBuilder;
+///
+/// mustache::set_redaction(|key, value| {
+///     if key == "password" || value.contains('@') {
+///         Some("[redacted]".to_string())
+///     } else {
+///         None
+///     }
+/// });
+///
+/// let data = MapBuilder::new()
+///     .insert_str("password", "hunter2")
+///     .insert_vec("emails", |v| v.push_str("ann@example.com"))
+///     .build();
+///
+/// assert_eq!(data.to_pretty_string(), "\
+/// map (2)
+///   emails: vec (1)
+///     0: string \"[redacted]\"
+///   password: string \"[redacted]\"
+/// ");
+/// assert!(!format!("{:?}", data).contains("hunter2"));
+/// ```
+pub fn set_redaction<F>(f: F)
+where F: Fn(&str, &str) -> Option<String> + Send + Sync + 'static
+{
+    let redaction: Arc<Redaction> = Arc::new(f);
+    let mut registered = REDACTION.write().unwrap_or_else(|err| err.into_inner());
+    *registered = Some(redaction);
+}
+
+/// What `value`, under `key`, shows as with the registered redaction, for
+/// printing values of the data in diagnostics of your own.
+pub fn redacted<'a>(key: &str, value: &'a str) -> Cow<'a, str> {
+    // Called with the lock released, so the redaction may print data too.
+    let redaction = match *REDACTION.read().unwrap_or_else(|err| err.into_inner()) {
+        Some(ref redaction) => redaction.clone(),
+        None => return Cow::Borrowed(value),
+    };
+
+    match redaction(key, value) {
+        Some(shown) => Cow::Owned(shown),
+        None => Cow::Borrowed(value),
+    }
+}
@@ -0,0 +1,106 @@
+use std::str;
+
+use serde::Serialize;
+
+use template::{Buffers, RenderOptions};
+use {to_data, Data, Error, PartialLoader, Result, Template};
+
+/// Renders templates into an output buffer it keeps, along with the stacks
+/// the renderer walks the template and the data with, so rendering over and
+/// over doesn't allocate them anew every time.
+///
+/// The output is borrowed from the `Renderer` until the next render. Like
+/// [`Data`](enum.Data.html), a `Renderer` stays on the thread it was made
+/// on: keep one per thread, in a `thread_local!` for instance.
+///
+/// ```rust
+/// use mustache::{MapBuilder, Renderer};
+///
+/// let template = mustache::compile_str("{{#users}}<li>{{name}}</li>{{/users}}").unwrap();
+/// let mut renderer = Renderer::new();
+///
+/// for name in &["Ann", "Bob"] {
+///     let data = MapBuilder::new()
+///         .insert_vec("users", |v| v.push_map(|m| m.insert_str("name", *name)))
+///         .build();
+///
+///     let output = renderer.render_data(&template, &data).unwrap();
+///     assert_eq!(output, format!("<li>{}</li>", name));
+/// }
+/// ```
+#[derive(Default)]
+pub struct Renderer {
+    output: Vec<u8>,
+    buffers: Buffers,
+}
+
+impl Renderer {
+    /// A renderer with nothing allocated yet.
+    pub fn new() -> Renderer {
+        Renderer::default()
+    }
+
+    /// A renderer whose output buffer holds `capacity` bytes to begin with.
+    pub fn with_capacity(capacity: usize) -> Renderer {
+        Renderer {
+            output: Vec::with_capacity(capacity),
+            buffers: Buffers::default(),
+        }
+    }
+
+    /// Renders `template` with the `Encodable` data.
+    pub fn render<P: PartialLoader, T: Serialize>(&mut self, template: &Template<P>, data: &T) -> Result<&str> {
+        let data = to_data(data)?;
+        self.render_data(template, &data)
+    }
+
+    /// Renders `template` with the `Data`.
+    pub fn render_data<P: PartialLoader>(&mut self, template: &Template<P>, data: &Data) -> Result<&str> {
+        self.render_data_with(template, data, &RenderOptions::default())
+    }
+
+    /// Renders `template` with the `Data` and per-render `options`.
+    pub fn render_data_with<P: PartialLoader>(&mut self,
+                                              template: &Template<P>,
+                                              data: &Data,
+                                              options: &RenderOptions) -> Result<&str> {
+        self.output.clear();
+        self.output.reserve(template.reserved());
+        template.render_data_buffered(&mut self.output, data, options, &mut self.buffers)?;
+
+        str::from_utf8(&self.output).map_err(|_| Error::InvalidStr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Renderer;
+    use {Data, Error, MapBuilder, RenderOptions};
+
+    #[test]
+    fn reused() {
+        let template = ::compile_str("{{>missing}}{{#items}}[{{name}}{{#tags}}, {{.}}{{/tags}}]{{/items}}").unwrap();
+        let mut renderer = Renderer::new();
+
+        for n in 0..3 {
+            let data = MapBuilder::new()
+                .insert_vec("items", |mut v| {
+                    for i in 0..n {
+                        v = v.push_map(|m| m.insert_str("name", i.to_string()).insert_vec("tags", |t| t.push_str("t")));
+                    }
+                    v
+                })
+                .build();
+
+            let expected = template.render_data_to_string(&data).unwrap();
+            assert_eq!(renderer.render_data(&template, &data).unwrap(), expected);
+        }
+
+        // A failed render leaves nothing behind for the next one.
+        let options = RenderOptions { max_iterations: Some(1), ..RenderOptions::default() };
+        let data = MapBuilder::new().insert_vec("items", |v| v.push_str("a").push_str("b")).build();
+        assert!(matches!(renderer.render_data_with(&template, &data, &options), Err(Error::TooManyIterations(1))));
+        assert_eq!(renderer.render_data(&template, &Data::Null).unwrap(), "");
+        assert_eq!(renderer.render(&template, &()).unwrap(), "");
+    }
+}
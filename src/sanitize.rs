@@ -0,0 +1,111 @@
+//! Sanitizing untrusted HTML with [ammonia](https://docs.rs/ammonia), for
+//! rich content from users that has to be written unescaped.
+//!
+//! A [`Sanitizer`] set as
+//! [`RenderOptions::sanitize`](../struct.RenderOptions.html#structfield.sanitize)
+//! cleans whatever every `{{{name}}}` and `{{&name}}` tag of the render
+//! writes. To clean only some values, leave the option unset and put the
+//! [`Sanitizer::helper`] in the data around them instead,
+//! `{{#sanitize}}{{{bio}}}{{/sanitize}}`.
+//!
+//! ```
+//! use mustache::{MapBuilder, RenderOptions, Sanitizer};
+//!
+//! let template = mustache::compile_str("<div>{{{bio}}}</div>").unwrap();
+//! let data = MapBuilder::new()
+//!     .insert_str("bio", "<b onclick=\"steal()\">Hi</b><script>steal()</script>")
+//!     .build();
+//!
+//! let options = RenderOptions { sanitize: Some(Sanitizer::default()), ..RenderOptions::default() };
+//! let mut out = Vec::new();
+//! template.render_data_with(&mut out, &data, &options).unwrap();
+//! assert_eq!(String::from_utf8(out).unwrap(), "<div><b>Hi</b></div>");
+//! ```
+
+use std::cell::RefCell;
+use std::fmt;
+use std::sync::Arc;
+
+use ammonia::Builder;
+
+use {Data, Scope};
+
+/// An HTML sanitizer with the policy of an [`ammonia::Builder`], which says
+/// which tags, attributes and URL schemes are kept.
+#[derive(Clone)]
+pub struct Sanitizer {
+    policy: Arc<Builder<'static>>,
+}
+
+impl Sanitizer {
+    /// A sanitizer keeping what `policy` allows.
+    ///
+    /// ```
+    /// use mustache::Sanitizer;
+    /// use mustache::ammonia::Builder;
+    ///
+    /// let mut policy = Builder::default();
+    /// policy.rm_tags(&["img"]);
+    /// let sanitizer = Sanitizer::new(policy);
+    /// assert_eq!(sanitizer.clean("<p>Hi<img src=\"x.png\"></p>"), "<p>Hi</p>");
+    /// ```
+    pub fn new(policy: Builder<'static>) -> Sanitizer {
+        Sanitizer { policy: Arc::new(policy) }
+    }
+
+    /// `html` with everything the policy doesn't allow taken out.
+    pub fn clean(&self, html: &str) -> String {
+        self.policy.clean(html).to_string()
+    }
+
+    /// A [`Data::Helper`](../enum.Data.html#variant.Helper) lambda cleaning
+    /// what its section renders to.
+    pub fn helper(&self) -> Data {
+        let sanitizer = self.clone();
+        Data::Helper(RefCell::new(Box::new(move |content: String, _: &Scope| sanitizer.clean(&content))))
+    }
+}
+
+/// Ammonia's default policy, which keeps the usual formatting tags and
+/// links, and drops scripts, styles and event handlers.
+impl Default for Sanitizer {
+    fn default() -> Sanitizer {
+        Sanitizer::new(Builder::default())
+    }
+}
+
+impl fmt::Debug for Sanitizer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Sanitizer").field("policy", &self.policy).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Sanitizer;
+    use {compile_str, Data, MapBuilder, RenderOptions};
+
+    #[test]
+    fn unescaped_tags() {
+        let template = compile_str("{{{bio}}}|{{&bio}}|{{bio}}|{{#clean}}<i>{{{bio}}}</i>{{/clean}}").unwrap();
+        let bio = "<a href=\"javascript:x()\">me</a>";
+        let mut data = MapBuilder::new().insert_str("bio", bio).build();
+        if let Data::Map(ref mut map) = data {
+            map.insert("clean".to_string(), Sanitizer::default().helper());
+        }
+
+        let render = |options: &RenderOptions| {
+            let mut out = Vec::new();
+            template.render_data_with(&mut out, &data, options).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        let escaped = "&lt;a href=&quot;javascript:x()&quot;&gt;me&lt;/a&gt;";
+        let cleaned = "<a rel=\"noopener noreferrer\">me</a>";
+        assert_eq!(render(&RenderOptions::default()),
+                   format!("{}|{}|{}|<i>{}</i>", bio, bio, escaped, cleaned));
+
+        let options = RenderOptions { sanitize: Some(Sanitizer::default()), ..RenderOptions::default() };
+        assert_eq!(render(&options), format!("{}|{}|{}|<i>{}</i>", cleaned, cleaned, escaped, cleaned));
+    }
+}
@@ -0,0 +1,370 @@
+use std::collections::{BTreeMap, HashMap};
+
+#[cfg(feature = "schema")]
+use serde_json::{Map, Value};
+
+use compiler::PartialsMap;
+use parser::Token;
+use Data;
+
+// The words of the strings in sample data, taken in turn.
+const LOREM: [&str; 12] = [
+    "lorem", "ipsum", "dolor", "sit", "amet", "consectetur",
+    "adipiscing", "elit", "sed", "do", "eiusmod", "tempor",
+];
+
+// The items of a list in sample data.
+const SAMPLE_ITEMS: usize = 3;
+
+// What the template does with one name.
+#[derive(Default)]
+struct Shape {
+    // Written out by a variable tag.
+    written: bool,
+    // Opened by a section, which iterates over it if it is a list.
+    opened: bool,
+    // Looked inside of by a dotted name or a pointer.
+    dotted: bool,
+    // Written out as `{{.}}` inside its own section.
+    dot: bool,
+    // An end of a range.
+    counted: bool,
+    // Used outside inverted sections.
+    required: bool,
+    properties: BTreeMap<String, Shape>,
+}
+
+fn shape(tokens: &[Token], partials: &PartialsMap) -> Shape {
+    let mut walker = Walker {
+        partials,
+        including: Vec::new(),
+        root: Shape::default(),
+    };
+    walker.walk(tokens, &mut Vec::new(), true);
+    walker.root
+}
+
+/// Describes the data `tokens` expect as a JSON Schema, see
+/// `Template::json_schema`.
+#[cfg(feature = "schema")]
+pub fn json_schema(tokens: &[Token], partials: &PartialsMap) -> Value {
+    let root = shape(tokens, partials);
+
+    let mut schema = Map::new();
+    schema.insert("$schema".to_string(), "https://json-schema.org/draft/2020-12/schema".into());
+    schema.insert("type".to_string(), "object".into());
+    properties(&root, &mut schema);
+    Value::Object(schema)
+}
+
+/// Makes up data for `tokens` to render with, see `Template::sample_data`.
+pub fn sample_data(tokens: &[Token], partials: &PartialsMap) -> Data {
+    let mut words = LOREM.iter().cycle();
+    sample_map(&shape(tokens, partials), &mut words)
+}
+
+fn sample_map<'w, I: Iterator<Item = &'w &'static str>>(shape: &Shape, words: &mut I) -> Data {
+    let map: HashMap<_, _> = shape.properties.iter().map(|(name, shape)| (name.clone(), sample(shape, words))).collect();
+    Data::Map(map)
+}
+
+fn sample<'w, I: Iterator<Item = &'w &'static str>>(shape: &Shape, words: &mut I) -> Data {
+    let inner = !shape.properties.is_empty();
+
+    if shape.opened && (inner || shape.dot) {
+        let items = (0..SAMPLE_ITEMS).map(|_| if inner { sample_map(shape, words) } else { lorem(words) });
+        Data::Vec(items.collect())
+    } else if inner {
+        sample_map(shape, words)
+    } else if shape.counted {
+        Data::String(SAMPLE_ITEMS.to_string())
+    } else if shape.written {
+        lorem(words)
+    } else if shape.opened {
+        Data::Bool(true)
+    } else {
+        // Only used in inverted sections or comparisons, so left empty for
+        // them to show.
+        Data::Null
+    }
+}
+
+fn lorem<'w, I: Iterator<Item = &'w &'static str>>(words: &mut I) -> Data {
+    let text: Vec<&str> = words.take(2).cloned().collect();
+    Data::String(text.join(" "))
+}
+
+struct Walker<'a> {
+    partials: &'a PartialsMap,
+    // Partials currently being walked, so recursive partials terminate.
+    including: Vec<&'a str>,
+    root: Shape,
+}
+
+impl<'a> Walker<'a> {
+    // `scope` is the path to the innermost section, whose value names inside
+    // it are taken to belong to. `required` is false inside inverted
+    // sections.
+    fn walk(&mut self, tokens: &'a [Token], scope: &mut Vec<String>, required: bool) {
+        for token in tokens {
+            match *token {
+                Token::EscapedTag(ref path, _) | Token::UnescapedTag(ref path, _) => {
+                    if path.is_empty() {
+                        self.scope(scope).dot = true;
+                    } else {
+                        let shape = self.name(scope, path, required);
+                        shape.written = true;
+                    }
+                }
+                Token::FallbackTag(ref paths, _, _) => {
+                    // Any of them may be left out.
+                    for path in paths {
+                        if path.is_empty() {
+                            self.scope(scope).dot = true;
+                        } else {
+                            self.name(scope, path, false).written = true;
+                        }
+                    }
+                }
+                Token::PointerTag(ref pointer, _) => {
+                    // Pointers start from the data itself. Past an index
+                    // there is no telling what is what.
+                    let parts: Vec<_> = pointer.iter().take_while(|part| part.parse::<usize>().is_err()).cloned().collect();
+                    if !parts.is_empty() {
+                        let shape = self.name(&[], &parts, required);
+                        shape.written |= parts.len() == pointer.len();
+                    }
+                }
+                Token::Section(ref path, inverted, ref children, _, _, _, _, _) => {
+                    if path.is_empty() {
+                        self.walk(children, scope, required && !inverted);
+                    } else if inverted {
+                        self.name(scope, path, false);
+                        self.walk(children, scope, false);
+                    } else {
+                        self.name(scope, path, required).opened = true;
+
+                        let depth = scope.len();
+                        scope.extend(path.iter().cloned());
+                        self.walk(children, scope, required);
+                        scope.truncate(depth);
+                    }
+                }
+                // A range with a missing end is empty.
+                Token::Range(ref bounds, _, ref children, _, _, _, _, _) => {
+                    for path in bounds.names().into_iter().filter(|path| !path.is_empty()) {
+                        self.name(scope, path, false).counted = true;
+                    }
+                    self.walk(children, scope, false);
+                }
+                // Comparisons hold as well when a name is missing.
+                Token::Condition(ref comparison, _, ref children, _, _, _, _, _) => {
+                    for path in comparison.names().into_iter().filter(|path| !path.is_empty()) {
+                        self.name(scope, path, false);
+                    }
+                    self.walk(children, scope, false);
+                }
+                Token::Partial(ref name, _, _) => self.walk_partial(name, scope, required),
+                Token::PartialWithFallback(ref name, _, ref children, _, _, _) => {
+                    self.walk(children, scope, required);
+                    self.walk_partial(name, scope, required);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn walk_partial(&mut self, name: &'a str, scope: &mut Vec<String>, required: bool) {
+        if self.including.contains(&name) {
+            return;
+        }
+
+        if let Some(tokens) = self.partials.get(name) {
+            self.including.push(name);
+            self.walk(tokens, scope, required);
+            self.including.pop();
+        }
+    }
+
+    fn scope(&mut self, scope: &[String]) -> &mut Shape {
+        scope.iter().fold(&mut self.root, |shape, part| shape.properties.entry(part.clone()).or_default())
+    }
+
+    // The shape of a dotted name used in `scope`.
+    fn name(&mut self, scope: &[String], path: &[String], required: bool) -> &mut Shape {
+        let (last, parents) = path.split_last().expect("names have parts");
+
+        let mut shape = self.scope(scope);
+        for part in parents {
+            shape = shape.properties.entry(part.clone()).or_default();
+            shape.dotted = true;
+            shape.required |= required;
+        }
+
+        let shape = shape.properties.entry(last.clone()).or_default();
+        shape.required |= required;
+        shape
+    }
+}
+
+#[cfg(feature = "schema")]
+fn properties(shape: &Shape, schema: &mut Map<String, Value>) {
+    let properties: Map<_, _> = shape.properties.iter().map(|(name, shape)| (name.clone(), to_schema(shape))).collect();
+    let required: Vec<Value> = shape.properties
+        .iter()
+        .filter(|&(_, shape)| shape.required)
+        .map(|(name, _)| name.clone().into())
+        .collect();
+
+    schema.insert("properties".to_string(), Value::Object(properties));
+    if !required.is_empty() {
+        schema.insert("required".to_string(), Value::Array(required));
+    }
+}
+
+#[cfg(feature = "schema")]
+fn to_schema(shape: &Shape) -> Value {
+    let mut types = Vec::new();
+    let mut schema = Map::new();
+    let inner = !shape.properties.is_empty();
+
+    if shape.written || shape.dot {
+        types.extend(&["string", "number"]);
+    }
+    if shape.counted {
+        types.push("integer");
+    }
+    if shape.dotted || (shape.opened && inner) {
+        types.push("object");
+        properties(shape, &mut schema);
+    }
+    // A section over a list renders once for every item, with the names
+    // inside it looked up on the item.
+    if shape.opened && (inner || shape.dot) {
+        types.push("array");
+
+        let mut item = Map::new();
+        let mut item_types = Vec::new();
+        if inner {
+            item_types.push("object");
+            properties(shape, &mut item);
+        }
+        if shape.dot {
+            item_types.extend(&["string", "number"]);
+        }
+        item_types.sort();
+        item.insert("type".to_string(), type_value(&item_types));
+        schema.insert("items".to_string(), Value::Object(item));
+    }
+
+    // A section over nothing but text is a flag, anything will do.
+    if !types.is_empty() {
+        types.sort();
+        types.dedup();
+        schema.insert("type".to_string(), type_value(&types));
+    }
+    Value::Object(schema)
+}
+
+#[cfg(feature = "schema")]
+fn type_value(types: &[&str]) -> Value {
+    match *types {
+        [one] => one.into(),
+        _ => types.iter().map(|&t| Value::from(t)).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    #[cfg(feature = "schema")]
+    use serde_json::{self, Value};
+
+    use {Context, Data, MapBuilder};
+
+    #[test]
+    fn sample_data() {
+        let mut ctx = Context::new(PathBuf::from("."));
+        ctx.extensions.inline_partials = true;
+        let template = ctx.compile(concat!(
+            "{{<*item}}{{title}} {{price.amount}}{{/item}}",
+            "{{user.name}}{{#orders}}{{>item}}{{/orders}}{{#tags}}{{.}}{{/tags}}",
+            "{{#vip}}!{{/vip}}{{^discount}}{{note}}{{/discount}}",
+        ).chars()).unwrap();
+
+        let data = template.sample_data();
+        let order = |title: &str, amount: &str| {
+            MapBuilder::new()
+                .insert_map("price", |m| m.insert_str("amount", amount))
+                .insert_str("title", title)
+                .build()
+        };
+        assert_eq!(data, MapBuilder::new()
+            .insert("discount", &()).unwrap()
+            .insert_str("note", "lorem ipsum")
+            .insert_vec("orders", |v| v
+                .push(&order("amet consectetur", "dolor sit")).unwrap()
+                .push(&order("sed do", "adipiscing elit")).unwrap()
+                .push(&order("lorem ipsum", "eiusmod tempor")).unwrap())
+            .insert_vec("tags", |v| v.push_str("dolor sit").push_str("amet consectetur").push_str("adipiscing elit"))
+            .insert_map("user", |m| m.insert_str("name", "sed do"))
+            .insert_bool("vip", true)
+            .build());
+        assert!(template.render_data_to_string(&data).unwrap().contains("!"));
+        assert_eq!(Context::new(PathBuf::from(".")).compile("text".chars()).unwrap().sample_data(), Data::Map(Default::default()));
+    }
+
+    #[cfg(feature = "schema")]
+    #[test]
+    fn schema() {
+        let mut ctx = Context::new(PathBuf::from("."));
+        ctx.extensions.inline_partials = true;
+        ctx.extensions.json_pointers = true;
+        let template = ctx.compile(concat!(
+            "{{<*item}}{{title}} {{price.amount}}{{/item}}",
+            "Hi {{user.name}}{{#orders}}{{>item}}{{/orders}}{{#tags}}{{.}}{{/tags}}",
+            "{{#vip}}!{{/vip}}{{^discount}}{{note}}{{/discount}}{{/meta/0/id}}",
+        ).chars()).unwrap();
+
+        let expected: Value = serde_json::from_str(r#"{
+            "$schema": "https://json-schema.org/draft/2020-12/schema",
+            "type": "object",
+            "properties": {
+                "discount": {},
+                "meta": {},
+                "note": {"type": ["number", "string"]},
+                "orders": {
+                    "type": ["array", "object"],
+                    "properties": {
+                        "price": {
+                            "type": "object",
+                            "properties": {"amount": {"type": ["number", "string"]}},
+                            "required": ["amount"]
+                        },
+                        "title": {"type": ["number", "string"]}
+                    },
+                    "required": ["price", "title"],
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "price": {
+                                "type": "object",
+                                "properties": {"amount": {"type": ["number", "string"]}},
+                                "required": ["amount"]
+                            },
+                            "title": {"type": ["number", "string"]}
+                        },
+                        "required": ["price", "title"]
+                    }
+                },
+                "tags": {"type": ["array", "number", "string"], "items": {"type": ["number", "string"]}},
+                "user": {"type": "object", "properties": {"name": {"type": ["number", "string"]}}, "required": ["name"]},
+                "vip": {}
+            },
+            "required": ["meta", "orders", "tags", "user", "vip"]
+        }"#).unwrap();
+
+        assert_eq!(template.json_schema(), expected);
+    }
+}
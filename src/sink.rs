@@ -0,0 +1,143 @@
+use std::cell::Cell;
+use std::io::{self, Write};
+use std::mem;
+use std::str;
+
+use {Error, Result};
+
+/// Where a render writes when its output isn't a byte stream, such as a
+/// rope, a stream of DOM nodes or a chunked HTTP body. See
+/// [`Template::render_data_to_sink`](struct.Template.html#method.render_data_to_sink).
+///
+/// Output arrives in the pieces the renderer makes it in, each one whole
+/// characters and every escaped value a piece of its own.
+///
+/// ```rust
+/// use mustache::{MapBuilder, OutputSink, Result};
+///
+/// // Keeps values apart from the markup around them.
+/// #[derive(Default)]
+/// struct Pieces(Vec<String>);
+///
+/// impl OutputSink for Pieces {
+///     fn write_str(&mut self, s: &str) -> Result<()> {
+///         self.0.push(s.to_string());
+///         Ok(())
+///     }
+///
+///     fn write_escaped(&mut self, s: &str) -> Result<()> {
+///         self.0.push(format!("[{}]", s));
+///         Ok(())
+///     }
+/// }
+///
+/// let template = mustache::compile_str("<b>{{name}}</b>").unwrap();
+/// let data = MapBuilder::new().insert_str("name", "Ann").build();
+///
+/// let mut pieces = Pieces::default();
+/// template.render_data_to_sink(&mut pieces, &data).unwrap();
+/// assert_eq!(pieces.0, vec!["<b>", "[Ann]", "</b>"]);
+/// ```
+pub trait OutputSink {
+    /// Writes template text, unescaped values and anything else passed
+    /// through as it is.
+    fn write_str(&mut self, s: &str) -> Result<()>;
+
+    /// Writes a value the renderer already escaped with the context's
+    /// [`Escape`](enum.Escape.html) mode. Goes to `write_str` by default.
+    fn write_escaped(&mut self, s: &str) -> Result<()> {
+        self.write_str(s)
+    }
+
+    /// Called once the render is done, and not if it failed.
+    fn finish(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl OutputSink for String {
+    fn write_str(&mut self, s: &str) -> Result<()> {
+        self.push_str(s);
+        Ok(())
+    }
+}
+
+impl<S: OutputSink + ?Sized> OutputSink for &mut S {
+    fn write_str(&mut self, s: &str) -> Result<()> {
+        (**self).write_str(s)
+    }
+
+    fn write_escaped(&mut self, s: &str) -> Result<()> {
+        (**self).write_escaped(s)
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        (**self).finish()
+    }
+}
+
+// Hands what the renderer writes to a sink. The renderer sets `escaping`
+// while it writes an escaped value.
+pub struct SinkWriter<'s, S: ?Sized> {
+    pub sink: &'s mut S,
+    pub escaping: &'s Cell<bool>,
+    // The start of a character split across writes.
+    partial: Vec<u8>,
+    // What the sink failed with, handed back once the render stops.
+    pub error: Option<Error>,
+}
+
+impl<'s, S: OutputSink + ?Sized> SinkWriter<'s, S> {
+    pub fn new(sink: &'s mut S, escaping: &'s Cell<bool>) -> SinkWriter<'s, S> {
+        SinkWriter { sink, escaping, partial: Vec::new(), error: None }
+    }
+
+    fn send(&mut self, s: &str) -> io::Result<()> {
+        if s.is_empty() {
+            return Ok(());
+        }
+
+        let sent = if self.escaping.get() {
+            self.sink.write_escaped(s)
+        } else {
+            self.sink.write_str(s)
+        };
+
+        sent.map_err(|err| {
+            self.error = Some(err);
+            // Never seen, `error` is returned instead.
+            io::Error::from(io::ErrorKind::Other)
+        })
+    }
+}
+
+impl<'s, S: OutputSink + ?Sized> Write for SinkWriter<'s, S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.partial.is_empty() {
+            if let Ok(s) = str::from_utf8(buf) {
+                self.send(s)?;
+                return Ok(buf.len());
+            }
+        }
+
+        let mut bytes = mem::take(&mut self.partial);
+        bytes.extend_from_slice(buf);
+
+        match str::from_utf8(&bytes) {
+            Ok(s) => self.send(s)?,
+            // Only the end of a character, keep it for the next write.
+            Err(err) if err.error_len().is_none() => {
+                let (valid, rest) = bytes.split_at(err.valid_up_to());
+                self.send(str::from_utf8(valid).expect("valid up to here"))?;
+                self.partial = rest.to_vec();
+            }
+            Err(_) => return Err(io::Error::new(io::ErrorKind::InvalidData, "the render wrote invalid UTF-8")),
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
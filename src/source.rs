@@ -0,0 +1,70 @@
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::str::Chars;
+
+use {Context, PartialLoader, Result, Template};
+
+/// What [`Context::compile`](struct.Context.html#method.compile) and
+/// [`mustache::compile`](fn.compile.html) accept: strings, their `chars()`,
+/// files and other readers, which are decoded as they are read.
+///
+/// Any other `Iterator<Item = char>` can be passed by `&mut`.
+pub trait Source {
+    /// Compiles the source with `ctx`.
+    fn compile_with<P: PartialLoader>(self, ctx: &Context<P>) -> Result<Template<P>>;
+}
+
+impl Source for &str {
+    fn compile_with<P: PartialLoader>(self, ctx: &Context<P>) -> Result<Template<P>> {
+        ctx.compile_chars(self.chars())
+    }
+}
+
+impl Source for &String {
+    fn compile_with<P: PartialLoader>(self, ctx: &Context<P>) -> Result<Template<P>> {
+        ctx.compile_chars(self.chars())
+    }
+}
+
+impl Source for String {
+    fn compile_with<P: PartialLoader>(self, ctx: &Context<P>) -> Result<Template<P>> {
+        ctx.compile_chars(self.chars())
+    }
+}
+
+impl<'a> Source for Cow<'a, str> {
+    fn compile_with<P: PartialLoader>(self, ctx: &Context<P>) -> Result<Template<P>> {
+        ctx.compile_chars(self.chars())
+    }
+}
+
+impl<'a> Source for Chars<'a> {
+    fn compile_with<P: PartialLoader>(self, ctx: &Context<P>) -> Result<Template<P>> {
+        ctx.compile_chars(self)
+    }
+}
+
+impl<I: Iterator<Item = char>> Source for &mut I {
+    fn compile_with<P: PartialLoader>(self, ctx: &Context<P>) -> Result<Template<P>> {
+        ctx.compile_chars(self)
+    }
+}
+
+impl Source for File {
+    fn compile_with<P: PartialLoader>(self, ctx: &Context<P>) -> Result<Template<P>> {
+        ctx.compile_reader(self)
+    }
+}
+
+impl<R: Read> Source for BufReader<R> {
+    fn compile_with<P: PartialLoader>(self, ctx: &Context<P>) -> Result<Template<P>> {
+        ctx.compile_reader(self)
+    }
+}
+
+impl Source for &[u8] {
+    fn compile_with<P: PartialLoader>(self, ctx: &Context<P>) -> Result<Template<P>> {
+        ctx.compile_reader(self)
+    }
+}
@@ -0,0 +1,209 @@
+//! Running the [mustache spec](https://github.com/mustache/spec) against a
+//! configured [`Context`](../struct.Context.html), enabled with the `spec`
+//! feature, to check a custom loader or escape mode still conforms.
+//!
+//! The spec ships every YAML suite as JSON next to it, and those are what
+//! [`run`] reads. Each test gets a fresh context from a closure given the
+//! test's partials, usually wrapped in the loader being checked:
+//!
+//! ```no_run
+//! use mustache::{spec, Context, KvLoader};
+//!
+//! let results = spec::run("spec/specs", |partials| {
+//!     let partials = partials.clone();
+//!     Context::with_loader(KvLoader::new(move |key| Ok(partials.get(key).cloned())))
+//! }).unwrap();
+//!
+//! for result in results.iter().filter(|result| !result.passed()) {
+//!     println!("{}: {} {:?}", result.suite, result.name, result.outcome);
+//! }
+//! ```
+
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde_json::{self, Value};
+
+use {to_data, Context, Error, PartialLoader, Result};
+
+/// How one spec test went.
+#[derive(Debug)]
+pub enum Outcome {
+    Passed,
+    /// Rendered something other than expected, given here.
+    Failed(String),
+    /// Failed to compile or render.
+    Error(Error),
+    /// Needs lambdas written in another language, which the data can't
+    /// hold.
+    Skipped,
+}
+
+/// The result of one spec test.
+#[derive(Debug)]
+pub struct TestResult {
+    /// The file the test came from, without extension, like `sections` or
+    /// `~lambdas`.
+    pub suite: String,
+    pub name: String,
+    pub desc: String,
+    pub expected: String,
+    pub outcome: Outcome,
+}
+
+impl TestResult {
+    /// Whether the test passed, skipped tests don't.
+    pub fn passed(&self) -> bool {
+        matches!(self.outcome, Outcome::Passed)
+    }
+}
+
+/// Runs the suite at `path`, or every `.json` suite in it if it is a
+/// directory, compiling each test with the context `context` makes from the
+/// test's partials. Only reading the suites can fail, the tests themselves
+/// fail in their [`Outcome`].
+pub fn run<P, F>(path: impl AsRef<Path>, mut context: F) -> Result<Vec<TestResult>>
+where
+    P: PartialLoader,
+    F: FnMut(&HashMap<String, String>) -> Context<P>,
+{
+    let path = path.as_ref();
+    let mut suites = Vec::new();
+
+    if path.is_dir() {
+        for entry in fs::read_dir(path)? {
+            let path = entry?.path();
+            if path.extension() == Some(OsStr::new("json")) {
+                suites.push(path);
+            }
+        }
+        suites.sort();
+    } else {
+        suites.push(path.to_path_buf());
+    }
+
+    let mut results = Vec::new();
+    for suite in suites {
+        let name = suite.file_stem().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        let json: Value = serde_json::from_reader(fs::File::open(&suite)?).map_err(io::Error::from)?;
+
+        let tests = match json.get("tests") {
+            Some(Value::Array(tests)) => tests,
+            _ => return Err(invalid(&suite, "a suite holds a \"tests\" array")),
+        };
+        for test in tests {
+            results.push(run_test(&name, test, &mut context).ok_or_else(|| invalid(&suite, "malformed test"))?);
+        }
+    }
+
+    Ok(results)
+}
+
+fn invalid(suite: &Path, message: &str) -> Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", suite.display(), message)).into()
+}
+
+fn run_test<P, F>(suite: &str, test: &Value, context: &mut F) -> Option<TestResult>
+where
+    P: PartialLoader,
+    F: FnMut(&HashMap<String, String>) -> Context<P>,
+{
+    let string = |key| test.get(key).and_then(Value::as_str).map(str::to_string);
+
+    let template = string("template")?;
+    let mut result = TestResult {
+        suite: suite.to_string(),
+        name: string("name")?,
+        desc: string("desc").unwrap_or_default(),
+        expected: string("expected")?,
+        outcome: Outcome::Skipped,
+    };
+
+    let data = test.get("data").unwrap_or(&Value::Null);
+    if has_code(data) {
+        return Some(result);
+    }
+
+    let mut partials = HashMap::new();
+    if let Some(Value::Object(map)) = test.get("partials") {
+        for (name, source) in map {
+            partials.insert(name.clone(), source.as_str()?.to_string());
+        }
+    }
+
+    let rendered = to_data(data)
+        .map_err(Error::from)
+        .and_then(|data| context(&partials).compile(&template)?.render_data_to_string(&data));
+
+    result.outcome = match rendered {
+        Ok(ref actual) if *actual == result.expected => Outcome::Passed,
+        Ok(actual) => Outcome::Failed(actual),
+        Err(err) => Outcome::Error(err),
+    };
+    Some(result)
+}
+
+// Lambdas appear in the data as `{"__tag__": "code", "ruby": ..., ...}`.
+fn has_code(value: &Value) -> bool {
+    match *value {
+        Value::Object(ref map) => {
+            map.get("__tag__").and_then(Value::as_str) == Some("code") || map.values().any(has_code)
+        }
+        Value::Array(ref values) => values.iter().any(has_code),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::Write;
+
+    use tempdir::TempDir;
+
+    use super::{run, Outcome};
+    use {Context, Escape, KvLoader};
+
+    const SUITE: &str = r#"{"tests": [
+        {"name": "Tag", "desc": "d", "data": {"a": "<"}, "template": "{{a}}", "expected": "&lt;"},
+        {"name": "Partial", "data": {}, "template": "[{{>p}}]", "partials": {"p": "x"}, "expected": "[x]"},
+        {"name": "Unclosed", "data": {}, "template": "{{#a}}", "expected": ""},
+        {"name": "Lambda", "data": {"l": {"__tag__": "code", "ruby": "proc {}"}}, "template": "{{l}}", "expected": ""}
+    ]}"#;
+
+    #[test]
+    fn suites() {
+        let dir = TempDir::new("spec").unwrap();
+        File::create(dir.path().join("basics.json")).unwrap().write_all(SUITE.as_bytes()).unwrap();
+        File::create(dir.path().join("notes.txt")).unwrap();
+
+        let run_with = |escape: Escape| run(dir.path(), |partials| {
+            let partials = partials.clone();
+            let mut ctx = Context::with_loader(KvLoader::new(move |key| Ok(partials.get(key).cloned())));
+            ctx.escape = escape;
+            ctx
+        }).unwrap();
+
+        let results = run_with(Escape::Html);
+        let outcomes: Vec<_> = results.iter().map(|r| (&r.suite[..], &r.name[..], r.passed())).collect();
+        assert_eq!(outcomes, vec![
+            ("basics", "Tag", true),
+            ("basics", "Partial", true),
+            ("basics", "Unclosed", false),
+            ("basics", "Lambda", false),
+        ]);
+        assert!(matches!(results[2].outcome, Outcome::Error(_)));
+        assert!(matches!(results[3].outcome, Outcome::Skipped));
+
+        // LaTeX leaves `<` alone.
+        match run_with(Escape::Latex)[0].outcome {
+            Outcome::Failed(ref actual) => assert_eq!(actual, "<"),
+            ref other => panic!("{:?}", other),
+        }
+
+        assert!(run(dir.path().join("notes.txt"), |_| Context::new(".".into())).is_err());
+    }
+}
@@ -0,0 +1,339 @@
+//! Rendering a `Serialize` value as it is serialized, for
+//! `Template::render_streaming`.
+//!
+//! The top-level fields are converted to `Data` and kept, except for the
+//! list the streamed section goes through: the template up to the section is
+//! rendered when that list starts, then the section's content once for every
+//! item as it comes, and the rest once every field is in.
+
+use std::collections::HashMap;
+use std::io::Write;
+
+use serde::ser::{self, Impossible, Serialize};
+
+use encoder::{Encoder, Error as EncoderError};
+use parser::Token;
+use template::{Pieces, RenderOptions};
+use {Data, Error, PartialLoader, Result, Template};
+
+type EncoderResult<T> = ::std::result::Result<T, EncoderError>;
+
+pub fn render<P, W, T>(template: &Template<P>, wr: &mut W, data: &T, section: &str, options: &RenderOptions) -> Result<()>
+where P: PartialLoader,
+      W: Write,
+      T: Serialize,
+{
+    let tokens = &template.tokens[..];
+    let at = tokens.iter().position(|token| match *token {
+        Token::Section(ref path, false, _, _, _, _, _, _) => path.len() == 1 && path[0] == section,
+        _ => false,
+    });
+    let at = match at {
+        Some(at) => at,
+        None => return Err(Error::NoSuchSection(section.to_string())),
+    };
+
+    let mut stream = Stream {
+        pieces: Pieces::new(template, options),
+        wr,
+        tokens,
+        at,
+        section,
+        data: Data::Map(HashMap::new()),
+        key: None,
+        started: false,
+        items: false,
+        error: None,
+    };
+
+    let serialized = data.serialize(&mut stream);
+    if let Some(err) = stream.error.take() {
+        return Err(err);
+    }
+    serialized?;
+
+    // The rest of the template sees every field, and only whether the list
+    // had items since they are gone.
+    if let (true, Data::Map(ref mut map)) = (stream.started, &mut stream.data) {
+        let list = if stream.items { Data::Bool(true) } else { Data::Vec(Vec::new()) };
+        map.insert(section.to_string(), list);
+    }
+    let rest = if stream.started { &tokens[at + 1..] } else { tokens };
+    stream.pieces.render(stream.wr, &stream.data, rest)
+}
+
+struct Stream<'s, 'a, P: PartialLoader, W> {
+    pieces: Pieces<'a, P>,
+    wr: &'s mut W,
+    tokens: &'a [Token],
+    // Where the streamed section is in `tokens`.
+    at: usize,
+    section: &'s str,
+    // The fields so far, in a map.
+    data: Data,
+    // The key of the map entry being serialized.
+    key: Option<String>,
+    // Whether the streamed list started, and had any items.
+    started: bool,
+    items: bool,
+    // What failed the render, which the serializer can only be told of.
+    error: Option<Error>,
+}
+
+impl<'s, 'a, P: PartialLoader, W: Write> Stream<'s, 'a, P, W> {
+    fn field<T: Serialize + ?Sized>(&mut self, key: String, value: &T) -> EncoderResult<()> {
+        if key == self.section && !self.started {
+            return value.serialize(Rows(self));
+        }
+
+        let value = value.serialize(Encoder::new())?;
+        if let Data::Map(ref mut map) = self.data {
+            map.insert(key, value);
+        }
+        Ok(())
+    }
+
+    // Renders the template up to the streamed section.
+    fn start(&mut self) -> EncoderResult<()> {
+        self.started = true;
+
+        let rendered = self.pieces.render(self.wr, &self.data, &self.tokens[..self.at]);
+        self.failed(rendered)
+    }
+
+    fn item<T: Serialize + ?Sized>(&mut self, value: &T) -> EncoderResult<()> {
+        self.items = true;
+        let item = value.serialize(Encoder::new())?;
+        let children = match self.tokens[self.at] {
+            Token::Section(_, _, ref children, _, _, _, _, _) => &children[..],
+            _ => &[],
+        };
+
+        let rendered = self.pieces.render_item(self.wr, &self.data, &item, children);
+        self.failed(rendered)
+    }
+
+    // Keeps the error of a failed render for `render` to return, stopping
+    // the serializer with one of its own.
+    fn failed(&mut self, rendered: Result<()>) -> EncoderResult<()> {
+        rendered.map_err(|err| {
+            self.error = Some(err);
+            EncoderError::Message("the render failed".to_string())
+        })
+    }
+}
+
+macro_rules! unsupported {
+    ($($name:ident($($ty:ty),*) -> $ok:ty;)*) => {
+        $(
+            fn $name(self, $(_: $ty),*) -> EncoderResult<$ok> {
+                Err(EncoderError::UnsupportedType)
+            }
+        )*
+    };
+}
+
+macro_rules! unsupported_scalars {
+    () => {
+        unsupported! {
+            serialize_bool(bool) -> ();
+            serialize_i8(i8) -> ();
+            serialize_i16(i16) -> ();
+            serialize_i32(i32) -> ();
+            serialize_i64(i64) -> ();
+            serialize_u8(u8) -> ();
+            serialize_u16(u16) -> ();
+            serialize_u32(u32) -> ();
+            serialize_u64(u64) -> ();
+            serialize_f32(f32) -> ();
+            serialize_f64(f64) -> ();
+            serialize_char(char) -> ();
+            serialize_str(&str) -> ();
+            serialize_bytes(&[u8]) -> ();
+            serialize_unit_struct(&'static str) -> ();
+            serialize_unit_variant(&'static str, u32, &'static str) -> ();
+            serialize_tuple_variant(&'static str, u32, &'static str, usize) -> Self::SerializeTupleVariant;
+            serialize_struct_variant(&'static str, u32, &'static str, usize) -> Self::SerializeStructVariant;
+        }
+
+        fn serialize_newtype_variant<T: Serialize + ?Sized>(self,
+                                                            _: &'static str,
+                                                            _: u32,
+                                                            _: &'static str,
+                                                            _: &T) -> EncoderResult<()> {
+            Err(EncoderError::UnsupportedType)
+        }
+    };
+}
+
+// The data the template is rendered with, which has to be a map.
+impl<'r, 's, 'a, P: PartialLoader, W: Write> ser::Serializer for &'r mut Stream<'s, 'a, P, W> {
+    type Ok = ();
+    type Error = EncoderError;
+
+    type SerializeSeq = Impossible<(), EncoderError>;
+    type SerializeTuple = Impossible<(), EncoderError>;
+    type SerializeTupleStruct = Impossible<(), EncoderError>;
+    type SerializeTupleVariant = Impossible<(), EncoderError>;
+    type SerializeMap = Self;
+    type SerializeStruct = Self;
+    type SerializeStructVariant = Impossible<(), EncoderError>;
+
+    unsupported_scalars!();
+
+    unsupported! {
+        serialize_unit() -> ();
+        serialize_none() -> ();
+        serialize_seq(Option<usize>) -> Self::SerializeSeq;
+        serialize_tuple(usize) -> Self::SerializeTuple;
+        serialize_tuple_struct(&'static str, usize) -> Self::SerializeTupleStruct;
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> EncoderResult<()> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _: &'static str, value: &T) -> EncoderResult<()> {
+        value.serialize(self)
+    }
+
+    fn serialize_map(self, _: Option<usize>) -> EncoderResult<Self> {
+        Ok(self)
+    }
+
+    fn serialize_struct(self, _: &'static str, _: usize) -> EncoderResult<Self> {
+        Ok(self)
+    }
+}
+
+impl<'r, 's, 'a, P: PartialLoader, W: Write> ser::SerializeStruct for &'r mut Stream<'s, 'a, P, W> {
+    type Ok = ();
+    type Error = EncoderError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> EncoderResult<()> {
+        self.field(key.to_string(), value)
+    }
+
+    fn end(self) -> EncoderResult<()> {
+        Ok(())
+    }
+}
+
+impl<'r, 's, 'a, P: PartialLoader, W: Write> ser::SerializeMap for &'r mut Stream<'s, 'a, P, W> {
+    type Ok = ();
+    type Error = EncoderError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> EncoderResult<()> {
+        match key.serialize(Encoder::new())? {
+            Data::String(key) => {
+                self.key = Some(key);
+                Ok(())
+            }
+            _ => Err(EncoderError::KeyIsNotString),
+        }
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> EncoderResult<()> {
+        match self.key.take() {
+            Some(key) => self.field(key, value),
+            None => Err(EncoderError::MissingElements),
+        }
+    }
+
+    fn end(self) -> EncoderResult<()> {
+        Ok(())
+    }
+}
+
+// The list the streamed section goes through. A missing list is empty.
+struct Rows<'r, 's, 'a, P: PartialLoader, W>(&'r mut Stream<'s, 'a, P, W>);
+
+impl<'r, 's, 'a, P: PartialLoader, W: Write> ser::Serializer for Rows<'r, 's, 'a, P, W> {
+    type Ok = ();
+    type Error = EncoderError;
+
+    type SerializeSeq = Self;
+    type SerializeTuple = Self;
+    type SerializeTupleStruct = Self;
+    type SerializeTupleVariant = Impossible<(), EncoderError>;
+    type SerializeMap = Impossible<(), EncoderError>;
+    type SerializeStruct = Impossible<(), EncoderError>;
+    type SerializeStructVariant = Impossible<(), EncoderError>;
+
+    unsupported_scalars!();
+
+    unsupported! {
+        serialize_map(Option<usize>) -> Self::SerializeMap;
+        serialize_struct(&'static str, usize) -> Self::SerializeStruct;
+    }
+
+    fn serialize_unit(self) -> EncoderResult<()> {
+        self.0.start()
+    }
+
+    fn serialize_none(self) -> EncoderResult<()> {
+        self.0.start()
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> EncoderResult<()> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _: &'static str, value: &T) -> EncoderResult<()> {
+        value.serialize(self)
+    }
+
+    fn serialize_seq(self, _: Option<usize>) -> EncoderResult<Self> {
+        self.0.start()?;
+        Ok(self)
+    }
+
+    fn serialize_tuple(self, _: usize) -> EncoderResult<Self> {
+        self.0.start()?;
+        Ok(self)
+    }
+
+    fn serialize_tuple_struct(self, _: &'static str, _: usize) -> EncoderResult<Self> {
+        self.0.start()?;
+        Ok(self)
+    }
+}
+
+impl<'r, 's, 'a, P: PartialLoader, W: Write> ser::SerializeSeq for Rows<'r, 's, 'a, P, W> {
+    type Ok = ();
+    type Error = EncoderError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> EncoderResult<()> {
+        self.0.item(value)
+    }
+
+    fn end(self) -> EncoderResult<()> {
+        Ok(())
+    }
+}
+
+impl<'r, 's, 'a, P: PartialLoader, W: Write> ser::SerializeTuple for Rows<'r, 's, 'a, P, W> {
+    type Ok = ();
+    type Error = EncoderError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> EncoderResult<()> {
+        self.0.item(value)
+    }
+
+    fn end(self) -> EncoderResult<()> {
+        Ok(())
+    }
+}
+
+impl<'r, 's, 'a, P: PartialLoader, W: Write> ser::SerializeTupleStruct for Rows<'r, 's, 'a, P, W> {
+    type Ok = ();
+    type Error = EncoderError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> EncoderResult<()> {
+        self.0.item(value)
+    }
+
+    fn end(self) -> EncoderResult<()> {
+        Ok(())
+    }
+}
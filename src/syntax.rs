@@ -0,0 +1,610 @@
+//! A lossless view of a template's source, for editors and other tooling.
+
+use std::collections::HashMap;
+
+use parser::{Error, Extensions, Parser};
+
+/// A range of bytes in a template's source.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// A node of the tree returned by [`parse_tree`](fn.parse_tree.html).
+///
+/// Names are given as written in the tag, with surrounding whitespace
+/// removed.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Node {
+    /// Text copied to the output as is.
+    Text(Span),
+    /// Whitespace around a tag that is left out of the output, such as the
+    /// indentation and newline of a standalone tag.
+    Whitespace(Span),
+    /// A `{{! comment }}`.
+    Comment(Span),
+    /// A `{{name}}`, or a `{{{name}}}` or `{{& name}}` if not `escaped`.
+    Variable { span: Span, name: String, escaped: bool },
+    /// A `{{#name}}` or `{{^name}}` section, with the spans of its opening
+    /// and closing tags.
+    Section { name: String, inverted: bool, open: Span, children: Vec<Node>, close: Span },
+    /// A `{{> name}}`.
+    Partial { span: Span, name: String },
+    /// A `{{> name}}` closed like a section, with the spans of its opening
+    /// and closing tags and the fallback between them.
+    PartialWithFallback { name: String, open: Span, children: Vec<Node>, close: Span },
+    /// A `{{<*name}}` partial defined inline, with the spans of its opening
+    /// and closing tags.
+    Definition { name: String, open: Span, children: Vec<Node>, close: Span },
+    /// A `{{=open close=}}`.
+    Delimiters { span: Span, open: String, close: String },
+    /// A `{{{{name}}}}` block written out as it is, with the spans of its
+    /// opening and closing tags and the text and whitespace between them.
+    Raw { name: String, open: Span, children: Vec<Node>, close: Span },
+}
+
+impl Node {
+    /// The bytes the node covers, including any children.
+    pub fn span(&self) -> Span {
+        match *self {
+            Node::Text(span) | Node::Whitespace(span) | Node::Comment(span) => span,
+            Node::Variable { span, .. } |
+            Node::Partial { span, .. } |
+            Node::Delimiters { span, .. } => span,
+            Node::Section { open, close, .. } |
+            Node::PartialWithFallback { open, close, .. } |
+            Node::Definition { open, close, .. } |
+            Node::Raw { open, close, .. } => Span { start: open.start, end: close.end },
+        }
+    }
+}
+
+/// A tag or stripped whitespace, as seen by the parser.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Lexeme {
+    Whitespace,
+    Comment(String),
+    Variable(String, bool),
+    Open(String, bool),
+    Define(String),
+    OpenRaw(String),
+    Close,
+    Partial(String),
+    ClosePartial(String),
+    Delimiters(String, String),
+}
+
+/// Parses `source` into a tree that covers every one of its bytes, in order.
+///
+/// Unlike compiling, this keeps comments, delimiter changes and the
+/// whitespace standalone tags strip, so the source can be rebuilt from the
+/// spans alone.
+///
+/// ```rust
+/// use mustache::{Extensions, Node, Span};
+///
+/// let tree = mustache::parse_tree("Hi {{! who? }}{{name}}", Extensions::default()).unwrap();
+/// assert_eq!(tree, vec![
+///     Node::Text(Span { start: 0, end: 3 }),
+///     Node::Comment(Span { start: 3, end: 14 }),
+///     Node::Variable { span: Span { start: 14, end: 22 }, name: "name".to_string(), escaped: true },
+/// ]);
+/// ```
+pub fn parse_tree(source: &str, extensions: Extensions) -> Result<Vec<Node>, Error> {
+    Ok(build(source, lex(source, extensions)?, &mut Vec::new()))
+}
+
+/// An error found by [`parse_tree_lenient`](fn.parse_tree_lenient.html),
+/// and the bytes it is about.
+#[derive(Debug, PartialEq)]
+pub struct Diagnostic {
+    pub span: Span,
+    pub error: Error,
+}
+
+/// Parses `source` like [`parse_tree`](fn.parse_tree.html), carrying on past
+/// errors to report all of them at once, in order, for editors and batch
+/// linting.
+///
+/// The tree is still lossless. A tag in error is taken as text, and so is
+/// the opening tag of a section that is never closed, with what it holds
+/// moved up a level.
+///
+/// ```rust
+/// use mustache::{Extensions, Node, ParserError, Span};
+///
+/// let (tree, diagnostics) = mustache::parse_tree_lenient("{{#a}}{{}}{{b}}", Extensions::default());
+/// assert_eq!(tree, vec![
+///     Node::Text(Span { start: 0, end: 6 }),
+///     Node::Text(Span { start: 6, end: 10 }),
+///     Node::Variable { span: Span { start: 10, end: 15 }, name: "b".to_string(), escaped: true },
+/// ]);
+/// assert_eq!(diagnostics[0].error, ParserError::UnclosedSection("a".to_string()));
+/// assert_eq!(diagnostics[1].error, ParserError::EmptyTag);
+/// ```
+pub fn parse_tree_lenient(source: &str, extensions: Extensions) -> (Vec<Node>, Vec<Diagnostic>) {
+    let mut chars = source.chars();
+    let (mut lexemes, errors) = Parser::new(&mut chars, "{{", "}}", extensions).parse_lexemes_lenient();
+    lexemes.sort_by_key(|&(span, _)| span.start);
+
+    let mut diagnostics: Vec<_> = errors.into_iter().map(|(span, error)| Diagnostic { span, error }).collect();
+    let nodes = build(source, lexemes, &mut diagnostics);
+    diagnostics.sort_by_key(|diagnostic| diagnostic.span.start);
+    (nodes, diagnostics)
+}
+
+/// What a span of a template is, as given by [`classify`](fn.classify.html).
+///
+/// This type is not intended to be matched exhaustively as new variants
+/// may be added in future without a version bump.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[allow(clippy::manual_non_exhaustive)]
+pub enum Class {
+    Text,
+    /// Whitespace around a tag that is left out of the output.
+    Whitespace,
+    Comment,
+    /// A `{{name}}`, or a `{{/pointer}}` with the `json_pointers` extension.
+    Variable,
+    /// A `{{{name}}}` or `{{& name}}`.
+    UnescapedVariable,
+    SectionOpen,
+    InvertedSectionOpen,
+    /// A `{{<*name}}` opening an inline partial.
+    DefinitionOpen,
+    /// A `{{{{name}}}}` opening a raw block.
+    RawOpen,
+    /// The closing tag of a section, definition, raw block or partial with
+    /// a fallback.
+    Close,
+    /// A `{{> name}}`, closed or not.
+    Partial,
+    Delimiters,
+    /// A tag the parser found an error in.
+    Error,
+
+    #[doc(hidden)]
+    __Nonexhaustive,
+}
+
+/// Splits `source` into classified spans covering all of it in order, to
+/// drive syntax highlighting. Errors don't stop it: tags in error are
+/// classified as [`Class::Error`](enum.Class.html#variant.Error), see
+/// [`parse_tree_lenient`](fn.parse_tree_lenient.html) for what they are.
+///
+/// ```rust
+/// use mustache::{Class, Extensions, Span};
+///
+/// let spans = mustache::classify("{{#a}}Hi {{{b}}}{{/a}}", Extensions::default());
+/// assert_eq!(spans, vec![
+///     (Span { start: 0, end: 6 }, Class::SectionOpen),
+///     (Span { start: 6, end: 9 }, Class::Text),
+///     (Span { start: 9, end: 16 }, Class::UnescapedVariable),
+///     (Span { start: 16, end: 22 }, Class::Close),
+/// ]);
+/// ```
+pub fn classify(source: &str, extensions: Extensions) -> Vec<(Span, Class)> {
+    let (nodes, diagnostics) = parse_tree_lenient(source, extensions);
+    let mut errors: Vec<Span> = diagnostics.into_iter().map(|diagnostic| diagnostic.span).collect();
+    errors.dedup();
+
+    let mut spans = Vec::new();
+    classify_nodes(&nodes, &errors, &mut spans);
+    spans
+}
+
+fn classify_nodes(nodes: &[Node], errors: &[Span], spans: &mut Vec<(Span, Class)>) {
+    for node in nodes {
+        let class = match *node {
+            Node::Text(span) => {
+                classify_text(span, errors, spans);
+                continue;
+            }
+            Node::Whitespace(_) => Class::Whitespace,
+            Node::Comment(_) => Class::Comment,
+            Node::Variable { escaped: true, .. } => Class::Variable,
+            Node::Variable { escaped: false, .. } => Class::UnescapedVariable,
+            Node::Partial { .. } | Node::PartialWithFallback { .. } => Class::Partial,
+            Node::Delimiters { .. } => Class::Delimiters,
+            Node::Section { inverted: false, .. } => Class::SectionOpen,
+            Node::Section { inverted: true, .. } => Class::InvertedSectionOpen,
+            Node::Definition { .. } => Class::DefinitionOpen,
+            Node::Raw { .. } => Class::RawOpen,
+        };
+
+        match *node {
+            Node::Section { open, ref children, close, .. } |
+            Node::PartialWithFallback { open, ref children, close, .. } |
+            Node::Definition { open, ref children, close, .. } |
+            Node::Raw { open, ref children, close, .. } => {
+                spans.push((open, class));
+                classify_nodes(children, errors, spans);
+                spans.push((close, Class::Close));
+            }
+            ref node => spans.push((node.span(), class)),
+        }
+    }
+}
+
+// Splits text around the tags in error inside it, which a lenient parse
+// takes as text.
+fn classify_text(span: Span, errors: &[Span], spans: &mut Vec<(Span, Class)>) {
+    let mut pos = span.start;
+    for &error in errors {
+        if error.start < pos || error.end > span.end {
+            continue;
+        }
+        if error.start > pos {
+            spans.push((Span { start: pos, end: error.start }, Class::Text));
+        }
+        spans.push((error, Class::Error));
+        pos = error.end;
+    }
+
+    if span.end > pos {
+        spans.push((Span { start: pos, end: span.end }, Class::Text));
+    }
+}
+
+/// A `{{! comment }}`, found by [`comments`](fn.comments.html).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Comment {
+    pub span: Span,
+    /// What the comment says, without its delimiters and the whitespace
+    /// around it.
+    pub text: String,
+    /// The tag right after the comment, if only whitespace and other
+    /// comments come between them.
+    pub tag: Option<Node>,
+}
+
+/// Every comment in `source` in order, sections included, with the tag each
+/// one comes before, for documenting templates from annotations.
+///
+/// ```rust
+/// use mustache::{Extensions, Node};
+///
+/// let source = "{{! doc: who to greet }}\n{{name}}\n{{! todo }}\nBye";
+/// let comments = mustache::comments(source, Extensions::default()).unwrap();
+///
+/// assert_eq!(comments[0].text, "doc: who to greet");
+/// assert!(matches!(comments[0].tag, Some(Node::Variable { ref name, .. }) if name == "name"));
+/// assert_eq!(comments[1].tag, None);
+/// ```
+pub fn comments(source: &str, extensions: Extensions) -> Result<Vec<Comment>, Error> {
+    let lexemes = lex(source, extensions)?;
+    let texts: HashMap<usize, String> = lexemes
+        .iter()
+        .filter_map(|&(span, ref lexeme)| match *lexeme {
+            Lexeme::Comment(ref text) => Some((span.start, text.clone())),
+            _ => None,
+        })
+        .collect();
+
+    let mut comments = Vec::new();
+    collect_comments(source, &build(source, lexemes, &mut Vec::new()), &texts, &mut comments);
+    Ok(comments)
+}
+
+fn collect_comments(source: &str, nodes: &[Node], texts: &HashMap<usize, String>, comments: &mut Vec<Comment>) {
+    for (i, node) in nodes.iter().enumerate() {
+        match *node {
+            Node::Comment(span) => {
+                let next = nodes[i + 1..].iter().find(|node| match **node {
+                    Node::Whitespace(_) | Node::Comment(_) => false,
+                    Node::Text(span) => !source[span.start..span.end].trim().is_empty(),
+                    _ => true,
+                });
+                comments.push(Comment {
+                    span,
+                    text: texts.get(&span.start).cloned().unwrap_or_default(),
+                    tag: next.filter(|node| !matches!(**node, Node::Text(_))).cloned(),
+                });
+            }
+            Node::Section { ref children, .. } |
+            Node::PartialWithFallback { ref children, .. } |
+            Node::Definition { ref children, .. } => collect_comments(source, children, texts, comments),
+            _ => {}
+        }
+    }
+}
+
+fn lex(source: &str, extensions: Extensions) -> Result<Vec<(Span, Lexeme)>, Error> {
+    let mut chars = source.chars();
+    let mut lexemes = Parser::new(&mut chars, "{{", "}}", extensions).parse_lexemes()?;
+    lexemes.sort_by_key(|&(span, _)| span.start);
+    Ok(lexemes)
+}
+
+fn build(source: &str, lexemes: Vec<(Span, Lexeme)>, diagnostics: &mut Vec<Diagnostic>) -> Vec<Node> {
+    // Sections and definitions being filled in, innermost last, above the top
+    // level nodes.
+    let mut open: Vec<(Lexeme, Span, Vec<Node>)> = Vec::new();
+    let mut nodes = Vec::new();
+    let mut pos = 0;
+
+    for (span, lexeme) in lexemes {
+        let current = match open.last_mut() {
+            Some(&mut (_, _, ref mut children)) => children,
+            None => &mut nodes,
+        };
+
+        if span.start > pos {
+            current.push(Node::Text(Span { start: pos, end: span.start }));
+        }
+        pos = span.end;
+
+        match lexeme {
+            Lexeme::Whitespace => current.push(Node::Whitespace(span)),
+            Lexeme::Comment(_) => current.push(Node::Comment(span)),
+            Lexeme::Variable(name, escaped) => current.push(Node::Variable { span, name, escaped }),
+            Lexeme::Partial(name) => current.push(Node::Partial { span, name }),
+            Lexeme::Delimiters(open, close) => current.push(Node::Delimiters { span, open, close }),
+            lexeme @ Lexeme::Open(..) | lexeme @ Lexeme::Define(_) | lexeme @ Lexeme::OpenRaw(_) => open.push((lexeme, span, Vec::new())),
+            Lexeme::Close => {
+                // The parser already checked that sections are balanced.
+                let (lexeme, open_span, children) = match open.pop() {
+                    Some(opened) => opened,
+                    None => continue,
+                };
+                let node = match lexeme {
+                    Lexeme::Define(name) => Node::Definition { name, open: open_span, children, close: span },
+                    Lexeme::OpenRaw(name) => Node::Raw { name, open: open_span, children, close: span },
+                    Lexeme::Open(name, inverted) => {
+                        Node::Section { name, inverted, open: open_span, children, close: span }
+                    }
+                    _ => continue,
+                };
+                match open.last_mut() {
+                    Some(&mut (_, _, ref mut children)) => children.push(node),
+                    None => nodes.push(node),
+                }
+            }
+            Lexeme::ClosePartial(closed) => {
+                // The fallback is everything after the latest partial of that
+                // name at this level.
+                let at = current.iter().rposition(|node| matches!(*node, Node::Partial { ref name, .. } if *name == closed));
+                if let Some(at) = at {
+                    let children = current.split_off(at + 1);
+                    if let Some(Node::Partial { span: open, name }) = current.pop() {
+                        current.push(Node::PartialWithFallback { name, open, children, close: span });
+                    }
+                }
+            }
+        }
+    }
+
+    if source.len() > pos {
+        let current = match open.last_mut() {
+            Some(&mut (_, _, ref mut children)) => children,
+            None => &mut nodes,
+        };
+        current.push(Node::Text(Span { start: pos, end: source.len() }));
+    }
+
+    // Only a lenient parse leaves sections open. Their opening tags are
+    // taken as text, and what they hold goes to their parent.
+    while let Some((lexeme, span, children)) = open.pop() {
+        let name = match lexeme {
+            Lexeme::Open(name, _) | Lexeme::Define(name) | Lexeme::OpenRaw(name) => name,
+            _ => String::new(),
+        };
+        diagnostics.push(Diagnostic { span, error: Error::UnclosedSection(name) });
+
+        let parent = match open.last_mut() {
+            Some(&mut (_, _, ref mut children)) => children,
+            None => &mut nodes,
+        };
+        parent.push(Node::Text(span));
+        parent.extend(children);
+    }
+
+    nodes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree(source: &str) -> Vec<Node> {
+        parse_tree(source, Extensions::default()).expect("Failed to parse")
+    }
+
+    fn span(start: usize, end: usize) -> Span {
+        Span { start, end }
+    }
+
+    // Every byte should be covered exactly once, in order.
+    fn assert_lossless(source: &str) {
+        assert_lossless_nodes(source, &tree(source));
+    }
+
+    fn assert_lossless_nodes(source: &str, nodes: &[Node]) {
+        fn collect(nodes: &[Node], spans: &mut Vec<Span>) {
+            for node in nodes {
+                match *node {
+                    Node::Section { open, ref children, close, .. } |
+                    Node::PartialWithFallback { open, ref children, close, .. } |
+                    Node::Definition { open, ref children, close, .. } |
+                    Node::Raw { open, ref children, close, .. } => {
+                        spans.push(open);
+                        collect(children, spans);
+                        spans.push(close);
+                    }
+                    ref node => spans.push(node.span()),
+                }
+            }
+        }
+
+        let mut spans = Vec::new();
+        collect(nodes, &mut spans);
+
+        let mut pos = 0;
+        for span in spans {
+            assert_eq!(span.start, pos, "gap or overlap in {:?}", source);
+            pos = span.end;
+        }
+        assert_eq!(pos, source.len());
+    }
+
+    #[test]
+    fn empty() {
+        assert_eq!(tree(""), vec![]);
+    }
+
+    #[test]
+    fn standalone_whitespace() {
+        assert_eq!(tree("a\n  {{#b}}\n{{c}}\n{{/b}}\n"), vec![
+            Node::Text(span(0, 2)),
+            Node::Whitespace(span(2, 4)),
+            Node::Section {
+                name: "b".to_string(),
+                inverted: false,
+                open: span(4, 10),
+                children: vec![
+                    Node::Whitespace(span(10, 11)),
+                    Node::Variable { span: span(11, 16), name: "c".to_string(), escaped: true },
+                    Node::Text(span(16, 17)),
+                ],
+                close: span(17, 23),
+            },
+            Node::Whitespace(span(23, 24)),
+        ]);
+    }
+
+    #[test]
+    fn delimiters_and_partials() {
+        assert_eq!(tree("{{=<% %>=}}<%> p %><%{x}%>"), vec![
+            Node::Delimiters { span: span(0, 11), open: "<%".to_string(), close: "%>".to_string() },
+            Node::Partial { span: span(11, 19), name: "p".to_string() },
+            Node::Variable { span: span(19, 26), name: "x".to_string(), escaped: false },
+        ]);
+    }
+
+    #[test]
+    fn partial_fallbacks() {
+        let extensions = Extensions { partial_fallbacks: true, ..Extensions::default() };
+        let source = "{{>a}}{{>b}}{{ x }}{{/a}}";
+        assert_eq!(parse_tree(source, extensions).unwrap(), vec![
+            Node::PartialWithFallback {
+                name: "a".to_string(),
+                open: span(0, 6),
+                children: vec![
+                    Node::Partial { span: span(6, 12), name: "b".to_string() },
+                    Node::Variable { span: span(12, 19), name: "x".to_string(), escaped: true },
+                ],
+                close: span(19, 25),
+            },
+        ]);
+    }
+
+    #[test]
+    fn definitions() {
+        let extensions = Extensions { inline_partials: true, ..Extensions::default() };
+        assert_eq!(parse_tree("{{<*a}}x{{/a}}", extensions).unwrap(), vec![
+            Node::Definition {
+                name: "a".to_string(),
+                open: span(0, 7),
+                children: vec![Node::Text(span(7, 8))],
+                close: span(8, 14),
+            },
+        ]);
+    }
+
+    #[test]
+    fn raw_blocks() {
+        let extensions = Extensions { raw_blocks: true, ..Extensions::default() };
+        let source = "{{{{raw}}}}\n{{x}}\n{{{{/raw}}}}\n";
+        assert_eq!(parse_tree(source, extensions).unwrap(), vec![
+            Node::Raw {
+                name: "raw".to_string(),
+                open: span(0, 11),
+                children: vec![Node::Whitespace(span(11, 12)), Node::Text(span(12, 18))],
+                close: span(18, 30),
+            },
+            Node::Whitespace(span(30, 31)),
+        ]);
+    }
+
+    #[test]
+    fn comments() {
+        let source = "{{! a }}\n  {{!b}}\n  {{#s}}{{! c }} {{> p}}{{/s}}{{!d}} text {{x}}{{!\n e\n}}";
+        let comments = super::comments(source, Extensions::default()).unwrap();
+
+        let section = tree(source).into_iter().find(|node| matches!(*node, Node::Section { .. })).unwrap();
+        let partial = Node::Partial { span: span(35, 42), name: "p".to_string() };
+        assert_eq!(comments, vec![
+            Comment { span: span(0, 8), text: "a".to_string(), tag: Some(section.clone()) },
+            Comment { span: span(11, 17), text: "b".to_string(), tag: Some(section) },
+            Comment { span: span(26, 34), text: "c".to_string(), tag: Some(partial) },
+            Comment { span: span(48, 54), text: "d".to_string(), tag: None },
+            Comment { span: span(65, 74), text: "e".to_string(), tag: None },
+        ]);
+    }
+
+    #[test]
+    fn lenient() {
+        let source = "a {{#s}}{{/t}} {{}} {{=x=}} {{x}?y {{#u}}{{/s}}\n{{/u}}{{#v}} {{unclosed";
+        let (nodes, diagnostics) = parse_tree_lenient(source, Extensions::default());
+
+        let errors: Vec<_> = diagnostics.iter().map(|d| (d.span, &d.error)).collect();
+        assert_eq!(errors, vec![
+            (span(2, 8), &Error::UnclosedSection("s".to_string())),
+            (span(8, 14), &Error::UnclosedSection("s".to_string())),
+            (span(15, 19), &Error::EmptyTag),
+            (span(20, 27), &Error::MissingSetDelimeterClosingTag),
+            (span(28, 32), &Error::BadClosingTag('?', '}')),
+            (span(41, 47), &Error::UnclosedSection("u".to_string())),
+            (span(54, 60), &Error::UnclosedSection("v".to_string())),
+            (span(61, 71), &Error::UnclosedTag),
+        ]);
+
+        assert_eq!(nodes[1], Node::Text(span(2, 8)));
+        assert_eq!(nodes[nodes.len() - 1], Node::Text(span(60, 71)));
+        assert!(nodes.iter().any(|node| matches!(*node, Node::Section { ref name, .. } if name == "u")));
+        assert_lossless_nodes(source, &nodes);
+    }
+
+    #[test]
+    fn classified() {
+        let extensions = Extensions { raw_blocks: true, inline_partials: true, ..Extensions::default() };
+        let source = "{{! c }}\n{{^a}}{{b}}{{/a}}{{<*p}}{{/p}}{{> p}}{{{{r}}}}x{{{{/r}}}}{{=| |=}}| |x}";
+        let spans: Vec<_> = classify(source, extensions).into_iter().map(|(span, class)| (&source[span.start..span.end], class)).collect();
+        assert_eq!(spans, vec![
+            ("{{! c }}", Class::Comment),
+            ("\n", Class::Whitespace),
+            ("{{^a}}", Class::InvertedSectionOpen),
+            ("{{b}}", Class::Variable),
+            ("{{/a}}", Class::Close),
+            ("{{<*p}}", Class::DefinitionOpen),
+            ("{{/p}}", Class::Close),
+            ("{{> p}}", Class::Partial),
+            ("{{{{r}}}}", Class::RawOpen),
+            ("x", Class::Text),
+            ("{{{{/r}}}}", Class::Close),
+            ("{{=| |=}}", Class::Delimiters),
+            ("| |", Class::Error),
+            ("x}", Class::Text),
+        ]);
+    }
+
+    #[test]
+    fn lossless() {
+        assert_lossless("héllo {{wörld}} {{!c}}\n");
+        assert_lossless("  {{#a}}  \r\n {{> b}}\n\t{{^c}}{{{d}}}{{/c}}\n{{/a}}");
+        assert_lossless("{{=| |=}}\n|#x|\n  |= {{ }} =|\n{{/x}}\n");
+
+        let source = "a  {{- b -}}  c\n  {{-! d}}";
+        let nodes = parse_tree(source, Extensions { trim_markers: true, ..Extensions::default() }).unwrap();
+        assert_eq!(nodes, vec![
+            Node::Text(span(0, 1)),
+            Node::Whitespace(span(1, 3)),
+            Node::Variable { span: span(3, 12), name: "b".to_string(), escaped: true },
+            Node::Whitespace(span(12, 14)),
+            Node::Text(span(14, 15)),
+            Node::Whitespace(span(15, 18)),
+            Node::Comment(span(18, 26)),
+        ]);
+    }
+}
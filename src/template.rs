@@ -1,35 +1,610 @@
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::cell::{Cell, OnceCell, RefCell};
+use std::cmp;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+#[cfg(any(feature = "tokio", feature = "futures"))]
+use std::future::Future;
 use std::mem;
-use std::io::Write;
+use std::ops::{Range, RangeInclusive};
+use std::panic::{self, AssertUnwindSafe};
+use std::io::{self, Write};
+use std::iter::Peekable;
+use std::slice;
 use std::str;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
 
-use compiler::Compiler;
+#[cfg(any(feature = "tokio", feature = "futures"))]
+use async_write;
+use compiler::{self, Compiler, PartialsMap};
+use diff::{self, Splice};
+#[cfg(feature = "yaml")]
+use front_matter;
 // for bug!
+use locale::Locale;
 use log::{log, error};
-use parser::Token;
+use metrics::Metrics;
+use parser::{self, Bounds, CompareOp, Comparison, Operand, Token};
+use print;
+use schema;
+#[cfg(feature = "ammonia")]
+use sanitize::Sanitizer;
 use serde::Serialize;
+#[cfg(feature = "yaml")]
+use serde::de::DeserializeOwned;
 
-use super::{Context, PartialLoader, Data, Error, Result, to_data};
+use sink::{OutputSink, SinkWriter};
+use stream;
+use super::{Context, PartialLoader, Data, DataIter, Error, Result, Warning, to_data};
+use validate::{self, Missing, Usage};
 
 /// `Template` represents a compiled mustache file.
 #[derive(Debug, Clone)]
 pub struct Template<P: PartialLoader> {
-    ctx: Context<P>,
-    tokens: Vec<Token>,
-    partials: HashMap<String, Vec<Token>>,
+    // Shared so cloning a template doesn't clone its loader.
+    pub(crate) ctx: Arc<Context<P>>,
+    pub(crate) tokens: Vec<Token>,
+    pub(crate) partials: PartialsMap,
+    size_hint: usize,
+    // The whole output when the template has no tags.
+    text: Option<String>,
+    // Whether the template is only text and variables.
+    flat: bool,
+    // The YAML of the front matter taken off the template's file.
+    pub(crate) front_matter: Option<String>,
 }
 
 /// Construct a `Template`. This is not part of the impl of Template so it is
 /// not exported outside of mustache.
-pub fn new<P: PartialLoader>(ctx: Context<P>, tokens: Vec<Token>, partials: HashMap<String, Vec<Token>>) -> Template<P> {
+pub fn new<P: PartialLoader>(ctx: Arc<Context<P>>, tokens: Vec<Token>, partials: PartialsMap) -> Template<P> {
+    let size_hint = estimate(&tokens, &partials, &mut Vec::new(), &mut HashMap::new());
+    let text = static_text(&tokens);
+    let flat = tokens.iter().all(|token| {
+        matches!(*token, Token::Text(..) | Token::Raw(..) | Token::EscapedTag(..) | Token::UnescapedTag(..) | Token::Deprecated(_))
+    });
+
     Template {
-        ctx: ctx,
-        tokens: tokens,
-        partials: partials,
+        ctx,
+        tokens,
+        partials,
+        size_hint,
+        text,
+        flat,
+        front_matter: None,
+    }
+}
+
+// Joins the text of `tokens` if that's all there is.
+fn static_text(tokens: &[Token]) -> Option<String> {
+    let mut text = String::new();
+
+    for token in tokens {
+        match *token {
+            Token::Text(ref s) | Token::Raw(ref s, _) => text.push_str(s),
+            Token::Deprecated(_) => {}
+            _ => return None,
+        }
+    }
+
+    Some(text)
+}
+
+// A rough guess at how many bytes a tag renders to.
+const TAG_SIZE_HINT: usize = 16;
+
+// The most the renders to a buffer of their own reserve up front, whatever
+// the size hint, as it may be far off.
+const MAX_RESERVED: usize = 64 * 1024;
+
+// Estimates the size of the output of `tokens`, counting the text as is and
+// sections as if they render once. The estimates of partials are kept in
+// `estimated`, so each is only worked out once.
+fn estimate<'a>(tokens: &'a [Token],
+                partials: &'a PartialsMap,
+                including: &mut Vec<&'a str>,
+                estimated: &mut HashMap<&'a str, usize>)
+                -> usize {
+    let mut size: usize = 0;
+
+    for token in tokens {
+        size = size.saturating_add(match *token {
+            Token::Text(ref text) | Token::Raw(ref text, _) => text.len(),
+            Token::EscapedTag(..) | Token::UnescapedTag(..) | Token::PointerTag(..) | Token::FallbackTag(..) => TAG_SIZE_HINT,
+            Token::Section(_, _, ref children, _, _, _, _, _) |
+            Token::Condition(_, _, ref children, _, _, _, _, _) |
+            Token::Range(_, _, ref children, _, _, _, _, _) => estimate(children, partials, including, estimated),
+            Token::Partial(ref name, _, _) | Token::PartialWithFallback(ref name, _, _, _, _, _) => {
+                match (estimated.get(&name[..]), partials.get(name)) {
+                    (Some(&size), _) => size,
+                    (None, Some(tokens)) if !including.contains(&&name[..]) => {
+                        including.push(name);
+                        let size = estimate(tokens, partials, including, estimated);
+                        including.pop();
+                        estimated.insert(name, size);
+                        size
+                    }
+                    _ => 0,
+                }
+            }
+            Token::Definition(..) | Token::Deprecated(_) | Token::IncompleteSection(..) | Token::IncompleteDefinition(..) => 0,
+        });
+    }
+
+    size
+}
+
+// 64-bit FNV-1a, spelled out so fingerprints don't change with the
+// compiler or platform.
+pub struct Fnv(pub u64);
+
+impl Fnv {
+    pub fn new() -> Fnv {
+        Fnv(0xcbf2_9ce4_8422_2325)
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 ^= u64::from(b);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    // Lengths keep `"ab", "c"` and `"a", "bc"` apart.
+    pub fn str(&mut self, s: &str) {
+        self.bytes(&(s.len() as u64).to_le_bytes());
+        self.bytes(s.as_bytes());
+    }
+
+    fn strs(&mut self, strs: &[String]) {
+        self.bytes(&(strs.len() as u64).to_le_bytes());
+        for s in strs {
+            self.str(s);
+        }
+    }
+
+    fn tokens(&mut self, tokens: &[Token]) {
+        self.bytes(&(tokens.len() as u64).to_le_bytes());
+
+        for token in tokens {
+            match *token {
+                Token::Text(ref text) => {
+                    self.bytes(&[0]);
+                    self.str(text);
+                }
+                Token::EscapedTag(ref path, ref src) => {
+                    self.bytes(&[1]);
+                    self.strs(path);
+                    self.str(src);
+                }
+                Token::UnescapedTag(ref path, ref src) => {
+                    self.bytes(&[2]);
+                    self.strs(path);
+                    self.str(src);
+                }
+                Token::PointerTag(ref pointer, ref src) => {
+                    self.bytes(&[3]);
+                    self.strs(pointer);
+                    self.str(src);
+                }
+                Token::FallbackTag(ref paths, escaped, ref src) => {
+                    self.bytes(&[12, escaped as u8]);
+                    self.bytes(&(paths.len() as u64).to_le_bytes());
+                    for path in paths {
+                        self.strs(path);
+                    }
+                    self.str(src);
+                }
+                Token::Section(ref path, inverted, ref children, ref otag, ref osection, ref src, ref tag, ref ctag) => {
+                    self.bytes(&[4, inverted as u8]);
+                    self.strs(path);
+                    self.tokens(children);
+                    for s in &[otag, osection, src, tag, ctag] {
+                        self.str(s);
+                    }
+                }
+                Token::Condition(ref comparison, inverted, ref children, ref otag, ref osection, ref src, ref tag, ref ctag) => {
+                    self.bytes(&[13, inverted as u8]);
+                    self.str(&comparison.to_string());
+                    self.tokens(children);
+                    for s in &[otag, osection, src, tag, ctag] {
+                        self.str(s);
+                    }
+                }
+                Token::Range(ref bounds, inverted, ref children, ref otag, ref osection, ref src, ref tag, ref ctag) => {
+                    self.bytes(&[14, inverted as u8]);
+                    self.str(&bounds.to_string());
+                    self.tokens(children);
+                    for s in &[otag, osection, src, tag, ctag] {
+                        self.str(s);
+                    }
+                }
+                Token::Partial(ref name, ref indent, ref src) => {
+                    self.bytes(&[5]);
+                    for s in &[name, indent, src] {
+                        self.str(s);
+                    }
+                }
+                Token::PartialWithFallback(ref name, ref indent, ref children, ref otag, ref src, ref ctag) => {
+                    self.bytes(&[6]);
+                    self.str(name);
+                    self.str(indent);
+                    self.tokens(children);
+                    for s in &[otag, src, ctag] {
+                        self.str(s);
+                    }
+                }
+                Token::Definition(ref name, ref children, ref otag, ref src, ref ctag) => {
+                    self.bytes(&[7]);
+                    self.str(name);
+                    self.tokens(children);
+                    for s in &[otag, src, ctag] {
+                        self.str(s);
+                    }
+                }
+                // Never left in a compiled template, see `render_token`.
+                Token::IncompleteSection(..) | Token::IncompleteDefinition(..) => self.bytes(&[8]),
+                Token::Raw(ref text, ref src) => {
+                    self.bytes(&[9]);
+                    self.str(text);
+                    self.str(src);
+                }
+                Token::Deprecated(ref message) => {
+                    self.bytes(&[10]);
+                    self.str(message);
+                }
+            }
+        }
     }
 }
 
 impl<P: PartialLoader> Template<P> {
+    /// A hash of the template and of every partial it was compiled with,
+    /// the same on every platform and for every build of this version of
+    /// the crate, for cache busting and spotting templates that differ
+    /// between deployments.
+    ///
+    /// It is taken over the compiled template, so sources that only differ
+    /// in their comments hash the same.
+    ///
+    /// ```rust
+    /// let a = mustache::compile_str("Hello {{name}}{{! a comment }}").unwrap();
+    /// let b = mustache::compile_str("Hello {{name}}").unwrap();
+    /// let c = mustache::compile_str("Hello {{name}}!").unwrap();
+    ///
+    /// assert_eq!(a.fingerprint(), b.fingerprint());
+    /// assert_ne!(a.fingerprint(), c.fingerprint());
+    /// println!("app.css?v={:016x}", a.fingerprint());
+    /// ```
+    pub fn fingerprint(&self) -> u64 {
+        let mut hash = Fnv::new();
+        hash.tokens(&self.tokens);
+
+        let mut names: Vec<_> = self.partials.keys().collect();
+        names.sort();
+        for name in names {
+            hash.str(name);
+            hash.tokens(&self.partials[name]);
+        }
+
+        hash.0
+    }
+
+    /// The names of every partial the template includes, directly or through
+    /// other partials, as they were asked of the loader.
+    ///
+    /// Partials defined inline are left out, as are partials that only
+    /// appear in the output of lambdas.
+    pub fn partial_dependencies(&self) -> BTreeSet<String> {
+        let mut defined = Vec::new();
+        compiler::defined(&self.tokens, &mut defined);
+        for tokens in self.partials.values() {
+            compiler::defined(tokens, &mut defined);
+        }
+
+        let mut pending = Vec::new();
+        compiler::included(&self.tokens, &mut pending);
+
+        let mut seen = BTreeSet::new();
+        while let Some(name) = pending.pop() {
+            if let Some(tokens) = self.partials.get(&name) {
+                if !seen.contains(&name) {
+                    compiler::included(tokens, &mut pending);
+                }
+            }
+            seen.insert(name);
+        }
+
+        seen.into_iter().filter(|name| !defined.contains(name)).collect()
+    }
+
+    /// The dotted names of every variable tag in the template and the
+    /// partials it includes, `{{.}}` left out and JSON Pointer tags given as
+    /// written. A name inside a section is as written too, so it may be
+    /// looked up on the section's value rather than on the data.
+    ///
+    /// ```rust
+    /// let template = mustache::compile_str("{{#user}}{{name}} {{&bio}}{{/user}}{{^admin}}{{.}}{{/admin}}").unwrap();
+    /// let names: Vec<_> = template.variables().into_iter().collect();
+    /// assert_eq!(names, vec!["bio", "name"]);
+    /// ```
+    pub fn variables(&self) -> BTreeSet<String> {
+        let mut names = Vec::new();
+        for tokens in self.included_tokens() {
+            compiler::variables(tokens, &mut names);
+        }
+        names.into_iter().collect()
+    }
+
+    /// The dotted names of every section in the template and the partials
+    /// it includes, inverted sections too.
+    ///
+    /// ```rust
+    /// let template = mustache::compile_str("{{#user}}{{name}}{{/user}}{{^user.admin}}!{{/user.admin}}").unwrap();
+    /// let names: Vec<_> = template.sections().into_iter().collect();
+    /// assert_eq!(names, vec!["user", "user.admin"]);
+    /// ```
+    pub fn sections(&self) -> BTreeSet<String> {
+        let mut names = Vec::new();
+        for tokens in self.included_tokens() {
+            compiler::sections(tokens, &mut names);
+        }
+        names.into_iter().collect()
+    }
+
+    /// The names of every partial the template includes, directly or through
+    /// other partials, those defined inline and those that could not be
+    /// loaded included. See
+    /// [`partial_dependencies`](#method.partial_dependencies) for only the
+    /// ones asked of the loader.
+    pub fn partials(&self) -> BTreeSet<String> {
+        let mut names = Vec::new();
+        for tokens in self.included_tokens() {
+            compiler::included(tokens, &mut names);
+        }
+        names.into_iter().collect()
+    }
+
+    /// The `{{! @deprecated ...}}` comments of the template and of the
+    /// partials it includes, the template's first and then the partials' by
+    /// name. Compiling a template logs a warning for each of them.
+    ///
+    /// ```rust
+    /// use mustache::{Context, Deprecation, KvLoader};
+    ///
+    /// let loader = KvLoader::new(|key| Ok(match key {
+    ///     "header" => Some("{{! @deprecated use page_header }}<h1>".to_string()),
+    ///     _ => None,
+    /// }));
+    ///
+    /// let template = Context::with_loader(loader).compile("{{> header}}").unwrap();
+    /// assert_eq!(template.deprecations(), vec![Deprecation {
+    ///     partial: Some("header".to_string()),
+    ///     message: "use page_header".to_string(),
+    /// }]);
+    /// ```
+    pub fn deprecations(&self) -> Vec<Deprecation> {
+        let mut deprecations = Vec::new();
+        if let Some(message) = deprecated(&self.tokens) {
+            deprecations.push(Deprecation { partial: None, message: message.to_string() });
+        }
+
+        for name in self.partials() {
+            if let Some(message) = self.partials.get(&name).and_then(|tokens| deprecated(tokens)) {
+                deprecations.push(Deprecation { partial: Some(name), message: message.to_string() });
+            }
+        }
+        deprecations
+    }
+
+    /// Checks `data` against the template without rendering it, reporting
+    /// the names it lacks or has in the wrong shape. Sections are followed
+    /// the way a render would, every item of a list included, and lambdas
+    /// are taken at their word.
+    ///
+    /// ```rust
+    /// use mustache::{MapBuilder, Missing};
+    ///
+    /// let template = mustache::compile_str("{{#users}}{{name}} {{email}}{{/users}}").unwrap();
+    /// let data = MapBuilder::new()
+    ///     .insert_vec("users", |v| v.push_map(|m| m.insert_str("name", "a")))
+    ///     .build();
+    ///
+    /// assert_eq!(template.validate(&data), vec![Missing::Absent("email".to_string())]);
+    /// ```
+    pub fn validate(&self, data: &Data) -> Vec<Missing> {
+        validate::validate(&self.tokens, &self.partials, data)
+    }
+
+    /// Walks the template against `data` the way
+    /// [`validate`](#method.validate) does, without writing anything,
+    /// returning the paths in the data it read and the ones it found
+    /// nothing at. For cutting a large payload down to what the template
+    /// uses.
+    ///
+    /// ```rust
+    /// use mustache::MapBuilder;
+    ///
+    /// let template = mustache::compile_str("{{title}}{{#users}}{{name}} {{email}}{{/users}}").unwrap();
+    /// let data = MapBuilder::new()
+    ///     .insert_str("title", "Users")
+    ///     .insert_str("unused", "")
+    ///     .insert_vec("users", |v| v.push_map(|m| m.insert_str("name", "a").insert_str("age", "3")))
+    ///     .build();
+    ///
+    /// let usage = template.dry_run(&data);
+    /// assert_eq!(usage.read.into_iter().collect::<Vec<_>>(), ["title", "users", "users.*.name"]);
+    /// assert_eq!(usage.missing.into_iter().collect::<Vec<_>>(), ["users.*.email"]);
+    /// ```
+    pub fn dry_run(&self, data: &Data) -> Usage {
+        validate::usage(&self.tokens, &self.partials, data)
+    }
+
+    /// A JSON Schema of the data the template expects, enabled with the
+    /// `schema` feature: the names it uses and which of them it writes out,
+    /// iterates over or looks inside of.
+    ///
+    /// A name inside a section is taken to belong to the section's value, so
+    /// a section with names inside may be a map or a list of maps, and one
+    /// with only text inside may be anything. Names only used in inverted
+    /// sections are not required.
+    ///
+    /// ```rust
+    /// let template = mustache::compile_str("{{#users}}{{name}}{{/users}}").unwrap();
+    /// let schema = template.json_schema();
+    /// assert_eq!(schema["properties"]["users"]["items"]["required"][0], "name");
+    /// ```
+    #[cfg(feature = "schema")]
+    pub fn json_schema(&self) -> ::serde_json::Value {
+        schema::json_schema(&self.tokens, &self.partials)
+    }
+
+    /// Made-up data for the template to render with, for previews and
+    /// screenshot tests without a real payload: a value for every name it
+    /// uses, with sections over names inside them given lists of three
+    /// maps, sections over `{{.}}` lists of three strings, sections over
+    /// text `true`, and written names a couple of lorem ipsum words.
+    ///
+    /// Names are taken to belong to the innermost section, as for
+    /// `json_schema`. Those only used in inverted sections are `Null`, so
+    /// the sections show.
+    ///
+    /// ```rust
+    /// let template = mustache::compile_str("<h1>{{title}}</h1>{{#posts}}<p>{{body}}</p>{{/posts}}").unwrap();
+    /// let preview = template.render_data_to_string(&template.sample_data()).unwrap();
+    /// assert_eq!(preview.matches("<p>").count(), 3);
+    /// ```
+    pub fn sample_data(&self) -> Data {
+        schema::sample_data(&self.tokens, &self.partials)
+    }
+
+    /// The template written back out as mustache source, for formatters and
+    /// tools that rewrite templates. Compiled again with the same extensions
+    /// it renders the same, but it isn't the source it was compiled from:
+    /// comments are gone, tags use the default delimiters and no spaces,
+    /// and the whitespace standalone tags took off their lines stays off.
+    /// Text that has `{{` in it is written between other delimiters.
+    /// Partials are written as tags, not inlined.
+    ///
+    /// ```rust
+    /// let template = mustache::compile_str("{{! greeting }}\n{{#names}}\n  Hi {{ . }}!\n{{/names}}\n").unwrap();
+    /// assert_eq!(template.to_source(), "{{#names}}  Hi {{.}}!\n{{/names}}");
+    /// ```
+    pub fn to_source(&self) -> String {
+        print::to_source(&self.tokens)
+    }
+
+    // The template's tokens and those of every partial it includes, each
+    // partial once.
+    fn included_tokens(&self) -> Vec<&[Token]> {
+        let mut all = vec![&self.tokens[..]];
+        let mut pending = Vec::new();
+        compiler::included(&self.tokens, &mut pending);
+
+        let mut seen = BTreeSet::new();
+        while let Some(name) = pending.pop() {
+            if let Some(tokens) = self.partials.get(&name) {
+                if !seen.contains(&name) {
+                    compiler::included(tokens, &mut pending);
+                    all.push(tokens);
+                }
+            }
+            seen.insert(name);
+        }
+
+        all
+    }
+
+    /// A guess at the size in bytes of the rendered output, counting every
+    /// section once. The `render_*to_string` methods reserve this much up
+    /// front, up to 64 KiB.
+    pub fn size_hint(&self) -> usize {
+        self.size_hint
+    }
+
+    // What to reserve for the output, the size hint up to a bound.
+    pub(crate) fn reserved(&self) -> usize {
+        cmp::min(self.size_hint, MAX_RESERVED)
+    }
+
+    /// The output of the template if it has no tags at all, and so renders
+    /// the same whatever the data.
+    pub fn static_text(&self) -> Option<&str> {
+        self.text.as_ref().map(|text| &text[..])
+    }
+
+    /// The YAML of the front matter taken off the template's file, see
+    /// [`Context::parse_front_matter`](struct.Context.html#method.parse_front_matter).
+    pub fn front_matter_source(&self) -> Option<&str> {
+        self.front_matter.as_ref().map(|yaml| &yaml[..])
+    }
+
+    /// The front matter of the template deserialized as a `T`, or as
+    /// [`Data`](enum.Data.html) to render it. `None` if the template's file
+    /// had none.
+    ///
+    /// ```rust,no_run
+    /// #[macro_use]
+    /// extern crate serde_derive;
+    /// extern crate mustache;
+    ///
+    /// use std::path::PathBuf;
+    /// use mustache::Context;
+    ///
+    /// #[derive(Deserialize)]
+    /// struct Page {
+    ///     title: String,
+    ///     layout: Option<String>,
+    /// }
+    ///
+    /// fn main() {
+    ///     let mut ctx = Context::new(PathBuf::from("templates"));
+    ///     ctx.parse_front_matter();
+    ///
+    ///     let template = ctx.compile_path("about.mustache").unwrap();
+    ///     let page: Option<Page> = template.front_matter().unwrap();
+    /// }
+    /// ```
+    #[cfg(feature = "yaml")]
+    pub fn front_matter<T: DeserializeOwned>(&self) -> Result<Option<T>> {
+        match self.front_matter {
+            Some(ref yaml) => front_matter::parse(yaml).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// A template rendering this one and then `other`, with the same data,
+    /// for pages put together from pieces compiled on their own. A partial
+    /// both include is this template's, and the result keeps this one's
+    /// context and no front matter.
+    ///
+    /// ```rust
+    /// use mustache::MapBuilder;
+    ///
+    /// let header = mustache::compile_str("<h1>{{title}}</h1>\n").unwrap();
+    /// let body = mustache::compile_str("{{#items}}<p>{{.}}</p>{{/items}}").unwrap();
+    ///
+    /// let page = header.concat(&body);
+    /// let data = MapBuilder::new()
+    ///     .insert_str("title", "News")
+    ///     .insert_vec("items", |v| v.push_str("a").push_str("b"))
+    ///     .build();
+    /// assert_eq!(page.render_data_to_string(&data).unwrap(), "<h1>News</h1>\n<p>a</p><p>b</p>");
+    /// ```
+    pub fn concat(&self, other: &Template<P>) -> Template<P> {
+        let mut tokens = Vec::with_capacity(self.tokens.len() + other.tokens.len());
+        tokens.extend_from_slice(&self.tokens);
+        tokens.extend_from_slice(&other.tokens);
+
+        let mut partials = self.partials.clone();
+        for (name, partial) in &other.partials {
+            partials.entry(name.clone()).or_insert_with(|| partial.clone());
+        }
+
+        new(self.ctx.clone(), tokens, partials)
+    }
+
     /// Renders the template with the `Encodable` data.
     pub fn render<W, T>(&self, wr: &mut W, data: &T) -> Result<()>
     where W: Write,
@@ -41,77 +616,1745 @@ impl<P: PartialLoader> Template<P> {
 
     /// Renders the template with the `Data`.
     pub fn render_data<W: Write>(&self, wr: &mut W, data: &Data) -> Result<()> {
-        let mut render_ctx = RenderContext::new(self);
-        let mut stack = vec![data];
+        self.render_data_with(wr, data, &RenderOptions::default())
+    }
+
+    /// Renders the template with the `Encodable` data and per-render
+    /// `options`.
+    pub fn render_with<W, T>(&self, wr: &mut W, data: &T, options: &RenderOptions) -> Result<()>
+    where W: Write,
+          T: Serialize,
+    {
+        let data = to_data(data)?;
+        self.render_data_with(wr, &data, options)
+    }
 
-        render_ctx.render(wr, &mut stack, &self.tokens)
+    /// Renders the template with the `Data` and per-render `options`.
+    ///
+    /// ```rust
+    /// use mustache::{Error, MapBuilder, RenderOptions};
+    /// use std::sync::Arc;
+    /// use std::sync::atomic::AtomicBool;
+    ///
+    /// let template = mustache::compile_str("{{#items}}{{.}}{{/items}}").unwrap();
+    /// let data = MapBuilder::new()
+    ///     .insert_vec("items", |v| v.push_str("a").push_str("b"))
+    ///     .build();
+    ///
+    /// let options = RenderOptions {
+    ///     cancel: Some(Arc::new(AtomicBool::new(true))),
+    ///     ..RenderOptions::default()
+    /// };
+    ///
+    /// match template.render_data_with(&mut Vec::new(), &data, &options) {
+    ///     Err(Error::Cancelled) => {}
+    ///     _ => panic!("expected the render to be cancelled"),
+    /// }
+    /// ```
+    pub fn render_data_with<W: Write>(&self, wr: &mut W, data: &Data, options: &RenderOptions) -> Result<()> {
+        self.render_data_buffered(wr, data, options, &mut Buffers::default())
     }
 
-    /// Renders the template to a `String` with the `Encodable` data.
-    pub fn render_to_string<T: Serialize>(&self, data: &T) -> Result<String> {
+    // Renders like `render_data_with`, taking the renderer's stacks from
+    // `buffers` and leaving them there for the next render.
+    pub(crate) fn render_data_buffered<W: Write>(&self,
+                                                 wr: &mut W,
+                                                 data: &Data,
+                                                 options: &RenderOptions,
+                                                 buffers: &mut Buffers) -> Result<()> {
+        self.measured(wr, |wr| self.render_data_progress(wr, data, options, buffers))
+    }
+
+    // Renders with `render` into `wr`, telling the context's metrics sink
+    // how long it took and how much it wrote. Every render goes through
+    // here, so the numbers don't depend on how it was started.
+    fn measured<W, T, F>(&self, wr: &mut W, render: F) -> Result<T>
+    where W: Write,
+          F: FnOnce(&mut Counted<&mut W>) -> Result<T>,
+    {
+        let started = self.ctx.metrics.as_ref().map(|_| Instant::now());
+        let written = Cell::new(0);
+        let rendered = render(&mut Counted { inner: wr, written: &written });
+        if let Some(started) = started {
+            self.measure(started, written.get(), rendered.is_ok());
+        }
+        rendered
+    }
+
+    // Tells the context's metrics sink of a render that started at
+    // `started` and wrote `written` bytes, or failed.
+    pub(crate) fn measure(&self, started: Instant, written: usize, succeeded: bool) {
+        match self.ctx.metrics {
+            Some(Metrics(ref sink)) if succeeded => sink.rendered(started.elapsed(), written),
+            Some(Metrics(ref sink)) => sink.render_failed(started.elapsed()),
+            None => {}
+        }
+    }
+
+    fn render_data_progress<W: Write>(&self,
+                                      wr: &mut W,
+                                      data: &Data,
+                                      options: &RenderOptions,
+                                      buffers: &mut Buffers) -> Result<()> {
+        let callback = match options.progress {
+            Some(ref callback) => callback,
+            None => return self.render_data_tracked(wr, data, options, None, buffers),
+        };
+
+        let tracker = Tracker::new(callback);
+        self.render_data_tracked(&mut Tracked { inner: wr, tracker: &tracker }, data, options, Some(&tracker), buffers)?;
+        tracker.report();
+        Ok(())
+    }
+
+    fn render_data_tracked<W: Write>(&self,
+                                     wr: &mut W,
+                                     data: &Data,
+                                     options: &RenderOptions,
+                                     tracker: Option<&Tracker>,
+                                     buffers: &mut Buffers) -> Result<()> {
+        // Nothing to walk or escape, so write the text out in one go.
+        if let Some(ref text) = self.text {
+            if let Some(ref cancel) = options.cancel {
+                if cancel.load(Ordering::Relaxed) {
+                    return Err(Error::Cancelled);
+                }
+            }
+            wr.write_all(text.as_bytes())?;
+            return Ok(());
+        }
+
+        if self.flat && options.flat_defaults() {
+            return self.render_flat(wr, data, options, tracker);
+        }
+
+        let mut render_ctx = RenderContext::new(self, options);
+        render_ctx.tracker = tracker;
+        render_ctx.buffers = mem::take(buffers);
+        let mut stack = recycle(mem::take(&mut render_ctx.buffers.stack));
+        stack.push(data);
+
+        let rendered = render_ctx.render(wr, &mut stack, &self.tokens);
+        render_ctx.buffers.stack = recycle(stack);
+        *buffers = render_ctx.buffers;
+        rendered
+    }
+
+    // Renders a template of only text and variables without a context stack
+    // or work list, escaping values straight into `wr`. Anything but strings
+    // and nulls, lambdas mostly, goes through the full renderer.
+    fn render_flat<W: Write>(&self,
+                             wr: &mut W,
+                             data: &Data,
+                             options: &RenderOptions,
+                             tracker: Option<&Tracker>) -> Result<()> {
+        let mut fallback = None;
+
+        for token in &self.tokens {
+            if let Some(ref cancel) = options.cancel {
+                if cancel.load(Ordering::Relaxed) {
+                    return Err(Error::Cancelled);
+                }
+            }
+
+            let (path, escaped) = match *token {
+                Token::Text(ref text) | Token::Raw(ref text, _) => {
+                    wr.write_all(text.as_bytes())?;
+                    continue;
+                }
+                Token::EscapedTag(ref path, _) => (&path[..], true),
+                Token::UnescapedTag(ref path, _) => (&path[..], false),
+                Token::Deprecated(_) => continue,
+                _ => {
+                    bug!("render_flat: unexpected token {:?}", token);
+                    continue;
+                }
+            };
+
+            let value = match *data {
+                Data::Map(ref map) if path.len() == 1 => map.get(&path[0]),
+                _ => find(path, slice::from_ref(&data)),
+            };
+
+            match value {
+                None | Some(&Data::Null) => {}
+                Some(Data::String(value)) if escaped => escape(wr, value.as_bytes(), self.ctx.escape)?,
+                Some(Data::String(value)) => wr.write_all(value.as_bytes())?,
+                Some(_) => {
+                    let render_ctx = fallback.get_or_insert_with(|| {
+                        let mut render_ctx = RenderContext::new(self, options);
+                        render_ctx.tracker = tracker;
+                        render_ctx
+                    });
+                    render_ctx.render(wr, &mut vec![data], slice::from_ref(token))?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Renders the template with the `Encodable` data, returning the warnings
+    /// noticed along the way.
+    pub fn render_with_warnings<W, T>(&self, wr: &mut W, data: &T) -> Result<Vec<Warning>>
+    where W: Write,
+          T: Serialize,
+    {
+        let data = to_data(data)?;
+        self.render_data_with_warnings(wr, &data)
+    }
+
+    /// Renders the template with the `Data`, returning the warnings noticed
+    /// along the way.
+    ///
+    /// ```rust
+    /// use mustache::{MapBuilder, Warning};
+    ///
+    /// let template = mustache::compile_str("Hello {{name}}{{suffix}}").unwrap();
+    /// let data = MapBuilder::new().insert_str("name", "world").build();
+    ///
+    /// let mut out = Vec::new();
+    /// let warnings = template.render_data_with_warnings(&mut out, &data).unwrap();
+    ///
+    /// assert_eq!(out, b"Hello world");
+    /// assert_eq!(warnings, vec![Warning::MissingKey("suffix".to_string())]);
+    /// ```
+    pub fn render_data_with_warnings<W: Write>(&self, wr: &mut W, data: &Data) -> Result<Vec<Warning>> {
+        let options = RenderOptions::default();
+        self.measured(wr, |wr| {
+            let mut render_ctx = RenderContext::new(self, &options);
+            render_ctx.warnings = Some(Vec::new());
+            let mut stack = vec![data];
+
+            render_ctx.render(wr, &mut stack, &self.tokens)?;
+            Ok(render_ctx.warnings.unwrap_or_default())
+        })
+    }
+
+    /// Renders the template to a `String` with the `Encodable` data, along
+    /// with how it differs from `previous`. See
+    /// [`render_data_changes`](#method.render_data_changes).
+    pub fn render_changes<T: Serialize>(&self, previous: &str, data: &T) -> Result<(String, Vec<Splice>)> {
+        let data = to_data(data)?;
+        self.render_data_changes(previous, &data)
+    }
+
+    /// Renders the template to a `String` with the `Data`, along with the
+    /// [`Splice`](struct.Splice.html)s turning `previous`, an earlier output
+    /// of this template or of the one it was changed from, into it. See
+    /// [`diff_output`](fn.diff_output.html).
+    ///
+    /// ```rust
+    /// use mustache::MapBuilder;
+    ///
+    /// let template = mustache::compile_str("<h1>{{title}}</h1>\n<p>{{body}}</p>\n").unwrap();
+    /// let page = template.render_data_to_string(&MapBuilder::new().insert_str("title", "Draft").build()).unwrap();
+    ///
+    /// let data = MapBuilder::new().insert_str("title", "Draft").insert_str("body", "Hi").build();
+    /// let (output, splices) = template.render_data_changes(&page, &data).unwrap();
+    /// assert_eq!(output, "<h1>Draft</h1>\n<p>Hi</p>\n");
+    /// assert_eq!((splices.len(), &splices[0].text[..]), (1, "Hi"));
+    /// ```
+    pub fn render_data_changes(&self, previous: &str, data: &Data) -> Result<(String, Vec<Splice>)> {
+        let output = self.render_data_to_string(data)?;
+        let splices = diff::diff_output(previous, &output);
+        Ok((output, splices))
+    }
+
+    /// Renders the template to a `String` with the `Encodable` data, along
+    /// with where each part of the output came from. See
+    /// [`render_data_traced`](#method.render_data_traced).
+    pub fn render_traced<T: Serialize>(&self, data: &T) -> Result<(String, Vec<Region>)> {
+        let data = to_data(data)?;
+        self.render_data_traced(&data)
+    }
+
+    /// Renders the template to a `String` with the `Data`, along with the
+    /// [`Region`](struct.Region.html)s of the output, in order, telling the
+    /// partials and the tag each one came from. For finding out where some
+    /// markup comes from when it isn't obvious from the templates.
+    ///
+    /// ```rust
+    /// use mustache::MapBuilder;
+    ///
+    /// let template = mustache::compile_str("<p>{{#user}}{{name}}</div>{{/user}}</p>").unwrap();
+    /// let data = MapBuilder::new().insert_map("user", |m| m.insert_str("name", "Ann")).build();
+    ///
+    /// let (output, regions) = template.render_data_traced(&data).unwrap();
+    /// assert_eq!(output, "<p>Ann</div></p>");
+    ///
+    /// let at = output.find("</div>").unwrap();
+    /// let region = regions.iter().find(|region| region.range.contains(&at)).unwrap();
+    /// assert_eq!((region.partials.len(), &region.tag), (0, &None));
+    /// assert_eq!(regions[1].tag.as_ref().unwrap(), "{{name}}");
+    /// ```
+    pub fn render_data_traced(&self, data: &Data) -> Result<(String, Vec<Region>)> {
+        let options = RenderOptions::default();
+        let written = Cell::new(0);
+        let mut output = Vec::with_capacity(self.reserved());
+
+        let regions = self.measured(&mut output, |wr| {
+            let mut render_ctx = RenderContext::new(self, &options);
+            render_ctx.trace = Some(Trace { written: &written, regions: Vec::new() });
+            render_ctx.render(&mut Counted { inner: wr, written: &written }, &mut vec![data], &self.tokens)?;
+            Ok(render_ctx.trace.map(|trace| trace.regions).unwrap_or_default())
+        })?;
+
+        let output = String::from_utf8(output).map_err(|_| Error::InvalidStr)?;
+        Ok((output, regions))
+    }
+
+    /// Renders the template with the `Encodable` data, then renders `layout`
+    /// around it. See [`render_data_in_layout`](#method.render_data_in_layout).
+    pub fn render_in_layout<W, T>(&self, wr: &mut W, layout: &Template<P>, data: &T) -> Result<()>
+    where W: Write,
+          T: Serialize,
+    {
+        let data = to_data(data)?;
+        self.render_data_in_layout(wr, layout, &data)
+    }
+
+    /// Renders the template with the `Data`, then renders `layout` with the
+    /// same data and the output available as `content`. The layout should use
+    /// `{{{content}}}` or `{{&content}}` so it isn't escaped twice.
+    ///
+    /// ```rust
+    /// use mustache::MapBuilder;
+    ///
+    /// let page = mustache::compile_str("<p>{{name}}</p>").unwrap();
+    /// let layout = mustache::compile_str("<title>{{title}}</title>{{{content}}}").unwrap();
+    /// let data = MapBuilder::new()
+    ///     .insert_str("title", "Home")
+    ///     .insert_str("name", "world")
+    ///     .build();
+    ///
+    /// let mut out = Vec::new();
+    /// page.render_data_in_layout(&mut out, &layout, &data).unwrap();
+    ///
+    /// assert_eq!(out, b"<title>Home</title><p>world</p>");
+    /// ```
+    pub fn render_data_in_layout<W: Write>(&self, wr: &mut W, layout: &Template<P>, data: &Data) -> Result<()> {
+        let options = RenderOptions::default();
+
+        // Measured as one render, the page's.
+        self.measured(wr, |wr| {
+            let mut content = Vec::with_capacity(self.reserved());
+            self.render_data_progress(&mut content, data, &options, &mut Buffers::default())?;
+            let content = String::from_utf8(content).map_err(|_| Error::InvalidStr)?;
+
+            let mut slot = HashMap::new();
+            slot.insert("content".to_string(), Data::String(content));
+            let slot = Data::Map(slot);
+
+            let mut render_ctx = RenderContext::new(layout, &options);
+            let mut stack = vec![data, &slot];
+            render_ctx.render(wr, &mut stack, &layout.tokens)
+        })
+    }
+
+    /// Renders only the content of the section `name` with the `Encodable`
+    /// data. See [`render_data_section`](#method.render_data_section).
+    pub fn render_section<W, T>(&self, wr: &mut W, name: &str, data: &T) -> Result<()>
+    where W: Write,
+          T: Serialize,
+    {
+        let data = to_data(data)?;
+        self.render_data_section(wr, name, &data)
+    }
+
+    /// Renders only the content of the first section named `name`, as
+    /// written, in the template or the partials it includes, once and with
+    /// `data` as its context, the way a section renders one item of a list.
+    /// For updating a fragment of a page without splitting the template up.
+    ///
+    /// Fails with [`Error::NoSuchSection`](enum.Error.html#variant.NoSuchSection)
+    /// if there is no such section.
+    ///
+    /// ```rust
+    /// use mustache::MapBuilder;
+    ///
+    /// let template = mustache::compile_str("<ul>{{#rows}}<li>{{name}}</li>{{/rows}}</ul>").unwrap();
+    /// let row = MapBuilder::new().insert_str("name", "new").build();
+    ///
+    /// let mut out = Vec::new();
+    /// template.render_data_section(&mut out, "rows", &row).unwrap();
+    /// assert_eq!(out, b"<li>new</li>");
+    /// ```
+    pub fn render_data_section<W: Write>(&self, wr: &mut W, name: &str, data: &Data) -> Result<()> {
+        let path = parser::split_name(name);
+        let children = self.included_tokens()
+            .into_iter()
+            .filter_map(|tokens| compiler::section(tokens, &path))
+            .next()
+            .ok_or_else(|| Error::NoSuchSection(name.to_string()))?;
+
+        let options = RenderOptions::default();
+        self.measured(wr, |wr| RenderContext::new(self, &options).render(wr, &mut vec![data], children))
+    }
+
+    /// Renders the template with the `Encodable` data while it is being
+    /// serialized, so the items of the list in the top-level section
+    /// `section` are converted to `Data` one at a time, and the whole list
+    /// never is. For reports of many rows, where the data would take up more
+    /// memory than the output.
+    ///
+    /// `data` has to serialize as a struct or map, the value under `section`
+    /// as a sequence and `section` has to be in the template itself, not
+    /// inside another section or a partial, or the render fails with
+    /// [`Error::NoSuchSection`](enum.Error.html#variant.NoSuchSection). The
+    /// fields are kept in the order they are serialized in, a struct's in
+    /// the order they are declared: the template ahead of the section only
+    /// sees those ahead of the list. The template after it only sees whether
+    /// the list had items, so `{{^rows}}` works as usual but another
+    /// `{{#rows}}` renders once at most.
+    ///
+    /// ```rust
+    /// #[macro_use]
+    /// extern crate serde_derive;
+    /// extern crate mustache;
+    ///
+    /// #[derive(Serialize)]
+    /// struct Report {
+    ///     title: &'static str,
+    ///     rows: Vec<&'static str>,
+    ///     total: u32,
+    /// }
+    ///
+    /// # fn main() {
+    /// let template = mustache::compile_str("{{title}}:{{#rows}} {{.}}{{/rows}} ({{total}})").unwrap();
+    /// let report = Report { title: "Stock", rows: vec!["ink", "pens"], total: 2 };
+    ///
+    /// let mut out = Vec::new();
+    /// template.render_streaming(&mut out, &report, "rows").unwrap();
+    /// assert_eq!(String::from_utf8(out).unwrap(), "Stock: ink pens (2)");
+    /// # }
+    /// ```
+    pub fn render_streaming<W, T>(&self, wr: &mut W, data: &T, section: &str) -> Result<()>
+    where W: Write,
+          T: Serialize,
+    {
+        let options = RenderOptions::default();
+        self.measured(wr, |wr| stream::render(self, wr, data, section, &options))
+    }
+
+    /// Renders the template with the `Data` into a tokio `AsyncWrite`, a
+    /// socket say, as fast as it takes the output: a few kilobytes are
+    /// rendered ahead of the writer at most, but for a lambda's output or a
+    /// section over a [`Data::Iter`](enum.Data.html#variant.Iter), which
+    /// are rendered whole. The future borrows the data, so it can't be sent
+    /// to another thread.
+    ///
+    /// ```rust
+    /// # extern crate futures;
+    /// # extern crate mustache;
+    /// use mustache::MapBuilder;
+    ///
+    /// # fn main() {
+    /// let template = mustache::compile_str("Hello, {{name}}!").unwrap();
+    /// let data = MapBuilder::new().insert_str("name", "world").build();
+    ///
+    /// let mut out = Vec::new();
+    /// futures::executor::block_on(template.render_to_async_write(&mut out, &data)).unwrap();
+    /// assert_eq!(out, b"Hello, world!");
+    /// # }
+    /// ```
+    #[cfg(feature = "tokio")]
+    pub fn render_to_async_write<'a, W>(&'a self, wr: W, data: &'a Data) -> impl Future<Output = Result<()>> + 'a
+    where W: ::tokio::io::AsyncWrite + Unpin + 'a
+    {
+        self.render_to_async_write_with(wr, data, async_write::default_options())
+    }
+
+    /// Renders like [`render_to_async_write`](#method.render_to_async_write)
+    /// with per-render `options`.
+    #[cfg(feature = "tokio")]
+    pub fn render_to_async_write_with<'a, W>(&'a self,
+                                             wr: W,
+                                             data: &'a Data,
+                                             options: &'a RenderOptions) -> impl Future<Output = Result<()>> + 'a
+    where W: ::tokio::io::AsyncWrite + Unpin + 'a
+    {
+        async_write::render(self, async_write::Tokio(wr), data, options)
+    }
+
+    /// Renders like [`render_to_async_write`](#method.render_to_async_write)
+    /// into a futures `AsyncWrite`.
+    #[cfg(feature = "futures")]
+    pub fn render_to_futures_write<'a, W>(&'a self, wr: W, data: &'a Data) -> impl Future<Output = Result<()>> + 'a
+    where W: ::futures_io::AsyncWrite + Unpin + 'a
+    {
+        self.render_to_futures_write_with(wr, data, async_write::default_options())
+    }
+
+    /// Renders like [`render_to_futures_write`](#method.render_to_futures_write)
+    /// with per-render `options`.
+    #[cfg(feature = "futures")]
+    pub fn render_to_futures_write_with<'a, W>(&'a self,
+                                               wr: W,
+                                               data: &'a Data,
+                                               options: &'a RenderOptions) -> impl Future<Output = Result<()>> + 'a
+    where W: ::futures_io::AsyncWrite + Unpin + 'a
+    {
+        async_write::render(self, async_write::Futures(wr), data, options)
+    }
+
+    /// Renders only the content of the section `name` to a `String` with the
+    /// `Encodable` data.
+    pub fn render_section_to_string<T: Serialize>(&self, name: &str, data: &T) -> Result<String> {
         let mut output = Vec::new();
-        self.render(&mut output, data)?;
+        self.render_section(&mut output, name, data)?;
         String::from_utf8(output).map_err(|_| Error::InvalidStr)
     }
 
+    /// Renders the template to a `String` with the `Encodable` data.
+    pub fn render_to_string<T: Serialize>(&self, data: &T) -> Result<String> {
+        let data = to_data(data)?;
+        self.render_data_to_string(&data)
+    }
+
     /// Renders the template to a `String` with the `Data`.
     pub fn render_data_to_string(&self, data: &Data) -> Result<String> {
-        let mut output = Vec::new();
+        if let (Some(ref text), None) = (&self.text, &self.ctx.metrics) {
+            return Ok(text.clone());
+        }
+
+        let mut output = Vec::with_capacity(self.reserved());
         self.render_data(&mut output, data)?;
         String::from_utf8(output).map_err(|_| Error::InvalidStr)
     }
+
+    /// Renders the template with the `Encodable` data, borrowing the output
+    /// from the template when it has no tags.
+    pub fn render_to_cow<T: Serialize>(&self, data: &T) -> Result<Cow<'_, str>> {
+        let data = to_data(data)?;
+        self.render_data_to_cow(&data)
+    }
+
+    /// Renders the template with the `Data`, borrowing the output from the
+    /// template when it has no tags. The data isn't looked at then.
+    ///
+    /// ```rust
+    /// use std::borrow::Cow;
+    /// use mustache::Data;
+    ///
+    /// let template = mustache::compile_str("Hello{{! a comment }} world").unwrap();
+    ///
+    /// match template.render_data_to_cow(&Data::Null).unwrap() {
+    ///     Cow::Borrowed(text) => assert_eq!(text, "Hello world"),
+    ///     Cow::Owned(_) => panic!("expected borrowed output"),
+    /// }
+    /// ```
+    pub fn render_data_to_cow(&self, data: &Data) -> Result<Cow<'_, str>> {
+        match (&self.text, &self.ctx.metrics) {
+            (Some(ref text), None) => Ok(Cow::Borrowed(text)),
+            _ => self.render_data_to_string(data).map(Cow::Owned),
+        }
+    }
+
+    /// Renders the template with the `Encodable` data to `sink`, see
+    /// [`render_data_to_sink`](#method.render_data_to_sink).
+    pub fn render_to_sink<S, T>(&self, sink: &mut S, data: &T) -> Result<()>
+    where S: OutputSink + ?Sized,
+          T: Serialize,
+    {
+        let data = to_data(data)?;
+        self.render_data_to_sink(sink, &data)
+    }
+
+    /// Renders the template with the `Data` to `sink`, telling escaped
+    /// values apart from the rest of the output, then finishes the sink.
+    /// See [`OutputSink`](trait.OutputSink.html).
+    pub fn render_data_to_sink<S: OutputSink + ?Sized>(&self, sink: &mut S, data: &Data) -> Result<()> {
+        let options = RenderOptions::default();
+        let escaping = Cell::new(false);
+        let mut wr = SinkWriter::new(&mut *sink, &escaping);
+
+        self.measured(&mut wr, |wr| {
+            let mut render_ctx = RenderContext::new(self, &options);
+            render_ctx.escaping = Some(&escaping);
+            let rendered = render_ctx.render(wr, &mut vec![data], &self.tokens);
+
+            if let Some(err) = wr.inner.error.take() {
+                return Err(err);
+            }
+            rendered
+        })?;
+        sink.finish()
+    }
+
+    /// Renders the template with the `Encodable` data to every writer in
+    /// `writers`, see [`render_data_tee`](#method.render_data_tee).
+    pub fn render_tee<T: Serialize>(&self, writers: &mut [&mut dyn Write], data: &T) -> Result<()> {
+        let data = to_data(data)?;
+        self.render_data_tee(writers, &data)
+    }
+
+    /// Renders the template with the `Data` to every writer in `writers`,
+    /// each piece of output going to all of them in turn as it is made.
+    /// The first writer to fail stops the render, and the writers after it
+    /// miss the piece that failed.
+    ///
+    /// ```rust
+    /// use mustache::MapBuilder;
+    ///
+    /// let template = mustache::compile_str("Hello {{name}}").unwrap();
+    /// let data = MapBuilder::new().insert_str("name", "world").build();
+    ///
+    /// let (mut response, mut audit) = (Vec::new(), Vec::new());
+    /// template.render_data_tee(&mut [&mut response, &mut audit], &data).unwrap();
+    ///
+    /// assert_eq!(response, b"Hello world");
+    /// assert_eq!(audit, b"Hello world");
+    /// ```
+    pub fn render_data_tee(&self, writers: &mut [&mut dyn Write], data: &Data) -> Result<()> {
+        self.render_data(&mut Tee(writers), data)
+    }
+
+    /// Renders the template with the `Encodable` data as chunks, see
+    /// [`render_data_chunks`](#method.render_data_chunks).
+    pub fn render_chunks<T: Serialize>(&self, data: &T) -> Result<Vec<Cow<'_, str>>> {
+        let data = to_data(data)?;
+        self.render_data_chunks(&data)
+    }
+
+    /// Renders the template with the `Data` as a list of chunks that make up
+    /// the output, for response bodies that take one buffer after another.
+    ///
+    /// The text of the template and its partials is borrowed from the
+    /// template rather than copied, and only what tags write is allocated,
+    /// each run of it as one chunk. Text a lambda returns is copied too,
+    /// since it is compiled for the render. A template kept in a `static`
+    /// gives `'static` chunks.
+    ///
+    /// ```rust
+    /// use std::borrow::Cow;
+    /// use mustache::MapBuilder;
+    ///
+    /// let template = mustache::compile_str("<p>Hello {{name}}!</p>").unwrap();
+    /// let data = MapBuilder::new().insert_str("name", "<world>").build();
+    ///
+    /// let chunks = template.render_data_chunks(&data).unwrap();
+    /// assert_eq!(chunks, ["<p>Hello ", "&lt;world&gt;", "!</p>"]);
+    /// assert!(matches!(chunks[0], Cow::Borrowed(_)) && matches!(chunks[1], Cow::Owned(_)));
+    /// ```
+    pub fn render_data_chunks(&self, data: &Data) -> Result<Vec<Cow<'_, str>>> {
+        let mut texts = Vec::new();
+        if let Some(ref text) = self.text {
+            texts.push(&text[..]);
+        }
+        text_regions(&self.tokens, &mut texts);
+        for tokens in self.partials.values() {
+            text_regions(tokens, &mut texts);
+        }
+        texts.sort_by_key(|text| text.as_ptr() as usize);
+
+        let mut chunks = Chunks { texts, chunks: Vec::new(), pending: Vec::new() };
+        self.render_data(&mut chunks, data)?;
+        chunks.finish()
+    }
+}
+
+// The text of `tokens` and their sections.
+fn text_regions<'t>(tokens: &'t [Token], texts: &mut Vec<&'t str>) {
+    for token in tokens {
+        match *token {
+            Token::Text(ref text) | Token::Raw(ref text, _) => texts.push(text),
+            Token::Section(_, _, ref children, _, _, _, _, _) |
+            Token::Condition(_, _, ref children, _, _, _, _, _) |
+            Token::Range(_, _, ref children, _, _, _, _, _) |
+            Token::PartialWithFallback(_, _, ref children, _, _, _) |
+            Token::Definition(_, ref children, _, _, _) => text_regions(children, texts),
+            _ => {}
+        }
+    }
+}
+
+// Collects the output as chunks, borrowing whatever is written straight out
+// of one of `texts`, which are sorted by address.
+struct Chunks<'t> {
+    texts: Vec<&'t str>,
+    chunks: Vec<Cow<'t, str>>,
+    // Output to be copied into the next owned chunk.
+    pending: Vec<u8>,
+}
+
+impl<'t> Chunks<'t> {
+    // The part of one of the texts that `buf` is, if it is one.
+    fn borrowed(&self, buf: &[u8]) -> Option<&'t str> {
+        let start = buf.as_ptr() as usize;
+        let i = match self.texts.binary_search_by_key(&start, |text| text.as_ptr() as usize) {
+            Ok(i) => i,
+            Err(0) => return None,
+            Err(i) => i - 1,
+        };
+
+        let text = self.texts[i];
+        let offset = start - text.as_ptr() as usize;
+        text.get(offset..offset + buf.len())
+    }
+
+    fn push_pending(&mut self) -> Result<()> {
+        if !self.pending.is_empty() {
+            let pending = String::from_utf8(mem::take(&mut self.pending)).map_err(|_| Error::InvalidStr)?;
+            self.chunks.push(Cow::Owned(pending));
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<Vec<Cow<'t, str>>> {
+        self.push_pending()?;
+        Ok(self.chunks)
+    }
+}
+
+impl<'t> Write for Chunks<'t> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.borrowed(buf) {
+            Some(text) if !text.is_empty() => {
+                self.push_pending().map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid str"))?;
+                self.chunks.push(Cow::Borrowed(text));
+            }
+            _ => self.pending.extend_from_slice(buf),
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Copies everything written to all the writers.
+struct Tee<'w, 'a>(&'w mut [&'a mut dyn Write]);
+
+impl<'w, 'a> Write for Tee<'w, 'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for wr in self.0.iter_mut() {
+            wr.write_all(buf)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        for wr in self.0.iter_mut() {
+            wr.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// Settings for a single render, see
+/// [`Template::render_data_with`](struct.Template.html#method.render_data_with).
+#[derive(Clone, Default)]
+pub struct RenderOptions {
+    /// Checked before every tag and text run; once it is set the render
+    /// stops with [`Error::Cancelled`](enum.Error.html#variant.Cancelled).
+    /// Anything already written stays written.
+    pub cancel: Option<Arc<AtomicBool>>,
+    /// Called around every variable tag and partial that renders.
+    pub hook: Option<Arc<dyn RenderHook>>,
+    /// The most partials the render may include, counting every time one
+    /// is, before failing with
+    /// [`Error::TooManyPartials`](enum.Error.html#variant.TooManyPartials).
+    pub max_partials: Option<usize>,
+    /// The most times the render may go through a section's content, for
+    /// all sections and items together, before failing with
+    /// [`Error::TooManyIterations`](enum.Error.html#variant.TooManyIterations).
+    ///
+    /// With `max_partials` this bounds the work a small template can make,
+    /// such as partials that include each other several times over.
+    pub max_iterations: Option<usize>,
+    /// Reports how far the render got as it goes.
+    pub progress: Option<ProgressCallback>,
+    /// Turns a panic in a lambda into
+    /// [`Error::LambdaPanicked`](enum.Error.html#variant.LambdaPanicked)
+    /// instead of letting it unwind through the render. The panic hook still
+    /// runs, and the lambda may be left halfway through whatever it was
+    /// changing.
+    pub catch_lambda_panics: bool,
+    /// How [`Data::Bytes`](enum.Data.html#variant.Bytes) values are written
+    /// out, base64 by default.
+    pub bytes: BytesEncoding,
+    /// The locale the [`locale`](locale/index.html) helpers format for,
+    /// `en` when unset.
+    pub locale: Option<Locale>,
+    /// Partials used instead of the template's own of the same name.
+    pub partials: PartialOverrides,
+    /// Matches names to keys of the data regardless of case when there is
+    /// no key spelled exactly the same, the smallest such key, comparing
+    /// bytes, winning a tie. JSON pointer tags still match exactly.
+    pub ignore_case: bool,
+    /// Keys of the data to look up instead of names that aren't in it,
+    /// e.g. `customer_name` to `customerName`, so templates keep working
+    /// while the data they're given is being renamed. Each name of a dotted
+    /// name is looked up on its own.
+    pub aliases: HashMap<String, String>,
+    /// Cleans the HTML that unescaped tags write, for data holding HTML from
+    /// users. See the [`Sanitizer`](struct.Sanitizer.html).
+    #[cfg(feature = "ammonia")]
+    pub sanitize: Option<Sanitizer>,
+    /// What becomes of the Unicode bidirectional controls in the values of
+    /// the data, which can make text, code especially, read differently
+    /// from what it is. Kept by default.
+    pub bidi_controls: BidiControls,
+}
+
+impl fmt::Debug for RenderOptions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut f = f.debug_struct("RenderOptions");
+        f
+            .field("cancel", &self.cancel)
+            .field("hook", &self.hook.as_ref().map(|_| ".."))
+            .field("max_partials", &self.max_partials)
+            .field("max_iterations", &self.max_iterations)
+            .field("progress", &self.progress)
+            .field("catch_lambda_panics", &self.catch_lambda_panics)
+            .field("bytes", &self.bytes)
+            .field("locale", &self.locale)
+            .field("partials", &self.partials)
+            .field("ignore_case", &self.ignore_case)
+            .field("aliases", &self.aliases)
+            .field("bidi_controls", &self.bidi_controls);
+        #[cfg(feature = "ammonia")]
+        f.field("sanitize", &self.sanitize);
+        f.finish()
+    }
+}
+
+impl RenderOptions {
+    // Whether the options are all at their defaults but for the cancel flag
+    // and progress callback, which `render_flat` handles. Every field is
+    // named so a new one can't be missed.
+    fn flat_defaults(&self) -> bool {
+        let RenderOptions {
+            cancel: _,
+            progress: _,
+            hook,
+            max_partials,
+            max_iterations,
+            catch_lambda_panics,
+            bytes,
+            locale,
+            partials,
+            ignore_case,
+            aliases,
+            #[cfg(feature = "ammonia")]
+            sanitize: _,
+            bidi_controls,
+        } = self;
+
+        hook.is_none() && max_partials.is_none() && max_iterations.is_none() && !catch_lambda_panics
+            && *bytes == BytesEncoding::default() && locale.is_none() && partials.is_empty() && !ignore_case
+            && aliases.is_empty() && !sanitizes(self) && *bidi_controls == BidiControls::default()
+    }
+}
+
+// Whether unescaped tags go through a sanitizer.
+fn sanitizes(options: &RenderOptions) -> bool {
+    #[cfg(feature = "ammonia")]
+    {
+        options.sanitize.is_some()
+    }
+    #[cfg(not(feature = "ammonia"))]
+    {
+        let _ = options;
+        false
+    }
+}
+
+/// Partials that shadow a template's own for the renders given them in
+/// [`RenderOptions::partials`](struct.RenderOptions.html#structfield.partials),
+/// to theme a template for one tenant without compiling it again.
+///
+/// ```rust
+/// use mustache::{Context, MapBuilder, PartialOverrides, RenderOptions};
+/// use std::path::PathBuf;
+///
+/// let mut ctx = Context::new(PathBuf::from("."));
+/// ctx.extensions.inline_partials = true;
+/// let template = ctx.compile("{{<*header}}Welcome{{/header}}{{>header}}!").unwrap();
+///
+/// let mut partials = PartialOverrides::new();
+/// partials.insert("header", &mustache::compile_str("Hello {{name}}").unwrap());
+/// let options = RenderOptions { partials, ..RenderOptions::default() };
+///
+/// let data = MapBuilder::new().insert_str("name", "Ann").build();
+/// let mut out = Vec::new();
+/// template.render_data_with(&mut out, &data, &options).unwrap();
+/// assert_eq!(String::from_utf8(out).unwrap(), "Hello Ann!");
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct PartialOverrides {
+    partials: PartialsMap,
+    // The partials the overrides include, for those the template lacks.
+    included: PartialsMap,
+}
+
+impl PartialOverrides {
+    pub fn new() -> PartialOverrides {
+        PartialOverrides::default()
+    }
+
+    /// Renders `template` wherever the partial `name` is included. The
+    /// partials it includes itself are looked up in the overrides, then in
+    /// the template being rendered, then in those `template` was compiled
+    /// with.
+    pub fn insert<P: PartialLoader>(&mut self, name: impl Into<String>, template: &Template<P>) {
+        self.partials.insert(name.into(), Arc::new(template.tokens.clone()));
+        for (name, tokens) in &template.partials {
+            self.included.entry(name.clone()).or_insert_with(|| tokens.clone());
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.partials.is_empty()
+    }
+}
+
+/// A template or partial marked with a `{{! @deprecated message }}` comment,
+/// see [`Template::deprecations`](struct.Template.html#method.deprecations).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Deprecation {
+    /// The deprecated partial, `None` for the template itself.
+    pub partial: Option<String>,
+    /// What the comment says after `@deprecated`, such as what to use
+    /// instead.
+    pub message: String,
+}
+
+impl fmt::Display for Deprecation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.partial {
+            Some(ref name) => write!(f, "partial {:?} is deprecated", name)?,
+            None => write!(f, "the template is deprecated")?,
+        }
+        if !self.message.is_empty() {
+            write!(f, ": {}", self.message)?;
+        }
+        Ok(())
+    }
+}
+
+// The message a parsed template was deprecated with.
+fn deprecated(tokens: &[Token]) -> Option<&str> {
+    match tokens.first() {
+        Some(Token::Deprecated(message)) => Some(message),
+        _ => None,
+    }
+}
+
+/// How far a render got, as given to a [`ProgressCallback`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Progress {
+    /// Passes through section contents, counted as for
+    /// [`RenderOptions::max_iterations`](struct.RenderOptions.html#structfield.max_iterations).
+    pub iterations: usize,
+    /// Bytes written out.
+    pub bytes: u64,
+}
+
+/// A part of the output of
+/// [`Template::render_data_traced`](struct.Template.html#method.render_data_traced).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Region {
+    /// Where it is in the output, in bytes.
+    pub range: Range<usize>,
+    /// The partials it was rendered in, outermost first.
+    pub partials: Vec<String>,
+    /// The source of the tag that wrote it, such as `{{name}}` or the
+    /// opening tag of a lambda section, `None` for text of the template.
+    pub tag: Option<String>,
+}
+
+/// A callback for
+/// [`RenderOptions::progress`](struct.RenderOptions.html#structfield.progress),
+/// run every so many iterations or bytes, whichever are set, and once more
+/// when the render succeeds. It runs on the rendering thread, so it should
+/// be quick.
+///
+/// ```
+/// use std::sync::{Arc, Mutex};
+/// use mustache::{MapBuilder, Progress, ProgressCallback, RenderOptions};
+///
+/// let template = mustache::compile_str("{{#items}}{{.}}{{/items}}").unwrap();
+/// let data = MapBuilder::new()
+///     .insert_vec("items", |v| v.push_str("a").push_str("b").push_str("c"))
+///     .build();
+///
+/// let reports = Arc::new(Mutex::new(Vec::new()));
+/// let log = reports.clone();
+/// let progress = ProgressCallback::new(move |progress| log.lock().unwrap().push(progress)).every_iterations(2);
+/// let options = RenderOptions { progress: Some(progress), ..RenderOptions::default() };
+///
+/// template.render_data_with(&mut Vec::new(), &data, &options).unwrap();
+/// assert_eq!(*reports.lock().unwrap(), vec![
+///     Progress { iterations: 2, bytes: 1 },
+///     Progress { iterations: 3, bytes: 3 },
+/// ]);
+/// ```
+#[derive(Clone)]
+pub struct ProgressCallback {
+    every_iterations: Option<usize>,
+    every_bytes: Option<u64>,
+    callback: Arc<dyn Fn(Progress) + Send + Sync>,
+}
+
+impl ProgressCallback {
+    /// Only calls `callback` when the render succeeds, until the intervals
+    /// are set.
+    pub fn new<F: Fn(Progress) + Send + Sync + 'static>(callback: F) -> Self {
+        ProgressCallback {
+            every_iterations: None,
+            every_bytes: None,
+            callback: Arc::new(callback),
+        }
+    }
+
+    /// Reports every `n` iterations.
+    pub fn every_iterations(mut self, n: usize) -> Self {
+        self.every_iterations = Some(n.max(1));
+        self
+    }
+
+    /// Reports every time another `n` bytes have been written.
+    pub fn every_bytes(mut self, n: u64) -> Self {
+        self.every_bytes = Some(n.max(1));
+        self
+    }
+}
+
+impl fmt::Debug for ProgressCallback {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ProgressCallback")
+            .field("every_iterations", &self.every_iterations)
+            .field("every_bytes", &self.every_bytes)
+            .finish()
+    }
+}
+
+// The counts of a render with a progress callback. The writer and the
+// renderer both update it, hence the cells.
+struct Tracker<'a> {
+    callback: &'a ProgressCallback,
+    iterations: Cell<usize>,
+    bytes: Cell<u64>,
+    // When the next reports are due.
+    next_iterations: Cell<usize>,
+    next_bytes: Cell<u64>,
+}
+
+impl<'a> Tracker<'a> {
+    fn new(callback: &'a ProgressCallback) -> Self {
+        Tracker {
+            callback,
+            iterations: Cell::new(0),
+            bytes: Cell::new(0),
+            next_iterations: Cell::new(callback.every_iterations.unwrap_or(usize::MAX)),
+            next_bytes: Cell::new(callback.every_bytes.unwrap_or(u64::MAX)),
+        }
+    }
+
+    fn report(&self) {
+        (self.callback.callback)(Progress { iterations: self.iterations.get(), bytes: self.bytes.get() });
+    }
+
+    fn iterated(&self, iterations: usize) {
+        self.iterations.set(iterations);
+
+        if iterations >= self.next_iterations.get() {
+            if let Some(every) = self.callback.every_iterations {
+                self.next_iterations.set(iterations.saturating_add(every));
+            }
+            self.report();
+        }
+    }
+
+    fn wrote(&self, n: usize) {
+        let bytes = self.bytes.get() + n as u64;
+        self.bytes.set(bytes);
+
+        if bytes >= self.next_bytes.get() {
+            if let Some(every) = self.callback.every_bytes {
+                self.next_bytes.set((bytes - bytes % every).saturating_add(every));
+            }
+            self.report();
+        }
+    }
+}
+
+// Renders a template a few tokens at a time, for `stream`, which gets the
+// data bit by bit as it is serialized.
+pub(crate) struct Pieces<'a, P: PartialLoader> {
+    render_ctx: RenderContext<'a, P>,
+}
+
+impl<'a, P: PartialLoader> Pieces<'a, P> {
+    pub(crate) fn new(template: &'a Template<P>, options: &'a RenderOptions) -> Pieces<'a, P> {
+        Pieces { render_ctx: RenderContext::new(template, options) }
+    }
+
+    // Renders `tokens` with `data` as the context.
+    pub(crate) fn render<W: Write>(&mut self, wr: &mut W, data: &Data, tokens: &'a [Token]) -> Result<()> {
+        self.render_ctx.render(wr, &mut vec![data], tokens)
+    }
+
+    // Renders `tokens` once for `item` of a list in `data`.
+    pub(crate) fn render_item<W: Write>(&mut self,
+                                        wr: &mut W,
+                                        data: &Data,
+                                        item: &Data,
+                                        tokens: &'a [Token]) -> Result<()> {
+        self.render_ctx.iterate()?;
+        self.render_ctx.render(wr, &mut vec![data, item], tokens)
+    }
+}
+
+// Renders a template into a buffer a piece at a time, for the writers of
+// `async_write`, which can only take output when they are ready for it.
+#[cfg(any(feature = "tokio", feature = "futures"))]
+pub(crate) struct Resumable<'a, P: PartialLoader> {
+    render_ctx: RenderContext<'a, P>,
+    stack: Vec<&'a Data>,
+    work: Vec<Frame<'a, 'a>>,
+}
+
+#[cfg(any(feature = "tokio", feature = "futures"))]
+impl<'a, P: PartialLoader> Resumable<'a, P> {
+    pub(crate) fn new(template: &'a Template<P>, data: &'a Data, options: &'a RenderOptions) -> Resumable<'a, P> {
+        Resumable {
+            render_ctx: RenderContext::new(template, options),
+            stack: vec![data],
+            work: vec![Frame::Tokens(template.tokens.iter())],
+        }
+    }
+
+    // Renders into `buf` until it holds `size` bytes or the render is done,
+    // saying whether there is more to come. What a single token renders,
+    // a lambda or a section over a `Data::Iter`, goes in whole.
+    pub(crate) fn fill(&mut self, buf: &mut Vec<u8>, size: usize) -> Result<bool> {
+        while buf.len() < size {
+            match self.work.pop() {
+                Some(frame) => {
+                    let render_ctx = &mut self.render_ctx;
+                    render_ctx.step(buf, &mut self.stack, frame, &mut self.work, None)
+                        .map_err(|err| render_ctx.in_partials(err))?;
+                }
+                None => return Ok(false),
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+// Counts what goes through to the writer of a traced or measured render.
+struct Counted<'c, W> {
+    inner: W,
+    written: &'c Cell<usize>,
+}
+
+impl<'c, W: Write> Write for Counted<'c, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.written.set(self.written.get() + n);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// Counts what goes through to the render's writer.
+struct Tracked<'t, W> {
+    inner: W,
+    tracker: &'t Tracker<'t>,
+}
+
+impl<'t, W: Write> Write for Tracked<'t, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.tracker.wrote(n);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Callbacks around the variable tags and partials of a render, set with
+/// [`RenderOptions::hook`](struct.RenderOptions.html#structfield.hook).
+/// They all do nothing by default.
+///
+/// Tags are given by their name as written, `.` for the implicit iterator,
+/// the value it resolved to, if any, and the tag's source.
+///
+/// ```
+/// use std::sync::{Arc, Mutex};
+/// use mustache::{Data, MapBuilder, RenderHook, RenderOptions};
+///
+/// #[derive(Default)]
+/// struct Audit(Mutex<Vec<String>>);
+///
+/// impl RenderHook for Audit {
+///     fn before_tag(&self, name: &str, _value: Option<&Data>, _src: &str) {
+///         self.0.lock().unwrap().push(name.to_string());
+///     }
+///
+///     fn after_tag(&self, _name: &str, _value: Option<&Data>, _src: &str, output: &mut Vec<u8>) {
+///         output.make_ascii_uppercase();
+///     }
+/// }
+///
+/// let audit = Arc::new(Audit::default());
+/// let options = RenderOptions { hook: Some(audit.clone()), ..RenderOptions::default() };
+///
+/// let template = mustache::compile_str("Hello {{name}}").unwrap();
+/// let data = MapBuilder::new().insert_str("name", "world").build();
+///
+/// let mut out = Vec::new();
+/// template.render_data_with(&mut out, &data, &options).unwrap();
+///
+/// assert_eq!(out, b"Hello WORLD");
+/// assert_eq!(*audit.0.lock().unwrap(), vec!["name".to_string()]);
+/// ```
+pub trait RenderHook: Send + Sync {
+    /// Called before a tag renders.
+    fn before_tag(&self, _name: &str, _value: Option<&Data>, _src: &str) {}
+
+    /// Called with what a tag rendered to, escaping included, before it is
+    /// written. The output may be rewritten.
+    fn after_tag(&self, _name: &str, _value: Option<&Data>, _src: &str, _output: &mut Vec<u8>) {}
+
+    /// Called before the partial `name` renders. Missing and empty partials
+    /// are skipped.
+    fn before_partial(&self, _name: &str) {}
+
+    /// Called once everything the partial `name` renders is written.
+    fn after_partial(&self, _name: &str) {}
+}
+
+/// What a [`Data::Helper`](enum.Data.html#variant.Helper) can see of the
+/// render calling it.
+pub struct Scope<'s> {
+    stack: &'s [&'s Data],
+    source: &'s str,
+    partial: Option<&'s str>,
+    locale: &'s Locale,
+    options: &'s RenderOptions,
+    escape: Escape,
+}
+
+impl<'s> Scope<'s> {
+    /// Looks up a dotted name the way a tag in the helper's section would,
+    /// `.` being the innermost value.
+    pub fn lookup(&self, name: &str) -> Option<&'s Data> {
+        find_with(&parser::split_name(name), self.stack, self.options)
+    }
+
+    /// The innermost value of the context stack, what `{{.}}` would write.
+    pub fn innermost(&self) -> Option<&'s Data> {
+        self.stack.last().cloned()
+    }
+
+    /// The context stack, from the data the render was given to the
+    /// innermost value.
+    pub fn stack(&self) -> &'s [&'s Data] {
+        self.stack
+    }
+
+    /// The helper's section as written in the template, empty for a tag.
+    pub fn source(&self) -> &'s str {
+        self.source
+    }
+
+    /// The name of the innermost partial being rendered, `None` in the
+    /// template itself.
+    pub fn partial(&self) -> Option<&'s str> {
+        self.partial
+    }
+
+    /// The [`RenderOptions::locale`](struct.RenderOptions.html#structfield.locale),
+    /// `en` when unset.
+    pub fn locale(&self) -> &'s Locale {
+        self.locale
+    }
+
+    /// Escapes `text` the way the render escapes `{{name}}` tags, for
+    /// helpers writing values of the data. Characters XML does not allow are
+    /// left out rather than failing.
+    pub fn escape(&self, text: &str) -> String {
+        let text = match self.escape {
+            Escape::Xml => Cow::Owned(text.chars().filter(|&c| is_xml_char(c)).collect()),
+            _ => Cow::Borrowed(text),
+        };
+
+        // Nothing is left to fail on once writing to a vector.
+        let mut escaped = Vec::with_capacity(text.len());
+        let _ = escape(&mut escaped, text.as_bytes(), self.escape);
+        String::from_utf8(escaped).unwrap_or_default()
+    }
+}
+
+// The allocations a render makes along the way, kept by a `Renderer` for
+// the next one. They are always empty in between.
+#[derive(Default)]
+pub struct Buffers {
+    work: Vec<Frame<'static, 'static>>,
+    stack: Vec<&'static Data>,
+    // What escaped values render to before they are escaped.
+    bytes: Vec<u8>,
+}
+
+// An empty `Vec` with the allocation of `v`, for borrows that live longer or
+// shorter. Collecting an emptied `Vec` into one of the same layout reuses it.
+fn recycle<'x, 'y, T: 'x, U: 'y>(mut v: Vec<T>) -> Vec<U> {
+    v.clear();
+    v.into_iter().map(|_| unreachable!()).collect()
+}
+
+// Where the values of a section that makes them as it goes come from, the
+// numbers of a range or the items of a `Data::Iter`.
+enum Values<'d> {
+    Range(RangeInclusive<i64>),
+    Iter(&'d RefCell<Peekable<Box<DataIter>>>),
+}
+
+impl<'d> Values<'d> {
+    fn next(&mut self) -> Option<Data> {
+        match *self {
+            Values::Range(ref mut range) => range.next().map(|number| Data::String(number.to_string())),
+            // Not borrowed for longer, the item's pass may look at the list.
+            Values::Iter(items) => items.borrow_mut().next(),
+        }
+    }
+}
+
+// How many passes over made values may be inside one another, each in a run
+// of its own so that its value is dropped right after it. Deeper passes,
+// which only partials that include themselves make, go on in the innermost
+// run rather than overflow the stack, their values held until it ends.
+const MAX_NESTED_RUNS: usize = 32;
+
+// A value made for a pass, and those made after it in the same run. They are
+// only ever added to, so references to them last as long as the run.
+struct Held {
+    value: Data,
+    next: OnceCell<Box<Held>>,
+}
+
+impl Held {
+    fn new(value: Data) -> Held {
+        Held { value, next: OnceCell::new() }
+    }
+}
+
+impl Drop for Held {
+    // One at a time, dropping a long list through its boxes would overflow
+    // the stack.
+    fn drop(&mut self) {
+        let mut next = self.next.take();
+        while let Some(mut held) = next {
+            next = held.next.take();
+        }
+    }
+}
+
+// The last value held in a run, which the next one goes after.
+struct Holder<'d>(Cell<&'d Held>);
+
+impl<'d> Holder<'d> {
+    fn hold(&self, value: Data) -> &'d Data {
+        let held: &'d Held = self.0.get().next.get_or_init(|| Box::new(Held::new(value)));
+        self.0.set(held);
+        &held.value
+    }
+}
+
+// Work left over by the renderer, see `RenderContext::render`.
+enum Frame<'t, 'd> {
+    Tokens(slice::Iter<'t, Token>),
+    // Renders the tokens once with every item pushed on the stack.
+    Each(slice::Iter<'d, Data>, &'t [Token]),
+    // The same for values made as they are needed, which are held until the
+    // run ends.
+    Made(Values<'d>, &'t [Token]),
+    // Pops the stack once the tokens above it are done.
+    Pop,
+    // Restores the indentation from before a partial.
+    Indent(String),
+    // Leaves the innermost partial, telling the hook.
+    LeavePartial(&'t str),
 }
 
 struct RenderContext<'a, P: PartialLoader> {
     template: &'a Template<P>,
+    options: &'a RenderOptions,
     indent: String,
     line_start: bool,
+    warnings: Option<Vec<Warning>>,
+    // Partials included and section contents rendered so far.
+    partials: usize,
+    iterations: usize,
+    tracker: Option<&'a Tracker<'a>>,
+    // The partials being rendered, innermost last.
+    partial_names: Vec<String>,
+    // The runs of passes over made values inside one another.
+    nested: usize,
+    // Set while an escaped value is written, for an `OutputSink`.
+    escaping: Option<&'a Cell<bool>>,
+    buffers: Buffers,
+    trace: Option<Trace<'a>>,
 }
 
-impl<'a, P: PartialLoader> RenderContext<'a, P> {
-    fn new(template: &'a Template<P>) -> RenderContext<'a, P> {
-        RenderContext {
-            template: template,
-            indent: "".to_string(),
-            line_start: true,
+// The regions of a traced render, and how much of it has been written.
+struct Trace<'a> {
+    written: &'a Cell<usize>,
+    regions: Vec<Region>,
+}
+
+impl<'a, P: PartialLoader> RenderContext<'a, P> {
+    fn new(template: &'a Template<P>, options: &'a RenderOptions) -> RenderContext<'a, P> {
+        RenderContext {
+            template,
+            options,
+            indent: "".to_string(),
+            line_start: true,
+            warnings: None,
+            partials: 0,
+            iterations: 0,
+            tracker: None,
+            partial_names: Vec::new(),
+            nested: 0,
+            escaping: None,
+            buffers: Buffers::default(),
+            trace: None,
+        }
+    }
+
+    // Counts one more pass through a section's content.
+    fn iterate(&mut self) -> Result<()> {
+        self.iterations += 1;
+        if let Some(tracker) = self.tracker {
+            tracker.iterated(self.iterations);
+        }
+        match self.options.max_iterations {
+            Some(max) if self.iterations > max => Err(Error::TooManyIterations(max)),
+            _ => Ok(()),
+        }
+    }
+
+    // Records the output of `token`, from `start`, as regions of its own
+    // but for what the tokens it rendered itself recorded since `mark`.
+    // Only lambdas render tokens from within `render_token`.
+    fn traced(&mut self, token: &Token, start: usize, mark: usize) {
+        let trace = match self.trace {
+            Some(ref mut trace) => trace,
+            None => return,
+        };
+
+        let mut gaps = Vec::new();
+        let mut at = start;
+        for region in &trace.regions[mark..] {
+            if region.range.start > at {
+                gaps.push(at..region.range.start);
+            }
+            at = region.range.end;
+        }
+        if trace.written.get() > at {
+            gaps.push(at..trace.written.get());
+        }
+
+        let tag = match *token {
+            Token::EscapedTag(_, ref src) | Token::UnescapedTag(_, ref src) | Token::PointerTag(_, ref src) |
+            Token::FallbackTag(_, _, ref src) | Token::Section(_, _, _, _, ref src, _, _, _) |
+            Token::Condition(_, _, _, _, ref src, _, _, _) | Token::Range(_, _, _, _, ref src, _, _, _) => Some(src.clone()),
+            _ => None,
+        };
+        // The text rendered from what a lambda returned came from the lambda.
+        for region in &mut trace.regions[mark..] {
+            if region.tag.is_none() {
+                region.tag = tag.clone();
+            }
+        }
+        for range in gaps {
+            trace.regions.push(Region { range, partials: self.partial_names.clone(), tag: tag.clone() });
+        }
+        trace.regions[mark..].sort_by_key(|region| region.range.start);
+    }
+
+    // Records a warning, if anyone is listening.
+    fn warn<F: FnOnce() -> Warning>(&mut self, f: F) {
+        if let Some(ref mut warnings) = self.warnings {
+            warnings.push(f());
+        }
+    }
+
+    // Renders `tokens` with an explicit list of what is left to do rather
+    // than by recursing, so deeply nested data or recursive partials don't
+    // overflow the stack.
+    fn render<'t, 'd, W: Write>(&mut self,
+                                wr: &mut W,
+                                stack: &mut Vec<&'d Data>,
+                                tokens: &'t [Token]) -> Result<()>
+    where 'a: 't
+    {
+        self.render_holding(wr, stack, tokens, None)
+    }
+
+    // Renders `tokens` in a run that may hold the values made by it in
+    // `holder`.
+    fn render_holding<'t, 'd, W: Write>(&mut self,
+                                        wr: &mut W,
+                                        stack: &mut Vec<&'d Data>,
+                                        tokens: &'t [Token],
+                                        holder: Option<&Holder<'d>>) -> Result<()>
+    where 'a: 't
+    {
+        let mut work = recycle(mem::take(&mut self.buffers.work));
+        work.push(Frame::Tokens(tokens.iter()));
+
+        let rendered = self.run(wr, stack, &mut work, holder).map_err(|err| self.in_partials(err));
+        self.buffers.work = recycle(work);
+        rendered
+    }
+
+    // Tells which partials were being rendered when `err` happened, unless
+    // a nested render already did or it's a limit on the whole render.
+    fn in_partials(&self, err: Error) -> Error {
+        match err {
+            Error::InPartials { .. } | Error::Cancelled | Error::TooManyPartials(_) | Error::TooManyIterations(_) => err,
+            _ if self.partial_names.is_empty() => err,
+            _ => Error::InPartials { partials: self.partial_names.clone(), source: Box::new(err) },
+        }
+    }
+
+    fn run<'t, 'd, W: Write>(&mut self,
+                             wr: &mut W,
+                             stack: &mut Vec<&'d Data>,
+                             work: &mut Vec<Frame<'t, 'd>>,
+                             holder: Option<&Holder<'d>>) -> Result<()>
+    where 'a: 't
+    {
+        while let Some(frame) = work.pop() {
+            self.step(wr, stack, frame, work, holder)?;
         }
+
+        Ok(())
     }
 
-    fn render<W: Write>(&mut self, wr: &mut W, stack: &mut Vec<&Data>, tokens: &[Token]) -> Result<()> {
-        for token in tokens.iter() {
-            self.render_token(wr, stack, token)?;
+    // Does the work of one frame, leaving what comes of it to `work`.
+    fn step<'t, 'd, W: Write>(&mut self,
+                              wr: &mut W,
+                              stack: &mut Vec<&'d Data>,
+                              frame: Frame<'t, 'd>,
+                              work: &mut Vec<Frame<'t, 'd>>,
+                              holder: Option<&Holder<'d>>) -> Result<()>
+    where 'a: 't
+    {
+        match frame {
+            Frame::Tokens(mut tokens) => {
+                if let Some(token) = tokens.next() {
+                    work.push(Frame::Tokens(tokens));
+
+                    if let Some(ref cancel) = self.options.cancel {
+                        if cancel.load(Ordering::Relaxed) {
+                            return Err(Error::Cancelled);
+                        }
+                    }
+
+                    match self.trace {
+                        Some(ref trace) => {
+                            let (start, mark) = (trace.written.get(), trace.regions.len());
+                            self.render_token(wr, stack, token, work, holder)?;
+                            self.traced(token, start, mark);
+                        }
+                        None => self.render_token(wr, stack, token, work, holder)?,
+                    }
+                }
+            }
+            Frame::Each(mut items, children) => {
+                if let Some(item) = items.next() {
+                    self.iterate()?;
+                    work.push(Frame::Each(items, children));
+                    stack.push(item);
+                    work.push(Frame::Pop);
+                    work.push(Frame::Tokens(children.iter()));
+                }
+            }
+            Frame::Made(mut values, children) => {
+                if let (Some(value), Some(holder)) = (values.next(), holder) {
+                    self.iterate()?;
+                    work.push(Frame::Made(values, children));
+                    stack.push(holder.hold(value));
+                    work.push(Frame::Pop);
+                    work.push(Frame::Tokens(children.iter()));
+                }
+            }
+            Frame::Pop => {
+                stack.pop();
+            }
+            Frame::Indent(indent) => self.indent = indent,
+            Frame::LeavePartial(name) => {
+                self.partial_names.pop();
+                if let Some(ref hook) = self.options.hook {
+                    hook.after_partial(name);
+                }
+            }
         }
 
         Ok(())
     }
 
-    fn render_token<W: Write>(&mut self, wr: &mut W, stack: &mut Vec<&Data>, token: &Token) -> Result<()> {
+    // Renders a token, leaving anything nested in it to `work`.
+    fn render_token<'t, 'd, W: Write>(&mut self,
+                                      wr: &mut W,
+                                      stack: &mut Vec<&'d Data>,
+                                      token: &'t Token,
+                                      work: &mut Vec<Frame<'t, 'd>>,
+                                      holder: Option<&Holder<'d>>) -> Result<()>
+    where 'a: 't
+    {
         match *token {
-            Token::Text(ref value) => {
+            Token::Text(ref value) | Token::Raw(ref value, _) => {
                 self.render_text(wr, value)
             }
-            Token::EscapedTag(ref path, _) => {
-                self.render_etag(wr, stack, path)
+            Token::EscapedTag(ref path, ref src) => {
+                match self.options.hook {
+                    Some(ref hook) => {
+                        let value = self.find(path, stack);
+                        self.render_hooked(wr, stack, &**hook, &tag_name(path), value, src, |ctx, wr, stack| {
+                            ctx.render_etag(wr, stack, path)
+                        })
+                    }
+                    None => self.render_etag(wr, stack, path),
+                }
+            }
+            Token::UnescapedTag(ref path, ref src) => {
+                match self.options.hook {
+                    Some(ref hook) => {
+                        let value = self.find(path, stack);
+                        self.render_hooked(wr, stack, &**hook, &tag_name(path), value, src, |ctx, wr, stack| {
+                            ctx.render_raw(wr, stack, path)
+                        })
+                    }
+                    None => self.render_raw(wr, stack, path),
+                }
+            }
+            Token::PointerTag(ref pointer, ref src) => {
+                match self.options.hook {
+                    Some(ref hook) => {
+                        let value = stack.first().and_then(|root| find_pointer(pointer, root));
+                        self.render_hooked(wr, stack, &**hook, &join_pointer(pointer), value, src, |ctx, wr, stack| {
+                            ctx.render_pointer(wr, stack, pointer)
+                        })
+                    }
+                    None => self.render_pointer(wr, stack, pointer),
+                }
             }
-            Token::UnescapedTag(ref path, _) => {
-                self.render_utag(wr, stack, path)
+            Token::FallbackTag(ref paths, escaped, ref src) => {
+                let path = self.first_written(paths, stack);
+                match self.options.hook {
+                    Some(ref hook) => {
+                        let value = self.find(path, stack);
+                        self.render_hooked(wr, stack, &**hook, &tag_name(path), value, src, |ctx, wr, stack| {
+                            if escaped {
+                                ctx.render_etag(wr, stack, path)
+                            } else {
+                                ctx.render_raw(wr, stack, path)
+                            }
+                        })
+                    }
+                    None if escaped => self.render_etag(wr, stack, path),
+                    None => self.render_raw(wr, stack, path),
+                }
             }
             Token::Section(ref path, true, ref children, _, _, _, _, _) => {
-                self.render_inverted_section(wr, stack, path, children)
+                self.render_inverted_section(stack, path, children, work)
+            }
+            Token::Condition(ref comparison, inverted, ref children, _, _, _, _, _) => {
+                if holds(comparison, |path| self.find(path, stack)) != inverted {
+                    self.iterate()?;
+                    work.push(Frame::Tokens(children.iter()));
+                }
+                Ok(())
+            }
+            Token::Range(ref bounds, inverted, ref children, _, _, _, _, _) => {
+                let range = numbers(bounds, |path| self.find(path, stack));
+                if inverted {
+                    if range.is_empty() {
+                        self.iterate()?;
+                        work.push(Frame::Tokens(children.iter()));
+                    }
+                    return Ok(());
+                }
+                self.render_values(wr, stack, Values::Range(range), children, work, holder)
             }
             Token::Section(ref path, false, ref children, ref otag, _, ref src, _, ref ctag) => {
-                self.render_section(wr, stack, path, children, src, otag, ctag)
+                self.render_section(wr, stack, path, children, src, otag, ctag, work, holder)
             }
             Token::Partial(ref name, ref indent, _) => {
-                self.render_partial(wr, stack, name, indent)
+                self.render_partial(name, indent, work)
+            }
+            Token::PartialWithFallback(ref name, ref indent, ref children, _, _, _) => {
+                match self.partial(name) {
+                    Some(tokens) if !tokens.is_empty() => self.render_partial(name, indent, work),
+                    _ => {
+                        work.push(Frame::Tokens(children.iter()));
+                        Ok(())
+                    }
+                }
             }
-            Token::IncompleteSection(..) => {
+            // Only the partials lookup cares about definitions.
+            Token::Definition(..) | Token::Deprecated(_) => Ok(()),
+            Token::IncompleteSection(..) | Token::IncompleteDefinition(..) => {
                 bug!("render_token should not encounter IncompleteSections");
                 Err(Error::IncompleteSection)
             }
         }
     }
 
+    // Renders a tag into a buffer so `hook` gets to see and rewrite it.
+    #[allow(clippy::too_many_arguments)]
+    fn render_hooked<'d, W, F>(&mut self,
+                               wr: &mut W,
+                               stack: &mut Vec<&'d Data>,
+                               hook: &dyn RenderHook,
+                               name: &str,
+                               value: Option<&Data>,
+                               src: &str,
+                               render: F) -> Result<()>
+    where W: Write,
+          F: FnOnce(&mut Self, &mut Vec<u8>, &mut Vec<&'d Data>) -> Result<()>
+    {
+        hook.before_tag(name, value, src);
+
+        let mut output = vec![];
+        render(self, &mut output, stack)?;
+
+        hook.after_tag(name, value, src, &mut output);
+        wr.write_all(&output)?;
+
+        Ok(())
+    }
+
+    fn write_escaped<W: Write>(&self, wr: &mut W, bytes: &[u8]) -> Result<()> {
+        let escaping = match self.escaping {
+            Some(escaping) => escaping,
+            None => return escape(wr, bytes, self.template.ctx.escape),
+        };
+
+        // In one piece rather than however `escape` splits it up.
+        let mut escaped = Vec::with_capacity(bytes.len());
+        escape(&mut escaped, bytes, self.template.ctx.escape)?;
+
+        escaping.set(true);
+        let written = wr.write_all(&escaped);
+        escaping.set(false);
+        Ok(written?)
+    }
+
     fn write_tracking_newlines<W: Write>(&mut self, wr: &mut W, value: &str) -> Result<()> {
         wr.write_all(value.as_bytes())?;
         self.line_start = match value.chars().last() {
@@ -123,6 +2366,12 @@ impl<'a, P: PartialLoader> RenderContext<'a, P> {
         Ok(())
     }
 
+    // Writes a value of the data, minding its bidi controls.
+    fn write_value<W: Write>(&mut self, wr: &mut W, value: &str) -> Result<()> {
+        let value = bidi_controls(value, self.options.bidi_controls)?;
+        self.write_tracking_newlines(wr, &value)
+    }
+
     fn write_indent<W: Write>(&mut self, wr: &mut W) -> Result<()> {
         if self.line_start {
             wr.write_all(self.indent.as_bytes())?;
@@ -154,7 +2403,8 @@ impl<'a, P: PartialLoader> RenderContext<'a, P> {
                     }
                 };
 
-                if line.as_bytes()[0] != b'\n' {
+                // Blank lines stay blank, whichever line ending they use.
+                if line != "\n" && line != "\r\n" {
                     self.write_indent(wr)?;
                 }
 
@@ -166,119 +2416,232 @@ impl<'a, P: PartialLoader> RenderContext<'a, P> {
     }
 
     fn render_etag<W: Write>(&mut self, wr: &mut W, stack: &mut Vec<&Data>, path: &[String]) -> Result<()> {
-        let mut bytes = vec![];
+        let mut bytes = mem::take(&mut self.buffers.bytes);
 
         self.render_utag(&mut bytes, stack, path)?;
 
-        for b in bytes {
-            match b {
-                b'<' => wr.write_all(b"&lt;")?,
-                b'>' => wr.write_all(b"&gt;")?,
-                b'&' => wr.write_all(b"&amp;")?,
-                b'"' => wr.write_all(b"&quot;")?,
-                b'\'' => wr.write_all(b"&#39;")?,
-                _ => wr.write_all(&[b])?,
+        self.write_escaped(wr, &bytes)?;
+        bytes.clear();
+        self.buffers.bytes = bytes;
+        Ok(())
+    }
+
+    fn render_pointer<W: Write>(&mut self, wr: &mut W, stack: &mut Vec<&Data>, pointer: &[String]) -> Result<()> {
+        // Pointers start from the data the template was rendered with.
+        match stack.first().and_then(|root| find_pointer(pointer, root)) {
+            None => self.warn(|| Warning::MissingKey(join_pointer(pointer))),
+            Some(value) => {
+                let mut bytes = mem::take(&mut self.buffers.bytes);
+
+                self.render_value(&mut bytes, stack, pointer, value)?;
+
+                self.write_escaped(wr, &bytes)?;
+                bytes.clear();
+                self.buffers.bytes = bytes;
             }
-        }
+        };
 
         Ok(())
     }
 
+    // The first of the names of a fallback chain that has something to
+    // write, else the last.
+    fn first_written<'t>(&self, paths: &'t [Vec<String>], stack: &[&Data]) -> &'t [String] {
+        paths.iter()
+            .find(|path| writes_something(self.find(path, stack)))
+            .or_else(|| paths.last())
+            .map_or(&[], |path| &path[..])
+    }
+
+    // Renders an unescaped tag, through the sanitizer if there is one.
+    fn render_raw<W: Write>(&mut self, wr: &mut W, stack: &mut Vec<&Data>, path: &[String]) -> Result<()> {
+        #[cfg(feature = "ammonia")]
+        {
+            if let Some(ref sanitizer) = self.options.sanitize {
+                let mut html = Vec::new();
+                self.render_utag(&mut html, stack, path)?;
+                let html = String::from_utf8(html).map_err(|_| Error::InvalidStr)?;
+                wr.write_all(sanitizer.clean(&html).as_bytes())?;
+                return Ok(());
+            }
+        }
+
+        self.render_utag(wr, stack, path)
+    }
+
     fn render_utag<W: Write>(&mut self, wr: &mut W, stack: &mut Vec<&Data>, path: &[String]) -> Result<()> {
         match self.find(path, stack) {
-            None => {}
-            Some(value) => {
-                self.write_indent(wr)?;
+            None => self.warn(|| Warning::MissingKey(path.join("."))),
+            Some(value) => self.render_value(wr, stack, path, value)?,
+        };
 
-                // Currently this doesn't allow Option<Option<Foo>>, which
-                // would be un-nameable in the view anyway, so I'm unsure if it's
-                // a real problem. Having {{foo}} render only when `foo = Some(Some(val))`
-                // seems unintuitive and may be surprising in practice.
-                if let Data::Null = *value {
-                    return Ok(());
-                }
+        Ok(())
+    }
 
-                match *value {
-                    Data::String(ref value) => {
-                        self.write_tracking_newlines(wr, value)?;
-                    }
+    fn render_value<W: Write>(&mut self,
+                              wr: &mut W,
+                              stack: &mut Vec<&Data>,
+                              path: &[String],
+                              value: &Data) -> Result<()> {
+        self.write_indent(wr)?;
 
-                    // etags and utags use the default delimiter.
-                    Data::Fun(ref fcell) => {
-                        let f = &mut *fcell.borrow_mut();
-                        let tokens = self.render_fun("", "{{", "}}", f)?;
-                        self.render(wr, stack, &tokens)?;
-                    }
+        match *value {
+            // A `None` was encoded as `Null`, which renders nothing.
+            Data::Null => {}
 
-                    ref value => {
-                        bug!("render_utag: unexpected value {:?}", value);
-                    }
-                }
+            Data::String(ref value) => {
+                self.write_value(wr, value)?;
+            }
+
+            Data::Bytes(ref value) => {
+                self.write_value(wr, &encode_bytes(value, self.options.bytes))?;
+            }
+
+            Data::Helper(_) => {
+                let output = self.call_helper(stack, path, value, String::new(), "")?;
+                self.write_value(wr, &output)?;
+            }
+
+            // etags and utags use the default delimiter.
+            Data::Fun(_) | Data::TryFun(_) => {
+                let tokens = self.render_fun(path, value, "", "{{", "}}")?;
+                self.render(wr, stack, &tokens)?;
             }
-        };
+
+            ref value => {
+                bug!("render_value: unexpected value {:?}", value);
+            }
+        }
 
         Ok(())
     }
 
-    fn render_inverted_section<W: Write>(&mut self,
-                                         wr: &mut W,
-                                         stack: &mut Vec<&Data>,
-                                         path: &[String],
-                                         children: &[Token]) -> Result<()> {
+    fn render_inverted_section<'t, 'd>(&mut self,
+                                       stack: &[&'d Data],
+                                       path: &[String],
+                                       children: &'t [Token],
+                                       work: &mut Vec<Frame<'t, 'd>>) -> Result<()> {
+        // A missing value is what inverted sections are for, so it is not
+        // worth a warning.
         match self.find(path, stack) {
             None => {}
             Some(&Data::Null) => {}
             Some(&Data::Bool(false)) => {}
-            Some(&Data::Vec(ref xs)) if xs.is_empty() => {}
+            Some(Data::Vec(xs)) if xs.is_empty() => {}
+            Some(Data::Iter(items)) if items.borrow_mut().peek().is_none() => {}
             Some(_) => {
                 return Ok(());
             }
         }
 
-        self.render(wr, stack, children)
+        self.iterate()?;
+        work.push(Frame::Tokens(children.iter()));
+        Ok(())
     }
 
-    fn render_section<W: Write>(&mut self,
-                                wr: &mut W,
-                                stack: &mut Vec<&Data>,
-                                path: &[String],
-                                children: &[Token],
-                                src: &str,
-                                otag: &str,
-                                ctag: &str) -> Result<()> {
+    // Renders the tokens once for every value made. Each value only lives
+    // while its pass is rendered, in a run of its own, unless the passes are
+    // already too deep in one another.
+    fn render_values<'t, 'd, W: Write>(&mut self,
+                                       wr: &mut W,
+                                       stack: &mut Vec<&'d Data>,
+                                       mut values: Values<'d>,
+                                       children: &'t [Token],
+                                       work: &mut Vec<Frame<'t, 'd>>,
+                                       holder: Option<&Holder<'d>>) -> Result<()>
+    where 'a: 't
+    {
+        if holder.is_some() && self.nested >= MAX_NESTED_RUNS {
+            work.push(Frame::Made(values, children));
+            return Ok(());
+        }
+
+        let mut inner: Vec<&Data> = Vec::with_capacity(stack.len() + 1);
+        while let Some(value) = values.next() {
+            self.iterate()?;
+
+            let held = Held::new(value);
+            let holder = Holder(Cell::new(&held));
+            let mut pass = recycle(inner);
+            pass.extend(stack.iter().cloned());
+            pass.push(&held.value);
+
+            self.nested += 1;
+            let rendered = self.render_holding(wr, &mut pass, children, Some(&holder));
+            self.nested -= 1;
+            rendered?;
+            inner = recycle(pass);
+        }
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn render_section<'t, 'd, W: Write>(&mut self,
+                                        wr: &mut W,
+                                        stack: &mut Vec<&'d Data>,
+                                        path: &[String],
+                                        children: &'t [Token],
+                                        src: &str,
+                                        otag: &str,
+                                        ctag: &str,
+                                        work: &mut Vec<Frame<'t, 'd>>,
+                                        holder: Option<&Holder<'d>>) -> Result<()>
+    where 'a: 't
+    {
         match self.find(path, stack) {
-            None => {}
+            None => self.warn(|| Warning::MissingKey(path.join("."))),
             Some(value) => {
                 match *value {
                     Data::Null => {
                         // do nothing
                     }
-                    Data::Bool(true) => self.render(wr, stack, children)?,
+                    Data::Bool(true) => {
+                        self.iterate()?;
+                        work.push(Frame::Tokens(children.iter()));
+                    }
                     Data::Bool(false) => (),
+                    Data::Bytes(ref val) if !val.is_empty() => {
+                        self.iterate()?;
+                        stack.push(value);
+                        work.push(Frame::Pop);
+                        work.push(Frame::Tokens(children.iter()));
+                    }
+                    Data::Bytes(_) => (),
                     Data::String(ref val) => {
                         if !val.is_empty() {
+                            self.iterate()?;
                             stack.push(value);
-                            self.render(wr, stack, children)?;
-                            stack.pop();
+                            work.push(Frame::Pop);
+                            work.push(Frame::Tokens(children.iter()));
                         }
                     }
-                    Data::Vec(ref vs) => {
-                        for v in vs.iter() {
-                            stack.push(v);
-                            self.render(wr, stack, children)?;
-                            stack.pop();
-                        }
+                    Data::Vec(ref vs) => work.push(Frame::Each(vs.iter(), children)),
+                    Data::Iter(ref items) => {
+                        self.render_values(wr, stack, Values::Iter(items), children, work, holder)?;
                     }
                     Data::Map(_) => {
+                        self.iterate()?;
                         stack.push(value);
-                        self.render(wr, stack, children)?;
-                        stack.pop();
+                        work.push(Frame::Pop);
+                        work.push(Frame::Tokens(children.iter()));
                     }
-                    Data::Fun(ref fcell) => {
-                        let f = &mut *fcell.borrow_mut();
-                        let tokens = self.render_fun(src, otag, ctag, f)?;
+                    Data::Fun(_) | Data::TryFun(_) => {
+                        let tokens = self.render_fun(path, value, src, otag, ctag)?;
                         self.render(wr, stack, &tokens)?;
                     }
+                    Data::Helper(_) => {
+                        // The content is rendered without indentation, the
+                        // lambda's output is indented where it lands.
+                        let (indent, line_start) = (mem::take(&mut self.indent), self.line_start);
+                        let mut content = Vec::new();
+                        self.render(&mut content, stack, children)?;
+                        self.indent = indent;
+                        self.line_start = line_start;
+
+                        let content = String::from_utf8(content).map_err(|_| Error::InvalidStr)?;
+                        let output = self.call_helper(stack, path, value, content, src)?;
+                        self.render_text(wr, &output)?;
+                    }
                 }
             }
         };
@@ -286,34 +2649,58 @@ impl<'a, P: PartialLoader> RenderContext<'a, P> {
         Ok(())
     }
 
-    fn render_partial<W: Write>(&mut self,
-                                wr: &mut W,
-                                stack: &mut Vec<&Data>,
-                                name: &str,
-                                indent: &str) -> Result<()> {
-        match self.template.partials.get(name) {
-            None => (),
-            Some(ref tokens) => {
-                let mut indent = self.indent.clone() + indent;
-
-                mem::swap(&mut self.indent, &mut indent);
-                self.render(wr, stack, &tokens)?;
-                mem::swap(&mut self.indent, &mut indent);
+    fn render_partial<'t, 'd>(&mut self, name: &'t str, indent: &str, work: &mut Vec<Frame<'t, 'd>>) -> Result<()>
+    where 'a: 't
+    {
+        match self.partial(name) {
+            Some(tokens) if !tokens.is_empty() => {
+                self.partials += 1;
+                if let Some(max) = self.options.max_partials {
+                    if self.partials > max {
+                        return Err(Error::TooManyPartials(max));
+                    }
+                }
+
+                let indent = self.indent.clone() + indent;
+
+                if let Some(ref hook) = self.options.hook {
+                    hook.before_partial(name);
+                }
+                self.partial_names.push(name.to_string());
+                work.push(Frame::LeavePartial(name));
+                work.push(Frame::Indent(mem::replace(&mut self.indent, indent)));
+                work.push(Frame::Tokens(tokens.iter()));
             }
+            _ => self.warn(|| Warning::EmptyPartial(name.to_string())),
         };
 
         Ok(())
     }
 
     fn render_fun(&self,
+                  path: &[String],
+                  value: &Data,
                   src: &str,
                   otag: &str,
-                  ctag: &str,
-                  f: &mut Box<dyn FnMut(String) -> String + Send + 'static>)
+                  ctag: &str)
                   -> Result<Vec<Token>> {
-        let src = f(src.to_string());
+        let src = match *value {
+            Data::Fun(ref fcell) => {
+                let f = &mut *fcell.borrow_mut();
+                self.call(path, || f(src.to_string()))?
+            }
+            Data::TryFun(ref fcell) => {
+                let f = &mut *fcell.borrow_mut();
+                self.call(path, || f(src.to_string()))?
+                    .map_err(|source| Error::Lambda { name: path.join("."), source })?
+            }
+            ref value => {
+                bug!("render_fun: unexpected value {:?}", value);
+                return Ok(Vec::new());
+            }
+        };
 
-        let compiler = Compiler::new_with(self.template.ctx.clone(),
+        let compiler = Compiler::new_with(&self.template.ctx,
                                           src.chars(),
                                           self.template.partials.clone(),
                                           otag.to_string(),
@@ -323,60 +2710,455 @@ impl<'a, P: PartialLoader> RenderContext<'a, P> {
         Ok(tokens)
     }
 
-    fn find<'c>(&self, path: &[String], stack: &mut Vec<&'c Data>) -> Option<&'c Data> {
-        // If we have an empty path, we just want the top value in our stack.
-        if path.is_empty() {
-            match stack.last() {
-                None => {
-                    return None;
-                }
-                Some(data) => {
-                    return Some(*data);
-                }
+    // Unlike the other lambdas', the output is text: it is made from
+    // content that was already rendered, tags from the data included.
+    fn call_helper(&self,
+                   stack: &[&Data],
+                   path: &[String],
+                   value: &Data,
+                   content: String,
+                   src: &str)
+                   -> Result<String> {
+        let fcell = match *value {
+            Data::Helper(ref fcell) => fcell,
+            ref value => {
+                bug!("call_helper: unexpected value {:?}", value);
+                return Ok(String::new());
             }
+        };
+
+        let default;
+        let locale = match self.options.locale {
+            Some(ref locale) => locale,
+            None => {
+                default = Locale::default();
+                &default
+            }
+        };
+        let scope = Scope {
+            stack,
+            source: src,
+            partial: self.partial_names.last().map(|name| &name[..]),
+            locale,
+            options: self.options,
+            escape: self.template.ctx.escape,
+        };
+
+        let f = &mut *fcell.borrow_mut();
+        self.call(path, || f(content, &scope))
+    }
+
+    // Calls the lambda at `path`, catching its panics if asked to.
+    fn call<T, F: FnOnce() -> T>(&self, path: &[String], f: F) -> Result<T> {
+        if !self.options.catch_lambda_panics {
+            return Ok(f());
         }
 
-        // Otherwise, find the stack that has the first part of our path.
-        let mut value = None;
+        panic::catch_unwind(AssertUnwindSafe(f)).map_err(|_| Error::LambdaPanicked { name: path.join(".") })
+    }
 
-        for data in stack.iter().rev() {
-            match **data {
-                Data::Map(ref m) => {
-                    if let Some(v) = m.get(&path[0]) {
-                        value = Some(v);
-                        break;
-                    }
-                }
-                _ => { /* continue searching the stack */ },
+    fn find<'c>(&self, path: &[String], stack: &[&'c Data]) -> Option<&'c Data> {
+        find_with(path, stack, self.options)
+    }
+
+    // The tokens of the partial `name`, overrides first.
+    fn partial(&self, name: &str) -> Option<&'a Arc<Vec<Token>>> {
+        let overrides = &self.options.partials;
+        overrides.partials.get(name)
+            .or_else(|| self.template.partials.get(name))
+            .or_else(|| overrides.included.get(name))
+    }
+}
+
+/// Whether `comparison` holds, looking names up with `find`. See
+/// [`Extensions::comparisons`](struct.Extensions.html#structfield.comparisons).
+pub fn holds<'c, F>(comparison: &Comparison, mut find: F) -> bool
+where F: FnMut(&[String]) -> Option<&'c Data>
+{
+    let mut side = |operand: &Operand| match *operand {
+        Operand::Name(ref path) => match find(path) {
+            Some(Data::String(value)) => Some(value.clone()),
+            Some(Data::Bool(value)) => Some(value.to_string()),
+            Some(Data::Vec(items)) => Some(items.len().to_string()),
+            _ => None,
+        },
+        Operand::Text(ref text) | Operand::Number(ref text) => Some(text.clone()),
+    };
+
+    let ordering = match (side(&comparison.left), side(&comparison.right)) {
+        (Some(left), Some(right)) => match (left.parse::<f64>(), right.parse::<f64>()) {
+            (Ok(left), Ok(right)) => left.partial_cmp(&right),
+            _ => Some(left.cmp(&right)),
+        },
+        (None, None) => Some(cmp::Ordering::Equal),
+        _ => None,
+    };
+
+    match comparison.op {
+        CompareOp::Eq => ordering == Some(cmp::Ordering::Equal),
+        CompareOp::Ne => ordering != Some(cmp::Ordering::Equal),
+        CompareOp::Lt => ordering == Some(cmp::Ordering::Less),
+        CompareOp::Le => ordering == Some(cmp::Ordering::Less) || ordering == Some(cmp::Ordering::Equal),
+        CompareOp::Gt => ordering == Some(cmp::Ordering::Greater),
+        CompareOp::Ge => ordering == Some(cmp::Ordering::Greater) || ordering == Some(cmp::Ordering::Equal),
+    }
+}
+
+/// The numbers a range section goes through. An end that is missing or
+/// isn't a whole number makes it empty, a list counts as its number of
+/// items.
+pub fn numbers<'c, F>(bounds: &Bounds, mut find: F) -> RangeInclusive<i64>
+where F: FnMut(&[String]) -> Option<&'c Data>
+{
+    let mut bound = |operand: &Operand| match *operand {
+        Operand::Name(ref path) => match find(path) {
+            Some(Data::String(value)) => value.trim().parse().ok(),
+            Some(Data::Vec(items)) => Some(items.len() as i64),
+            _ => None,
+        },
+        Operand::Number(ref number) => number.parse().ok(),
+        Operand::Text(_) => None,
+    };
+
+    match (bound(&bounds.start), bound(&bounds.end)) {
+        (Some(start), Some(end)) => start..=end,
+        #[allow(clippy::reversed_empty_ranges)]
+        _ => 1..=0,
+    }
+}
+
+/// Whether a variable tag with `value` writes anything out: lambdas do,
+/// strings and bytes unless empty, nothing else does.
+pub fn writes_something(value: Option<&Data>) -> bool {
+    match value {
+        Some(Data::String(v)) => !v.is_empty(),
+        Some(Data::Bytes(v)) => !v.is_empty(),
+        Some(Data::Fun(_)) | Some(Data::TryFun(_)) | Some(Data::Helper(_)) => true,
+        _ => false,
+    }
+}
+
+/// The name of a tag as it was written.
+pub fn tag_name(path: &[String]) -> String {
+    if path.is_empty() {
+        ".".to_string()
+    } else {
+        path.join(".")
+    }
+}
+
+/// How `{{name}}` tags escape their values, set on the
+/// [`Context`](struct.Context.html).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Escape {
+    /// The HTML special characters, the default.
+    Html,
+    /// The XML special characters, failing the render with
+    /// [`Error::InvalidXmlChar`](enum.Error.html#variant.InvalidXmlChar) on
+    /// control characters XML 1.0 does not allow.
+    Xml,
+    /// The LaTeX special characters, `\ % $ & # _ { } ^ ~`, for documents
+    /// compiled with LaTeX.
+    Latex,
+    /// Nothing, for plain text such as the text body of an email.
+    Plain,
+}
+
+// `#[default]` on the variant needs a newer compiler.
+#[allow(clippy::derivable_impls)]
+impl Default for Escape {
+    fn default() -> Escape {
+        Escape::Html
+    }
+}
+
+/// How [`Data::Bytes`](enum.Data.html#variant.Bytes) values render, set in
+/// [`RenderOptions::bytes`](struct.RenderOptions.html#structfield.bytes).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BytesEncoding {
+    /// Standard base64 with padding, the default, as in `data:` URLs.
+    Base64,
+    /// URL-safe base64 without padding, as in JWT segments.
+    Base64Url,
+    /// Lowercase hexadecimal.
+    Hex,
+    /// As UTF-8, invalid sequences becoming U+FFFD.
+    Utf8Lossy,
+}
+
+#[allow(clippy::derivable_impls)]
+impl Default for BytesEncoding {
+    fn default() -> BytesEncoding {
+        BytesEncoding::Base64
+    }
+}
+
+/// What the values of the data have their Unicode bidirectional controls
+/// turned into, set in
+/// [`RenderOptions::bidi_controls`](struct.RenderOptions.html#structfield.bidi_controls).
+/// These are the embeddings, overrides and isolates U+202A to U+202E and
+/// U+2066 to U+2069, and the marks U+061C, U+200E and U+200F.
+///
+/// ```rust
+/// use mustache::{BidiControls, MapBuilder, RenderOptions};
+///
+/// let template = mustache::compile_str("if (user == \"{{name}}\")").unwrap();
+/// let data = MapBuilder::new().insert_str("name", "admin\u{202E} \u{2066}// guest").build();
+///
+/// let render = |bidi_controls| {
+///     let mut out = Vec::new();
+///     let options = RenderOptions { bidi_controls, ..RenderOptions::default() };
+///     template.render_data_with(&mut out, &data, &options).map(|_| String::from_utf8(out).unwrap())
+/// };
+/// assert_eq!(render(BidiControls::Strip).unwrap(), "if (user == \"admin // guest\")");
+/// assert_eq!(render(BidiControls::Escape).unwrap(), "if (user == \"admin\\u202E \\u2066// guest\")");
+/// assert!(render(BidiControls::Reject).is_err());
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BidiControls {
+    /// Written out as they are, the default.
+    Keep,
+    /// Left out.
+    Strip,
+    /// Written as `\u202E` escapes, which read as the character again in
+    /// the string literals of most languages and JSON.
+    Escape,
+    /// Fail the render with
+    /// [`Error::BidiControl`](enum.Error.html#variant.BidiControl).
+    Reject,
+}
+
+#[allow(clippy::derivable_impls)]
+impl Default for BidiControls {
+    fn default() -> BidiControls {
+        BidiControls::Keep
+    }
+}
+
+fn is_bidi_control(c: char) -> bool {
+    matches!(c, '\u{061C}' | '\u{200E}' | '\u{200F}' | '\u{202A}'..='\u{202E}' | '\u{2066}'..='\u{2069}')
+}
+
+fn bidi_controls(value: &str, mode: BidiControls) -> Result<Cow<'_, str>> {
+    if mode == BidiControls::Keep || !value.chars().any(is_bidi_control) {
+        return Ok(Cow::Borrowed(value));
+    }
+
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        if !is_bidi_control(c) {
+            out.push(c);
+            continue;
+        }
+        match mode {
+            BidiControls::Escape => out.push_str(&format!("\\u{:04X}", c as u32)),
+            BidiControls::Reject => return Err(Error::BidiControl(c)),
+            BidiControls::Keep | BidiControls::Strip => {}
+        }
+    }
+    Ok(Cow::Owned(out))
+}
+
+fn encode_bytes(bytes: &[u8], encoding: BytesEncoding) -> Cow<'_, str> {
+    const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    const BASE64_URL: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+    const HEX: &[u8; 16] = b"0123456789abcdef";
+
+    let alphabet = match encoding {
+        BytesEncoding::Base64 => BASE64,
+        BytesEncoding::Base64Url => BASE64_URL,
+        BytesEncoding::Hex => {
+            let mut out = String::with_capacity(bytes.len() * 2);
+            for &b in bytes {
+                out.push(HEX[usize::from(b >> 4)] as char);
+                out.push(HEX[usize::from(b & 0xf)] as char);
+            }
+            return Cow::Owned(out);
+        }
+        BytesEncoding::Utf8Lossy => return String::from_utf8_lossy(bytes),
+    };
+
+    let mut out = String::with_capacity(bytes.len() / 3 * 4 + 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, &b)| n | u32::from(b) << (16 - 8 * i));
+        // Three bytes make four characters, fewer make one more than them.
+        for i in 0..=chunk.len() {
+            out.push(alphabet[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+        }
+        if encoding == BytesEncoding::Base64 {
+            for _ in chunk.len()..3 {
+                out.push('=');
             }
         }
+    }
+    Cow::Owned(out)
+}
+
+// Writes `bytes` with the special characters of `mode` escaped.
+fn escape<W: Write>(wr: &mut W, bytes: &[u8], mode: Escape) -> Result<()> {
+    if mode == Escape::Xml {
+        let value = str::from_utf8(bytes).map_err(|_| Error::InvalidStr)?;
+        if let Some(c) = value.chars().find(|&c| !is_xml_char(c)) {
+            return Err(Error::InvalidXmlChar(c));
+        }
+    }
+
+    match mode {
+        Escape::Latex => return escape_latex(wr, bytes),
+        Escape::Plain => return Ok(wr.write_all(bytes)?),
+        _ => {}
+    }
+
+    for &b in bytes {
+        match b {
+            b'<' => wr.write_all(b"&lt;")?,
+            b'>' => wr.write_all(b"&gt;")?,
+            b'&' => wr.write_all(b"&amp;")?,
+            b'"' => wr.write_all(b"&quot;")?,
+            b'\'' if mode == Escape::Xml => wr.write_all(b"&apos;")?,
+            b'\'' => wr.write_all(b"&#39;")?,
+            _ => wr.write_all(&[b])?,
+        }
+    }
+
+    Ok(())
+}
+
+fn escape_latex<W: Write>(wr: &mut W, bytes: &[u8]) -> Result<()> {
+    for &b in bytes {
+        match b {
+            b'\\' => wr.write_all(b"\\textbackslash{}")?,
+            b'^' => wr.write_all(b"\\textasciicircum{}")?,
+            b'~' => wr.write_all(b"\\textasciitilde{}")?,
+            b'%' | b'$' | b'&' | b'#' | b'_' | b'{' | b'}' => wr.write_all(&[b'\\', b])?,
+            _ => wr.write_all(&[b])?,
+        }
+    }
+
+    Ok(())
+}
+
+// The `Char` production of XML 1.0.
+fn is_xml_char(c: char) -> bool {
+    match c {
+        '\t' | '\n' | '\r' => true,
+        '\u{0}'..='\u{1f}' | '\u{fffe}' | '\u{ffff}' => false,
+        _ => true,
+    }
+}
+
+/// Resolves the reference tokens of a JSON Pointer against `root`, indexing
+/// vectors as well as maps.
+pub fn find_pointer<'c>(pointer: &[String], root: &'c Data) -> Option<&'c Data> {
+    let mut value = root;
+
+    for part in pointer {
+        value = match *value {
+            Data::Map(ref m) => m.get(part)?,
+            // Indices have no sign or leading zeros.
+            Data::Vec(ref v) if part == "0" || (!part.starts_with('0') && part.bytes().all(|b| b.is_ascii_digit())) => {
+                v.get(part.parse::<usize>().ok()?)?
+            }
+            _ => return None,
+        };
+    }
+
+    Some(value)
+}
 
-        // Walk the rest of the path to find our final value.
-        let mut value = match value {
-            Some(value) => value,
+/// Spells reference tokens back out as a JSON Pointer.
+pub fn join_pointer(pointer: &[String]) -> String {
+    pointer.iter()
+        .map(|part| format!("/{}", part.replace('~', "~0").replace('/', "~1")))
+        .collect()
+}
+
+/// Resolves a dotted `path` against a context `stack`, the way the renderer
+/// does: the first name is looked up from the innermost frame outwards, the
+/// rest of the path is walked from there.
+pub fn find<'c>(path: &[String], stack: &[&'c Data]) -> Option<&'c Data> {
+    find_by(path, stack, |map, name| map.get(name))
+}
+
+/// Resolves like [`find`], except that a name with no exact match goes
+/// through the aliases of `options` and then, if they ignore case, matches
+/// a key differing from it only by case. Of several such keys the smallest
+/// one, comparing bytes, wins.
+pub fn find_with<'c>(path: &[String], stack: &[&'c Data], options: &RenderOptions) -> Option<&'c Data> {
+    if !options.ignore_case && options.aliases.is_empty() {
+        return find(path, stack);
+    }
+
+    find_by(path, stack, |map, name| {
+        map.get(name)
+            .or_else(|| options.aliases.get(name).and_then(|alias| map.get(alias)))
+            .or_else(|| if options.ignore_case { get_ignoring_case(map, name) } else { None })
+    })
+}
+
+fn get_ignoring_case<'c>(map: &'c HashMap<String, Data>, name: &str) -> Option<&'c Data> {
+    let folded = || name.chars().flat_map(char::to_lowercase);
+    map.iter()
+        .filter(|(key, _)| key.chars().flat_map(char::to_lowercase).eq(folded()))
+        .min_by(|(a, _), (b, _)| a.cmp(b))
+        .map(|(_, value)| value)
+}
+
+fn find_by<'c, G>(path: &[String], stack: &[&'c Data], get: G) -> Option<&'c Data>
+where G: Fn(&'c HashMap<String, Data>, &str) -> Option<&'c Data>
+{
+    // If we have an empty path, we just want the top value in our stack.
+    if path.is_empty() {
+        match stack.last() {
             None => {
                 return None;
             }
-        };
+            Some(data) => {
+                return Some(*data);
+            }
+        }
+    }
 
-        for part in path[1..].iter() {
-            match *value {
-                Data::Map(ref m) => {
-                    match m.get(part) {
-                        Some(v) => {
-                            value = v;
-                        }
-                        None => {
-                            return None;
-                        }
-                    }
-                }
-                _ => {
-                    return None;
+    // Otherwise, find the stack that has the first part of our path.
+    let mut value = None;
+
+    for data in stack.iter().rev() {
+        match **data {
+            Data::Map(ref m) => {
+                if let Some(v) = get(m, &path[0]) {
+                    value = Some(v);
+                    break;
                 }
             }
+            _ => { /* continue searching the stack */ },
         }
+    }
+
+    // Walk the rest of the path to find our final value.
+    let mut value = match value {
+        Some(value) => value,
+        None => {
+            return None;
+        }
+    };
 
-        Some(value)
+    for part in path[1..].iter() {
+        match *value {
+            Data::Map(ref m) => {
+                match get(m, part) {
+                    Some(v) => {
+                        value = v;
+                    }
+                    None => {
+                        return None;
+                    }
+                }
+            }
+            _ => {
+                return None;
+            }
+        }
     }
+
+    Some(value)
 }
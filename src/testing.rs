@@ -0,0 +1,354 @@
+//! Scaffolding for the tests of code built on this crate: custom loaders,
+//! helpers and the templates themselves.
+//!
+//! [`MockLoader`] serves partials from memory and records which ones were
+//! asked for, [`assert_renders`] checks what a template renders to and says
+//! where it went wrong, and [`Fixture`]s describe spec-style cases, a
+//! template with its data, partials and expected output, to check in bulk.
+//! [`assert_snapshot`] checks an output against a golden file kept with the
+//! tests.
+//!
+//! ```
+//! use mustache::testing::{self, Fixture, MockLoader};
+//! use mustache::{Context, MapBuilder};
+//!
+//! let loader = MockLoader::new().with("header", "<h1>{{title}}</h1>");
+//! let template = Context::with_loader(loader.clone()).compile("{{>header}}{{>footer}}".chars()).unwrap();
+//! assert_eq!(loader.requests(), ["header", "footer"]);
+//!
+//! let data = MapBuilder::new().insert_str("title", "Hi").build();
+//! testing::assert_renders(&template, &data, "<h1>Hi</h1>");
+//!
+//! testing::assert_fixtures(&[
+//!     Fixture::new("escaped", "{{x}}", "&amp;").data(MapBuilder::new().insert_str("x", "&").build()),
+//!     Fixture::new("partial", "[{{>p}}]", "[p]").partial("p", "p"),
+//! ]);
+//! ```
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use diff::diff_output;
+use {Context, Data, PartialLoader, Result, Template};
+
+/// The environment variable that has [`assert_snapshot`] write the golden
+/// files instead of checking them, when set to anything but `0`.
+pub const UPDATE_SNAPSHOTS: &str = "MUSTACHE_UPDATE_SNAPSHOTS";
+
+/// A [`PartialLoader`](../trait.PartialLoader.html) serving partials from
+/// memory, and empty ones for names it doesn't have, that records every
+/// name it is asked to load.
+///
+/// Clones share their record, so a loader kept aside sees the loads of the
+/// one given to a [`Context`](../struct.Context.html).
+#[derive(Clone, Debug, Default)]
+pub struct MockLoader {
+    partials: HashMap<String, String>,
+    requests: Arc<Mutex<Vec<String>>>,
+}
+
+impl MockLoader {
+    pub fn new() -> MockLoader {
+        MockLoader::default()
+    }
+
+    /// Serves `source` for the partial `name`.
+    pub fn with(mut self, name: impl Into<String>, source: impl Into<String>) -> MockLoader {
+        self.partials.insert(name.into(), source.into());
+        self
+    }
+
+    /// The names loaded so far, in order, repeats and missing partials
+    /// included.
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().map(|requests| requests.clone()).unwrap_or_default()
+    }
+
+    /// Whether `name` was loaded.
+    pub fn requested(&self, name: &str) -> bool {
+        self.requests().iter().any(|request| request == name)
+    }
+
+    /// Forgets the names loaded so far.
+    pub fn clear_requests(&self) {
+        if let Ok(mut requests) = self.requests.lock() {
+            requests.clear();
+        }
+    }
+
+    fn request(&self, name: &Path) -> Option<&String> {
+        let name = name.to_string_lossy();
+        if let Ok(mut requests) = self.requests.lock() {
+            requests.push(name.to_string());
+        }
+        self.partials.get(&name[..])
+    }
+}
+
+impl PartialLoader for MockLoader {
+    fn load(&self, name: impl AsRef<Path>) -> Result<String> {
+        Ok(self.request(name.as_ref()).cloned().unwrap_or_default())
+    }
+
+    fn load_str(&self, name: impl AsRef<Path>) -> Result<Cow<'_, str>> {
+        Ok(Cow::Borrowed(self.request(name.as_ref()).map_or("", |source| &source[..])))
+    }
+}
+
+/// Renders `template` with `data` and panics unless it renders to
+/// `expected`, saying at which line and column the output first differs.
+pub fn assert_renders<P: PartialLoader>(template: &Template<P>, data: &Data, expected: &str) {
+    let actual = match template.render_data_to_string(data) {
+        Ok(actual) => actual,
+        Err(err) => panic!("render failed: {}", err),
+    };
+    if let Err(message) = compare(expected, &actual) {
+        panic!("{}", message);
+    }
+}
+
+// Fails with where `actual` first differs from `expected` and both of them.
+fn compare(expected: &str, actual: &str) -> ::std::result::Result<(), String> {
+    if expected == actual {
+        return Ok(());
+    }
+
+    let common = expected.char_indices()
+        .zip(actual.chars())
+        .find(|&((_, e), a)| e != a)
+        .map_or_else(|| expected.len().min(actual.len()), |((i, _), _)| i);
+    let before = &expected[..common];
+    let line = before.matches('\n').count() + 1;
+    let column = before.rsplit('\n').next().map_or(0, |line| line.chars().count()) + 1;
+
+    let mut message = String::new();
+    let _ = write!(message, "output differs at line {}, column {}\n  expected: {:?}\n    actual: {:?}",
+                   line, column, expected, actual);
+    Err(message)
+}
+
+/// A spec-style test case: a template, the data and partials it is
+/// rendered with, and what it should render to.
+#[derive(Debug)]
+pub struct Fixture {
+    pub name: String,
+    pub template: String,
+    pub data: Data,
+    pub partials: HashMap<String, String>,
+    pub expected: String,
+}
+
+impl Fixture {
+    /// A case rendering `template` to `expected` with empty data and no
+    /// partials.
+    pub fn new(name: impl Into<String>, template: impl Into<String>, expected: impl Into<String>) -> Fixture {
+        Fixture {
+            name: name.into(),
+            template: template.into(),
+            data: Data::Map(HashMap::new()),
+            partials: HashMap::new(),
+            expected: expected.into(),
+        }
+    }
+
+    /// Renders with `data`.
+    pub fn data(mut self, data: Data) -> Fixture {
+        self.data = data;
+        self
+    }
+
+    /// Gives the case the partial `name`.
+    pub fn partial(mut self, name: impl Into<String>, source: impl Into<String>) -> Fixture {
+        self.partials.insert(name.into(), source.into());
+        self
+    }
+
+    /// Compiles and renders the case with its partials in a
+    /// [`MockLoader`], failing with what went wrong.
+    pub fn check(&self) -> ::std::result::Result<(), String> {
+        self.check_with(|partials| {
+            let loader = partials.iter().fold(MockLoader::new(), |loader, (name, source)| loader.with(&name[..], &source[..]));
+            Context::with_loader(loader)
+        })
+    }
+
+    /// Like [`check`](#method.check), with the context `context` makes from
+    /// the case's partials, to run the case against a loader or settings of
+    /// your own.
+    pub fn check_with<P, F>(&self, context: F) -> ::std::result::Result<(), String>
+    where
+        P: PartialLoader,
+        F: FnOnce(&HashMap<String, String>) -> Context<P>,
+    {
+        let template = context(&self.partials)
+            .compile(self.template.chars())
+            .map_err(|err| format!("compile failed: {}", err))?;
+        let actual = template.render_data_to_string(&self.data).map_err(|err| format!("render failed: {}", err))?;
+        compare(&self.expected, &actual)
+    }
+}
+
+/// Checks every fixture and panics listing the ones that failed.
+pub fn assert_fixtures(fixtures: &[Fixture]) {
+    let failures: Vec<_> = fixtures.iter()
+        .filter_map(|fixture| fixture.check().err().map(|err| format!("{}: {}", fixture.name, err)))
+        .collect();
+    if !failures.is_empty() {
+        panic!("{} of {} fixtures failed:\n{}", failures.len(), fixtures.len(), failures.join("\n"));
+    }
+}
+
+/// Renders `template` with `data` and panics unless it renders to what the
+/// golden file at `path` holds, showing the lines that differ.
+///
+/// With [`UPDATE_SNAPSHOTS`] set, the file is written with the output
+/// instead, directories included, and the output is taken as right. A
+/// missing file fails until it is written that way.
+///
+/// ```no_run
+/// use mustache::testing;
+/// use mustache::MapBuilder;
+///
+/// let template = mustache::compile_path("templates/welcome").unwrap();
+/// let data = MapBuilder::new().insert_str("name", "Ann").build();
+/// testing::assert_snapshot(&template, &data, "tests/snapshots/welcome.html");
+/// ```
+pub fn assert_snapshot<P: PartialLoader>(template: &Template<P>, data: &Data, path: impl AsRef<Path>) {
+    let update = match env::var_os(UPDATE_SNAPSHOTS) {
+        Some(value) => !value.is_empty() && value != "0",
+        None => false,
+    };
+    if let Err(message) = check_snapshot(template, data, path.as_ref(), update) {
+        panic!("{}", message);
+    }
+}
+
+fn check_snapshot<P: PartialLoader>(template: &Template<P>, data: &Data, path: &Path, update: bool)
+                                    -> ::std::result::Result<(), String> {
+    let actual = template.render_data_to_string(data).map_err(|err| format!("render failed: {}", err))?;
+
+    if update {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|err| format!("could not create {}: {}", dir.display(), err))?;
+        }
+        return fs::write(path, &actual).map_err(|err| format!("could not write {}: {}", path.display(), err));
+    }
+
+    let expected = fs::read_to_string(path).map_err(|err| {
+        format!("could not read snapshot {}: {}\nrun with {}=1 to write it", path.display(), err, UPDATE_SNAPSHOTS)
+    })?;
+    if expected == actual {
+        return Ok(());
+    }
+    Err(format!("snapshot {} differs, run with {}=1 to accept the new output\n{}",
+                path.display(), UPDATE_SNAPSHOTS, line_diff(&expected, &actual)))
+}
+
+// The lines of `expected` that `actual` changes, marked `-`, each time
+// followed by what they became, marked `+`.
+fn line_diff(expected: &str, actual: &str) -> String {
+    let mut out = String::new();
+    // Where `actual` is for the end of the last splice in `expected`.
+    let (mut from, mut to) = (0, 0);
+
+    for splice in diff_output(expected, actual) {
+        // What comes between the splices is the same in both.
+        let at = to + splice.range.start - from;
+        let changed_end = at + splice.text.len();
+
+        let start = expected[..splice.range.start].rfind('\n').map_or(0, |i| i + 1);
+        let mut end = splice.range.end;
+        if !at_line_start(expected, end) || !at_line_start(actual, changed_end) {
+            end = expected[end..].find('\n').map_or(expected.len(), |i| end + i + 1);
+        }
+
+        let _ = writeln!(out, "@@ line {}", expected[..start].matches('\n').count() + 1);
+        lines(&mut out, '-', &expected[start..end]);
+        lines(&mut out, '+', &actual[at - (splice.range.start - start)..changed_end + (end - splice.range.end)]);
+
+        from = splice.range.end;
+        to = changed_end;
+    }
+    out
+}
+
+fn at_line_start(text: &str, at: usize) -> bool {
+    at == 0 || at == text.len() || text[..at].ends_with('\n')
+}
+
+fn lines(out: &mut String, mark: char, text: &str) {
+    for line in text.lines() {
+        let _ = writeln!(out, "{}{}", mark, line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::{check_snapshot, compare, line_diff, Fixture, MockLoader};
+    use {compile_str, Context, MapBuilder};
+
+    #[test]
+    fn mock_loader() {
+        let loader = MockLoader::new().with("a", "{{>b}}{{>c}}").with("b", "b");
+        let ctx = Context::with_loader(loader.clone());
+        let template = ctx.compile("{{>a}}{{>b}}".chars()).unwrap();
+
+        assert_eq!(template.render_data_to_string(&MapBuilder::new().build()).unwrap(), "bb");
+        assert_eq!(loader.requests(), ["a", "b", "c"]);
+        assert!(loader.requested("c") && !loader.requested("d"));
+
+        loader.clear_requests();
+        assert!(loader.requests().is_empty());
+    }
+
+    #[test]
+    fn differences() {
+        assert_eq!(compare("ab\ncd", "ab\ncd"), Ok(()));
+        assert_eq!(compare("ab\ncd", "ab\nce"), Err("output differs at line 2, column 2\n  expected: \"ab\\ncd\"\n    actual: \"ab\\nce\"".into()));
+        assert_eq!(compare("ab", "abc").unwrap_err().lines().next(), Some("output differs at line 1, column 3"));
+        assert_eq!(compare("é", "").unwrap_err().lines().next(), Some("output differs at line 1, column 1"));
+    }
+
+    #[test]
+    fn fixtures() {
+        let fixture = Fixture::new("greeting", "{{>hello}}!", "Hello, Ann!")
+            .partial("hello", "Hello, {{name}}")
+            .data(MapBuilder::new().insert_str("name", "Ann").build());
+        assert_eq!(fixture.check(), Ok(()));
+
+        let fixture = Fixture::new("broken", "{{#a}}", "");
+        assert_eq!(fixture.check(), Err("compile failed: found an unclosed section: \"a\"".into()));
+    }
+
+    #[test]
+    fn snapshots() {
+        let tmpdir = TempDir::new("").unwrap();
+        let path = tmpdir.path().join("snapshots/greeting.txt");
+        let template = compile_str("Hello\n{{name}}\n").unwrap();
+        let ann = MapBuilder::new().insert_str("name", "Ann").build();
+
+        let err = check_snapshot(&template, &ann, &path, false).unwrap_err();
+        assert!(err.starts_with("could not read snapshot"));
+
+        assert_eq!(check_snapshot(&template, &ann, &path, true), Ok(()));
+        assert_eq!(check_snapshot(&template, &ann, &path, false), Ok(()));
+
+        let bob = MapBuilder::new().insert_str("name", "Bob").build();
+        let err = check_snapshot(&template, &bob, &path, false).unwrap_err();
+        assert!(err.ends_with("differs, run with MUSTACHE_UPDATE_SNAPSHOTS=1 to accept the new output\n@@ line 2\n-Ann\n+Bob\n"));
+    }
+
+    #[test]
+    fn line_diffs() {
+        assert_eq!(line_diff("a\nb\nc\n", "a\nB\nc\nd\n"), "@@ line 2\n-b\n+B\n@@ line 4\n+d\n");
+        assert_eq!(line_diff("a\nb\nc\n", "a\nc\n"), "@@ line 2\n-b\n");
+        assert_eq!(line_diff("<p>Hi Ann</p>", "<p>Hi Bob</p>"), "@@ line 1\n-<p>Hi Ann</p>\n+<p>Hi Bob</p>\n");
+    }
+}
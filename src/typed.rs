@@ -0,0 +1,306 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io::Write;
+use std::marker::PhantomData;
+
+use parser::Token;
+use {compiler, DefaultLoader, Error, PartialLoader, Result, Template};
+
+/// What a [`TemplateData`] type serializes to, as far as the names a
+/// template can look up in it go.
+///
+/// Fields are given as functions so recursive types can describe
+/// themselves.
+#[derive(Clone, Debug)]
+pub enum Shape {
+    /// A string, number, boolean or bytes.
+    Scalar,
+    /// A list of values of one shape.
+    List(fn() -> Shape),
+    /// A struct and its fields.
+    Struct(Vec<Field>),
+    /// Anything at all, such as a map with keys only known at run time.
+    Any,
+}
+
+/// A field of a [`Shape::Struct`], by the name it serializes under.
+pub type Field = (&'static str, fn() -> Shape);
+
+/// A type whose [`Shape`] is known up front, so a [`TypedTemplate`] can
+/// check every name a template uses against it.
+///
+/// With the `derive` feature, `#[derive(TemplateData)]` implements it for
+/// structs with named fields, following their `rename` and `skip` serde
+/// attributes, for newtypes and for enums of unit variants.
+pub trait TemplateData: ::serde::Serialize {
+    fn shape() -> Shape;
+}
+
+macro_rules! scalars {
+    ($($ty:ty),*) => {
+        $(
+            impl TemplateData for $ty {
+                fn shape() -> Shape {
+                    Shape::Scalar
+                }
+            }
+        )*
+    };
+}
+
+scalars!(bool, char, String, str, u8, u16, u32, u64, usize, i8, i16, i32, i64, isize, f32, f64, ());
+
+impl<T: TemplateData + ?Sized> TemplateData for &T {
+    fn shape() -> Shape {
+        T::shape()
+    }
+}
+
+impl<'a> TemplateData for Cow<'a, str> {
+    fn shape() -> Shape {
+        Shape::Scalar
+    }
+}
+
+impl<T: TemplateData + ?Sized> TemplateData for Box<T> {
+    fn shape() -> Shape {
+        T::shape()
+    }
+}
+
+// `None` is `Data::Null`, which the names inside a `Some` are fine with.
+impl<T: TemplateData> TemplateData for Option<T> {
+    fn shape() -> Shape {
+        T::shape()
+    }
+}
+
+impl<T: TemplateData> TemplateData for Vec<T> {
+    fn shape() -> Shape {
+        Shape::List(T::shape)
+    }
+}
+
+impl<T: TemplateData> TemplateData for [T] {
+    fn shape() -> Shape {
+        Shape::List(T::shape)
+    }
+}
+
+impl<K: ::serde::Serialize, V: TemplateData, S: ::std::hash::BuildHasher> TemplateData for HashMap<K, V, S> {
+    fn shape() -> Shape {
+        Shape::Any
+    }
+}
+
+impl<K: ::serde::Serialize, V: TemplateData> TemplateData for BTreeMap<K, V> {
+    fn shape() -> Shape {
+        Shape::Any
+    }
+}
+
+/// A template checked against the [`Shape`] of the data it renders, so
+/// every name it uses, in sections and partials too, is known to exist on
+/// `T`.
+///
+/// ```rust
+/// # #[macro_use] extern crate serde_derive;
+/// # extern crate mustache;
+/// use mustache::{Shape, TemplateData, TypedTemplate};
+///
+/// #[derive(Serialize)]
+/// struct User {
+///     name: String,
+/// }
+///
+/// // Or `#[derive(TemplateData)]` with the `derive` feature.
+/// impl TemplateData for User {
+///     fn shape() -> Shape {
+///         Shape::Struct(vec![("name", String::shape)])
+///     }
+/// }
+///
+/// # fn main() {
+/// let template = mustache::compile_str("Hi {{name}}").unwrap();
+/// let typed = TypedTemplate::<User>::new(template).unwrap();
+/// assert_eq!(typed.render_to_string(&User { name: "Ann".into() }).unwrap(), "Hi Ann");
+///
+/// let template = mustache::compile_str("Hi {{nmae}}").unwrap();
+/// assert!(TypedTemplate::<User>::new(template).is_err());
+/// # }
+/// ```
+pub struct TypedTemplate<T, P: PartialLoader = DefaultLoader> {
+    template: Template<P>,
+    data: PhantomData<fn(&T)>,
+}
+
+impl<T: TemplateData, P: PartialLoader> TypedTemplate<T, P> {
+    /// Checks `template` against `T`, failing with
+    /// [`Error::UnknownNames`](enum.Error.html#variant.UnknownNames) if it
+    /// uses names `T` doesn't have.
+    pub fn new(template: Template<P>) -> Result<TypedTemplate<T, P>> {
+        let mut checker = Checker {
+            partials: &template.partials,
+            including: Vec::new(),
+            unknown: Vec::new(),
+        };
+        checker.check(&template.tokens, &mut vec![T::shape()]);
+
+        if !checker.unknown.is_empty() {
+            return Err(Error::UnknownNames(checker.unknown));
+        }
+        Ok(TypedTemplate { template, data: PhantomData })
+    }
+
+    pub fn render<W: Write>(&self, wr: &mut W, data: &T) -> Result<()> {
+        self.template.render(wr, data)
+    }
+
+    pub fn render_to_string(&self, data: &T) -> Result<String> {
+        self.template.render_to_string(data)
+    }
+
+    pub fn template(&self) -> &Template<P> {
+        &self.template
+    }
+
+    pub fn into_template(self) -> Template<P> {
+        self.template
+    }
+}
+
+impl<T, P: PartialLoader> Clone for TypedTemplate<T, P> {
+    fn clone(&self) -> Self {
+        TypedTemplate { template: self.template.clone(), data: PhantomData }
+    }
+}
+
+impl<T, P: PartialLoader + fmt::Debug> fmt::Debug for TypedTemplate<T, P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("TypedTemplate").field(&self.template).finish()
+    }
+}
+
+struct Checker<'a> {
+    partials: &'a compiler::PartialsMap,
+    // Partials currently being checked, so recursive partials terminate.
+    including: Vec<&'a str>,
+    unknown: Vec<String>,
+}
+
+impl<'a> Checker<'a> {
+    // `stack` mirrors the context stack a render would have.
+    fn check(&mut self, tokens: &'a [Token], stack: &mut Vec<Shape>) {
+        for token in tokens {
+            match *token {
+                Token::EscapedTag(ref path, _) | Token::UnescapedTag(ref path, _) => {
+                    self.find(path, stack);
+                }
+                Token::PointerTag(ref pointer, _) => self.find_pointer(pointer, &stack[0]),
+                Token::FallbackTag(ref paths, _, _) => {
+                    for path in paths {
+                        self.find(path, stack);
+                    }
+                }
+                Token::Section(ref path, inverted, ref children, _, _, _, _, _) => {
+                    match self.find(path, stack) {
+                        // Inverted sections don't push anything.
+                        Some(ref shape) if !inverted => {
+                            let inner = match *shape {
+                                Shape::List(item) => item(),
+                                ref shape => shape.clone(),
+                            };
+                            stack.push(inner);
+                            self.check(children, stack);
+                            stack.pop();
+                        }
+                        _ => self.check(children, stack),
+                    }
+                }
+                Token::Condition(ref comparison, _, ref children, _, _, _, _, _) => {
+                    for path in comparison.names() {
+                        self.find(path, stack);
+                    }
+                    self.check(children, stack);
+                }
+                // The content sees a number on top of the stack.
+                Token::Range(ref bounds, inverted, ref children, _, _, _, _, _) => {
+                    for path in bounds.names() {
+                        self.find(path, stack);
+                    }
+                    if !inverted {
+                        stack.push(Shape::Scalar);
+                    }
+                    self.check(children, stack);
+                    if !inverted {
+                        stack.pop();
+                    }
+                }
+                Token::Partial(ref name, _, _) => self.check_partial(name, stack),
+                Token::PartialWithFallback(ref name, _, ref children, _, _, _) => {
+                    self.check(children, stack);
+                    self.check_partial(name, stack);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn check_partial(&mut self, name: &'a str, stack: &mut Vec<Shape>) {
+        if self.including.contains(&name) {
+            return;
+        }
+
+        if let Some(tokens) = self.partials.get(name) {
+            self.including.push(name);
+            self.check(tokens, stack);
+            self.including.pop();
+        }
+    }
+
+    // Resolves `path` the way `template::find` resolves it in data, noting
+    // it if it can't be.
+    fn find(&mut self, path: &[String], stack: &[Shape]) -> Option<Shape> {
+        let (first, rest) = match path.split_first() {
+            None => return stack.last().cloned(),
+            Some(parts) => parts,
+        };
+
+        let found = stack.iter().rev().filter_map(|shape| field(shape, first)).next();
+        let shape = found.and_then(|shape| rest.iter().try_fold(shape, |shape, part| field(&shape, part)));
+
+        if shape.is_none() && !self.unknown.contains(&path.join(".")) {
+            self.unknown.push(path.join("."));
+        }
+        shape
+    }
+
+    fn find_pointer(&mut self, pointer: &[String], root: &Shape) {
+        let mut shape = root.clone();
+        for part in pointer {
+            shape = match shape {
+                Shape::List(item) if part.parse::<usize>().is_ok() => item(),
+                ref shape => match field(shape, part) {
+                    Some(shape) => shape,
+                    None => {
+                        let name = ::template::join_pointer(pointer);
+                        if !self.unknown.contains(&name) {
+                            self.unknown.push(name);
+                        }
+                        return;
+                    }
+                },
+            };
+        }
+    }
+}
+
+fn field(shape: &Shape, name: &str) -> Option<Shape> {
+    match *shape {
+        Shape::Struct(ref fields) => fields.iter().find(|&&(field, _)| field == name).map(|&(_, shape)| shape()),
+        Shape::Any => Some(Shape::Any),
+        Shape::Scalar | Shape::List(_) => None,
+    }
+}
+
@@ -0,0 +1,364 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::ptr;
+
+use compiler::PartialsMap;
+use parser::Token;
+use template;
+use Data;
+
+/// Something the data lacks for a template, as reported by
+/// [`Template::validate`](struct.Template.html#method.validate).
+///
+/// This type is not intended to be matched exhaustively as new variants
+/// may be added in future without a version bump.
+#[derive(Clone, Debug, PartialEq)]
+#[allow(clippy::manual_non_exhaustive)]
+pub enum Missing {
+    /// A variable tag or section names nothing in the data.
+    Absent(String),
+    /// A variable tag names a list, map or boolean, which it writes out as
+    /// nothing.
+    NotScalar(String),
+    /// A section is over a string or bytes and names inside it are absent,
+    /// which usually means the data has a scalar where the template expects
+    /// a list or a map.
+    Scalar(String),
+
+    #[doc(hidden)]
+    __Nonexhaustive,
+}
+
+impl fmt::Display for Missing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Missing::Absent(ref name) => write!(f, "no value found for {:?}", name),
+            Missing::NotScalar(ref name) => write!(f, "{:?} is not a value a tag can write", name),
+            Missing::Scalar(ref name) => write!(f, "section {:?} is over a scalar but looks inside it", name),
+            Missing::__Nonexhaustive => unreachable!(),
+        }
+    }
+}
+
+/// The paths in the data a template reads, as reported by
+/// [`Template::dry_run`](struct.Template.html#method.dry_run).
+///
+/// Paths are dotted from the root of the data, with `*` standing for every
+/// item of a list: `users.*.name` is the name of each user. Pointer tags
+/// give theirs the same way.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Usage {
+    /// The paths the template found a value at, sections included.
+    pub read: BTreeSet<String>,
+    /// The paths the template looked for and found nothing at, given from
+    /// the innermost section they were looked up in.
+    pub missing: BTreeSet<String>,
+}
+
+/// Walks `tokens` the way a render with `data` would, without writing
+/// anything or calling lambdas.
+pub fn validate(tokens: &[Token], partials: &PartialsMap, data: &Data) -> Vec<Missing> {
+    let mut validator = Validator::new(partials, None);
+    validator.walk(tokens, &mut vec![data]);
+    validator.missing
+}
+
+/// Walks `tokens` like [`validate`], noting the paths it reads.
+pub fn usage(tokens: &[Token], partials: &PartialsMap, data: &Data) -> Usage {
+    let mut validator = Validator::new(partials, Some(Usage::default()));
+    validator.walk(tokens, &mut vec![data]);
+    validator.usage.unwrap_or_default()
+}
+
+struct Validator<'a> {
+    partials: &'a PartialsMap,
+    // Partials currently being walked and the innermost value they were
+    // included with. A partial included again with the same value would go
+    // on forever, in a render too.
+    including: Vec<(&'a str, *const Data)>,
+    missing: Vec<Missing>,
+    // The paths read so far, and where each value of the stack is in the
+    // data, when asked for.
+    usage: Option<Usage>,
+    paths: Vec<String>,
+}
+
+impl<'a> Validator<'a> {
+    fn new(partials: &'a PartialsMap, usage: Option<Usage>) -> Validator<'a> {
+        Validator {
+            partials,
+            including: Vec::new(),
+            missing: Vec::new(),
+            usage,
+            paths: vec![String::new()],
+        }
+    }
+
+    fn report(&mut self, missing: Missing) {
+        if !self.missing.contains(&missing) {
+            self.missing.push(missing);
+        }
+    }
+
+    // Notes that `path` was looked up and found `value`.
+    fn used(&mut self, path: &[String], value: Option<&Data>, stack: &[&Data]) {
+        if self.usage.is_none() {
+            return;
+        }
+
+        let at = self.locate(path, stack);
+        if let Some(ref mut usage) = self.usage {
+            match value {
+                Some(_) => usage.read.insert(at),
+                None => usage.missing.insert(at),
+            };
+        }
+    }
+
+    // Where `path` is in the data: under the innermost map that has its
+    // first name, as `template::find` looks it up, or the innermost value.
+    fn locate(&self, path: &[String], stack: &[&Data]) -> String {
+        let frame = path.first()
+            .and_then(|first| stack.iter().rposition(|data| matches!(**data, Data::Map(ref m) if m.contains_key(first))))
+            .unwrap_or(stack.len() - 1);
+
+        join(&self.paths[frame], &path.join("."))
+    }
+
+    // Where the section at `path` is in the data, if that's asked for.
+    fn section_path(&self, path: &[String], stack: &[&Data]) -> Option<String> {
+        self.usage.as_ref().map(|_| self.locate(path, stack))
+    }
+
+    // Pushes `value`, found at `at` in the data, for a section's content.
+    fn push<'d>(&mut self, stack: &mut Vec<&'d Data>, value: &'d Data, at: Option<String>) {
+        if let Some(at) = at {
+            self.paths.push(at);
+        }
+        stack.push(value);
+    }
+
+    fn pop(&mut self, stack: &mut Vec<&Data>) {
+        if self.usage.is_some() {
+            self.paths.pop();
+        }
+        stack.pop();
+    }
+
+    fn walk<'d>(&mut self, tokens: &'a [Token], stack: &mut Vec<&'d Data>) {
+        for token in tokens {
+            match *token {
+                Token::EscapedTag(ref path, _) | Token::UnescapedTag(ref path, _) => {
+                    let value = template::find(path, stack);
+                    self.used(path, value, stack);
+                    self.check_value(template::tag_name(path), value);
+                }
+                Token::FallbackTag(ref paths, _, _) => {
+                    // Only a chain with nothing at all to fall back on is
+                    // missing something.
+                    let mut found = false;
+                    for path in paths {
+                        let value = template::find(path, stack);
+                        self.used(path, value, stack);
+                        found |= value.is_some();
+                        if template::writes_something(value) {
+                            break;
+                        }
+                    }
+                    if !found {
+                        let names: Vec<_> = paths.iter().map(|path| template::tag_name(path)).collect();
+                        self.report(Missing::Absent(names.join(" || ")));
+                    }
+                }
+                Token::PointerTag(ref pointer, _) => {
+                    let value = stack.first().and_then(|root| template::find_pointer(pointer, root));
+                    self.used(pointer, value, &stack[..1]);
+                    self.check_value(template::join_pointer(pointer), value);
+                }
+                Token::Section(ref path, true, ref children, _, _, _, _, _) => {
+                    let value = template::find(path, stack);
+                    self.used(path, value, stack);
+                    match value {
+                        None | Some(&Data::Null) | Some(&Data::Bool(false)) => self.walk(children, stack),
+                        Some(Data::Vec(items)) if items.is_empty() => self.walk(children, stack),
+                        Some(_) => {}
+                    }
+                }
+                Token::Section(ref path, false, ref children, _, _, _, _, _) => {
+                    let value = template::find(path, stack);
+                    self.used(path, value, stack);
+                    match value {
+                        None => self.report(Missing::Absent(path.join("."))),
+                        Some(value) => self.walk_section(path, value, children, stack),
+                    }
+                }
+                Token::Condition(ref comparison, _, ref children, _, _, _, _, _) => {
+                    for path in comparison.names() {
+                        let value = template::find(path, stack);
+                        self.used(path, value, stack);
+                    }
+                    self.walk(children, stack);
+                }
+                Token::Range(ref bounds, _, ref children, _, _, _, _, _) => {
+                    for path in bounds.names() {
+                        let value = template::find(path, stack);
+                        self.used(path, value, stack);
+                    }
+                    self.walk(children, stack);
+                }
+                Token::Partial(ref name, _, _) => {
+                    if let Some(tokens) = self.partials.get(name) {
+                        self.walk_partial(name, tokens, stack);
+                    }
+                }
+                Token::PartialWithFallback(ref name, _, ref children, _, _, _) => {
+                    match self.partials.get(name) {
+                        Some(tokens) if !tokens.is_empty() => self.walk_partial(name, tokens, stack),
+                        _ => self.walk(children, stack),
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn check_value(&mut self, name: String, value: Option<&Data>) {
+        match value {
+            None => self.report(Missing::Absent(name)),
+            Some(&Data::Bool(_)) | Some(&Data::Vec(_)) | Some(&Data::Iter(_)) | Some(&Data::Map(_)) => self.report(Missing::NotScalar(name)),
+            Some(_) => {}
+        }
+    }
+
+    // Mirrors `RenderContext::render_section`, walking the content once for
+    // every item of a list.
+    fn walk_section<'d>(&mut self,
+                        path: &[String],
+                        value: &'d Data,
+                        children: &'a [Token],
+                        stack: &mut Vec<&'d Data>) {
+        match *value {
+            Data::Bool(true) => self.walk(children, stack),
+            Data::String(ref s) if !s.is_empty() => self.walk_scalar(path, value, children, stack),
+            Data::Bytes(ref b) if !b.is_empty() => self.walk_scalar(path, value, children, stack),
+            Data::Vec(ref items) => {
+                let at = self.section_path(path, stack).map(|at| join(&at, "*"));
+                for item in items {
+                    self.push(stack, item, at.clone());
+                    self.walk(children, stack);
+                    self.pop(stack);
+                }
+            }
+            Data::Map(_) => {
+                let at = self.section_path(path, stack);
+                self.push(stack, value, at);
+                self.walk(children, stack);
+                self.pop(stack);
+            }
+            // Lambdas decide for themselves what their content needs.
+            _ => {}
+        }
+    }
+
+    fn walk_scalar<'d>(&mut self,
+                       path: &[String],
+                       value: &'d Data,
+                       children: &'a [Token],
+                       stack: &mut Vec<&'d Data>) {
+        let before = self.missing.len();
+        let at = self.section_path(path, stack);
+        self.push(stack, value, at);
+        self.walk(children, stack);
+        self.pop(stack);
+
+        let absent = self.missing[before..].iter().any(|missing| matches!(*missing, Missing::Absent(_)));
+        if absent {
+            self.report(Missing::Scalar(path.join(".")));
+        }
+    }
+
+    fn walk_partial<'d>(&mut self, name: &'a str, tokens: &'a [Token], stack: &mut Vec<&'d Data>) {
+        let key = (name, stack.last().map_or(ptr::null(), |&value| value as *const Data));
+        if !self.including.contains(&key) {
+            self.including.push(key);
+            self.walk(tokens, stack);
+            self.including.pop();
+        }
+    }
+}
+
+// Joins two dotted paths, either of which may be empty.
+fn join(parent: &str, path: &str) -> String {
+    match (parent.is_empty(), path.is_empty()) {
+        (true, true) => ".".to_string(),
+        (true, false) => path.to_string(),
+        (false, true) => parent.to_string(),
+        (false, false) => format!("{}.{}", parent, path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::Missing;
+    use {Context, MapBuilder};
+
+    #[test]
+    fn missing() {
+        let mut ctx = Context::new(PathBuf::from("."));
+        ctx.extensions.inline_partials = true;
+        let template = ctx.compile(concat!(
+            "{{<*row}}{{name}}{{#children}}{{>row}}{{/children}}{{/row}}",
+            "{{title}}{{#rows}}{{>row}}{{/rows}}{{^none}}{{fallback}}{{/none}}{{^rows}}{{hidden}}{{/rows}}",
+            "{{flags}}{{#tag}}{{label}}{{/tag}}{{#off}}{{ignored}}{{/off}}{{#gone}}{{unseen}}{{/gone}}",
+        ).chars()).unwrap();
+
+        let data = MapBuilder::new()
+            .insert_vec("rows", |v| {
+                v.push_map(|m| m.insert_str("name", "a").insert_vec("children", |v| v.push_map(|m| m.insert_vec("children", |v| v))))
+                    .push_map(|m| m.insert_str("name", "b"))
+            })
+            .insert_vec("flags", |v| v)
+            .insert_str("tag", "x")
+            .insert_bool("off", false)
+            .build();
+
+        assert_eq!(template.validate(&data), vec![
+            Missing::Absent("title".to_string()),
+            Missing::Absent("children".to_string()),
+            Missing::Absent("fallback".to_string()),
+            Missing::NotScalar("flags".to_string()),
+            Missing::Absent("label".to_string()),
+            Missing::Scalar("tag".to_string()),
+            Missing::Absent("gone".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn usage() {
+        let template = ::compile_str(concat!(
+            "{{title}}{{#page}}{{title}}{{#items}}{{.}}{{/items}}{{/page}}",
+            "{{#users}}{{name}}{{#address}}{{city}}{{zip}}{{/address}}{{/users}}{{^users}}{{empty}}{{/users}}",
+            "{{#flag}}{{note}}{{/flag}}",
+        )).unwrap();
+
+        let data = MapBuilder::new()
+            .insert_str("title", "t")
+            .insert_map("page", |m| m.insert_vec("items", |v| v.push_str("a")))
+            .insert_vec("users", |v| {
+                v.push_map(|m| m.insert_str("name", "a").insert_map("address", |m| m.insert_str("city", "c")))
+                    .push_map(|m| m.insert_str("unused", "b"))
+            })
+            .insert_bool("flag", true)
+            .build();
+
+        let usage = template.dry_run(&data);
+        let set = |paths: &[&str]| paths.iter().map(|p| p.to_string()).collect();
+        assert_eq!(usage.read, set(&[
+            "flag", "page", "page.items", "page.items.*", "title", "users", "users.*.address",
+            "users.*.address.city", "users.*.name",
+        ]));
+        assert_eq!(usage.missing, set(&["note", "users.*.address", "users.*.address.zip", "users.*.name"]));
+    }
+}
@@ -55,7 +55,7 @@ fn test_map_fn_builder() {
         .build();
 
     assert_let!(Data::Map(m) = data => {
-        assert_let!(Some(&Data::Fun(ref f)) = m.get("count") => {
+        assert_let!(Some(Data::Fun(f)) = m.get("count") => {
             let f = &mut *f.borrow_mut();
             assert_eq!((*f)("count: ".to_string()), "count: 1".to_string());
             assert_eq!((*f)("count: ".to_string()), "count: 2".to_string());
@@ -64,6 +64,23 @@ fn test_map_fn_builder() {
     })
 }
 
+#[test]
+fn test_optional_and_extend() {
+    let nick: Option<String> = None;
+    let data = MapBuilder::new()
+        .insert_opt("name", Some(&"Jane")).unwrap()
+        .insert_opt("nick", nick.as_ref()).unwrap()
+        .insert_vec("works", |builder| {
+            builder.extend(vec!["Emma", "Persuasion"]).unwrap().extend([1813usize].iter()).unwrap()
+        })
+        .build();
+
+    assert_eq!(data, MapBuilder::new()
+        .insert_str("name", "Jane")
+        .insert_vec("works", |builder| builder.push_str("Emma").push_str("Persuasion").push_str("1813"))
+        .build());
+}
+
 #[test]
 fn test_vec_fn_builder() {
     // We can't directly compare closures, so just make sure we thread
@@ -80,7 +97,7 @@ fn test_vec_fn_builder() {
     assert_let!(Data::Vec(vs) = data => {
         let mut iter = vs.iter();
 
-        assert_let!(Some(&Data::Fun(ref f)) = iter.next() => {
+        assert_let!(Some(Data::Fun(f)) = iter.next() => {
             let f = &mut *f.borrow_mut();
             assert_eq!((*f)("count: ".to_string()), "count: 1".to_string());
             assert_eq!((*f)("count: ".to_string()), "count: 2".to_string());
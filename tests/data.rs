@@ -0,0 +1,181 @@
+use serde_json;
+
+use mustache::{to_data, Data, MapBuilder, MergeStrategy};
+
+fn defaults() -> Data {
+    MapBuilder::new()
+        .insert_str("title", "Home")
+        .insert_map("site", |m| m.insert_str("name", "Example").insert_str("lang", "en"))
+        .build()
+}
+
+fn overrides() -> Data {
+    MapBuilder::new()
+        .insert_str("user", "jo")
+        .insert_map("site", |m| m.insert_str("lang", "fr"))
+        .build()
+}
+
+#[test]
+fn test_merge_overwrite() {
+    let mut data = defaults();
+    data.merge(overrides(), MergeStrategy::Overwrite);
+
+    assert_eq!(data, MapBuilder::new()
+        .insert_str("title", "Home")
+        .insert_str("user", "jo")
+        .insert_map("site", |m| m.insert_str("lang", "fr"))
+        .build());
+}
+
+#[test]
+fn test_merge_keep() {
+    let mut data = defaults();
+    data.merge(overrides(), MergeStrategy::Keep);
+
+    assert_eq!(data, MapBuilder::new()
+        .insert_str("title", "Home")
+        .insert_str("user", "jo")
+        .insert_map("site", |m| m.insert_str("name", "Example").insert_str("lang", "en"))
+        .build());
+}
+
+#[test]
+fn test_merge_recurse() {
+    let mut data = defaults();
+    data.merge(overrides(), MergeStrategy::Recurse);
+
+    assert_eq!(data, MapBuilder::new()
+        .insert_str("title", "Home")
+        .insert_str("user", "jo")
+        .insert_map("site", |m| m.insert_str("name", "Example").insert_str("lang", "fr"))
+        .build());
+
+    // A map only merges into another map.
+    let mut data = defaults();
+    data.merge(MapBuilder::new().insert_str("site", "none").build(), MergeStrategy::Recurse);
+    assert_eq!(data, MapBuilder::new().insert_str("title", "Home").insert_str("site", "none").build());
+}
+
+#[test]
+fn test_merge_non_maps() {
+    let mut data = Data::String("a".to_string());
+    data.merge(Data::Bool(true), MergeStrategy::Keep);
+    assert_eq!(data, Data::String("a".to_string()));
+
+    data.merge(Data::Bool(true), MergeStrategy::Overwrite);
+    assert_eq!(data, Data::Bool(true));
+}
+
+#[test]
+fn test_lookup() {
+    let data = defaults();
+
+    assert_eq!(data.lookup("site.lang"), Some(&Data::String("en".to_string())));
+    assert_eq!(data.lookup("title"), Some(&Data::String("Home".to_string())));
+    assert_eq!(data.lookup("."), Some(&data));
+    assert_eq!(data.lookup("site.missing"), None);
+    assert_eq!(data.lookup("title.length"), None);
+}
+
+#[test]
+fn test_insert_path() {
+    let mut data = defaults();
+
+    data.insert_path("site.lang", Data::String("fr".to_string())).unwrap();
+    data.insert_path("user.address.city", Data::String("Lyon".to_string())).unwrap();
+    assert_eq!(data.lookup("site.lang"), Some(&Data::String("fr".to_string())));
+    assert_eq!(data.lookup("user.address.city"), Some(&Data::String("Lyon".to_string())));
+
+    let before = defaults();
+    let mut data = defaults();
+    assert_eq!(data.insert_path("title.text", Data::Bool(true)), Err(Data::Bool(true)));
+    assert_eq!(data, before);
+
+    data.insert_path(".", Data::Null).unwrap();
+    assert_eq!(data, Data::Null);
+}
+
+#[test]
+fn test_serde_round_trip() {
+    let data = MapBuilder::new()
+        .insert_str("title", "Home")
+        .insert_bool("draft", false)
+        .insert_vec("tags", |v| v.push_str("a").push_map(|m| m.insert_str("b", "c")))
+        .build();
+
+    let json = serde_json::to_string(&data).unwrap();
+    assert_eq!(serde_json::from_str::<Data>(&json).unwrap(), data);
+
+    let data: Data = serde_json::from_str(r#"{"n": 1.5, "none": null, "list": [1, true]}"#).unwrap();
+    assert_eq!(data, MapBuilder::new()
+        .insert_str("n", "1.5")
+        .insert("none", &()).unwrap()
+        .insert_vec("list", |v| v.push_str("1").push_bool(true))
+        .build());
+    assert_eq!(to_data(&data).unwrap(), data);
+
+    assert!(serde_json::to_string(&MapBuilder::new().insert_fn("f", |s| s).build()).is_err());
+}
+
+#[test]
+fn test_to_pretty_string() {
+    let data = MapBuilder::new()
+        .insert_str("long", "x".repeat(50))
+        .insert("none", &()).unwrap()
+        .insert_map("user", |m| m.insert_fn("f", |s| s).insert_vec("empty", |v| v))
+        .build();
+
+    assert_eq!(data.to_pretty_string(), format!("\
+map (3)
+  long: string {:?}... (50 chars)
+  none: null
+  user: map (2)
+    empty: vec (0)
+    f: lambda
+", "x".repeat(40)));
+}
+
+#[test]
+fn test_from_values() {
+    assert_eq!(Data::from("a"), Data::String("a".to_string()));
+    assert_eq!(Data::from(String::from("a")), Data::String("a".to_string()));
+    assert_eq!(Data::from(true), Data::Bool(true));
+    assert_eq!(Data::from(-3), Data::String("-3".to_string()));
+    assert_eq!(Data::from(1.5), Data::String("1.5".to_string()));
+    assert_eq!(Data::from(None::<&str>), Data::Null);
+    assert_eq!(Data::from(Some(2u8)), to_data(2u8).unwrap());
+    assert_eq!(Data::from(vec![Data::from("a")]), Data::Vec(vec![Data::String("a".to_string())]));
+
+    let user: Data = vec![("name", Data::from("Ann")), ("tags", vec!["a".into(), "b".into()].into())].into_iter().collect();
+    assert_eq!(user, MapBuilder::new()
+        .insert_str("name", "Ann")
+        .insert_vec("tags", |v| v.push_str("a").push_str("b"))
+        .build());
+
+    let numbers: Data = (1..4).map(Data::from).collect();
+    assert_eq!(numbers, to_data(vec![1, 2, 3]).unwrap());
+}
+
+#[test]
+fn test_map_mutation() {
+    let mut data = defaults();
+
+    *data.get_mut("title").unwrap() = Data::from("About");
+    if let Some(site) = data.get_mut("site") {
+        site.remove("lang");
+        site.entry("name").unwrap().or_insert_with(|| Data::from("Other"));
+        site.entry("theme").unwrap().or_insert_with(|| Data::from("dark"));
+    }
+    assert_eq!(data.remove("missing"), None);
+
+    assert_eq!(data, MapBuilder::new()
+        .insert_str("title", "About")
+        .insert_map("site", |m| m.insert_str("name", "Example").insert_str("theme", "dark"))
+        .build());
+
+    let mut title = data.remove("title").unwrap();
+    assert!(title.get_mut("x").is_none());
+    assert!(title.remove("x").is_none());
+    assert!(title.entry("x").is_none());
+}
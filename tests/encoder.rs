@@ -0,0 +1,182 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::num::NonZeroU32;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use mustache::{compile_str, to_data, Data};
+
+fn string(s: &str) -> Data {
+    Data::String(s.to_string())
+}
+
+fn map(entries: Vec<(&str, Data)>) -> Data {
+    Data::Map(entries.into_iter().map(|(k, v)| (k.to_string(), v)).collect())
+}
+
+#[test]
+fn test_encode_maps() {
+    let mut btree = BTreeMap::new();
+    btree.insert(1u8, "a");
+    assert_eq!(to_data(btree).unwrap(), map(vec![("1", string("a"))]));
+
+    let mut hash = HashMap::new();
+    hash.insert('c', 2i64);
+    assert_eq!(to_data(hash).unwrap(), map(vec![("c", string("2"))]));
+
+    let mut flags = BTreeMap::new();
+    flags.insert(true, "yes");
+    flags.insert(false, "no");
+    assert_eq!(to_data(flags).unwrap(), map(vec![("true", string("yes")), ("false", string("no"))]));
+
+    let mut nested = BTreeMap::new();
+    nested.insert(vec![1], 1);
+    assert!(to_data(nested).is_err());
+}
+
+#[test]
+fn test_encode_sequences() {
+    let set: BTreeSet<i32> = vec![3, 1, 2].into_iter().collect();
+    assert_eq!(to_data(set).unwrap(), Data::Vec(vec![string("1"), string("2"), string("3")]));
+
+    let set: HashSet<&str> = vec!["x"].into_iter().collect();
+    assert_eq!(to_data(set).unwrap(), Data::Vec(vec![string("x")]));
+
+    assert_eq!(to_data((1, "a", true)).unwrap(), Data::Vec(vec![string("1"), string("a"), Data::Bool(true)]));
+    assert_eq!(to_data([1u8, 2]).unwrap(), Data::Vec(vec![string("1"), string("2")]));
+    assert_eq!(to_data([0u8; 0]).unwrap(), Data::Vec(vec![]));
+}
+
+#[test]
+fn test_encode_scalars() {
+    assert_eq!(to_data(Cow::Borrowed("cow")).unwrap(), string("cow"));
+    assert_eq!(to_data(PathBuf::from("a/b")).unwrap(), string("a/b"));
+    assert_eq!(to_data(NonZeroU32::new(5)).unwrap(), string("5"));
+    assert_eq!(to_data(u128::MAX).unwrap(), string("340282366920938463463374607431768211455"));
+    assert_eq!(to_data(-1i128).unwrap(), string("-1"));
+    assert_eq!(to_data(()).unwrap(), Data::Null);
+
+    #[derive(Serialize)]
+    struct Unit;
+    assert_eq!(to_data(Unit).unwrap(), Data::Null);
+
+    // Durations are written the way they debug print.
+    assert_eq!(to_data(Duration::from_millis(1500)).unwrap(), string("1.5s"));
+    assert_eq!(to_data(Duration::from_micros(20)).unwrap(), string("20µs"));
+}
+
+#[test]
+fn test_encode_bytes() {
+    // What `serde_bytes` serializes as.
+    struct Raw(&'static [u8]);
+
+    impl serde::Serialize for Raw {
+        fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.serialize_bytes(self.0)
+        }
+    }
+
+    assert_eq!(to_data(Raw(b"ab")).unwrap(), Data::Bytes(b"ab".to_vec()));
+    assert_eq!(to_data(Data::Bytes(vec![1])).unwrap(), Data::Bytes(vec![1]));
+    // A plain `Vec<u8>` is a sequence to serde.
+    assert_eq!(to_data(vec![1u8]).unwrap(), Data::Vec(vec![string("1")]));
+}
+
+#[test]
+fn test_encode_options() {
+    assert_eq!(to_data(None::<i32>).unwrap(), Data::Null);
+    assert_eq!(to_data(Some(1)).unwrap(), string("1"));
+    assert_eq!(to_data(Some(None::<i32>)).unwrap(), Data::Null);
+    assert_eq!(to_data(Some(Some("a"))).unwrap(), string("a"));
+
+    #[derive(Serialize)]
+    struct Inner {
+        name: Option<&'static str>,
+    }
+
+    #[derive(Serialize)]
+    struct Outer {
+        name: &'static str,
+        inner: Option<Inner>,
+    }
+
+    let template = compile_str("{{#inner}}[{{name}}]{{/inner}}{{^inner}}none{{/inner}}").unwrap();
+
+    let data = Outer { name: "outer", inner: None };
+    assert_eq!(template.render_to_string(&data).unwrap(), "none");
+
+    let data = Outer { name: "outer", inner: Some(Inner { name: Some("inner") }) };
+    assert_eq!(template.render_to_string(&data).unwrap(), "[inner]");
+
+    // The `None` is still a key, so the outer name doesn't show through.
+    let data = Outer { name: "outer", inner: Some(Inner { name: None }) };
+    assert_eq!(template.render_to_string(&data).unwrap(), "[]");
+
+    let mut out = Vec::new();
+    let warnings = template.render_with_warnings(&mut out, &data).unwrap();
+    assert!(warnings.is_empty());
+}
+
+#[derive(Serialize)]
+struct Author {
+    #[serde(rename = "displayName")]
+    name: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bio: Option<&'static str>,
+    #[serde(skip)]
+    #[allow(dead_code)]
+    password: &'static str,
+    #[serde(flatten)]
+    links: Links,
+    #[serde(flatten)]
+    extra: BTreeMap<&'static str, u32>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Links {
+    home_page: &'static str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    other_pages: Vec<&'static str>,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type")]
+enum Post {
+    Article { title: &'static str },
+    #[serde(rename = "note")]
+    Note(Note),
+}
+
+#[derive(Serialize)]
+struct Note {
+    text: &'static str,
+}
+
+#[test]
+fn test_encode_serde_attributes() {
+    let mut extra = BTreeMap::new();
+    extra.insert("karma", 3);
+    let author = Author {
+        name: "Ann",
+        bio: None,
+        password: "hunter2",
+        links: Links { home_page: "https://example.com", other_pages: vec![] },
+        extra,
+    };
+
+    let data = to_data(&author).unwrap();
+    assert_eq!(data, map(vec![
+        ("displayName", string("Ann")),
+        ("homePage", string("https://example.com")),
+        ("karma", string("3")),
+    ]));
+    // The same keys as the JSON of the struct.
+    assert_eq!(data, to_data(serde_json::to_value(&author).unwrap()).unwrap());
+
+    let template = compile_str("{{displayName}} {{homePage}}{{^bio}} (no bio){{/bio}}").unwrap();
+    assert_eq!(template.render_to_string(&author).unwrap(), "Ann https://example.com (no bio)");
+
+    assert_eq!(to_data(Post::Article { title: "Hi" }).unwrap(), map(vec![("type", string("Article")), ("title", string("Hi"))]));
+    assert_eq!(to_data(Post::Note(Note { text: "Hi" })).unwrap(), map(vec![("type", string("note")), ("text", string("Hi"))]));
+}
@@ -0,0 +1,63 @@
+use std::fs::File;
+use std::io::Write;
+
+use tempdir::TempDir;
+
+use mustache::{Context, Lint, MapBuilder};
+
+fn lint(source: &str) -> Vec<Lint> {
+    let tmpdir = TempDir::new("").expect("Failed to make tempdir");
+    Context::new(tmpdir.path().to_path_buf()).lint(source, None).expect("Failed to lint")
+}
+
+#[test]
+fn test_clean_template() {
+    assert_eq!(lint("{{#a}}{{b}}{{^c}}{{/c}}{{/a}}{{=<% %>=}}"), vec![]);
+}
+
+#[test]
+fn test_nested_identical_section() {
+    assert_eq!(lint("{{#a}}{{#a}}{{/a}}{{/a}}"),
+               vec![Lint::NestedIdenticalSection("a".to_string())]);
+    assert_eq!(lint("{{#a}}{{^a}}{{/a}}{{/a}}"),
+               vec![Lint::UnreachableInvertedSection("a".to_string())]);
+    assert_eq!(lint("{{#a}}{{#b}}{{#a}}{{/a}}{{/b}}{{/a}}"), vec![]);
+}
+
+#[test]
+fn test_unreachable_inverted_section() {
+    assert_eq!(lint("{{#a.b}}{{#c}}{{^a.b}}{{/a.b}}{{/c}}{{/a.b}}"),
+               vec![Lint::UnreachableInvertedSection("a.b".to_string())]);
+}
+
+#[test]
+fn test_suspicious_delimiters() {
+    assert_eq!(lint("{{=| |=}}|={{ }}=|{{=a b=}}"), vec![
+        Lint::SuspiciousDelimiters("|".to_string(), "|".to_string()),
+        Lint::SuspiciousDelimiters("a".to_string(), "b".to_string()),
+    ]);
+}
+
+#[test]
+fn test_unknown_sections() {
+    let tmpdir = TempDir::new("").expect("Failed to make tempdir");
+    File::create(tmpdir.path().join("item.mustache"))
+        .and_then(|mut f| f.write_all(b"{{#tags}}{{.}}{{/tags}}{{#price}}{{/price}}"))
+        .expect("Failed to write partial");
+
+    let sample = MapBuilder::new()
+        .insert_vec("items", |items| {
+            items.push_map(|item| item.insert_vec("tags", |tags| tags.push_str("new")))
+        })
+        .insert_vec("empty", |vec| vec)
+        .build();
+
+    let ctx = Context::new(tmpdir.path().to_path_buf());
+    let source = "{{#items}}{{> item}}{{/items}}{{#empty}}{{#anything}}{{/anything}}{{/empty}}{{#missing}}{{/missing}}";
+    let lints = ctx.lint(source, Some(&sample)).expect("Failed to lint");
+
+    assert_eq!(lints, vec![
+        Lint::UnknownSection("price".to_string()),
+        Lint::UnknownSection("missing".to_string()),
+    ]);
+}
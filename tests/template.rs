@@ -1,13 +1,18 @@
+use std::borrow::Cow;
 use std::cell::RefCell;
 use tempdir::TempDir;
 use std::fmt::Debug;
+use std::time::Duration;
 use std::fs::File;
 use std::io::Write;
 use std::path::{PathBuf, Path};
 use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
 
-use mustache::{self, DefaultLoader, PartialLoader, Data, Error, to_data};
-use mustache::{Context, Template};
+use mustache::{self, DefaultLoader, PartialLoader, Data, Error, Warning, to_data};
+use mustache::testing::MockLoader;
+use mustache::{BidiControls, BytesEncoding, Context, Deprecation, Escape, KvLoader, LruLoader, MapBuilder, MetricsSink, Region, RenderHook, RenderOptions, Template};
 
 use serde::Serialize;
 use serde_json;
@@ -33,7 +38,7 @@ struct Person {
 }
 
 fn compile_str(s: &str) -> Template<DefaultLoader> {
-    mustache::compile_str(s).expect(&format!("Failed to compile: {}", s))
+    mustache::compile_str(s).unwrap_or_else(|_| panic!("Failed to compile: {}", s))
 }
 
 fn assert_render<T>(template: &str, data: &T) -> String
@@ -332,6 +337,1789 @@ fn test_render_to_string() {
     assert_eq!(&result, "foobar");
 }
 
+#[test]
+fn test_render_trim_markers() {
+    let mut ctx = Context::new(PathBuf::from("."));
+    ctx.extensions.trim_markers = true;
+
+    let template = ctx.compile("ports = [\n{{#ports}}\n  {{- . -}},\n{{/ports}}\n]".chars())
+        .expect("Failed to compile");
+
+    let mut data = HashMap::new();
+    data.insert("ports", vec!["80", "443"]);
+
+    assert_eq!(template.render_to_string(&data).unwrap(), "ports = [\n80,\n443,\n]");
+}
+
+#[test]
+fn test_render_crlf() {
+    #[derive(Clone, Debug)]
+    struct Loader;
+
+    impl PartialLoader for Loader {
+        fn load(&self, _: impl AsRef<Path>) -> Result<String, Error> {
+            Ok("x\n\ny\n".to_string())
+        }
+    }
+
+    let data = MapBuilder::new().insert_bool("s", true).insert_str("x", "X").build();
+    let ctx = Context::with_loader(Loader);
+
+    for source in &["a\n{{#s}}\nb\n{{/s}}\nc\n", "  {{! c }}\n{{=<% %>=}}\n<%x%>\n", "a\n  {{>p}}\nb\n"] {
+        let lf = ctx.compile(*source).unwrap().render_data_to_string(&data).unwrap();
+        let crlf = ctx.compile(source.replace('\n', "\r\n")).unwrap().render_data_to_string(&data).unwrap();
+        assert_eq!(crlf.replace("\r\n", "\n"), lf, "{:?}", source);
+    }
+
+    // Including the partials written on Windows.
+    #[derive(Clone, Debug)]
+    struct CrlfLoader;
+
+    impl PartialLoader for CrlfLoader {
+        fn load(&self, _: impl AsRef<Path>) -> Result<String, Error> {
+            Ok("x\r\n\r\ny\r\n".to_string())
+        }
+    }
+
+    let template = Context::with_loader(CrlfLoader).compile("a\r\n  {{>p}}\r\nb").unwrap();
+    assert_eq!(template.render_data_to_string(&data).unwrap(), "a\r\n  x\r\n\r\n  y\r\nb");
+}
+
+#[test]
+fn test_render_bytes() {
+    let data = MapBuilder::new()
+        .insert_bytes("one", &b"M"[..])
+        .insert_bytes("two", &b"Ma\xff"[..])
+        .insert_bytes("empty", Vec::new())
+        .build();
+    let template = compile_str("{{one}} {{two}} {{#two}}[{{.}}]{{/two}}{{#empty}}no{{/empty}}");
+
+    let render = |bytes| {
+        let options = RenderOptions { bytes, ..RenderOptions::default() };
+        let mut out = Vec::new();
+        template.render_data_with(&mut out, &data, &options).unwrap();
+        String::from_utf8(out).unwrap()
+    };
+
+    assert_eq!(render(BytesEncoding::Base64), "TQ== TWH/ [TWH/]");
+    assert_eq!(render(BytesEncoding::Base64Url), "TQ TWH_ [TWH_]");
+    assert_eq!(render(BytesEncoding::Hex), "4d 4d61ff [4d61ff]");
+    assert_eq!(render(BytesEncoding::Utf8Lossy), "M Ma\u{fffd} [Ma\u{fffd}]");
+}
+
+#[test]
+fn test_concat() {
+    let ctx = Context::new(PathBuf::from("tests/test-data"));
+    let header = ctx.compile("Hi {{>username}}\n".chars()).unwrap();
+    let body = ctx.compile("{{#items}}{{>user}}{{/items}}".chars()).unwrap();
+
+    let page = header.concat(&body);
+    assert_eq!(page.partials().into_iter().collect::<Vec<_>>(), ["user", "username"]);
+
+    let data = MapBuilder::new()
+        .insert_str("name", "Ann")
+        .insert_vec("items", |v| v.push_map(|m| m.insert_str("name", "a")))
+        .build();
+    let expected = format!("{}{}", header.render_data_to_string(&data).unwrap(), body.render_data_to_string(&data).unwrap());
+    assert_eq!(page.render_data_to_string(&data).unwrap(), expected);
+
+    let text = compile_str("a").concat(&compile_str("b"));
+    assert_eq!(text.static_text(), Some("ab"));
+}
+
+#[test]
+fn test_render_bidi_controls() {
+    let data = MapBuilder::new()
+        .insert_str("name", "a\u{202E}b")
+        .insert_bytes("raw", "\u{2067}c".as_bytes())
+        .insert_vec("names", |v| v.push_str("\u{200F}d"))
+        .build();
+    let template = compile_str("\u{202E}{{name}} {{{name}}} {{raw}} {{#names}}{{.}}{{/names}}");
+
+    let render = |bidi_controls| {
+        let options = RenderOptions { bidi_controls, bytes: BytesEncoding::Utf8Lossy, ..RenderOptions::default() };
+        let mut out = Vec::new();
+        template.render_data_with(&mut out, &data, &options).map(|_| String::from_utf8(out).unwrap())
+    };
+
+    // The template's own text is left alone.
+    assert_eq!(render(BidiControls::Keep).unwrap(), "\u{202E}a\u{202E}b a\u{202E}b \u{2067}c \u{200F}d");
+    assert_eq!(render(BidiControls::Strip).unwrap(), "\u{202E}ab ab c d");
+    assert_eq!(render(BidiControls::Escape).unwrap(), "\u{202E}a\\u202Eb a\\u202Eb \\u2067c \\u200Fd");
+    assert_let!(Err(Error::BidiControl('\u{202E}')) = render(BidiControls::Reject) => ());
+}
+
+#[test]
+fn test_render_locale() {
+    use mustache::locale::{self, Locale, LocaleProvider, Plural};
+
+    // Spells everything out, to tell it from the built-in formatting.
+    struct Words;
+
+    impl LocaleProvider for Words {
+        fn format_number(&self, locale: &str, number: &str) -> Option<String> {
+            Some(format!("{}:{}", locale, number))
+        }
+
+        fn plural(&self, _: &str, number: &str) -> Plural {
+            if number == "2" { Plural::Two } else { Plural::Other }
+        }
+
+        fn categories(&self, _: &str) -> Vec<Plural> {
+            vec![Plural::Two, Plural::Other]
+        }
+
+        fn format_date(&self, _: &str, year: i32, month: u8, day: u8) -> Option<String> {
+            Some(format!("{}/{}/{}", day, month, year))
+        }
+    }
+
+    let mut data = MapBuilder::new()
+        .insert_str("n", "2")
+        .insert_str("day", "2024-03-09")
+        .build();
+    if let Data::Map(ref mut map) = data {
+        map.insert("number".to_string(), locale::number());
+        map.insert("date".to_string(), locale::date());
+        map.insert("plural".to_string(), locale::plural());
+    }
+
+    let template = compile_str("{{#number}}{{n}}000{{/number}} {{#date}}{{day}}{{/date}} {{#plural}}{{n}}|pair|{{n}} things{{/plural}} {{#date}}x{{/date}}");
+    let render = |locale| {
+        let options = RenderOptions { locale, ..RenderOptions::default() };
+        let mut out = Vec::new();
+        template.render_data_with(&mut out, &data, &options).unwrap();
+        String::from_utf8(out).unwrap()
+    };
+
+    assert_eq!(render(None), "2,000 2024-03-09 2 things x");
+    assert_eq!(render(Some(Locale::with_provider("el", Words))), "el:2000 9/3/2024 pair x");
+}
+
+#[test]
+fn test_render_progress() {
+    use std::sync::Mutex;
+    use mustache::{Progress, ProgressCallback};
+
+    let data = MapBuilder::new()
+        .insert_vec("items", |v| (0..10).fold(v, |v, _| v.push_str("abcd")))
+        .build();
+    let reports = Arc::new(Mutex::new(Vec::new()));
+    let log = reports.clone();
+    let progress = ProgressCallback::new(move |p: Progress| log.lock().unwrap().push(p.bytes)).every_bytes(10);
+    let options = RenderOptions { progress: Some(progress), ..RenderOptions::default() };
+
+    // Escaped values are written in pieces, so reports don't land on exact multiples.
+    let template = compile_str("{{#items}}<{{.}}>{{/items}}");
+    template.render_data_with(&mut Vec::new(), &data, &options).unwrap();
+    let bytes = reports.lock().unwrap().clone();
+    assert_eq!(bytes.last(), Some(&60));
+    assert_eq!(bytes.len(), 7);
+    assert!(bytes.windows(2).all(|w| w[1] / 10 > w[0] / 10 || w[1] == 60));
+
+    // Flat and static templates are counted too, and a failed render has no final report.
+    reports.lock().unwrap().clear();
+    compile_str("static text").render_data_with(&mut Vec::new(), &data, &options).unwrap();
+    assert_eq!(*reports.lock().unwrap(), vec![11, 11]);
+
+    reports.lock().unwrap().clear();
+    let cancelled = RenderOptions { cancel: Some(Arc::new(AtomicBool::new(true))), ..options.clone() };
+    assert!(compile_str("{{a}}").render_data_with(&mut Vec::new(), &data, &cancelled).is_err());
+    assert!(reports.lock().unwrap().is_empty());
+}
+
+#[test]
+fn test_render_tee() {
+    use std::io;
+
+    // Stands in for a hasher, seeing only the bytes.
+    struct Count(usize);
+
+    impl Write for Count {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0 += buf.len();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let planets = vec![
+        Planet { name: "Mercury".to_string(), info: None },
+        Planet { name: "<Venus>".to_string(), info: None },
+    ];
+    let template = compile_str("{{#.}}{{name}} {{/.}}");
+
+    let (mut out, mut count) = (Vec::new(), Count(0));
+    template.render_tee(&mut [&mut out, &mut count], &planets).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "Mercury &lt;Venus&gt; ");
+    assert_eq!(count.0, 22);
+
+    // A full writer fails the render.
+    let mut full = [0u8; 4];
+    let mut out = Vec::new();
+    assert_let!(Err(Error::Io(_)) = template.render_tee(&mut [&mut &mut full[..], &mut out], &planets) => ());
+    assert_eq!(&full, b"Merc");
+}
+
+#[test]
+fn test_render_partial_fallbacks() {
+    let mut ctx = Context::new(PathBuf::from("tests/test-data"));
+    ctx.extensions.partial_fallbacks = true;
+
+    let template = ctx.compile("{{>missing}}<i>{{name}}</i>{{/missing}} {{>username}}?{{/username}}".chars())
+        .expect("Failed to compile");
+
+    let mut data = HashMap::new();
+    data.insert("name", "a");
+
+    assert_eq!(template.render_to_string(&data).unwrap(), "<i>a</i> a");
+}
+
+#[test]
+fn test_render_with_layout() {
+    let ctx = Context::new(PathBuf::from("tests/test-data"));
+
+    let mut data = HashMap::new();
+    data.insert("title", "<Home>");
+
+    let mut bytes = vec![];
+    ctx.render_with_layout(&mut bytes, "page", "layout", &data).expect("Failed to render");
+
+    assert_eq!(String::from_utf8(bytes).unwrap(),
+               "<main>\n  <h1>&lt;Home&gt;</h1>\n\n</main>\n");
+}
+
+#[test]
+fn test_default_loader_extension() {
+    let ctx = Context::new(PathBuf::from("tests/test-data"));
+    let data = MapBuilder::new().insert_str("name", "Jo").insert_str("product", "Widget").build();
+
+    // The extension of a name is replaced.
+    let template = ctx.compile_path("greeting.html").expect("Failed to compile");
+    assert_eq!(template.render_data_to_string(&data).unwrap(), "Hello Jo");
+
+    let template = ctx.compile_path("welcome.txt.mustache").expect("Failed to compile");
+    assert_eq!(template.render_data_to_string(&data).unwrap(), "Hello Jo, Widget is ready.\n");
+}
+
+#[test]
+fn test_render_email() {
+    let ctx = Context::new(PathBuf::from("tests/test-data"));
+
+    let mut data = HashMap::new();
+    data.insert("name", "Jo & co");
+    data.insert("product", "Widget");
+
+    let email = ctx.render_email("welcome", &data).expect("Failed to render");
+
+    assert_eq!(email.html, "<p>Hello Jo &amp; co, <b>Widget</b> is ready.</p>\n");
+    assert_eq!(email.text, "Hello Jo & co, Widget is ready.\n");
+}
+
+#[test]
+fn test_render_xml_escape() {
+    let mut ctx = Context::new(PathBuf::from("."));
+    ctx.escape = Escape::Xml;
+
+    let template = ctx.compile("<title>{{title}}</title>{{{raw}}}".chars()).expect("Failed to compile");
+
+    let data = MapBuilder::new()
+        .insert_str("title", "Tom's <\"News\"> & more\t")
+        .insert_str("raw", "\u{1}")
+        .build();
+    assert_eq!(template.render_data_to_string(&data).unwrap(),
+               "<title>Tom&apos;s &lt;&quot;News&quot;&gt; &amp; more\t</title>\u{1}");
+
+    let data = MapBuilder::new().insert_str("title", "bell\u{7}").build();
+    match template.render_data_to_string(&data) {
+        Err(Error::InvalidXmlChar('\u{7}')) => {}
+        result => panic!("unexpected result: {:?}", result),
+    }
+}
+
+#[test]
+fn test_render_latex_escape() {
+    let mut ctx = Context::new(PathBuf::from("."));
+    ctx.escape = Escape::Latex;
+
+    // LaTeX braces around a tag would read as a triple mustache.
+    let template = ctx.compile("{{=<< >>=}}\\textbf{<<name>>}: <<cost>>".chars()).expect("Failed to compile");
+    let data = MapBuilder::new()
+        .insert_str("name", "first_last")
+        .insert_str("cost", "5$ & 10% #1 {a} ^~\\")
+        .build();
+
+    assert_eq!(template.render_data_to_string(&data).unwrap(),
+               "\\textbf{first\\_last}: 5\\$ \\& 10\\% \\#1 \\{a\\} \\textasciicircum{}\\textasciitilde{}\\textbackslash{}");
+}
+
+#[test]
+fn test_render_cancelled() {
+    let template = compile_str("{{#items}}{{.}}{{#stop}}!{{/stop}}{{/items}}");
+
+    let cancel = Arc::new(AtomicBool::new(false));
+    let flag = cancel.clone();
+    let data = MapBuilder::new()
+        .insert_vec("items", |v| v.push_str("a").push_str("b"))
+        .insert_fn("stop", move |_| {
+            flag.store(true, Ordering::Relaxed);
+            String::new()
+        })
+        .build();
+
+    let options = RenderOptions { cancel: Some(cancel), ..RenderOptions::default() };
+    let mut bytes = vec![];
+
+    assert_let!(Err(Error::Cancelled) = template.render_data_with(&mut bytes, &data, &options) => ());
+    assert_eq!(bytes, b"a");
+}
+
+#[test]
+fn test_render_limits() {
+    // Each level includes the next one twice, for 2^20 copies of `x`.
+    #[derive(Clone, Debug)]
+    struct Loader;
+
+    impl PartialLoader for Loader {
+        fn load(&self, name: impl AsRef<Path>) -> Result<String, Error> {
+            let level: u32 = name.as_ref().to_str().unwrap()[1..].parse().unwrap();
+            if level == 20 {
+                Ok("x".to_string())
+            } else {
+                Ok(format!("{{{{>p{0}}}}}{{{{>p{0}}}}}", level + 1))
+            }
+        }
+    }
+
+    let template = Context::with_loader(Loader).compile("{{>p0}}".chars()).expect("Failed to compile");
+    let options = RenderOptions { max_partials: Some(100), ..RenderOptions::default() };
+    let mut bytes = vec![];
+    assert_let!(Err(Error::TooManyPartials(100)) = template.render_data_with(&mut bytes, &Data::Null, &options) => ());
+
+    let template = compile_str("{{#items}}{{#items}}{{.}}{{/items}}{{/items}}{{^none}}!{{/none}}");
+    let data = MapBuilder::new()
+        .insert_vec("items", |v| v.push_str("a").push_str("b").push_str("c"))
+        .build();
+
+    // The outer items, the inner items of each and the inverted section.
+    let options = RenderOptions { max_iterations: Some(13), ..RenderOptions::default() };
+    let mut bytes = vec![];
+    template.render_data_with(&mut bytes, &data, &options).unwrap();
+    assert_eq!(bytes, b"abcabcabc!");
+
+    let options = RenderOptions { max_iterations: Some(12), ..RenderOptions::default() };
+    let mut bytes = vec![];
+    assert_let!(Err(Error::TooManyIterations(12)) = template.render_data_with(&mut bytes, &data, &options) => ());
+}
+
+#[test]
+fn test_render_ignore_case() {
+    let data = MapBuilder::new()
+        .insert_str("Title", "a")
+        .insert_str("TITLE", "b")
+        .insert_str("name", "exact")
+        .insert_str("NAME", "loose")
+        .insert_map("User", |m| m.insert_str("EMAIL", "ann@example.com"))
+        .insert_vec("ITEMS", |v| v.push_str("x").push_str("y"))
+        .build();
+    let options = RenderOptions { ignore_case: true, ..RenderOptions::default() };
+
+    for &(source, expected) in &[
+        ("{{title}}/{{name}}/{{user.email}}", "b/exact/ann@example.com"),
+        ("{{#items}}{{.}}{{/items}}{{^Missing}}!{{/Missing}}{{#user}}{{Email}}{{/user}}", "xy!ann@example.com"),
+    ] {
+        let template = compile_str(source);
+        let mut bytes = vec![];
+        template.render_data_with(&mut bytes, &data, &options).unwrap();
+        assert_eq!(String::from_utf8(bytes).unwrap(), expected);
+    }
+
+    // Exact matches only, as before, when not asked.
+    assert_eq!(compile_str("{{title}}{{user.email}}").render_data_to_string(&data).unwrap(), "");
+}
+
+#[test]
+fn test_render_aliases() {
+    let data = MapBuilder::new()
+        .insert_str("customerName", "Ann")
+        .insert_str("customer_id", "7")
+        .insert_map("billingAddress", |m| m.insert_str("zipCode", "69001"))
+        .build();
+    let mut options = RenderOptions::default();
+    for &(old, new) in &[("customer_name", "customerName"), ("customer_id", "customerId"),
+                         ("billing", "billingAddress"), ("zip", "zipCode")] {
+        options.aliases.insert(old.to_string(), new.to_string());
+    }
+
+    // Names still in the data are used as they are.
+    let template = compile_str("{{customer_name}} {{customerName}} {{customer_id}} {{#billing}}{{zip}}{{/billing}}");
+    let mut bytes = vec![];
+    template.render_data_with(&mut bytes, &data, &options).unwrap();
+    assert_eq!(String::from_utf8(bytes).unwrap(), "Ann Ann 7 69001");
+
+    let mut bytes = vec![];
+    compile_str("{{billing.zip}}").render_data_with(&mut bytes, &data, &options).unwrap();
+    assert_eq!(bytes, b"69001");
+}
+
+#[test]
+fn test_render_hook() {
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<String>>);
+
+    impl RenderHook for Recorder {
+        fn before_tag(&self, name: &str, value: Option<&Data>, src: &str) {
+            self.0.lock().unwrap().push(format!("tag {} {} {:?}", name, src, value));
+        }
+
+        fn after_tag(&self, name: &str, _value: Option<&Data>, _src: &str, output: &mut Vec<u8>) {
+            if name == "." {
+                output.insert(0, b'*');
+            }
+        }
+
+        fn before_partial(&self, name: &str) {
+            self.0.lock().unwrap().push(format!("enter {}", name));
+        }
+
+        fn after_partial(&self, name: &str) {
+            self.0.lock().unwrap().push(format!("leave {}", name));
+        }
+    }
+
+    let template = Context::new(PathBuf::from("tests/test-data"))
+        .compile("{{#names}}{{.}}{{/names}} {{>user}}{{>missing}}{{&missing}}".chars())
+        .expect("Failed to compile");
+
+    let data = MapBuilder::new()
+        .insert_vec("names", |v| v.push_str("a").push_str("<b>"))
+        .insert_str("name", "c")
+        .build();
+
+    let recorder = Arc::new(Recorder::default());
+    let options = RenderOptions { hook: Some(recorder.clone()), ..RenderOptions::default() };
+
+    let mut bytes = vec![];
+    template.render_data_with(&mut bytes, &data, &options).unwrap();
+
+    assert_eq!(String::from_utf8(bytes).unwrap(), "*a*&lt;b&gt; <strong>c</strong>\n");
+    assert_eq!(*recorder.0.lock().unwrap(), vec![
+        "tag . {{.}} Some(StrVal(a))".to_string(),
+        "tag . {{.}} Some(StrVal(<b>))".to_string(),
+        "enter user".to_string(),
+        "enter username".to_string(),
+        "tag name {{name}} Some(StrVal(c))".to_string(),
+        "leave username".to_string(),
+        "leave user".to_string(),
+        "tag missing {{&missing}} None".to_string(),
+    ]);
+}
+
+#[test]
+fn test_shared_partial_loader() {
+    use std::sync::Mutex;
+
+    // Not `Clone` on its own, so it has to be shared.
+    #[derive(Debug, Default)]
+    struct Loader {
+        loaded: Mutex<Vec<String>>,
+    }
+
+    impl Clone for Loader {
+        fn clone(&self) -> Self {
+            panic!("the loader should not be cloned")
+        }
+    }
+
+    impl PartialLoader for Loader {
+        fn load(&self, name: impl AsRef<Path>) -> Result<String, Error> {
+            let name = name.as_ref().to_str().ok_or(Error::InvalidStr)?.to_string();
+            self.loaded.lock().unwrap().push(name.clone());
+            Ok(format!("<{}>", name))
+        }
+    }
+
+    let loader = Arc::new(Loader::default());
+    let ctx = Context::with_loader(loader.clone());
+
+    let template = ctx.compile("{{>a}}{{#f}}{{/f}}".chars()).expect("Failed to compile");
+    let data = MapBuilder::new().insert_fn("f", |_| "{{>b}}".to_string()).build();
+
+    template.clone().render_data_to_string(&data).expect("Failed to render");
+    assert_eq!(*loader.loaded.lock().unwrap(), vec!["a".to_string(), "b".to_string()]);
+}
+
+#[test]
+fn test_shared_context() {
+    #[derive(Debug)]
+    struct Loader;
+
+    impl Clone for Loader {
+        fn clone(&self) -> Self {
+            panic!("the loader should not be cloned")
+        }
+    }
+
+    impl PartialLoader for Loader {
+        fn load(&self, name: impl AsRef<Path>) -> Result<String, Error> {
+            Ok(format!("<{}>", name.as_ref().display()))
+        }
+    }
+
+    let ctx = Context::with_loader(Loader).shared();
+    let data = MapBuilder::new().insert_fn("f", |_| "{{>b}}".to_string()).build();
+
+    for source in &["{{>a}}{{#f}}{{/f}}", "{{#f}}{{/f}}"] {
+        let template = ctx.compile(*source).expect("Failed to compile");
+        template.render_data_to_string(&data).expect("Failed to render");
+    }
+}
+
+#[test]
+fn test_cache_partials() {
+    use std::sync::atomic::AtomicUsize;
+
+    #[derive(Clone, Debug, Default)]
+    struct Loader {
+        loads: Arc<AtomicUsize>,
+    }
+
+    impl PartialLoader for Loader {
+        fn load(&self, name: impl AsRef<Path>) -> Result<String, Error> {
+            self.loads.fetch_add(1, Ordering::Relaxed);
+            match name.as_ref().to_str() {
+                Some("header") => Ok("[{{>title}}]".to_string()),
+                _ => Ok("{{title}}".to_string()),
+            }
+        }
+    }
+
+    let loader = Loader::default();
+    let mut ctx = Context::with_loader(loader.clone());
+    ctx.cache_partials();
+
+    let mut data = HashMap::new();
+    data.insert("title", "t");
+
+    let a = ctx.compile("a{{>header}}".chars()).expect("Failed to compile");
+    let b = ctx.clone().compile("b{{>header}}".chars()).expect("Failed to compile");
+
+    assert_eq!(a.render_to_string(&data).unwrap(), "a[t]");
+    assert_eq!(b.render_to_string(&data).unwrap(), "b[t]");
+    assert_eq!(loader.loads.load(Ordering::Relaxed), 2);
+
+    ctx.clear_partial_cache();
+    ctx.compile("{{>header}}".chars()).expect("Failed to compile");
+    assert_eq!(loader.loads.load(Ordering::Relaxed), 4);
+}
+
+#[test]
+fn test_cache_partials_extensions() {
+    let mut ctx = Context::with_loader(KvLoader::new(|_| Ok(Some("{{a || b}}".to_string()))));
+    ctx.cache_partials();
+    ctx.compile("{{>p}}").expect("Failed to compile");
+
+    // The partial compiled without fallback chains doesn't serve a clone
+    // with them.
+    let mut chains = ctx.clone();
+    chains.extensions.fallback_chains = true;
+    let data = MapBuilder::new().insert_str("b", "b").build();
+    let template = chains.compile("{{>p}}").expect("Failed to compile");
+    assert_eq!(template.render_data_to_string(&data).unwrap(), "b");
+
+    // Nor one that allows shorter sources than it was checked against.
+    let mut strict = ctx.clone();
+    strict.limits.max_source_len = Some(4);
+    assert_let!(Err(Error::SourceTooLong(4)) = strict.compile("{{>p}}") => ());
+}
+
+#[test]
+fn test_inline_static_partials() {
+    let mut partials = HashMap::new();
+    partials.insert("nav".to_string(), "<nav>\n{{> links}}</nav>\n".to_string());
+    partials.insert("links".to_string(), "  <a>home</a>\n\n  <a>about</a>\n".to_string());
+    partials.insert("big".to_string(), "0123456789".repeat(10));
+    partials.insert("user".to_string(), "{{name}}".to_string());
+
+    let mut ctx = Context::with_loader(KvLoader::new(move |key| Ok(partials.get(key).cloned())));
+    let source = "<body>\n  {{> nav}}\n  {{> links}}{{#items}}[{{> user}}]{{/items}}{{> big}}\n</body>";
+    let data = MapBuilder::new()
+        .insert_vec("items", |v| v.push_map(|m| m.insert_str("name", "a")))
+        .build();
+
+    let rendered = ctx.compile(source).unwrap().render_data_to_string(&data).unwrap();
+
+    ctx.inline_static_partials(64);
+    let template = ctx.compile(source).unwrap();
+    assert_eq!(template.render_data_to_string(&data).unwrap(), rendered);
+
+    // An indented include is only inlined where a line is known to start.
+    let template = ctx.compile("  {{> nav}}\n").unwrap();
+    assert_eq!(template.static_text(), None);
+    let template = ctx.compile("x\n  {{> nav}}\n").unwrap();
+    assert_eq!(template.static_text(), Some("x\n  <nav>\n    <a>home</a>\n\n    <a>about</a>\n  </nav>\n"));
+}
+
+#[test]
+fn test_compile_limits() {
+    let mut partials = HashMap::new();
+    partials.insert("row".to_string(), "<td>{{a}}</td><td>{{b}}</td>".to_string());
+    partials.insert("long".to_string(), "x".repeat(100));
+
+    let mut ctx = Context::with_loader(KvLoader::new(move |key| Ok(partials.get(key).cloned())));
+    ctx.cache_partials();
+    ctx.compile("{{>row}}").unwrap();
+
+    ctx.limits.max_source_len = Some(32);
+    ctx.compile("{{#rows}}{{>row}}{{/rows}}").unwrap();
+    assert!(matches!(ctx.compile("{{#rows}}{{>row}}{{/rows}}{{! long enough }}"), Err(Error::SourceTooLong(32))));
+    assert!(matches!(ctx.compile_reader(&b"{{#rows}}{{>long}}{{/rows}}"[..]), Err(Error::SourceTooLong(32))));
+
+    // Tokens are counted over the partials, cached ones included.
+    ctx.limits.max_source_len = None;
+    ctx.limits.max_tokens = Some(7);
+    ctx.compile("{{#rows}}{{>row}}{{/rows}}").unwrap();
+    assert!(matches!(ctx.compile("{{#rows}}{{>row}}{{/rows}}!"), Err(Error::TooManyTokens(7))));
+
+    ctx.limits.max_tokens = None;
+    ctx.limits.max_depth = Some(2);
+    ctx.compile("{{#a}}{{^b}}{{/b}}{{/a}}").unwrap();
+    assert!(matches!(ctx.compile("{{#a}}{{^b}}{{#c}}{{/c}}{{/b}}{{/a}}"), Err(Error::NestingTooDeep(2))));
+}
+
+#[test]
+fn test_partial_load_errors() {
+    let mut partials = HashMap::new();
+    partials.insert("pages/home".to_string(), "<body>\n{{#user}}\n  {{> footer}}\n{{/user}}".to_string());
+    partials.insert("footer".to_string(), "{{> missing}}".to_string());
+    let ctx = Context::with_loader(KvLoader::new(move |key| match key {
+        "missing" => Err(Error::InvalidStr),
+        _ => Ok(partials.get(key).cloned()),
+    }));
+
+    let err = ctx.compile_path("pages/home").map(|_| ()).unwrap_err();
+    assert_let!(Error::PartialLoad { ref name, ref template, line, .. } = err => {
+        assert_eq!((&name[..], template.as_ref().map(|t| &t[..]), line), ("missing", Some("footer"), 1));
+    });
+    assert_eq!(err.to_string(), "partial \"missing\" referenced from footer:1: invalid str");
+
+    let err = ctx.compile("a\n\n{{> footer}}").map(|_| ()).unwrap_err();
+    assert_eq!(err.to_string(), "partial \"missing\" referenced from footer:1: invalid str");
+
+    let err = ctx.compile("a\n\nb {{> missing}}").map(|_| ()).unwrap_err();
+    assert_eq!(err.to_string(), "partial \"missing\" referenced from line 3: invalid str");
+}
+
+#[test]
+fn test_partial_syntax_errors() {
+    let tmpdir = TempDir::new("").unwrap();
+    File::create(tmpdir.path().join("page.mustache")).unwrap().write_all(b"{{> rows/row}}").unwrap();
+    std::fs::create_dir(tmpdir.path().join("rows")).unwrap();
+    File::create(tmpdir.path().join("rows/row.mustache")).unwrap().write_all(b"<tr>\n  {{name}x}}").unwrap();
+
+    let ctx = Context::new(tmpdir.path().to_path_buf());
+    let err = ctx.compile("first\nsecond {{> page}}".chars()).map(|_| ()).unwrap_err();
+    let path = tmpdir.path().join("rows/row.mustache");
+    assert_let!(Error::PartialSyntax { ref name, path: Some(ref at), line, column, .. } = err => {
+        assert_eq!((&name[..], at, line, column), ("rows/row", &path, 2, 10));
+    });
+    assert_eq!(err.to_string(), format!("partial \"rows/row\" at {}:2:10: character 'x' was unexpected in the closing tag, expected '}}'", path.display()));
+
+    // The loader may not know of paths.
+    let ctx = Context::with_loader(KvLoader::new(|key| Ok(Some(match key {
+        "row" => "\u{e9}{{#a}}".to_string(),
+        _ => "{{> row}}".to_string(),
+    }))));
+    let err = ctx.compile("{{> page}}".chars()).map(|_| ()).unwrap_err();
+    assert_eq!(err.to_string(), "partial \"row\" at line 1, column 8: found an unclosed section: \"a\"");
+
+    // The template's own errors are as they were.
+    assert_let!(Error::Parser(_) = ctx.compile("{{#a}}".chars()).map(|_| ()).unwrap_err() => ());
+}
+
+#[test]
+fn test_compile_many() {
+    let tmpdir = TempDir::new("").unwrap();
+    File::create(tmpdir.path().join("footer.mustache")).unwrap().write_all(b"-- {{team}}").unwrap();
+    File::create(tmpdir.path().join("welcome.mustache")).unwrap().write_all(b"\
+{{!-- template: subject --}}
+Welcome, {{name}}
+{{!-- template: html --}}
+<p>Hi {{name}}</p>
+{{> footer}}
+{{!-- template: text --}}
+Hi {{name}}
+{{> footer}}
+").unwrap();
+
+    let ctx = Context::new(tmpdir.path().to_path_buf());
+    let templates = ctx.compile_many_path("welcome").unwrap();
+    assert_eq!(templates.keys().collect::<Vec<_>>(), ["html", "subject", "text"]);
+
+    let data = MapBuilder::new().insert_str("name", "Ann").insert_str("team", "The team").build();
+    let render = |name: &str| templates[name].render_data_to_string(&data).unwrap();
+    assert_eq!(render("subject"), "Welcome, Ann");
+    assert_eq!(render("html"), "<p>Hi Ann</p>\n-- The team");
+    assert_eq!(render("text"), "Hi Ann\n-- The team");
+
+    let err = ctx.compile_many("{{!-- template: a --}}\n{{!-- template: a --}}").map(|_| ()).unwrap_err();
+    assert_let!(Error::DuplicateTemplate(ref name) = err => assert_eq!(name, "a"));
+
+    let err = ctx.compile_many("{{!-- template: a --}}\n{{#a}}").map(|_| ()).unwrap_err();
+    assert_let!(Error::Parser(_) = err => ());
+}
+
+#[test]
+fn test_front_matter() {
+    let tmpdir = TempDir::new("").unwrap();
+    File::create(tmpdir.path().join("footer.mustache")).unwrap().write_all(b"---\nfor: layouts\n---\n-- {{team}}").unwrap();
+    File::create(tmpdir.path().join("welcome.mustache")).unwrap().write_all(b"\
+---
+subject: Welcome, {{name}}
+---
+Hi {{name}}
+{{> footer}}
+").unwrap();
+
+    let mut ctx = Context::new(tmpdir.path().to_path_buf());
+    let data = MapBuilder::new().insert_str("name", "Ann").insert_str("team", "The team").build();
+
+    let template = ctx.compile_path("welcome.mustache").unwrap();
+    assert_eq!(template.front_matter_source(), None);
+    assert!(template.render_data_to_string(&data).unwrap().starts_with("---\nsubject: Welcome, Ann\n---\n"));
+
+    ctx.parse_front_matter();
+    let template = ctx.compile_path("welcome.mustache").unwrap();
+    assert_eq!(template.front_matter_source(), Some("subject: Welcome, {{name}}\n"));
+    assert_eq!(template.render_data_to_string(&data).unwrap(), "Hi Ann\n-- The team");
+
+    let templates = ctx.compile_dir(tmpdir.path(), "welcome.*").unwrap();
+    let template = templates[Path::new("welcome.mustache")].as_ref().unwrap();
+    assert_eq!(template.front_matter_source(), Some("subject: Welcome, {{name}}\n"));
+
+    // Only files have front matter.
+    assert_eq!(ctx.compile("---\na: 1\n---\n".chars()).unwrap().front_matter_source(), None);
+}
+
+#[test]
+fn test_prewarm() {
+    let loader = MockLoader::new().with("page", "{{>header}}{{name}}").with("header", "<h1>");
+    let ctx = Context::with_loader(loader.clone());
+    let template = ctx.compile("{{>page}}".chars()).unwrap();
+    assert_eq!(loader.requests(), ["page", "header"]);
+
+    let mut cached = ctx.clone();
+    cached.cache_partials();
+    cached.prewarm(&template).unwrap();
+    cached.compile("{{>header}}{{>page}}".chars()).unwrap();
+    assert_eq!(loader.requests().len(), 2);
+
+    let template = ctx.compile("{{>page}}{{>footer}}{{>a}}".chars()).unwrap();
+    let err = ctx.prewarm(&template).unwrap_err();
+    assert_eq!(err.to_string(), "missing partials: \"a\", \"footer\"");
+}
+
+// Writes down what the sink is told of, leaving out the timings.
+#[derive(Default)]
+struct Recorded(Mutex<Vec<String>>);
+
+impl MetricsSink for Recorded {
+    fn compiled(&self, name: Option<&str>, _: Duration) {
+        self.0.lock().unwrap().push(format!("compiled {:?}", name));
+    }
+
+    fn rendered(&self, _: Duration, bytes: usize) {
+        self.0.lock().unwrap().push(format!("rendered {}", bytes));
+    }
+
+    fn render_failed(&self, _: Duration) {
+        self.0.lock().unwrap().push("render failed".to_string());
+    }
+
+    fn cache_lookup(&self, cache: &str, name: &str, hit: bool) {
+        self.0.lock().unwrap().push(format!("{} {} {}", cache, name, if hit { "hit" } else { "miss" }));
+    }
+}
+
+#[test]
+fn test_metrics() {
+    let recorded = Arc::new(Recorded::default());
+    let loader = LruLoader::new(MockLoader::new().with("header", "<h1>")).record_metrics(recorded.clone());
+    let mut ctx = Context::with_loader(loader);
+    ctx.cache_partials();
+    ctx.record_metrics(recorded.clone());
+
+    let template = ctx.compile("{{>header}}{{name}}".chars()).unwrap();
+    ctx.compile("{{>header}}".chars()).unwrap();
+    let data = MapBuilder::new().insert_str("name", "Ann").build();
+    assert_eq!(template.render_data_to_string(&data).unwrap(), "<h1>Ann");
+
+    let options = RenderOptions { max_iterations: Some(0), ..RenderOptions::default() };
+    let failing = ctx.compile("{{#name}}x{{/name}}".chars()).unwrap();
+    assert!(failing.render_data_with(&mut Vec::new(), &data, &options).is_err());
+
+    // Text without tags is rendered rather than borrowed, to be measured.
+    let plain = ctx.compile("plain".chars()).unwrap();
+    assert_let!(Ok(Cow::Owned(ref s)) = plain.render_data_to_cow(&data) => assert_eq!(s, "plain"));
+
+    // Every way of rendering is measured.
+    let mut out = String::new();
+    template.render_data_to_sink(&mut out, &data).unwrap();
+    failing.render_data_section(&mut Vec::new(), "name", &data).unwrap();
+
+    assert_eq!(*recorded.0.lock().unwrap(), vec![
+        "partials header miss",
+        "lru header miss",
+        "compiled None",
+        "partials header hit",
+        "compiled None",
+        "rendered 7",
+        "compiled None",
+        "render failed",
+        "compiled None",
+        "rendered 5",
+        "rendered 7",
+        "rendered 1",
+    ]);
+}
+
+#[test]
+fn test_render_inline_partials() {
+    let mut ctx = Context::new(PathBuf::from("tests/test-data"));
+    ctx.extensions.inline_partials = true;
+
+    // `user` includes the `username` partial, which the definition replaces.
+    let template = ctx.compile("{{<*username}}@{{name}}{{/username}}\n{{#names}}{{>user}}{{/names}}".chars())
+        .expect("Failed to compile");
+
+    let data = MapBuilder::new()
+        .insert_vec("names", |v| v.push_map(|m| m.insert_str("name", "a")))
+        .build();
+
+    assert_eq!(template.render_data_to_string(&data).unwrap(), "\n<strong>@a</strong>\n");
+}
+
+#[test]
+fn test_partial_inline_partials() {
+    let mut partials = HashMap::new();
+    partials.insert("card", "{{<*title}}<h1>{{.}}</h1>{{/title}}{{>title}}");
+    partials.insert("title", "{{.}}");
+
+    let mut ctx = Context::with_loader(KvLoader::new(move |key| Ok(partials.get(key).map(|s| s.to_string()))));
+    ctx.extensions.inline_partials = true;
+
+    // The definition in `card` doesn't replace the `title` of the template.
+    let template = ctx.compile("{{#a}}{{>card}} {{>title}}{{/a}}").expect("Failed to compile");
+    let data = MapBuilder::new().insert_str("a", "x").build();
+    assert_eq!(template.render_data_to_string(&data).unwrap(), "<h1>x</h1> x");
+}
+
+#[test]
+fn test_borrowed_partial_loader() {
+    use std::borrow::Cow;
+
+    #[derive(Clone, Debug)]
+    struct Loader;
+
+    impl PartialLoader for Loader {
+        fn load(&self, _: impl AsRef<Path>) -> Result<String, Error> {
+            panic!("the compiler should borrow the partials");
+        }
+
+        fn load_str(&self, name: impl AsRef<Path>) -> Result<Cow<'_, str>, Error> {
+            match name.as_ref().to_str() {
+                Some("page") => Ok(Cow::Borrowed("[{{>user}}]")),
+                Some("user") => Ok(Cow::Borrowed("{{name}}")),
+                _ => Err(Error::InvalidStr),
+            }
+        }
+    }
+
+    let ctx = Context::with_loader(Arc::new(Loader));
+    let data = MapBuilder::new().insert_str("name", "n").build();
+
+    let template = ctx.compile_path("page").expect("Failed to compile");
+    assert_eq!(template.render_data_to_string(&data).unwrap(), "[n]");
+}
+
+#[test]
+fn test_partial_delimiters_are_scoped() {
+    #[derive(Clone, Debug)]
+    struct Loader;
+
+    impl PartialLoader for Loader {
+        fn load(&self, name: impl AsRef<Path>) -> Result<String, Error> {
+            match name.as_ref().to_str() {
+                Some("vendor") => Ok("{{=<% %>=}}<%name%>{{name}}".to_string()),
+                Some("plain") => Ok("{{name}}<%name%>".to_string()),
+                _ => Err(Error::InvalidStr),
+            }
+        }
+    }
+
+    let mut ctx = Context::with_loader(Loader);
+    ctx.cache_partials();
+
+    let data = MapBuilder::new().insert_str("name", "n").build();
+
+    // A delimiter change in a partial stays in the partial.
+    let template = ctx.compile("{{>vendor}}|{{name}}".chars()).expect("Failed to compile");
+    assert_eq!(template.render_data_to_string(&data).unwrap(), "n{{name}}|n");
+
+    // And the includer's delimiters don't reach into its partials, cached or not.
+    for source in &["{{=<% %>=}}<%>plain%>|<%name%>", "{{>plain}}|{{name}}"] {
+        let template = ctx.compile(source.chars()).expect("Failed to compile");
+        assert_eq!(template.render_data_to_string(&data).unwrap(), "n<%name%>|n");
+    }
+}
+
+#[test]
+fn test_template_deprecations() {
+    let mut partials = HashMap::new();
+    partials.insert("old".to_string(), "{{! @deprecated use new }}\n{{> inner}}".to_string());
+    partials.insert("inner".to_string(), "{{!@deprecated}}x".to_string());
+    partials.insert("new".to_string(), "{{> inner}}".to_string());
+
+    let mut ctx = Context::with_loader(KvLoader::new(move |key| Ok(partials.get(key).cloned())));
+    ctx.cache_partials();
+
+    // The second compile takes the partials from the cache.
+    for _ in 0..2 {
+        let template = ctx.compile("{{! @deprecated }}\n{{> old}}{{> new}}").unwrap();
+        assert_eq!(template.render_to_string(&()).unwrap(), "xx");
+
+        let deprecations = template.deprecations();
+        assert_eq!(deprecations, vec![
+            Deprecation { partial: None, message: "".to_string() },
+            Deprecation { partial: Some("inner".to_string()), message: "".to_string() },
+            Deprecation { partial: Some("old".to_string()), message: "use new".to_string() },
+        ]);
+        assert_eq!(deprecations[2].to_string(), "partial \"old\" is deprecated: use new");
+    }
+
+    let template = ctx.compile("{{> new}}").unwrap();
+    assert_eq!(template.deprecations().len(), 1);
+}
+
+#[test]
+fn test_render_raw_blocks() {
+    let mut ctx = Context::new(PathBuf::from("."));
+    ctx.extensions.raw_blocks = true;
+
+    let template = ctx.compile(concat!(
+        "struct {{name}};\n",
+        "{{{{raw}}}}\n",
+        "Hello {{name}}{{#items}}!{{/items}}\n",
+        "{{{{/raw}}}}\n",
+        "{{#items}}{{{{raw}}}}{{.}}{{{{/raw}}}}{{/items}}",
+    ).chars()).expect("Failed to compile");
+
+    let data = MapBuilder::new()
+        .insert_str("name", "Greeter")
+        .insert_vec("items", |v| v.push_str("a").push_str("b"))
+        .build();
+
+    assert_eq!(
+        template.render_data_to_string(&data).unwrap(),
+        "struct Greeter;\nHello {{name}}{{#items}}!{{/items}}\n{{.}}{{.}}",
+    );
+}
+
+#[test]
+fn test_render_json_pointers() {
+    let mut ctx = Context::new(PathBuf::from("."));
+    ctx.extensions.json_pointers = true;
+
+    let template = ctx.compile("{{#site}}{{/users/1/a.b}} {{/site/name}}{{/users/2/a.b}}{{/site}}".chars())
+        .expect("Failed to compile");
+
+    let data = MapBuilder::new()
+        .insert_vec("users", |v| {
+            v.push_map(|m| m.insert_str("a.b", "first"))
+                .push_map(|m| m.insert_str("a.b", "<second>"))
+        })
+        .insert_map("site", |m| m.insert_str("name", "example"))
+        .build();
+
+    let mut bytes = vec![];
+    let warnings = template.render_data_with_warnings(&mut bytes, &data).unwrap();
+
+    assert_eq!(String::from_utf8(bytes).unwrap(), "&lt;second&gt; example");
+    assert_eq!(warnings, vec![Warning::MissingKey("/users/2/a.b".to_string())]);
+}
+
+#[test]
+fn test_render_fallback_chains() {
+    let mut ctx = Context::new(PathBuf::from("."));
+    ctx.extensions.fallback_chains = true;
+    let template = ctx.compile("{{#users}}[{{display_name || username || email}}|{{{bio || .}}}]{{/users}}".chars())
+        .expect("Failed to compile");
+
+    let data = MapBuilder::new()
+        .insert_vec("users", |v| {
+            v.push_map(|m| m.insert_str("display_name", "Ann").insert_str("bio", "<b>hi</b>"))
+                .push_map(|m| m.insert_str("display_name", "").insert_str("username", "bob"))
+                .push_map(|m| m.insert_bool("username", true).insert_str("email", "c@example.com"))
+                .push_map(|m| m)
+        })
+        .build();
+
+    let mut bytes = vec![];
+    let warnings = template.render_data_with_warnings(&mut bytes, &data).unwrap();
+    assert_eq!(String::from_utf8(bytes).unwrap(), "[Ann|<b>hi</b>][bob|][c@example.com|][|]");
+    assert_eq!(warnings, vec![Warning::MissingKey("email".to_string())]);
+
+    assert_eq!(template.validate(&data), vec![mustache::Missing::Absent("display_name || username || email".to_string())]);
+}
+
+#[test]
+fn test_render_long_comments() {
+    let mut ctx = Context::new(PathBuf::from("."));
+    ctx.extensions.long_comments = true;
+    let template = ctx.compile("<ul>\n{{!-- {{#items}}\n  <li>{{.}}</li>\n{{/items}} --}}\n</ul>".chars())
+        .expect("Failed to compile");
+
+    let data = MapBuilder::new().insert_vec("items", |v| v.push_str("a")).build();
+    assert_eq!(template.render_data_to_string(&data).unwrap(), "<ul>\n</ul>");
+}
+
+#[test]
+fn test_render_comparisons() {
+    let mut ctx = Context::new(PathBuf::from("."));
+    ctx.extensions.comparisons = true;
+    let template = ctx.compile(concat!(
+        "{{#count > 0}}{{count}} new{{/count > 0}}",
+        "{{^status == \"active\"}}, {{status}}{{/status == \"active\"}}",
+        "{{#tags >= 2}}, tagged{{/tags >= 2}}",
+        "{{#version < \"10\"}}, old{{/version < \"10\"}}",
+        "{{#missing != 1}}.{{/missing != 1}}",
+    ).chars()).expect("Failed to compile");
+
+    let render = |data: &Data| template.render_data_to_string(data).unwrap();
+
+    let data = MapBuilder::new()
+        .insert_str("count", "3")
+        .insert_str("status", "paused")
+        .insert_vec("tags", |v| v.push_str("a").push_str("b"))
+        .insert_str("version", "10.1")
+        .build();
+    assert_eq!(render(&data), "3 new, paused, tagged.");
+
+    let data = MapBuilder::new()
+        .insert_str("count", "0")
+        .insert_str("status", "active")
+        .insert_vec("tags", |v| v.push_str("a"))
+        .insert_str("version", "9")
+        .build();
+    assert_eq!(render(&data), ", old.");
+}
+
+#[test]
+fn test_render_ranges() {
+    let mut ctx = Context::new(PathBuf::from("."));
+    ctx.extensions.ranges = true;
+    let template = ctx.compile(concat!(
+        "{{#1..3}}[{{.}}]{{/1..3}} ",
+        "{{#stars}}{{#1..rating}}*{{/1..rating}}{{^1..rating}}unrated{{/1..rating}}{{/stars}} ",
+        "{{#-1..1}}{{.}},{{/-1..1}}{{#3..1}}never{{/3..1}}",
+    ).chars()).expect("Failed to compile");
+
+    let render = |rating: &str| {
+        let data = MapBuilder::new().insert_bool("stars", true).insert_str("rating", rating).build();
+        template.render_data_to_string(&data).unwrap()
+    };
+    assert_eq!(render("4"), "[1][2][3] **** -1,0,1,");
+    assert_eq!(render("0"), "[1][2][3] unrated -1,0,1,");
+    assert_eq!(render("many"), "[1][2][3] unrated -1,0,1,");
+
+    let options = RenderOptions { max_iterations: Some(100), ..RenderOptions::default() };
+    let data = MapBuilder::new().insert_str("n", "1000000").build();
+    let template = ctx.compile("{{#1..n}}{{/1..n}}".chars()).unwrap();
+    assert!(matches!(template.render_data_with(&mut Vec::new(), &data, &options), Err(Error::TooManyIterations(100))));
+
+    // A partial including itself from a range goes on until it includes too
+    // many, rather than overflow the stack.
+    let mut ctx = Context::with_loader(KvLoader::new(|_| Ok(Some("{{#1..1}}{{>p}}{{/1..1}}".to_string()))));
+    ctx.extensions.ranges = true;
+    let template = ctx.compile("{{>p}}".chars()).unwrap();
+    let options = RenderOptions { max_partials: Some(1_000_000), ..RenderOptions::default() };
+    assert!(matches!(template.render_data_with(&mut Vec::new(), &Data::Null, &options), Err(Error::TooManyPartials(1_000_000))));
+
+    let err = ctx.compile("{{#1..2.5}}{{/1..2.5}}".chars()).unwrap_err();
+    assert_eq!(err.to_string(), "invalid range: \"1..2.5\"");
+}
+
+#[test]
+fn test_render_chunks() {
+    let mut ctx = Context::new(PathBuf::from("."));
+    ctx.extensions.inline_partials = true;
+    let template = ctx.compile("{{<*row}}<li>{{.}}</li>\n{{/row}}<ul>\n{{#items}}\n  {{>row}}\n{{/items}}\n</ul>{{#wrap}}x{{/wrap}}".chars())
+        .expect("Failed to compile");
+    let data = MapBuilder::new()
+        .insert_vec("items", |v| v.push_str("a").push_str("b&c"))
+        .insert_fn("wrap", |s| format!("[{}]", s))
+        .build();
+
+    let chunks = template.render_data_chunks(&data).unwrap();
+    assert_eq!(chunks.concat(), template.render_data_to_string(&data).unwrap());
+    let chunks: Vec<_> = chunks.iter().map(|chunk| match *chunk {
+        Cow::Borrowed(text) => format!("={}", text),
+        Cow::Owned(ref text) => format!("+{}", text),
+    }).collect();
+    assert_eq!(chunks, [
+        "=<ul>\n", "=  ", "=<li>", "+a", "=</li>\n", "=\n",
+        "=  ", "=<li>", "+b&amp;c", "=</li>\n", "=\n",
+        "=</ul>", "+[x]",
+    ]);
+}
+
+#[test]
+fn test_render_streaming() {
+    use serde::ser::{Serialize, SerializeSeq, Serializer};
+
+    // Rows made as they are serialized, so only the renderer could keep them.
+    struct Rows(u32);
+
+    impl Serialize for Rows {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            let mut seq = serializer.serialize_seq(None)?;
+            for i in 1..=self.0 {
+                let mut row = HashMap::new();
+                row.insert("n", i);
+                seq.serialize_element(&row)?;
+            }
+            seq.end()
+        }
+    }
+
+    #[derive(Serialize)]
+    struct Report {
+        title: &'static str,
+        rows: Rows,
+        total: u32,
+    }
+
+    let template = compile_str("{{title}} [{{total}}]\n{{#rows}}\n{{n}}/{{title}}\n{{/rows}}\n[{{total}}]");
+    let mut out = Vec::new();
+    template.render_streaming(&mut out, &Report { title: "t", rows: Rows(3), total: 6 }, "rows").unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "t []\n1/t\n2/t\n3/t\n[6]");
+
+    let mut data = HashMap::new();
+    data.insert("title", "t");
+    let mut out = Vec::new();
+    template.render_streaming(&mut out, &data, "rows").unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "t []\n[]");
+
+    // The rest of the template knows whether there were rows.
+    let inverted = compile_str("{{#rows}}{{n}}{{/rows}}{{^rows}}none{{/rows}}");
+    for &(rows, expected) in &[(2, "12"), (0, "none")] {
+        let mut out = Vec::new();
+        inverted.render_streaming(&mut out, &Report { title: "t", rows: Rows(rows), total: 0 }, "rows").unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), expected);
+    }
+
+    let err = template.render_streaming(&mut Vec::new(), &vec![1], "rows").map(|_| ()).unwrap_err();
+    assert_let!(Error::Encoder(_) = err => ());
+
+    let template = compile_str("{{#outer}}{{#rows}}{{/rows}}{{/outer}}");
+    let err = template.render_streaming(&mut Vec::new(), &data, "rows").map(|_| ()).unwrap_err();
+    assert_let!(Error::NoSuchSection(ref name) = err => assert_eq!(name, "rows"));
+}
+
+#[test]
+fn test_render_iter() {
+    use std::sync::atomic::AtomicUsize;
+
+    // How many rows were made, to see they are made as they are rendered.
+    let made = Arc::new(AtomicUsize::new(0));
+    let rows = {
+        let made = made.clone();
+        (1..4).map(move |i| {
+            made.fetch_add(1, Ordering::SeqCst);
+            MapBuilder::new().insert_str("n", i.to_string()).build()
+        })
+    };
+
+    let template = compile_str("{{#rows}}{{n}}/{{title}} {{/rows}}|{{^rows}}none{{/rows}}");
+    let data = MapBuilder::new()
+        .insert_str("title", "t")
+        .insert_iter("rows", rows)
+        .build();
+    assert_eq!(made.load(Ordering::SeqCst), 0);
+    assert_eq!(template.render_data_to_string(&data).unwrap(), "1/t 2/t 3/t |none");
+    assert_eq!(made.load(Ordering::SeqCst), 3);
+
+    let template = compile_str("{{^rows}}none{{/rows}}{{#rows}}{{.}}{{/rows}}");
+    let data = MapBuilder::new()
+        .insert_iter("rows", vec![Data::String("a".into()), Data::String("b".into())])
+        .build();
+    assert_eq!(template.render_data_to_string(&data).unwrap(), "ab");
+
+    let data = MapBuilder::new().insert_iter("rows", Vec::new()).build();
+    assert_eq!(template.render_data_to_string(&data).unwrap(), "none");
+
+    let data = MapBuilder::new()
+        .insert_iter("rows", (0..).map(|i: u32| Data::String(i.to_string())))
+        .build();
+    let options = RenderOptions { max_iterations: Some(5), ..RenderOptions::default() };
+    let err = template.render_data_with(&mut Vec::new(), &data, &options).unwrap_err();
+    assert_let!(Error::TooManyIterations(5) = err => ());
+
+    // The same for a partial including itself from every item.
+    let ctx = Context::with_loader(KvLoader::new(|_| Ok(Some("{{#rows}}{{>p}}{{/rows}}".to_string()))));
+    let template = ctx.compile("{{>p}}".chars()).unwrap();
+    let data = MapBuilder::new().insert_iter("rows", (0..).map(|_: u32| Data::Null)).build();
+    let options = RenderOptions { max_partials: Some(10_000), ..RenderOptions::default() };
+    let err = template.render_data_with(&mut Vec::new(), &data, &options).unwrap_err();
+    assert_let!(Error::TooManyPartials(10_000) = err => ());
+}
+
+#[test]
+fn test_render_failing_lambda() {
+    let template = compile_str("a{{#outer}}{{#check}}b{{/check}}{{/outer}}");
+
+    let data = MapBuilder::new()
+        .insert_map("outer", |m| {
+            m.insert_try_fn("check", |s| if s == "b" { Err("not allowed") } else { Ok(s) })
+        })
+        .build();
+
+    let mut bytes = vec![];
+    let err = template.render_data(&mut bytes, &data).unwrap_err();
+
+    assert_let!(Error::Lambda { name, source } = err => {
+        assert_eq!(name, "check");
+        assert_eq!(source.to_string(), "not allowed");
+    });
+    assert_eq!(bytes, b"a");
+}
+
+#[test]
+fn test_render_error_in_partials() {
+    let mut partials = HashMap::new();
+    partials.insert("layout".to_string(), "<main>{{> sidebar}}</main>".to_string());
+    partials.insert("sidebar".to_string(), "{{#widgets}}{{> widget}}{{/widgets}}".to_string());
+    partials.insert("widget".to_string(), "{{#check}}{{name}}{{/check}}".to_string());
+    let ctx = Context::with_loader(KvLoader::new(move |key| Ok(partials.get(key).cloned())));
+    let template = ctx.compile("{{> layout}}".chars()).unwrap();
+
+    let data = MapBuilder::new()
+        .insert_vec("widgets", |v| {
+            v.push_map(|m| m.insert_str("name", "ok"))
+                .push_map(|m| m.insert_str("name", "bad").insert_try_fn("check", |_| Err("not allowed")))
+        })
+        .build();
+
+    let err = template.render_data(&mut vec![], &data).unwrap_err();
+    assert_eq!(err.to_string(), "in partial \"layout\" > \"sidebar\" > \"widget\": lambda \"check\" failed: not allowed");
+    assert_let!(Error::InPartials { partials, source } = err => {
+        assert_eq!(partials, ["layout", "sidebar", "widget"]);
+        assert_let!(Error::Lambda { .. } = *source => ());
+    });
+
+    // Render limits are about the whole render rather than where it stopped.
+    let options = RenderOptions { max_iterations: Some(1), ..RenderOptions::default() };
+    assert_let!(Err(Error::TooManyIterations(1)) = template.render_data_with(&mut vec![], &data, &options) => ());
+}
+
+#[test]
+fn test_render_traced() {
+    let mut partials = HashMap::new();
+    partials.insert("layout".to_string(), "<main>\n  {{> card}}</main>".to_string());
+    partials.insert("card".to_string(), "<div>{{{title}}}\n</div>".to_string());
+    let ctx = Context::with_loader(KvLoader::new(move |key| Ok(partials.get(key).cloned())));
+    let template = ctx.compile("{{> layout}}{{#bold}}{{name}}{{/bold}}".chars()).unwrap();
+
+    let data = MapBuilder::new()
+        .insert_str("title", "T")
+        .insert_str("name", "n")
+        .insert_fn("bold", |s| format!("<b>{}</b>", s))
+        .build();
+
+    let (output, regions) = template.render_data_traced(&data).unwrap();
+    assert_eq!(output, "<main>\n  <div>T\n</div></main><b>n</b>");
+    assert_eq!(template.render_data_to_string(&data).unwrap(), output);
+
+    let region = |text: &str, partials: &[&str], tag: Option<&str>| Region {
+        range: 0..text.len(),
+        partials: partials.iter().map(|p| p.to_string()).collect(),
+        tag: tag.map(str::to_string),
+    };
+    let mut expected = vec![
+        region("<main>\n  ", &["layout"], None),
+        region("<div>", &["layout", "card"], None),
+        region("T", &["layout", "card"], Some("{{{title}}}")),
+        region("\n</div>", &["layout", "card"], None),
+        region("</main>", &["layout"], None),
+        region("<b>", &[], Some("{{#bold}}")),
+        region("n", &[], Some("{{name}}")),
+        region("</b>", &[], Some("{{#bold}}")),
+    ];
+    let mut at = 0;
+    for region in &mut expected {
+        region.range = at..at + region.range.end;
+        at = region.range.end;
+    }
+    assert_eq!(regions, expected);
+}
+
+#[test]
+fn test_render_panicking_lambda() {
+    use std::panic;
+
+    let data = MapBuilder::new()
+        .insert_map("outer", |m| m.insert_fn("boom", |_| panic!("helper bug")))
+        .insert_fn("ok", |s| s + "!")
+        .build();
+    let options = RenderOptions { catch_lambda_panics: true, ..RenderOptions::default() };
+
+    for &(source, path) in &[("a{{outer.boom}}", "outer.boom"), ("a{{#outer}}{{#boom}}b{{/boom}}{{/outer}}", "boom")] {
+        let template = compile_str(source);
+        let mut bytes = vec![];
+        let err = template.render_data_with(&mut bytes, &data, &options).unwrap_err();
+        assert_let!(Error::LambdaPanicked { name } = err => assert_eq!(name, path));
+        assert_eq!(bytes, b"a");
+
+        // It unwinds as before when not asked.
+        let caught = panic::catch_unwind(panic::AssertUnwindSafe(|| template.render_data(&mut vec![], &data)));
+        assert!(caught.is_err());
+    }
+
+    // The data is still usable afterwards.
+    let template = compile_str("{{#ok}}x{{/ok}}");
+    assert_eq!(template.render_data_to_string(&data).unwrap(), "x!");
+}
+
+#[test]
+fn test_render_helper_scope() {
+    let data = MapBuilder::new()
+        .insert_str("name", "outer")
+        .insert_vec("items", |v| v.push_map(|m| m.insert_str("name", "inner")))
+        .insert_helper("show", |content, scope| {
+            let name = match scope.lookup("name") {
+                Some(Data::String(name)) => &name[..],
+                _ => "",
+            };
+            let innermost = match scope.innermost() {
+                Some(Data::Map(map)) => map.len(),
+                _ => 0,
+            };
+            format!("[{}|{}|{}|{}|{}|{}]",
+                    content, name, scope.stack().len(), innermost, scope.source(), scope.partial().unwrap_or("-"))
+        })
+        .build();
+
+    let mut ctx = Context::new(PathBuf::from("."));
+    ctx.extensions.inline_partials = true;
+    ctx.extensions.json_pointers = true;
+    let template = ctx
+        .compile("{{<*row}}{{#show}}{{name}}{{/show}}{{/row}}{{#show}}x{{/show}} {{#items}}{{>row}}{{/items}} {{show}}".chars())
+        .expect("Failed to compile");
+
+    assert_eq!(template.render_data_to_string(&data).unwrap(),
+               "[x|outer|1|3|x|-] [inner|inner|2|1|{{name}}|row] [|outer|1|3||-]");
+}
+
+#[test]
+fn test_template_names() {
+    let names = |set: std::collections::BTreeSet<String>| set.into_iter().collect::<Vec<_>>();
+
+    let template = mustache::compile_path("tests/test-data/base").expect("Failed to compile");
+    assert_eq!(names(template.variables()), vec!["name"]);
+    assert_eq!(names(template.sections()), vec!["names"]);
+    assert_eq!(names(template.partials()), vec!["user", "username"]);
+
+    let mut ctx = Context::new(PathBuf::from("tests/test-data"));
+    ctx.extensions.inline_partials = true;
+    ctx.extensions.json_pointers = true;
+    let template = ctx
+        .compile("{{<*row}}{{a.b}}{{/row}}{{#list}}{{>row}}{{^empty}}{{/c}}{{/empty}}{{>missing}}{{/list}}{{{d}}}{{.}}".chars())
+        .expect("Failed to compile");
+    assert_eq!(names(template.variables()), vec!["/c", "a.b", "d"]);
+    assert_eq!(names(template.sections()), vec!["empty", "list"]);
+    assert_eq!(names(template.partials()), vec!["missing", "row"]);
+}
+
+#[test]
+fn test_render_partial_overrides() {
+    use mustache::PartialOverrides;
+
+    let template = mustache::compile_path("tests/test-data/base").expect("Failed to compile");
+    let data = MapBuilder::new()
+        .insert_vec("names", |v| v.push_map(|m| m.insert_str("name", "a")))
+        .build();
+
+    let mut ctx = Context::new(PathBuf::from("tests/test-data"));
+    ctx.extensions.inline_partials = true;
+    let mut partials = PartialOverrides::new();
+    partials.insert("user", &ctx.compile("<em>{{>username}}{{>page}}</em>".chars()).unwrap());
+    let options = RenderOptions { partials, ..RenderOptions::default() };
+
+    let render = |options: &RenderOptions| {
+        let mut bytes = vec![];
+        template.render_data_with(&mut bytes, &data, options).unwrap();
+        String::from_utf8(bytes).unwrap()
+    };
+
+    // `username` is the template's own, `page` only came with the override.
+    assert_eq!(render(&options), "<h2>Names</h2>\n  <em>a<h1></h1>\n</em>\n");
+    assert_eq!(render(&RenderOptions::default()), "<h2>Names</h2>\n  <strong>a</strong>\n\n");
+}
+
+#[test]
+fn test_render_section() {
+    let mut ctx = Context::new(PathBuf::from("."));
+    ctx.extensions.inline_partials = true;
+    let template = ctx
+        .compile("{{<*table}}{{^rows}}none{{/rows}}{{#rows}}\n<tr>{{name}}{{#admin}}*{{/admin}}</tr>\n{{/rows}}{{/table}}<table>{{>table}}</table>".chars())
+        .expect("Failed to compile");
+
+    let mut row = HashMap::new();
+    row.insert("name", "<b>");
+    assert_eq!(template.render_section_to_string("rows", &row).unwrap(), "\n<tr>&lt;b&gt;</tr>\n");
+    assert_eq!(template.render_section_to_string("admin", &row).unwrap(), "*");
+
+    let err = template.render_section_to_string("cols", &row).unwrap_err();
+    assert_let!(Error::NoSuchSection(name) = err => assert_eq!(name, "cols"));
+}
+
+#[test]
+fn test_render_to_sink() {
+    use mustache::OutputSink;
+
+    #[derive(Default)]
+    struct Recorder {
+        pieces: Vec<(bool, String)>,
+        finished: bool,
+        fail_on: Option<&'static str>,
+    }
+
+    impl OutputSink for Recorder {
+        fn write_str(&mut self, s: &str) -> mustache::Result<()> {
+            if Some(s) == self.fail_on {
+                return Err(Error::InvalidStr);
+            }
+            self.pieces.push((false, s.to_string()));
+            Ok(())
+        }
+
+        fn write_escaped(&mut self, s: &str) -> mustache::Result<()> {
+            self.pieces.push((true, s.to_string()));
+            Ok(())
+        }
+
+        fn finish(&mut self) -> mustache::Result<()> {
+            self.finished = true;
+            Ok(())
+        }
+    }
+
+    let template = compile_str("é{{a}}{{{b}}}{{#c}}<{{.}}>{{/c}}");
+    let data = MapBuilder::new()
+        .insert_str("a", "<ü>")
+        .insert_str("b", "<b>")
+        .insert_vec("c", |v| v.push_str("x"))
+        .build();
+
+    let mut recorder = Recorder::default();
+    template.render_data_to_sink(&mut recorder, &data).unwrap();
+    let pieces: Vec<_> = recorder.pieces.iter().map(|&(escaped, ref s)| (escaped, &s[..])).collect();
+    assert_eq!(pieces, vec![
+        (false, "é"),
+        (true, "&lt;ü&gt;"),
+        (false, "<b>"),
+        (false, "<"),
+        (true, "x"),
+        (false, ">"),
+    ]);
+    assert!(recorder.finished);
+
+    let mut string = String::new();
+    template.render_data_to_sink(&mut string, &data).unwrap();
+    assert_eq!(string, template.render_data_to_string(&data).unwrap());
+
+    // The sink's own error comes back, and it isn't finished.
+    let mut failing = Recorder { fail_on: Some("<b>"), ..Recorder::default() };
+    let err = template.render_data_to_sink(&mut failing, &data).unwrap_err();
+    assert_let!(Error::InvalidStr = err => ());
+    assert!(!failing.finished);
+}
+
+#[test]
+fn test_partial_dependencies() {
+    let template = mustache::compile_path("tests/test-data/base").expect("Failed to compile");
+    let names: Vec<_> = template.partial_dependencies().into_iter().collect();
+    assert_eq!(names, vec!["user".to_string(), "username".to_string()]);
+
+    let mut ctx = Context::new(PathBuf::from("tests/test-data"));
+    ctx.extensions.inline_partials = true;
+    let template = ctx.compile("{{<*username}}{{>page}}{{/username}}{{>user}}".chars())
+        .expect("Failed to compile");
+    let names: Vec<_> = template.partial_dependencies().into_iter().collect();
+    assert_eq!(names, vec!["page".to_string(), "user".to_string()]);
+}
+
+#[test]
+fn test_compile_sources() {
+    use std::fs::File;
+    use std::io::BufReader;
+
+    let ctx = Context::new(PathBuf::from("tests/test-data"));
+    let data = MapBuilder::new().insert_str("name", "n").build();
+    let render = |template: Template<DefaultLoader>| template.render_data_to_string(&data).unwrap();
+
+    let source = "<strong>{{name}}</strong>\n".to_string();
+    let mut line = source.chars().filter(|&c| c != '\n');
+
+    assert_eq!(render(ctx.compile(&source).unwrap()), "<strong>n</strong>\n");
+    assert_eq!(render(ctx.compile(&mut line).unwrap()), "<strong>n</strong>");
+    assert_eq!(render(ctx.compile(File::open("tests/test-data/user.mustache").unwrap()).unwrap()),
+               "<strong>n</strong>\n");
+    assert_eq!(render(ctx.compile(BufReader::new(source.as_bytes())).unwrap()), "<strong>n</strong>\n");
+    assert_eq!(render(mustache::compile(source).unwrap()), "<strong>n</strong>\n");
+}
+
+#[test]
+fn test_default_loader_stays_in_root() {
+    let tmpdir = TempDir::new("").expect("Failed to make tempdir");
+    let root = tmpdir.path().join("templates");
+    std::fs::create_dir_all(root.join("users")).unwrap();
+    File::create(root.join("users/card.mustache")).unwrap().write_all(b"card").unwrap();
+    File::create(tmpdir.path().join("secret.mustache")).unwrap().write_all(b"secret").unwrap();
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(tmpdir.path().join("secret.mustache"), root.join("link.mustache")).unwrap();
+
+    let mut ctx = Context::new(root.clone());
+    assert_eq!(ctx.partial_loader.load("./users/card").unwrap(), "card");
+    assert_eq!(ctx.partial_loader.load("users/none").unwrap(), "");
+
+    let secret = tmpdir.path().join("secret");
+    let secret = secret.to_str().unwrap();
+    let mut names = vec!["../secret", "users/../../secret", secret];
+    if cfg!(unix) {
+        names.push("link");
+    }
+    for &name in &names {
+        assert_let!(Err(Error::InvalidPartialName(ref n)) = ctx.partial_loader.load(name) => assert_eq!(n, name));
+    }
+
+    let err = ctx.compile("{{>../secret}}".chars()).map(|_| ()).unwrap_err();
+    assert_let!(Error::PartialLoad { ref source, .. } = err => {
+        assert_let!(Error::InvalidPartialName(_) = **source => ());
+    });
+
+    ctx.partial_loader = ctx.partial_loader.allow_outside_root();
+    for &name in &names {
+        assert_eq!(ctx.partial_loader.load(name).unwrap(), "secret");
+    }
+}
+
+#[test]
+fn test_compile_dir() {
+    let tmpdir = TempDir::new("").expect("Failed to make tempdir");
+    std::fs::create_dir_all(tmpdir.path().join("emails/en")).unwrap();
+
+    for &(name, source) in &[
+        ("page.mustache", "[{{>emails/en/user}}]"),
+        ("emails/en/user.mustache", "{{name}}"),
+        ("emails/broken.mustache", "{{#open}}"),
+        ("notes.txt", "{{name}}"),
+    ] {
+        File::create(tmpdir.path().join(name)).unwrap().write_all(source.as_bytes()).unwrap();
+    }
+
+    let ctx = Context::new(tmpdir.path().to_path_buf());
+    let templates = ctx.compile_dir(tmpdir.path(), "**/*.mustache").unwrap();
+    let data = MapBuilder::new().insert_str("name", "n").build();
+
+    assert_eq!(templates.len(), 3);
+    assert_eq!(templates[Path::new("page.mustache")].as_ref().unwrap().render_data_to_string(&data).unwrap(), "[n]");
+    assert_eq!(templates[Path::new("emails/en/user.mustache")].as_ref().unwrap().render_data_to_string(&data).unwrap(), "n");
+    assert_let!(Err(Error::Parser(_)) = templates[Path::new("emails/broken.mustache")] => ());
+
+    assert_eq!(ctx.compile_dir(tmpdir.path(), "emails/*").unwrap().len(), 1);
+    assert_let!(Err(Error::Io(_)) = ctx.compile_dir(tmpdir.path().join("missing"), "*") => ());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_compile_dir_symlinks() {
+    use std::os::unix::fs::symlink;
+
+    let tmpdir = TempDir::new("").expect("Failed to make tempdir");
+    let (root, shared) = (tmpdir.path().join("templates"), tmpdir.path().join("shared"));
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::create_dir_all(&shared).unwrap();
+    File::create(shared.join("footer.mustache")).unwrap().write_all(b"footer").unwrap();
+
+    // A linked directory is walked, a link back up is not walked again.
+    symlink(&shared, root.join("shared")).unwrap();
+    symlink(&root, root.join("loop")).unwrap();
+    symlink(tmpdir.path().join("missing"), root.join("broken.mustache")).unwrap();
+
+    let ctx = Context::new(root.clone());
+    let templates = ctx.compile_dir(&root, "**/*.mustache").unwrap();
+    assert_eq!(templates.keys().collect::<Vec<_>>(), [Path::new("broken.mustache"), Path::new("shared/footer.mustache")]);
+    assert_eq!(templates[Path::new("shared/footer.mustache")].as_ref().unwrap().render_data_to_string(&Data::Null).unwrap(), "footer");
+    assert_let!(Err(Error::Io(_)) = templates[Path::new("broken.mustache")] => ());
+}
+
+#[test]
+fn test_fingerprint() {
+    use std::sync::Mutex;
+
+    #[derive(Clone, Debug, Default)]
+    struct Loader(Arc<Mutex<String>>);
+
+    impl PartialLoader for Loader {
+        fn load(&self, _: impl AsRef<Path>) -> Result<String, Error> {
+            Ok(self.0.lock().unwrap().clone())
+        }
+    }
+
+    let loader = Loader::default();
+    let ctx = Context::with_loader(loader.clone());
+    let fingerprint = |source: &str| ctx.compile(source.chars()).expect("Failed to compile").fingerprint();
+
+    *loader.0.lock().unwrap() = "{{name}}".to_string();
+    let before = fingerprint("<{{>user}}>");
+    assert_eq!(fingerprint("<{{>user}}>"), before);
+    assert_ne!(fingerprint("<{{>other}}>"), before);
+
+    // A change in a partial shows too.
+    *loader.0.lock().unwrap() = "{{name}}!".to_string();
+    assert_ne!(fingerprint("<{{>user}}>"), before);
+
+    // The same on every platform.
+    assert_eq!(compile_str("").fingerprint(), 0xa8c7_f832_281a_39c5);
+}
+
+#[test]
+fn test_size_hint() {
+    assert_eq!(compile_str("").size_hint(), 0);
+    assert_eq!(compile_str("ab{{#s}}cd{{/s}}{{! comment }}").size_hint(), 4);
+
+    // Tags are guessed at, so only check that they count for something.
+    let plain = compile_str("ab").size_hint();
+    assert!(compile_str("ab{{x}}").size_hint() > plain);
+
+    let template = mustache::compile_path("tests/test-data/user").expect("Failed to compile");
+    assert!(template.size_hint() > "<strong></strong>\n".len());
+}
+
+#[test]
+fn test_size_hint_bounds() {
+    // Every partial includes the next one twice, so their output doubles
+    // with each.
+    let ctx = Context::with_loader(KvLoader::new(|key| {
+        let n: u32 = key[1..].parse().unwrap();
+        Ok(Some(if n == 80 { "x".to_string() } else { format!("{{{{>p{}}}}}{{{{>p{}}}}}", n + 1, n + 1) }))
+    }));
+
+    let template = ctx.compile("{{#none}}{{>p0}}{{/none}}").expect("Failed to compile");
+    assert_eq!(template.size_hint(), usize::MAX);
+    assert_eq!(template.render_data_to_string(&Data::Null).unwrap(), "");
+}
+
+#[test]
+fn test_static_text() {
+    use std::borrow::Cow;
+
+    let template = compile_str("a {{! comment }}{{=<% %>=}}<b>");
+    assert_eq!(template.static_text(), Some("a <b>"));
+    assert_let!(Ok(Cow::Borrowed("a <b>")) = template.render_data_to_cow(&Data::Null) => ());
+    assert_eq!(template.render_to_string(&()).unwrap(), "a <b>");
+
+    assert_eq!(compile_str("").static_text(), Some(""));
+    for source in &["a{{x}}", "{{#s}}a{{/s}}", "{{>user}}"] {
+        assert_eq!(compile_str(source).static_text(), None);
+    }
+
+    let template = compile_str("a{{x}}");
+    let data = MapBuilder::new().insert_str("x", "<b>").build();
+    assert_let!(Ok(Cow::Owned(ref s)) = template.render_data_to_cow(&data) => assert_eq!(s, "a&lt;b&gt;"));
+}
+
+#[test]
+fn test_render_flat() {
+    let template = compile_str("{{a}} {{{a}}} {{b.c}}{{missing}} {{f}} {{n}}|");
+    let data = MapBuilder::new()
+        .insert_str("a", "<a>")
+        .insert_map("b", |m| m.insert_str("c", "d"))
+        .insert_fn("f", |_| "{{b.c}}>".to_string())
+        .insert("n", &Option::None::<String>).unwrap()
+        .build();
+
+    assert_eq!(render_data(&template, &data), "&lt;a&gt; <a> d d&gt; |");
+
+    // Not a map at all.
+    let template = compile_str("<{{.}}>");
+    assert_eq!(template.render_data_to_string(&Data::String("&".to_string())).unwrap(), "<&amp;>");
+}
+
+#[test]
+fn test_render_deep_recursion() {
+    #[derive(Clone, Debug)]
+    struct Loader;
+
+    impl PartialLoader for Loader {
+        fn load(&self, _: impl AsRef<Path>) -> Result<String, Error> {
+            Ok("{{#.}}({{>node}}){{/.}}".to_string())
+        }
+    }
+
+    // Far deeper than the stack would allow if every level recursed.
+    let depth = 20_000;
+    let mut data = Data::Vec(vec![]);
+    for _ in 0..depth {
+        data = Data::Vec(vec![data]);
+    }
+
+    let template = Context::with_loader(Loader).compile("{{>node}}".chars()).expect("Failed to compile");
+    let output = template.render_data_to_string(&data).expect("Failed to render");
+
+    assert_eq!(output, "(".repeat(depth) + &")".repeat(depth));
+
+    // Dropping the data recurses too, so unwind it by hand.
+    while let Data::Vec(mut items) = data {
+        data = items.pop().unwrap_or(Data::Null);
+    }
+}
+
+#[test]
+fn test_compile_reader() {
+    let template = mustache::compile_reader(&b"h\xc3\xa9llo {{name}}"[..]).expect("Failed to compile");
+
+    let mut data = HashMap::new();
+    data.insert("name", "world");
+    assert_eq!(template.render_to_string(&data).unwrap(), "héllo world");
+
+    assert_let!(Err(Error::InvalidStr) = mustache::compile_reader(&b"{{na\xffme}}"[..]) => ());
+}
+
+#[test]
+fn test_render_with_warnings() {
+    let template = compile_str("{{#a}}{{b}}{{/a}}{{^c}}{{> missing}}{{/c}}{{d.e}}");
+
+    let mut ctx = HashMap::new();
+    ctx.insert("a", true);
+
+    let mut bytes = vec![];
+    let warnings = template.render_with_warnings(&mut bytes, &ctx).expect("Failed to render");
+
+    assert_eq!(bytes, b"");
+    assert_eq!(warnings, vec![
+        Warning::MissingKey("b".to_string()),
+        Warning::EmptyPartial("missing".to_string()),
+        Warning::MissingKey("d.e".to_string()),
+    ]);
+}
+
 #[test]
 fn test_render_data_to_string() {
     let ctx = HashMap::new();
@@ -457,9 +2245,9 @@ fn test_render_partial() {
 fn parse_spec_tests(src: &str) -> Vec<Json> {
     let path = PathBuf::from(src);
     let file = File::open(&path)
-        .expect(&format!("Could not read file {}", path.display()));
+        .unwrap_or_else(|_| panic!("Could not read file {}", path.display()));
     let json = serde_json::from_reader(file)
-        .expect(&format!("Invalid json in file {}", path.display()));
+        .unwrap_or_else(|_| panic!("Invalid json in file {}", path.display()));
 
     assert_let!(Json::Object(mut d) = json => {
         assert_let!(Some(Json::Array(tests)) = d.remove("tests") => {
@@ -482,11 +2270,11 @@ fn write_partials(tmpdir: &Path, value: &Json) {
 }
 
 fn run_test(test: serde_json::Map<String, Json>, data: Data) {
-    let template = assert_let!(Some(&Json::String(ref s)) = test.get("template") => {
+    let template = assert_let!(Some(Json::String(s)) = test.get("template") => {
         s.clone()
     });
 
-    let expected = assert_let!(Some(&Json::String(ref s)) = test.get("expected") => {
+    let expected = assert_let!(Some(Json::String(s)) = test.get("expected") => {
         s.clone()
     });
 
@@ -500,7 +2288,7 @@ fn run_test(test: serde_json::Map<String, Json>, data: Data) {
 
     let ctx = Context::new(tmpdir.path().to_path_buf());
     let template = ctx.compile(template.chars())
-                      .expect(&format!("Failed to compile: {}", template));
+                      .unwrap_or_else(|_| panic!("Failed to compile: {}", template));
     let result = render_data(&template, &data);
 
     if result != expected {
@@ -510,7 +2298,7 @@ fn run_test(test: serde_json::Map<String, Json>, data: Data) {
         println!("template: {:?}", template);
         println!("expected: {}", expected);
         println!("actual:   {}", result);
-        println!("");
+        println!();
     }
     assert_eq!(result, expected);
 }
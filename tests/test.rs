@@ -9,4 +9,8 @@ extern crate tempdir;
 mod macros;
 
 mod builder;
+mod data;
+mod encoder;
+mod lint;
 mod template;
+mod typed;
@@ -0,0 +1,91 @@
+use std::path::PathBuf;
+
+use mustache::{Context, Error, Shape, TemplateData, TypedTemplate};
+
+#[derive(Serialize)]
+struct Node {
+    label: String,
+    children: Vec<Node>,
+}
+
+impl TemplateData for Node {
+    fn shape() -> Shape {
+        Shape::Struct(vec![("label", String::shape), ("children", Vec::<Node>::shape)])
+    }
+}
+
+fn check(source: &str) -> Result<TypedTemplate<Node>, Vec<String>> {
+    let mut ctx = Context::new(PathBuf::from("."));
+    ctx.extensions.inline_partials = true;
+    ctx.extensions.json_pointers = true;
+    match TypedTemplate::new(ctx.compile(source.chars()).expect("Failed to compile")) {
+        Ok(typed) => Ok(typed),
+        Err(Error::UnknownNames(names)) => Err(names),
+        Err(err) => panic!("{:?}", err),
+    }
+}
+
+#[test]
+fn test_typed_names() {
+    let tree = "{{<*node}}{{label}}({{#children}}{{>node}}{{/children}}){{/node}}{{>node}}";
+    let typed = check(tree).unwrap();
+
+    let data = Node {
+        label: "a".to_string(),
+        children: vec![Node { label: "b".to_string(), children: vec![] }],
+    };
+    assert_eq!(typed.render_to_string(&data).unwrap(), "a(b())");
+
+    // Names further up the stack are found, missing ones are each reported
+    // once.
+    assert!(check("{{#children}}{{label}}{{children}}{{/children}}{{/children/0/label}}").is_ok());
+    assert_eq!(check("{{lable}}{{#children}}{{^missing}}{{lable}}{{/missing}}{{/children}}{{label.x}}{{/children/x}}").unwrap_err(),
+               vec!["lable", "missing", "label.x", "/children/x"]);
+}
+
+#[cfg(feature = "derive")]
+#[test]
+fn test_derive_template_data() {
+    use std::collections::HashMap;
+
+    #[derive(Serialize, TemplateData)]
+    struct Order {
+        #[serde(rename = "ref")]
+        reference: String,
+        #[serde(skip)]
+        #[allow(dead_code)]
+        secret: String,
+        lines: Vec<Line>,
+        extra: HashMap<String, String>,
+        status: Status,
+    }
+
+    #[derive(Serialize, TemplateData)]
+    struct Line(Item);
+
+    #[derive(Serialize, TemplateData)]
+    struct Item {
+        name: Option<String>,
+    }
+
+    #[derive(Serialize, TemplateData)]
+    #[allow(dead_code)]
+    enum Status {
+        Open,
+        Closed,
+    }
+
+    let typed = TypedTemplate::<Order>::new(mustache::compile_str("{{ref}} {{status}}{{#lines}} {{name}}{{/lines}}{{extra.anything}}").unwrap()).unwrap();
+    let order = Order {
+        reference: "A1".to_string(),
+        secret: "s".to_string(),
+        lines: vec![Line(Item { name: Some("pen".to_string()) })],
+        extra: HashMap::new(),
+        status: Status::Open,
+    };
+    assert_eq!(typed.render_to_string(&order).unwrap(), "A1 Open pen");
+
+    for source in &["{{reference}}", "{{secret}}", "{{status.x}}"] {
+        assert!(TypedTemplate::<Order>::new(mustache::compile_str(source).unwrap()).is_err(), "{}", source);
+    }
+}